@@ -0,0 +1,113 @@
+// The C interface between Trusty Commander and plugins that add archive
+// formats and file systems, in the spirit of Total Commander's WCX and WFX
+// plugins. A plugin is a dynamic library exporting one function,
+// `tc_plugin_entry`, which is given the newest interface version the host
+// speaks and returns a `PluginTable` for the newest version both speak, or
+// null if there is none. The table says what the plugin can do and holds
+// the functions doing it; functions the plugin lacks are null.
+//
+// Strings are UTF-8 and NUL-terminated. Paths inside an archive or a plugin
+// file system are relative to its root and separated by `/`; the root is
+// the empty string. Functions return `OK` or one of the error codes below.
+//
+// Calls may come from any thread, but never two at once for the same
+// session, or for streams opened from it.
+//
+// Later versions of the interface only add fields at the end of the table,
+// so a table is read up to its `size`.
+
+extern crate libloading;
+
+use std::os::raw::{c_char, c_void};
+
+pub mod loader;
+
+// the newest version of the interface, and the oldest one still loaded
+pub const ABI_VERSION: u32 = 1;
+pub const MIN_ABI_VERSION: u32 = 1;
+
+// the symbol every plugin exports
+pub const ENTRY_POINT: &[u8] = b"tc_plugin_entry\0";
+
+// Opens files matching `masks` as archives.
+pub const CAP_ARCHIVE: u32 = 1;
+// Has a file system of its own, opened with a null archive path.
+pub const CAP_FILESYSTEM: u32 = 2;
+// Can create, change and remove files: `write_open`, `write`, `write_close`,
+// `mkdir` and `remove`.
+pub const CAP_WRITE: u32 = 4;
+pub const CAP_RENAME: u32 = 8;
+
+pub const OK: i32 = 0;
+pub const ERROR_NOT_FOUND: i32 = 1;
+pub const ERROR_EXISTS: i32 = 2;
+pub const ERROR_PERMISSION: i32 = 3;
+pub const ERROR_UNSUPPORTED: i32 = 4;
+pub const ERROR_INVALID: i32 = 5;
+pub const ERROR_IO: i32 = 6;
+
+pub const ENTRY_DIR: u32 = 1;
+pub const ENTRY_LINK: u32 = 2;
+pub const ENTRY_HIDDEN: u32 = 4;
+pub const ENTRY_READ_ONLY: u32 = 8;
+// `modified_secs` and `modified_nanos` hold the modification time
+pub const ENTRY_HAS_TIME: u32 = 16;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    // the name within its directory; only valid during the call it is passed to
+    pub name: *const c_char,
+    pub flags: u32,
+    // 0 for directories
+    pub size: u64,
+    // seconds and nanoseconds since 1970-01-01 UTC
+    pub modified_secs: i64,
+    pub modified_nanos: u32,
+}
+
+pub type ListCallback = extern "C" fn(context: *mut c_void, entry: *const Entry);
+
+#[repr(C)]
+pub struct PluginTable {
+    // the size of this struct as the plugin knows it
+    pub size: u32,
+    // the version the plugin chose; not above the one the host offered
+    pub abi_version: u32,
+    pub capabilities: u32,
+    // shown to the user
+    pub name: *const c_char,
+    // `;` separated masks of the archives the plugin opens, like "*.a;*.ar"
+    pub masks: *const c_char,
+
+    // Opens the archive `archive`, a file on the disk, or the plugin's own
+    // file system when `archive` is null; stores the session in `session`.
+    pub open: Option<extern "C" fn(archive: *const c_char, session: *mut *mut c_void) -> i32>,
+    // Called once every stream opened from the session is closed.
+    pub close: Option<extern "C" fn(session: *mut c_void)>,
+    // Calls `callback` with `context` for each entry of `dir`.
+    pub list: Option<extern "C" fn(session: *mut c_void, dir: *const c_char,
+                                   callback: ListCallback, context: *mut c_void) -> i32>,
+    // Fills in `entry`; its name may be left null.
+    pub stat: Option<extern "C" fn(session: *mut c_void, path: *const c_char, entry: *mut Entry) -> i32>,
+
+    pub read_open: Option<extern "C" fn(session: *mut c_void, path: *const c_char, stream: *mut *mut c_void) -> i32>,
+    // Reads up to `len` bytes into `buffer`; 0 in `read` is the end of the file.
+    pub read: Option<extern "C" fn(stream: *mut c_void, buffer: *mut u8, len: usize, read: *mut usize) -> i32>,
+    pub read_close: Option<extern "C" fn(stream: *mut c_void)>,
+
+    // Creates the file `path`, or empties it if it exists.
+    pub write_open: Option<extern "C" fn(session: *mut c_void, path: *const c_char, stream: *mut *mut c_void) -> i32>,
+    pub write: Option<extern "C" fn(stream: *mut c_void, buffer: *const u8, len: usize, written: *mut usize) -> i32>,
+    // Completes the file; the stream is gone afterwards even on error.
+    pub write_close: Option<extern "C" fn(stream: *mut c_void) -> i32>,
+    pub mkdir: Option<extern "C" fn(session: *mut c_void, path: *const c_char) -> i32>,
+    // Removes a file or an empty directory.
+    pub remove: Option<extern "C" fn(session: *mut c_void, path: *const c_char) -> i32>,
+    pub rename: Option<extern "C" fn(session: *mut c_void, from: *const c_char, to: *const c_char) -> i32>,
+}
+
+// The table is static data of the plugin.
+unsafe impl Sync for PluginTable {}
+
+pub type EntryPoint = unsafe extern "C" fn(host_version: u32) -> *const PluginTable;
@@ -0,0 +1,355 @@
+// The host's side of the interface: loads a plugin library, agrees on a
+// version, checks the table against the capabilities it claims, and wraps
+// sessions and streams in safe types reporting `io::Error`s.
+
+use std::ffi::{CStr, CString};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::path::Path;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libloading::Library;
+
+use super::*;
+
+fn error(code: i32, what: &str) -> io::Error
+{
+    let (kind, reason) = match code {
+        ERROR_NOT_FOUND => (io::ErrorKind::NotFound, "not found"),
+        ERROR_EXISTS => (io::ErrorKind::AlreadyExists, "already exists"),
+        ERROR_PERMISSION => (io::ErrorKind::PermissionDenied, "permission denied"),
+        ERROR_UNSUPPORTED => (io::ErrorKind::Other, "not supported by the plugin"),
+        ERROR_INVALID => (io::ErrorKind::InvalidInput, "invalid name"),
+        _ => (io::ErrorKind::Other, "the plugin failed"),
+    };
+    io::Error::new(kind, format!("\"{}\": {}", what, reason))
+}
+
+fn check(code: i32, what: &str) -> io::Result<()>
+{
+    if code == OK { Ok(()) } else { Err(error(code, what)) }
+}
+
+fn invalid(message: String) -> io::Error
+{ io::Error::new(io::ErrorKind::InvalidData, message) }
+
+fn c_string(text: &str) -> io::Result<CString>
+{ CString::new(text).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" contains NUL", text))) }
+
+unsafe fn string_at(text: *const c_char) -> String
+{
+    if text.is_null() { String::new() } else { CStr::from_ptr(text).to_string_lossy().into_owned() }
+}
+
+// An entry as the host sees it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntryInfo {
+    pub name: String,
+    pub is_dir: bool,
+    pub is_link: bool,
+    pub hidden: bool,
+    pub read_only: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl EntryInfo {
+    unsafe fn from_entry(entry: &Entry) -> EntryInfo
+    {
+        let modified = if entry.flags & ENTRY_HAS_TIME == 0 {
+            None
+        } else if entry.modified_secs >= 0 {
+            Some(UNIX_EPOCH + Duration::new(entry.modified_secs as u64, entry.modified_nanos % 1_000_000_000))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(entry.modified_secs.wrapping_neg() as u64))
+        };
+        EntryInfo {
+            name: string_at(entry.name),
+            is_dir: entry.flags & ENTRY_DIR != 0,
+            is_link: entry.flags & ENTRY_LINK != 0,
+            hidden: entry.flags & ENTRY_HIDDEN != 0,
+            read_only: entry.flags & ENTRY_READ_ONLY != 0,
+            size: if entry.flags & ENTRY_DIR != 0 { 0 } else { entry.size },
+            modified,
+        }
+    }
+}
+
+pub struct Plugin {
+    table: &'static PluginTable,
+    name: String,
+    masks: String,
+    // kept last: the table lives in the library
+    _library: Library,
+}
+
+// Plugins promise calls from any thread are fine.
+unsafe impl Send for Plugin {}
+unsafe impl Sync for Plugin {}
+
+impl Plugin {
+    // Loads the plugin library `path`.
+    pub fn load(path: &Path) -> io::Result<Plugin>
+    { Plugin::load_for(path, ABI_VERSION) }
+
+    // Loads `path` as a host speaking versions up to `host_version` would.
+    pub fn load_for(path: &Path, host_version: u32) -> io::Result<Plugin>
+    {
+        let failed = |reason: String| invalid(format!("Cannot load the plugin \"{}\": {}", path.display(), reason));
+        let library = unsafe { Library::new(path) }.map_err(|e| failed(e.to_string()))?;
+        let table = unsafe {
+            let entry = library.get::<EntryPoint>(ENTRY_POINT).map_err(|e| failed(e.to_string()))?;
+            entry(host_version)
+        };
+        if table.is_null() {
+            return Err(failed(format!("it does not support version {} of the plugin interface", host_version)));
+        }
+        let table: &'static PluginTable = unsafe { &*table };
+        if table.abi_version < MIN_ABI_VERSION || table.abi_version > host_version {
+            return Err(failed(format!("it chose version {} of the plugin interface", table.abi_version)));
+        }
+        // version 1 is the first, so its table is all there is for now
+        if (table.size as usize) < mem::size_of::<PluginTable>() {
+            return Err(failed("its function table is too short".to_string()));
+        }
+        let has_all = |functions: &[bool]| functions.iter().all(|&f| f);
+        let basics = has_all(&[table.open.is_some(), table.close.is_some(), table.list.is_some(), table.stat.is_some(),
+                               table.read_open.is_some(), table.read.is_some(), table.read_close.is_some()]);
+        let writes = has_all(&[table.write_open.is_some(), table.write.is_some(), table.write_close.is_some(),
+                               table.mkdir.is_some(), table.remove.is_some()]);
+        if table.capabilities & (CAP_ARCHIVE | CAP_FILESYSTEM) == 0 {
+            return Err(failed("it provides neither archives nor a file system".to_string()));
+        }
+        if !basics
+            || (table.capabilities & CAP_WRITE != 0 && !writes)
+            || (table.capabilities & CAP_RENAME != 0 && table.rename.is_none()) {
+            return Err(failed("it lacks functions for what it claims to do".to_string()));
+        }
+        let (name, masks) = unsafe { (string_at(table.name), string_at(table.masks)) };
+        Ok(Plugin { table, name, masks, _library: library })
+    }
+
+    pub fn name(&self) -> &str
+    { &self.name }
+
+    pub fn masks(&self) -> &str
+    { &self.masks }
+
+    pub fn abi_version(&self) -> u32
+    { self.table.abi_version }
+
+    pub fn capabilities(&self) -> u32
+    { self.table.capabilities }
+
+    pub fn has(&self, capability: u32) -> bool
+    { self.table.capabilities & capability == capability }
+
+    // Opens the archive `archive`, or the plugin's file system if None.
+    pub fn open(plugin: &Arc<Plugin>, archive: Option<&Path>) -> io::Result<Session>
+    {
+        let what = archive.map_or(plugin.name.clone(), |a| a.display().to_string());
+        let path = match archive {
+            Some(archive) => Some(c_string(&archive.to_string_lossy())?),
+            None => None,
+        };
+        let mut handle = ptr::null_mut();
+        let open = plugin.table.open.unwrap();
+        check(open(path.as_ref().map_or(ptr::null(), |p| p.as_ptr()), &mut handle), &what)?;
+        Ok(Session { inner: Arc::new(SessionInner { plugin: plugin.clone(), handle, lock: Mutex::new(()) }) })
+    }
+}
+
+// The plugin's session, shared by the `Session` and its streams and closed
+// when the last of them is gone.
+struct SessionInner {
+    plugin: Arc<Plugin>,
+    handle: *mut c_void,
+    // calls on the session and its streams take turns
+    lock: Mutex<()>,
+}
+
+unsafe impl Send for SessionInner {}
+unsafe impl Sync for SessionInner {}
+
+impl Drop for SessionInner {
+    fn drop(&mut self)
+    {
+        let _turn = self.lock.lock().unwrap();
+        self.plugin.table.close.unwrap()(self.handle);
+    }
+}
+
+// An open archive or plugin file system.
+pub struct Session {
+    inner: Arc<SessionInner>,
+}
+
+extern "C" fn collect_entry(context: *mut c_void, entry: *const Entry)
+{
+    let entries = unsafe { &mut *(context as *mut Vec<EntryInfo>) };
+    if let Some(entry) = unsafe { entry.as_ref() } {
+        entries.push(unsafe { EntryInfo::from_entry(entry) });
+    }
+}
+
+impl Session {
+    pub fn plugin(&self) -> &Arc<Plugin>
+    { &self.inner.plugin }
+
+    fn supports(&self, capability: u32, path: &str) -> io::Result<()>
+    {
+        if self.inner.plugin.has(capability) { Ok(()) } else { Err(error(ERROR_UNSUPPORTED, path)) }
+    }
+
+    pub fn list(&self, dir: &str) -> io::Result<Vec<EntryInfo>>
+    {
+        let c_dir = c_string(dir)?;
+        let mut entries: Vec<EntryInfo> = Vec::new();
+        let _turn = self.inner.lock.lock().unwrap();
+        let list = self.inner.plugin.table.list.unwrap();
+        check(list(self.inner.handle, c_dir.as_ptr(), collect_entry, &mut entries as *mut _ as *mut c_void), dir)?;
+        Ok(entries)
+    }
+
+    pub fn stat(&self, path: &str) -> io::Result<EntryInfo>
+    {
+        let c_path = c_string(path)?;
+        let mut entry = Entry { name: ptr::null(), flags: 0, size: 0, modified_secs: 0, modified_nanos: 0 };
+        let _turn = self.inner.lock.lock().unwrap();
+        let stat = self.inner.plugin.table.stat.unwrap();
+        check(stat(self.inner.handle, c_path.as_ptr(), &mut entry), path)?;
+        let mut info = unsafe { EntryInfo::from_entry(&entry) };
+        info.name = path.rsplit('/').next().unwrap_or("").to_string();
+        Ok(info)
+    }
+
+    pub fn open_read(&self, path: &str) -> io::Result<Reader>
+    {
+        let c_path = c_string(path)?;
+        let mut stream = ptr::null_mut();
+        let _turn = self.inner.lock.lock().unwrap();
+        let read_open = self.inner.plugin.table.read_open.unwrap();
+        check(read_open(self.inner.handle, c_path.as_ptr(), &mut stream), path)?;
+        Ok(Reader { session: self.inner.clone(), stream, path: path.to_string() })
+    }
+
+    pub fn open_write(&self, path: &str) -> io::Result<Writer>
+    {
+        self.supports(CAP_WRITE, path)?;
+        let c_path = c_string(path)?;
+        let mut stream = ptr::null_mut();
+        let _turn = self.inner.lock.lock().unwrap();
+        let write_open = self.inner.plugin.table.write_open.unwrap();
+        check(write_open(self.inner.handle, c_path.as_ptr(), &mut stream), path)?;
+        Ok(Writer { session: self.inner.clone(), stream, path: path.to_string() })
+    }
+
+    pub fn mkdir(&self, path: &str) -> io::Result<()>
+    {
+        self.supports(CAP_WRITE, path)?;
+        let c_path = c_string(path)?;
+        let _turn = self.inner.lock.lock().unwrap();
+        check(self.inner.plugin.table.mkdir.unwrap()(self.inner.handle, c_path.as_ptr()), path)
+    }
+
+    pub fn remove(&self, path: &str) -> io::Result<()>
+    {
+        self.supports(CAP_WRITE, path)?;
+        let c_path = c_string(path)?;
+        let _turn = self.inner.lock.lock().unwrap();
+        check(self.inner.plugin.table.remove.unwrap()(self.inner.handle, c_path.as_ptr()), path)
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> io::Result<()>
+    {
+        self.supports(CAP_RENAME, from)?;
+        let (c_from, c_to) = (c_string(from)?, c_string(to)?);
+        let _turn = self.inner.lock.lock().unwrap();
+        check(self.inner.plugin.table.rename.unwrap()(self.inner.handle, c_from.as_ptr(), c_to.as_ptr()), from)
+    }
+}
+
+// A file being read. Keeps the session open until it is dropped.
+pub struct Reader {
+    session: Arc<SessionInner>,
+    stream: *mut c_void,
+    path: String,
+}
+
+unsafe impl Send for Reader {}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        let mut read = 0;
+        let _turn = self.session.lock.lock().unwrap();
+        let table = self.session.plugin.table;
+        check(table.read.unwrap()(self.stream, buf.as_mut_ptr(), buf.len(), &mut read), &self.path)?;
+        if read > buf.len() {
+            return Err(invalid(format!("\"{}\": the plugin read past the buffer", self.path)));
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self)
+    {
+        let _turn = self.session.lock.lock().unwrap();
+        self.session.plugin.table.read_close.unwrap()(self.stream);
+    }
+}
+
+// A file being written; it is complete once closed, by `close` or on drop.
+// Keeps the session open until then.
+pub struct Writer {
+    session: Arc<SessionInner>,
+    stream: *mut c_void,
+    path: String,
+}
+
+unsafe impl Send for Writer {}
+
+impl Writer {
+    pub fn close(mut self) -> io::Result<()>
+    { self.finish() }
+
+    fn finish(&mut self) -> io::Result<()>
+    {
+        let _turn = self.session.lock.lock().unwrap();
+        let stream = mem::replace(&mut self.stream, ptr::null_mut());
+        if stream.is_null() {
+            return Ok(());
+        }
+        check(self.session.plugin.table.write_close.unwrap()(stream), &self.path)
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        if self.stream.is_null() {
+            return Err(io::Error::other(format!("\"{}\" is already closed", self.path)));
+        }
+        let mut written = 0;
+        let _turn = self.session.lock.lock().unwrap();
+        let table = self.session.plugin.table;
+        check(table.write.unwrap()(self.stream, buf.as_ptr(), buf.len(), &mut written), &self.path)?;
+        if written > buf.len() {
+            return Err(invalid(format!("\"{}\": the plugin wrote past the buffer", self.path)));
+        }
+        Ok(written)
+    }
+
+    // Nothing is buffered on this side.
+    fn flush(&mut self) -> io::Result<()>
+    { Ok(()) }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self)
+    { let _ = self.finish(); }
+}
@@ -0,0 +1,390 @@
+// An example plugin: opens Unix `ar` archives (static libraries, .deb
+// packages) read-only, and has a file system of its own, "Memory", that
+// keeps files in memory for as long as the plugin is loaded.
+
+extern crate trusty_commander_plugin as api;
+
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use api::*;
+
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+const AR_HEADER_LEN: usize = 60;
+
+struct Member {
+    name: String,
+    modified: i64,
+    mode: u32,
+    data: Vec<u8>,
+}
+
+struct MemoryNode {
+    // None for directories
+    data: Option<Vec<u8>>,
+    modified: i64,
+}
+
+// The Memory file system, by path; the root is not in it.
+static MEMORY: Mutex<BTreeMap<String, MemoryNode>> = Mutex::new(BTreeMap::new());
+
+enum Session {
+    Archive(Vec<Member>),
+    Memory,
+}
+
+struct WriteStream {
+    path: String,
+    data: Vec<u8>,
+}
+
+fn now() -> i64
+{ SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0) }
+
+fn field(header: &[u8], from: usize, to: usize) -> String
+{ String::from_utf8_lossy(&header[from..to]).trim().to_string() }
+
+// Members of an ar archive, with GNU (`//` table, `/n` names) and BSD
+// (`#1/len`) long names.
+fn read_ar(data: &[u8]) -> Option<Vec<Member>>
+{
+    if !data.starts_with(AR_MAGIC) {
+        return None;
+    }
+    let mut members = Vec::new();
+    let mut long_names: &[u8] = &[];
+    let mut at = AR_MAGIC.len();
+    while at + AR_HEADER_LEN <= data.len() {
+        let header = &data[at..at + AR_HEADER_LEN];
+        if &header[58..60] != b"`\n" {
+            return None;
+        }
+        let size: usize = match field(header, 48, 58).parse() { Ok(size) => size, Err(_) => return None };
+        let start = at + AR_HEADER_LEN;
+        if start + size > data.len() {
+            return None;
+        }
+        let mut content = &data[start..start + size];
+        at = start + size + size % 2;
+        let raw_name = field(header, 0, 16);
+        let name = if raw_name == "//" {
+            long_names = content;
+            continue;
+        } else if raw_name == "/" || raw_name == "/SYM64/" || raw_name.starts_with("__.SYMDEF") {
+            continue;
+        } else if let Some(len) = raw_name.strip_prefix("#1/") {
+            let len: usize = match len.parse() { Ok(len) if len <= content.len() => len, _ => return None };
+            let name = String::from_utf8_lossy(&content[..len]).trim_end_matches('\0').to_string();
+            content = &content[len..];
+            name
+        } else if let Some(offset) = raw_name.strip_prefix('/') {
+            let offset: usize = match offset.parse() { Ok(offset) if offset < long_names.len() => offset, _ => return None };
+            let rest = &long_names[offset..];
+            let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+            String::from_utf8_lossy(&rest[..end]).trim_end_matches('/').to_string()
+        } else {
+            raw_name.trim_end_matches('/').to_string()
+        };
+        members.push(Member {
+            name,
+            modified: field(header, 16, 28).parse().unwrap_or(0),
+            mode: u32::from_str_radix(&field(header, 40, 48), 8).unwrap_or(0o644),
+            data: content.to_vec(),
+        });
+    }
+    Some(members)
+}
+
+unsafe fn text<'a>(text: *const c_char) -> Option<&'a str>
+{
+    if text.is_null() { None } else { CStr::from_ptr(text).to_str().ok() }
+}
+
+fn parent(path: &str) -> &str
+{ path.rfind('/').map_or("", |at| &path[..at]) }
+
+fn name_of(path: &str) -> &str
+{ path.rfind('/').map_or(path, |at| &path[at + 1..]) }
+
+fn memory_entry(node: &MemoryNode) -> Entry
+{
+    Entry {
+        name: ptr::null(),
+        flags: ENTRY_HAS_TIME | if node.data.is_none() { ENTRY_DIR } else { 0 },
+        size: node.data.as_ref().map_or(0, |d| d.len() as u64),
+        modified_secs: node.modified,
+        modified_nanos: 0,
+    }
+}
+
+fn member_entry(member: &Member) -> Entry
+{
+    Entry {
+        name: ptr::null(),
+        flags: ENTRY_HAS_TIME | if member.mode & 0o222 == 0 { ENTRY_READ_ONLY } else { 0 },
+        size: member.data.len() as u64,
+        modified_secs: member.modified,
+        modified_nanos: 0,
+    }
+}
+
+extern "C" fn open(archive: *const c_char, session: *mut *mut c_void) -> i32
+{
+    let opened = match unsafe { text(archive) } {
+        None if archive.is_null() => Session::Memory,
+        None => return ERROR_INVALID,
+        Some(path) => {
+            let mut data = Vec::new();
+            if File::open(path).and_then(|mut f| f.read_to_end(&mut data)).is_err() {
+                return ERROR_IO;
+            }
+            match read_ar(&data) {
+                Some(members) => Session::Archive(members),
+                None => return ERROR_INVALID,
+            }
+        },
+    };
+    unsafe { *session = Box::into_raw(Box::new(opened)) as *mut c_void };
+    OK
+}
+
+extern "C" fn close(session: *mut c_void)
+{ drop(unsafe { Box::from_raw(session as *mut Session) }) }
+
+extern "C" fn list(session: *mut c_void, dir: *const c_char, callback: ListCallback, context: *mut c_void) -> i32
+{
+    let dir = match unsafe { text(dir) } { Some(dir) => dir, None => return ERROR_INVALID };
+    let mut found: Vec<(String, Entry)> = Vec::new();
+    match unsafe { &*(session as *const Session) } {
+        // members are all in the root
+        Session::Archive(members) => {
+            if !dir.is_empty() {
+                return ERROR_NOT_FOUND;
+            }
+            found.extend(members.iter().map(|m| (m.name.clone(), member_entry(m))));
+        },
+        Session::Memory => {
+            let memory = MEMORY.lock().unwrap();
+            if !dir.is_empty() && memory.get(dir).is_none_or(|node| node.data.is_some()) {
+                return ERROR_NOT_FOUND;
+            }
+            found.extend(memory.iter()
+                         .filter(|&(path, _)| parent(path) == dir)
+                         .map(|(path, node)| (name_of(path).to_string(), memory_entry(node))));
+        },
+    }
+    for (name, mut entry) in found {
+        let name = match CString::new(name) { Ok(name) => name, Err(_) => continue };
+        entry.name = name.as_ptr();
+        callback(context, &entry);
+    }
+    OK
+}
+
+extern "C" fn stat(session: *mut c_void, path: *const c_char, entry: *mut Entry) -> i32
+{
+    let path = match unsafe { text(path) } { Some(path) => path, None => return ERROR_INVALID };
+    let found = match unsafe { &*(session as *const Session) } {
+        Session::Archive(members) => members.iter().find(|m| m.name == path).map(member_entry),
+        Session::Memory => MEMORY.lock().unwrap().get(path).map(memory_entry),
+    };
+    match found {
+        Some(found) => { unsafe { *entry = found }; OK },
+        None => ERROR_NOT_FOUND,
+    }
+}
+
+extern "C" fn read_open(session: *mut c_void, path: *const c_char, stream: *mut *mut c_void) -> i32
+{
+    let path = match unsafe { text(path) } { Some(path) => path, None => return ERROR_INVALID };
+    let data = match unsafe { &*(session as *const Session) } {
+        Session::Archive(members) => members.iter().find(|m| m.name == path).map(|m| m.data.clone()),
+        Session::Memory => MEMORY.lock().unwrap().get(path).and_then(|node| node.data.clone()),
+    };
+    match data {
+        Some(data) => {
+            unsafe { *stream = Box::into_raw(Box::new(Cursor::new(data))) as *mut c_void };
+            OK
+        },
+        None => ERROR_NOT_FOUND,
+    }
+}
+
+extern "C" fn read(stream: *mut c_void, buffer: *mut u8, len: usize, read: *mut usize) -> i32
+{
+    let cursor = unsafe { &mut *(stream as *mut Cursor<Vec<u8>>) };
+    let buffer = unsafe { slice::from_raw_parts_mut(buffer, len) };
+    match cursor.read(buffer) {
+        Ok(n) => { unsafe { *read = n }; OK },
+        Err(_) => ERROR_IO,
+    }
+}
+
+extern "C" fn read_close(stream: *mut c_void)
+{ drop(unsafe { Box::from_raw(stream as *mut Cursor<Vec<u8>>) }) }
+
+// Whether `path` can be created in the Memory file system.
+fn can_create(memory: &BTreeMap<String, MemoryNode>, path: &str) -> i32
+{
+    if path.is_empty() || name_of(path).is_empty() {
+        return ERROR_INVALID;
+    }
+    let dir = parent(path);
+    if !dir.is_empty() && memory.get(dir).is_none_or(|node| node.data.is_some()) {
+        return ERROR_NOT_FOUND;
+    }
+    OK
+}
+
+// Archives are read-only.
+fn is_memory(session: *mut c_void) -> bool
+{
+    match unsafe { &*(session as *const Session) } {
+        Session::Archive(_) => false,
+        Session::Memory => true,
+    }
+}
+
+extern "C" fn write_open(session: *mut c_void, path: *const c_char, stream: *mut *mut c_void) -> i32
+{
+    let path = match unsafe { text(path) } { Some(path) => path, None => return ERROR_INVALID };
+    if !is_memory(session) {
+        return ERROR_PERMISSION;
+    }
+    let memory = MEMORY.lock().unwrap();
+    let status = can_create(&memory, path);
+    if status != OK {
+        return status;
+    }
+    if memory.get(path).is_some_and(|node| node.data.is_none()) {
+        return ERROR_EXISTS;
+    }
+    let opened = WriteStream { path: path.to_string(), data: Vec::new() };
+    unsafe { *stream = Box::into_raw(Box::new(opened)) as *mut c_void };
+    OK
+}
+
+extern "C" fn write(stream: *mut c_void, buffer: *const u8, len: usize, written: *mut usize) -> i32
+{
+    let stream = unsafe { &mut *(stream as *mut WriteStream) };
+    stream.data.extend_from_slice(unsafe { slice::from_raw_parts(buffer, len) });
+    unsafe { *written = len };
+    OK
+}
+
+extern "C" fn write_close(stream: *mut c_void) -> i32
+{
+    let stream = unsafe { Box::from_raw(stream as *mut WriteStream) };
+    let mut memory = MEMORY.lock().unwrap();
+    let status = can_create(&memory, &stream.path);
+    if status == OK {
+        memory.insert(stream.path, MemoryNode { data: Some(stream.data), modified: now() });
+    }
+    status
+}
+
+extern "C" fn mkdir(session: *mut c_void, path: *const c_char) -> i32
+{
+    let path = match unsafe { text(path) } { Some(path) => path, None => return ERROR_INVALID };
+    if !is_memory(session) {
+        return ERROR_PERMISSION;
+    }
+    let mut memory = MEMORY.lock().unwrap();
+    let status = can_create(&memory, path);
+    if status != OK {
+        return status;
+    }
+    if memory.contains_key(path) {
+        return ERROR_EXISTS;
+    }
+    memory.insert(path.to_string(), MemoryNode { data: None, modified: now() });
+    OK
+}
+
+extern "C" fn remove(session: *mut c_void, path: *const c_char) -> i32
+{
+    let path = match unsafe { text(path) } { Some(path) => path, None => return ERROR_INVALID };
+    if !is_memory(session) {
+        return ERROR_PERMISSION;
+    }
+    let mut memory = MEMORY.lock().unwrap();
+    if !memory.contains_key(path) {
+        return ERROR_NOT_FOUND;
+    }
+    if memory.keys().any(|other| parent(other) == path) {
+        return ERROR_EXISTS;
+    }
+    memory.remove(path);
+    OK
+}
+
+extern "C" fn rename(session: *mut c_void, from: *const c_char, to: *const c_char) -> i32
+{
+    let (from, to) = match unsafe { (text(from), text(to)) } {
+        (Some(from), Some(to)) => (from, to),
+        _ => return ERROR_INVALID,
+    };
+    if !is_memory(session) {
+        return ERROR_PERMISSION;
+    }
+    let mut memory = MEMORY.lock().unwrap();
+    if !memory.contains_key(from) {
+        return ERROR_NOT_FOUND;
+    }
+    if memory.contains_key(to) {
+        return ERROR_EXISTS;
+    }
+    let status = can_create(&memory, to);
+    if status != OK {
+        return status;
+    }
+    if to.starts_with(&format!("{}/", from)) {
+        return ERROR_INVALID;
+    }
+    // the entry and everything below it
+    let moved: Vec<String> = memory.keys()
+        .filter(|path| *path == from || path.starts_with(&format!("{}/", from)))
+        .cloned()
+        .collect();
+    for path in moved {
+        let node = memory.remove(&path).unwrap();
+        memory.insert(format!("{}{}", to, &path[from.len()..]), node);
+    }
+    OK
+}
+
+static TABLE: PluginTable = PluginTable {
+    size: mem::size_of::<PluginTable>() as u32,
+    abi_version: 1,
+    capabilities: CAP_ARCHIVE | CAP_FILESYSTEM | CAP_WRITE | CAP_RENAME,
+    name: b"Memory\0" as *const u8 as *const c_char,
+    masks: b"*.a;*.ar;*.deb\0" as *const u8 as *const c_char,
+    open: Some(open),
+    close: Some(close),
+    list: Some(list),
+    stat: Some(stat),
+    read_open: Some(read_open),
+    read: Some(read),
+    read_close: Some(read_close),
+    write_open: Some(write_open),
+    write: Some(write),
+    write_close: Some(write_close),
+    mkdir: Some(mkdir),
+    remove: Some(remove),
+    rename: Some(rename),
+};
+
+// Version 1 is the only one there is so far.
+#[no_mangle]
+pub extern "C" fn tc_plugin_entry(host_version: u32) -> *const PluginTable
+{
+    if host_version >= 1 { &TABLE } else { ptr::null() }
+}
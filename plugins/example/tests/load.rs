@@ -0,0 +1,120 @@
+// Loads the example plugin the way the commander does and goes through
+// what it offers: an ar archive and the Memory file system.
+
+extern crate trusty_commander_plugin as api;
+
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use api::loader::Plugin;
+
+// The library cargo built for the test: next to it in `deps`, or one up.
+fn library() -> PathBuf
+{
+    let exe = env::current_exe().unwrap();
+    let name = format!("{}example_plugin{}", env::consts::DLL_PREFIX, env::consts::DLL_SUFFIX);
+    let deps = exe.parent().unwrap();
+    let beside = deps.join(&name);
+    if beside.exists() { beside } else { deps.parent().unwrap().join(&name) }
+}
+
+fn ar_member(out: &mut Vec<u8>, name: &str, mtime: u64, data: &[u8])
+{
+    out.extend_from_slice(format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, mtime, 0, 0, 644, data.len()).as_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(b'\n');
+    }
+}
+
+#[test]
+fn negotiates_and_lists_capabilities()
+{
+    let plugin = Plugin::load(&library()).unwrap();
+    assert_eq!(plugin.name(), "Memory");
+    assert_eq!(plugin.masks(), "*.a;*.ar;*.deb");
+    assert_eq!(plugin.abi_version(), 1);
+    assert!(plugin.has(api::CAP_ARCHIVE | api::CAP_FILESYSTEM | api::CAP_WRITE | api::CAP_RENAME));
+    // a host from before version 1 gets nothing
+    let old = Plugin::load_for(&library(), 0).err().unwrap();
+    assert!(old.to_string().contains("does not support version 0"), "{}", old);
+    // a newer host gets version 1
+    assert_eq!(Plugin::load_for(&library(), 7).unwrap().abi_version(), 1);
+    assert!(Plugin::load(&env::current_exe().unwrap()).is_err());
+}
+
+#[test]
+fn reads_ar_archives()
+{
+    let plugin = Arc::new(Plugin::load(&library()).unwrap());
+    let mut ar = b"!<arch>\n".to_vec();
+    let long = "a_member_with_a_long_name.o";
+    ar_member(&mut ar, "//", 0, format!("{}/\n", long).as_bytes());
+    ar_member(&mut ar, "short.o/", 1500000000, b"odd");
+    ar_member(&mut ar, "/0", 1600000000, b"long data");
+    let path = env::temp_dir().join(format!("example_plugin_test_{}.a", std::process::id()));
+    fs::write(&path, &ar).unwrap();
+
+    let session = Plugin::open(&plugin, Some(&path)).unwrap();
+    let names: Vec<String> = session.list("").unwrap().into_iter().map(|e| e.name).collect();
+    assert_eq!(names, vec!["short.o", long]);
+    let entry = session.stat(long).unwrap();
+    assert_eq!(entry.size, 9);
+    assert_eq!(entry.modified, Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1600000000)));
+    let mut data = String::new();
+    session.open_read("short.o").unwrap().read_to_string(&mut data).unwrap();
+    assert_eq!(data, "odd");
+    assert_eq!(session.stat("missing").err().unwrap().kind(), std::io::ErrorKind::NotFound);
+    assert_eq!(session.mkdir("dir").err().unwrap().kind(), std::io::ErrorKind::PermissionDenied);
+    drop(session);
+    fs::remove_file(&path).unwrap();
+
+    fs::write(&path, b"not an archive").unwrap();
+    assert!(Plugin::open(&plugin, Some(&path)).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn writes_to_its_file_system()
+{
+    let plugin = Arc::new(Plugin::load(&library()).unwrap());
+    let session = Plugin::open(&plugin, None).unwrap();
+    session.mkdir("docs").unwrap();
+    assert_eq!(session.mkdir("docs").err().unwrap().kind(), std::io::ErrorKind::AlreadyExists);
+    {
+        let mut file = session.open_write("docs/note.txt").unwrap();
+        file.write_all(b"kept in memory").unwrap();
+        file.close().unwrap();
+    }
+    assert!(session.open_write("nowhere/x").is_err());
+    let docs = session.list("docs").unwrap();
+    assert_eq!(docs.len(), 1);
+    assert_eq!((docs[0].name.as_str(), docs[0].size, docs[0].is_dir), ("note.txt", 14, false));
+    assert!(session.stat("docs").unwrap().is_dir);
+
+    session.rename("docs", "papers").unwrap();
+    // another session sees the same files, and reads while the first is open
+    let other = Plugin::open(&plugin, None).unwrap();
+    let mut data = String::new();
+    other.open_read("papers/note.txt").unwrap().read_to_string(&mut data).unwrap();
+    assert_eq!(data, "kept in memory");
+    assert!(other.remove("papers").is_err());
+    other.remove("papers/note.txt").unwrap();
+    other.remove("papers").unwrap();
+    assert!(session.list("").unwrap().is_empty());
+
+    // streams keep their session open
+    let mut file = other.open_write("outlived.txt").unwrap();
+    drop(other);
+    file.write_all(b"written after the session").unwrap();
+    file.close().unwrap();
+    let mut reader = session.open_read("outlived.txt").unwrap();
+    session.remove("outlived.txt").unwrap();
+    drop(session);
+    let mut data = String::new();
+    reader.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "written after the session");
+}
@@ -0,0 +1,41 @@
+// Archives the panels enter like directories. Enter on a file whose name
+// looks like a supported archive opens it as a file system shown at the
+// archive's own path.
+
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use ::mask;
+use ::plugins::{self, PluginFs};
+use ::tar::TarFs;
+use ::vfs::{self, Vfs};
+use ::zip::ZipFs;
+
+const ZIP_MASKS: &'static str = "*.zip;*.jar;*.apk;*.docx;*.xlsx;*.pptx;*.odt;*.ods;*.epub";
+const TAR_MASKS: &'static str = "*.tar;*.tar.gz;*.tgz;*.tar.xz;*.txz";
+
+pub fn is_archive(name: &str) -> bool
+{
+    mask::matches_any(ZIP_MASKS, name) || mask::matches_any(TAR_MASKS, name)
+        || plugins::archive_plugin(name).is_some()
+}
+
+// Opens the archive `path` of `vfs`. Archives that are not on the disk, such
+// as one inside another archive, are copied out first.
+pub fn open(vfs: &Vfs, path: &Path) -> io::Result<Arc<Vfs>>
+{
+    let file = try!(vfs::local_copy(vfs, path));
+    let name = path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    // changes to a copy would be lost
+    let writable = vfs.local_path(path).is_some();
+    // plugins come first, so they can take over a format
+    let opened: io::Result<Arc<Vfs>> = if let Some(plugin) = plugins::archive_plugin(&name) {
+        PluginFs::open(&plugin, Some(&file), path, writable).map(|fs| Arc::new(fs) as Arc<Vfs>)
+    } else if mask::matches_any(TAR_MASKS, &name) {
+        TarFs::open(&file, path).map(|tar| Arc::new(tar) as Arc<Vfs>)
+    } else {
+        ZipFs::open(&file, path, writable).map(|zip| Arc::new(zip) as Arc<Vfs>)
+    };
+    opened.map_err(|e| io::Error::new(e.kind(), format!("Cannot open \"{}\": {}", path.display(), e)))
+}
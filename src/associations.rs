@@ -0,0 +1,73 @@
+// File associations: what Enter does with a file. Each association maps file
+// name masks to a command template (see `command_template`); files no
+// association matches go to the system's default handler.
+//
+// In settings.ini they are the `[associations]` section, e.g.
+// `*.txt;*.log=notepad.exe "%P"`. The first matching line is the default,
+// all matching lines are offered by "Open with".
+
+use std::path::Path;
+
+use ::command_template;
+use ::mask;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Association {
+    pub masks: String,
+    pub command: String,
+}
+
+impl Association {
+    // Menu text: the program's name and the masks it is used for.
+    pub fn label(&self) -> String
+    {
+        let program = command_template::split(&self.command).into_iter().next().unwrap_or_default();
+        let file = program.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+        let name = Path::new(file).file_stem().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        format!("{} ({})", name, self.masks)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Associations {
+    pub entries: Vec<Association>,
+}
+
+impl Associations {
+    pub fn new() -> Associations
+    {
+        Associations { entries: Vec::new() }
+    }
+
+    pub fn add(&mut self, masks: &str, command: &str)
+    {
+        let (masks, command) = (masks.trim(), command.trim());
+        if !mask::split(masks).is_empty() && !command.is_empty() {
+            self.entries.push(Association { masks: masks.to_string(), command: command.to_string() });
+        }
+    }
+
+    // Like `add`, but ahead of the existing associations, so Enter uses it.
+    pub fn add_first(&mut self, masks: &str, command: &str)
+    {
+        let before = self.entries.len();
+        self.add(masks, command);
+        if self.entries.len() > before {
+            let added = self.entries.pop().unwrap();
+            self.entries.insert(0, added);
+        }
+    }
+
+    // Associations whose masks match the file name, in the order of the settings.
+    pub fn matching(&self, path: &Path) -> Vec<&Association>
+    {
+        let name = path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        self.entries.iter().filter(|a| mask::matches_any(&a.masks, &name)).collect()
+    }
+
+    // The command Enter runs, or None for the system's default handler.
+    pub fn command_for(&self, path: &Path) -> Option<&str>
+    {
+        self.matching(path).first().map(|a| a.command.as_str())
+    }
+}
@@ -0,0 +1,259 @@
+// Path header of a file panel: splitting the current path into clickable
+// segments, sibling lookup for the separator dropdowns, and validation and
+// tab completion for the free-form path editor.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use ::vfs::{LocalFs, Vfs};
+
+pub struct Segment {
+    pub label: String,
+    pub path: PathBuf,
+}
+
+// `C:\Users\me` gives `C:\`, `Users`, `me`; `/home/me` gives `/`, `home`, `me`.
+pub fn segments(path: &Path) -> Vec<Segment>
+{
+    let mut rv = Vec::new();
+    let mut acc = PathBuf::new();
+    let mut pending_prefix = false;
+    for component in path.components() {
+        acc.push(component.as_os_str());
+        let label = match component {
+            Component::Prefix(_) => { pending_prefix = true; continue; },
+            Component::RootDir => acc.to_string_lossy().into_owned(),
+            _ if pending_prefix => acc.to_string_lossy().into_owned(),
+            other => other.as_os_str().to_string_lossy().into_owned(),
+        };
+        pending_prefix = false;
+        rv.push(Segment { label: label, path: acc.clone() });
+    }
+    if pending_prefix {
+        rv.push(Segment { label: acc.to_string_lossy().into_owned(), path: acc });
+    }
+    rv
+}
+
+fn sort_names(names: &mut Vec<String>)
+{
+    names.sort_by(|a, b| a.to_lowercase().cmp(&b.to_lowercase()).then(a.cmp(b)));
+}
+
+// Names of the directories directly inside `path`, sorted case-insensitively.
+pub fn subdirectories(vfs: &Vfs, path: &Path) -> io::Result<Vec<String>>
+{
+    let mut names: Vec<String> = try!(vfs.list(path)).into_iter()
+        .filter(|entry| entry.meta.is_dir)
+        .map(|entry| entry.name)
+        .collect();
+    sort_names(&mut names);
+    Ok(names)
+}
+
+// Folds `.` and `..` without touching the filesystem, so the panel header
+// never shows `C:\a\..\b`.
+pub fn normalize(path: &Path) -> PathBuf
+{
+    let mut rv = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            // `..` above the root stays at the root
+            Component::ParentDir => if rv.file_name().is_some() { rv.pop(); },
+            other => rv.push(other.as_os_str()),
+        }
+    }
+    rv
+}
+
+// Turns what the user typed into an existing directory; relative input is
+// resolved against `base`, the directory the panel is showing.
+pub fn validate(input: &str, base: &Path) -> io::Result<PathBuf>
+{
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Path is empty"));
+    }
+    let path = normalize(&base.join(input));
+    match fs::metadata(&path) {
+        Ok(ref meta) if meta.is_dir() => Ok(path),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                    format!("Not a directory: {}", path.display()))),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound =>
+            Err(io::Error::new(io::ErrorKind::NotFound,
+                               format!("Directory does not exist: {}", path.display()))),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_separator(c: char) -> bool
+{ c == '/' || (cfg!(windows) && c == '\\') }
+
+fn starts_with_name(name: &str, prefix: &str) -> bool
+{
+    if cfg!(windows) { name.to_lowercase().starts_with(&prefix.to_lowercase()) }
+    else { name.starts_with(prefix) }
+}
+
+// Every directory the last component of `input` could be completed to,
+// each returned as the whole edited input.
+pub fn completions(input: &str, base: &Path) -> Vec<String>
+{
+    let split = input.rfind(is_separator).map_or(0, |idx| idx + 1);
+    let (dir_part, prefix) = input.split_at(split);
+    let dir = if dir_part.is_empty() { base.to_path_buf() } else { base.join(dir_part) };
+    let mut names: Vec<String> = subdirectories(&LocalFs, &dir).unwrap_or_default()
+        .into_iter()
+        .filter(|name| starts_with_name(name, prefix))
+        .collect();
+    sort_names(&mut names);
+    names.into_iter().map(|name| format!("{}{}", dir_part, name)).collect()
+}
+
+// Repeated Tab presses cycle through the candidates of the first press;
+// once the user edits the text the candidates are recomputed.
+pub struct TabCompletion {
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl TabCompletion {
+    pub fn new() -> TabCompletion
+    { TabCompletion { candidates: Vec::new(), index: 0 } }
+
+    pub fn advance(&mut self, current: &str, base: &Path) -> Option<String>
+    {
+        let cycling = self.candidates.get(self.index).map_or(false, |c| c == current);
+        if cycling {
+            self.index = (self.index + 1) % self.candidates.len();
+        } else {
+            self.candidates = completions(current, base);
+            self.index = 0;
+        }
+        self.candidates.get(self.index).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::vfs::MemoryFs;
+    use std::env;
+    use std::process;
+
+    fn labels(path: &str) -> Vec<(String, PathBuf)>
+    { segments(Path::new(path)).into_iter().map(|segment| (segment.label, segment.path)).collect() }
+
+    fn temp_dir(name: &str) -> PathBuf
+    {
+        let dir = env::temp_dir().join(format!("breadcrumb_test_{}_{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_segments()
+    {
+        assert_eq!(labels("/home/me"), vec![
+            ("/".to_string(), PathBuf::from("/")),
+            ("home".to_string(), PathBuf::from("/home")),
+            ("me".to_string(), PathBuf::from("/home/me")),
+        ]);
+        // a trailing separator adds nothing
+        assert_eq!(labels("/home/me/"), labels("/home/me"));
+        assert_eq!(labels("/"), vec![("/".to_string(), PathBuf::from("/"))]);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn drive_and_unc_segments()
+    {
+        assert_eq!(labels("C:\\Users\\me\\"), vec![
+            ("C:\\".to_string(), PathBuf::from("C:\\")),
+            ("Users".to_string(), PathBuf::from("C:\\Users")),
+            ("me".to_string(), PathBuf::from("C:\\Users\\me")),
+        ]);
+        assert_eq!(labels("C:\\"), vec![("C:\\".to_string(), PathBuf::from("C:\\"))]);
+        // a drive without its root is one segment
+        assert_eq!(labels("D:"), vec![("D:".to_string(), PathBuf::from("D:"))]);
+        assert_eq!(labels("\\\\server\\share\\dir"), vec![
+            ("\\\\server\\share\\".to_string(), PathBuf::from("\\\\server\\share\\")),
+            ("dir".to_string(), PathBuf::from("\\\\server\\share\\dir")),
+        ]);
+        assert_eq!(normalize(Path::new("C:\\a\\..\\..\\b")), PathBuf::from("C:\\b"));
+        assert_eq!(normalize(Path::new("\\\\server\\share\\a\\..")), PathBuf::from("\\\\server\\share\\"));
+    }
+
+    #[test]
+    fn dots_are_folded()
+    {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), PathBuf::from("/a/c"));
+        assert_eq!(normalize(Path::new("/a/b/")), PathBuf::from("/a/b"));
+        // not above the root
+        assert_eq!(normalize(Path::new("/a/../../..")), PathBuf::from("/"));
+        assert_eq!(normalize(Path::new("a/../../b")), PathBuf::from("b"));
+    }
+
+    #[test]
+    fn subdirectories_are_sorted_without_case()
+    {
+        let fs = MemoryFs::new();
+        for name in &["beta", "Alpha", "alpha", "Gamma"] {
+            fs.mkdir(&Path::new("/").join(name)).unwrap();
+        }
+        fs.add_file(Path::new("/apple.txt"), b"");
+        assert_eq!(subdirectories(&fs, Path::new("/")).unwrap(), vec!["Alpha", "alpha", "beta", "Gamma"]);
+        assert!(subdirectories(&fs, Path::new("/missing")).is_err());
+    }
+
+    #[test]
+    fn typed_paths_are_checked()
+    {
+        let dir = temp_dir("validate");
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("file.txt"), b"x").unwrap();
+        assert_eq!(validate("sub", &dir).unwrap(), dir.join("sub"));
+        assert_eq!(validate("  sub/../sub/  ", &dir).unwrap(), dir.join("sub"));
+        assert_eq!(validate(&dir.join("sub").to_string_lossy(), Path::new("/elsewhere")).unwrap(), dir.join("sub"));
+        assert_eq!(validate("   ", &dir).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(validate("file.txt", &dir).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let err = validate("missing", &dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("missing"), "{}", err);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tab_cycles_and_wraps_around()
+    {
+        let dir = temp_dir("complete");
+        for name in &["docs", "Downloads", "desktop", "music", "docs/inner"] {
+            fs::create_dir(dir.join(name)).unwrap();
+        }
+        fs::write(dir.join("data.txt"), b"").unwrap();
+        let expected: &[&str] = if cfg!(windows) { &["desktop", "docs", "Downloads"] } else { &["desktop", "docs"] };
+        assert_eq!(completions("d", &dir), expected);
+        assert_eq!(completions("docs/", &dir), vec!["docs/inner"]);
+        assert!(completions("x", &dir).is_empty());
+
+        let mut tab = TabCompletion::new();
+        let mut seen = Vec::new();
+        let mut current = "d".to_string();
+        for _ in 0..expected.len() + 1 {
+            current = tab.advance(&current, &dir).unwrap();
+            seen.push(current.clone());
+        }
+        // back to the first after the last
+        assert_eq!(&seen[..expected.len()], expected);
+        assert_eq!(seen[expected.len()], expected[0]);
+        // an edit starts over
+        assert_eq!(tab.advance("m", &dir), Some("music".to_string()));
+        assert_eq!(tab.advance("mu", &dir), Some("music".to_string()));
+        assert_eq!(tab.advance("zzz", &dir), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+// Command templates for external programs, such as the F4 editor:
+// `"C:\Program Files\Editor\edit.exe" -n%L "%P"`. The template is split into
+// arguments first and the placeholders are expanded inside each argument, so
+// a path with spaces stays one argument without any quoting of its own.
+//
+//   %P  full path    %N  file name    %L  line number, 1 if unknown    %%  a %
+//
+// A template without %P or %N gets the path as its last argument.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn invalid_template(template: &str, reason: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid command \"{}\": {}", template, reason))
+}
+
+// Splits at whitespace outside double quotes. Quotes are removed, `""` gives
+// an empty argument, and backslashes are ordinary characters so Windows paths
+// can be written as they are.
+pub fn split(template: &str) -> Vec<String>
+{
+    let mut rv = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quoted = false;
+    for c in template.chars() {
+        match c {
+            '"' => { quoted = !quoted; in_arg = true; },
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    rv.push(arg.clone());
+                    arg.clear();
+                    in_arg = false;
+                }
+            },
+            c => { arg.push(c); in_arg = true; },
+        }
+    }
+    if in_arg {
+        rv.push(arg);
+    }
+    rv
+}
+
+// Whether `arg` has a %P or %N placeholder.
+fn mentions_file(arg: &str) -> bool
+{
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.next() {
+                Some('P') | Some('p') | Some('N') | Some('n') => return true,
+                _ => {},
+            }
+        }
+    }
+    false
+}
+
+fn expand_arg(template: &str, arg: &str, file: &Path, line: Option<u64>) -> io::Result<String>
+{
+    let mut rv = String::new();
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            rv.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('P') | Some('p') => rv.push_str(&file.to_string_lossy()),
+            Some('N') | Some('n') => rv.push_str(&file.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned())),
+            Some('L') | Some('l') => rv.push_str(&line.unwrap_or(1).to_string()),
+            Some('%') => rv.push('%'),
+            Some(other) => return Err(invalid_template(template, &format!("unknown placeholder %{}", other))),
+            None => return Err(invalid_template(template, "a % at the end needs to be written as %%")),
+        }
+    }
+    Ok(rv)
+}
+
+// The program and its arguments for opening `file`.
+pub fn expand(template: &str, file: &Path, line: Option<u64>) -> io::Result<Vec<String>>
+{
+    let args = split(template);
+    if args.is_empty() || args[0].is_empty() {
+        return Err(invalid_template(template, "no program given"));
+    }
+    let mut rv = Vec::with_capacity(args.len() + 1);
+    for arg in &args {
+        rv.push(try!(expand_arg(template, arg, file, line)));
+    }
+    if !args.iter().any(|a| mentions_file(a)) {
+        rv.push(file.to_string_lossy().into_owned());
+    }
+    Ok(rv)
+}
+
+// Lower-case extension without the dot.
+fn extension_of(path: &Path) -> Option<String>
+{ path.extension().map(|e| e.to_string_lossy().to_lowercase()) }
+
+// `*.MD`, `.md` and `md` all mean the extension "md".
+pub fn normalize_extension(ext: &str) -> String
+{ ext.trim().trim_start_matches('*').trim_start_matches('.').to_lowercase() }
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct EditorSettings {
+    pub command: String,
+    // extensions and the template used for them instead of `command`
+    pub overrides: Vec<(Vec<String>, String)>,
+}
+
+impl EditorSettings {
+    pub fn new() -> EditorSettings
+    {
+        let command = if cfg!(windows) { "notepad.exe \"%P\"" } else { "xdg-open \"%P\"" };
+        EditorSettings { command: command.to_string(), overrides: Vec::new() }
+    }
+
+    // Sets the template for a comma separated list of extensions.
+    pub fn set_override(&mut self, extensions: &str, template: &str)
+    {
+        let extensions: Vec<String> = extensions.split(',')
+            .map(normalize_extension)
+            .filter(|e| !e.is_empty())
+            .collect();
+        if !extensions.is_empty() {
+            self.overrides.push((extensions, template.to_string()));
+        }
+    }
+
+    // The first override listing the file's extension, else the default command.
+    pub fn template_for(&self, path: &Path) -> &str
+    {
+        let ext = match extension_of(path) {
+            Some(ext) => ext,
+            None => return &self.command,
+        };
+        self.overrides.iter()
+            .find(|&&(ref extensions, _)| extensions.contains(&ext))
+            .map_or(&self.command, |&(_, ref template)| template)
+    }
+
+    // One command line per file; `line` is where to open a single file.
+    pub fn commands(&self, files: &[PathBuf], line: Option<u64>) -> io::Result<Vec<Vec<String>>>
+    {
+        files.iter().map(|file| expand(self.template_for(file), file, line)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String>
+    { args.iter().map(|arg| arg.to_string()).collect() }
+
+    #[test]
+    fn splitting()
+    {
+        assert_eq!(split(r#""C:\Program Files\Editor\edit.exe" -n%L "%P""#),
+                   strings(&[r"C:\Program Files\Editor\edit.exe", "-n%L", "%P"]));
+        assert_eq!(split("  vim   +%L\t%P  "), strings(&["vim", "+%L", "%P"]));
+        // quotes inside an argument and empty ones
+        assert_eq!(split(r#"edit --file="%P" "" x"y z"w"#), strings(&["edit", "--file=%P", "", "xy zw"]));
+        assert_eq!(split(r#"a "unclosed b"#), strings(&["a", "unclosed b"]));
+        assert!(split("   ").is_empty());
+    }
+
+    #[test]
+    fn placeholders()
+    {
+        let file = Path::new("/home/user/my notes.txt");
+        assert_eq!(expand("edit +%L %P", file, Some(42)).unwrap(), strings(&["edit", "+42", "/home/user/my notes.txt"]));
+        assert_eq!(expand("edit -l%l --name=%N 100%%", file, None).unwrap(),
+                   strings(&["edit", "-l1", "--name=my notes.txt", "100%"]));
+        assert_eq!(expand("edit %n", file, None).unwrap(), strings(&["edit", "my notes.txt"]));
+        // %% does not count as a mention of the file
+        assert_eq!(expand("edit %%P", file, None).unwrap(), strings(&["edit", "%P", "/home/user/my notes.txt"]));
+    }
+
+    #[test]
+    fn bad_templates()
+    {
+        let file = Path::new("a.txt");
+        let unknown = expand("edit %X %P", file, None).unwrap_err();
+        assert_eq!(unknown.kind(), io::ErrorKind::InvalidInput);
+        assert!(unknown.to_string().contains("unknown placeholder %X"), "{}", unknown);
+        assert!(expand("edit 100%", file, None).unwrap_err().to_string().contains("%%"));
+        assert!(expand("", file, None).is_err());
+        assert!(expand("\"\" %P", file, None).is_err());
+    }
+
+    #[test]
+    fn overrides_by_extension()
+    {
+        let mut settings = EditorSettings { command: "edit %P".to_string(), overrides: Vec::new() };
+        settings.set_override("*.MD, .markdown", "mdedit %P");
+        settings.set_override("md,rs", "never %P");
+        settings.set_override(" , ", "ignored %P");
+        assert_eq!(settings.overrides.len(), 2);
+        assert_eq!(settings.template_for(Path::new("README.md")), "mdedit %P");
+        assert_eq!(settings.template_for(Path::new("notes.Markdown")), "mdedit %P");
+        assert_eq!(settings.template_for(Path::new("main.rs")), "never %P");
+        assert_eq!(settings.template_for(Path::new("Makefile")), "edit %P");
+        assert_eq!(settings.template_for(Path::new("archive.md.gz")), "edit %P");
+        let files = vec![PathBuf::from("a.md"), PathBuf::from("b.txt")];
+        assert_eq!(settings.commands(&files, Some(3)).unwrap(),
+                   vec![strings(&["mdedit", "a.md"]), strings(&["edit", "b.txt"])]);
+        assert_eq!(normalize_extension(" *.TXT "), "txt");
+    }
+}
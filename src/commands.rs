@@ -0,0 +1,74 @@
+// Commands sent by the panels to the main window as `WM_COMMAND`,
+// and the keyboard shortcuts bound to them.
+
+use winapi::*;
+
+pub const CM_LEFT_DRIVE_MENU: WORD = 101;
+pub const CM_RIGHT_DRIVE_MENU: WORD = 102;
+// Repaint both panels after a setting changed.
+pub const CM_SETTINGS_CHANGED: WORD = 103;
+pub const CM_TOGGLE_HIDDEN: WORD = 104;
+pub const CM_MKDIR: WORD = 105;
+pub const CM_VIEW: WORD = 106;
+pub const CM_QUICK_VIEW: WORD = 107;
+// The cursor of the sending panel moved to another file.
+pub const CM_FOCUS_CHANGED: WORD = 108;
+pub const CM_EDIT: WORD = 109;
+pub const CM_OPEN_WITH: WORD = 110;
+pub const CM_FIND_FILES: WORD = 111;
+pub const CM_COPY: WORD = 112;
+pub const CM_DELETE: WORD = 113;
+// Ctrl+PgUp: the directory of a search result, or the parent directory.
+pub const CM_GO_TO_DIRECTORY: WORD = 114;
+// A file operation has finished; sent by its progress window.
+pub const CM_OPERATION_DONE: WORD = 115;
+// Alt+F5: pack the selection into a ZIP archive in the other panel's directory.
+pub const CM_PACK: WORD = 116;
+// Ctrl+F: the connection manager for FTP, SFTP and WebDAV sites.
+pub const CM_CONNECT: WORD = 117;
+// Shift+F2: mark the files that differ between the two panels.
+pub const CM_COMPARE: WORD = 118;
+// Ctrl+Shift+S: the Synchronize Directories window for the two panels.
+pub const CM_SYNC: WORD = 119;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub alt: bool,
+    pub ctrl: bool,
+    pub shift: bool,
+}
+
+// letter keys use their uppercase ASCII code as the virtual key
+const KEY_F: c_int = b'F' as c_int;
+const KEY_H: c_int = b'H' as c_int;
+const KEY_Q: c_int = b'Q' as c_int;
+const KEY_S: c_int = b'S' as c_int;
+
+pub fn key_command(vk: c_int, mods: Modifiers) -> Option<WORD>
+{
+    let plain = Modifiers { alt: false, ctrl: false, shift: false };
+    let alt = Modifiers { alt: true, .. plain };
+    let ctrl = Modifiers { ctrl: true, .. plain };
+    let shift = Modifiers { shift: true, .. plain };
+    let ctrl_shift = Modifiers { ctrl: true, shift: true, .. plain };
+    match (vk, mods) {
+        (VK_F1, m) if m == alt => Some(CM_LEFT_DRIVE_MENU),
+        (VK_F2, m) if m == alt => Some(CM_RIGHT_DRIVE_MENU),
+        (VK_F2, m) if m == shift => Some(CM_COMPARE),
+        (KEY_F, m) if m == ctrl => Some(CM_CONNECT),
+        (KEY_H, m) if m == ctrl => Some(CM_TOGGLE_HIDDEN),
+        (KEY_Q, m) if m == ctrl => Some(CM_QUICK_VIEW),
+        (KEY_S, m) if m == ctrl_shift => Some(CM_SYNC),
+        (VK_F3, m) if m == plain => Some(CM_VIEW),
+        (VK_F4, m) if m == plain => Some(CM_EDIT),
+        (VK_F5, m) if m == plain => Some(CM_COPY),
+        (VK_F5, m) if m == alt => Some(CM_PACK),
+        (VK_F8, m) if m == plain => Some(CM_DELETE),
+        (VK_DELETE, m) if m == plain => Some(CM_DELETE),
+        (VK_PRIOR, m) if m == ctrl => Some(CM_GO_TO_DIRECTORY),
+        (VK_F7, m) if m == plain => Some(CM_MKDIR),
+        (VK_F7, m) if m == alt => Some(CM_FIND_FILES),
+        (VK_RETURN, m) if m == shift => Some(CM_OPEN_WITH),
+        _ => None,
+    }
+}
@@ -0,0 +1,508 @@
+// Compare directories (Shift+F2): pairs the files of two directories by name
+// and finds those on one side only, those newer on one side and those that
+// otherwise differ. It works on listings and file systems, not on panels, so
+// either side can be a local directory, an archive or a remote site.
+//
+// Without a content check nothing is read: files differ when their sizes do
+// or when their times are further apart than the tolerance. With one, files
+// of the same size are read and differ only if their bytes do, whatever their
+// times.
+
+extern crate md5;
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use ::file_ops::Progress;
+use ::vfs::{DirEntry, Vfs};
+
+const READ_CHUNK: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentCheck {
+    Off,
+    // both files side by side, stopping at the first difference
+    Bytes,
+    // one file after the other, for file systems that serve a single
+    // transfer at a time, like FTP
+    Hash,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CompareOptions {
+    pub content: ContentCheck,
+    // times this close count as the same, as for FAT drives which keep
+    // them to two seconds
+    pub time_tolerance: Duration,
+}
+
+impl Default for CompareOptions {
+    fn default() -> CompareOptions
+    { CompareOptions { content: ContentCheck::Off, time_tolerance: Duration::from_secs(0) } }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl Item {
+    pub fn from_entry(entry: &DirEntry) -> Item
+    {
+        Item {
+            name: entry.name.clone(),
+            is_dir: entry.meta.is_dir,
+            size: entry.meta.size,
+            modified: entry.meta.modified,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Difference {
+    LeftOnly,
+    RightOnly,
+    LeftNewer,
+    RightNewer,
+    // different but neither is newer, or a file on one side and a
+    // directory on the other
+    Different,
+}
+
+impl Difference {
+    // Whether the left entry is marked for it; a newer file is marked on
+    // its side only.
+    pub fn marks_left(&self) -> bool
+    {
+        match *self {
+            Difference::LeftOnly | Difference::LeftNewer | Difference::Different => true,
+            Difference::RightOnly | Difference::RightNewer => false,
+        }
+    }
+
+    pub fn marks_right(&self) -> bool
+    {
+        match *self {
+            Difference::RightOnly | Difference::RightNewer | Difference::Different => true,
+            Difference::LeftOnly | Difference::LeftNewer => false,
+        }
+    }
+}
+
+// A pair that differs, with the names on each side; these only differ in
+// case when they do.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Found {
+    pub left: Option<String>,
+    pub right: Option<String>,
+    pub difference: Difference,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Comparison {
+    pub differences: Vec<Found>,
+    // pairs found to be the same, directories included
+    pub same: usize,
+}
+
+impl Comparison {
+    pub fn marked_left(&self) -> Vec<String>
+    {
+        self.differences.iter()
+            .filter(|found| found.difference.marks_left())
+            .filter_map(|found| found.left.clone())
+            .collect()
+    }
+
+    pub fn marked_right(&self) -> Vec<String>
+    {
+        self.differences.iter()
+            .filter(|found| found.difference.marks_right())
+            .filter_map(|found| found.right.clone())
+            .collect()
+    }
+}
+
+// Pairs `left` and `right` by name, in the order of `left` followed by what
+// is on the right only. Each item is in one pair. With `ignore_case`, names
+// that differ only in case are paired when neither has an exact match.
+pub fn pair<'a>(left: &'a [Item], right: &'a [Item], ignore_case: bool) -> Vec<(Option<&'a Item>, Option<&'a Item>)>
+{
+    let exact: HashMap<&str, usize> = right.iter().enumerate().map(|(idx, item)| (item.name.as_str(), idx)).collect();
+    let mut paired = HashSet::new();
+    let mut matches: Vec<Option<usize>> = left.iter()
+        .map(|item| exact.get(item.name.as_str()).cloned())
+        .collect();
+    paired.extend(matches.iter().filter_map(|&idx| idx));
+    if ignore_case {
+        let mut folded: HashMap<String, Vec<usize>> = HashMap::new();
+        for (idx, item) in right.iter().enumerate().filter(|&(idx, _)| !paired.contains(&idx)) {
+            folded.entry(item.name.to_lowercase()).or_insert_with(Vec::new).push(idx);
+        }
+        for (item, found) in left.iter().zip(matches.iter_mut()).filter(|&(_, ref found)| found.is_none()) {
+            let candidates = folded.get_mut(&item.name.to_lowercase());
+            *found = candidates.and_then(|candidates| if candidates.is_empty() { None } else { Some(candidates.remove(0)) });
+            paired.extend(*found);
+        }
+    }
+    let mut pairs: Vec<(Option<&Item>, Option<&Item>)> = left.iter()
+        .zip(matches)
+        .map(|(item, found)| (Some(item), found.map(|idx| &right[idx])))
+        .collect();
+    pairs.extend(right.iter().enumerate()
+        .filter(|&(idx, _)| !paired.contains(&idx))
+        .map(|(_, item)| (None, Some(item))));
+    pairs
+}
+
+// Whether the left time is later than the right one by more than
+// `tolerance`, or the other way round; None if neither is. Missing times are
+// taken to be the same.
+fn newer(left: Option<SystemTime>, right: Option<SystemTime>, tolerance: Duration) -> Option<bool>
+{
+    let (left, right) = match (left, right) {
+        (Some(left), Some(right)) => (left, right),
+        _ => return None,
+    };
+    match left.duration_since(right) {
+        Ok(ahead) if ahead > tolerance => Some(true),
+        Ok(_) => None,
+        Err(behind) if behind.duration() > tolerance => Some(false),
+        Err(_) => None,
+    }
+}
+
+// Reads until `buf` is full or the file ends.
+fn read_full(input: &mut Read, buf: &mut [u8]) -> io::Result<usize>
+{
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn cancelled(progress: &mut Progress, cancel: &AtomicBool) -> bool
+{
+    if cancel.load(Ordering::Relaxed) {
+        progress.cancelled = true;
+    }
+    progress.cancelled
+}
+
+fn hash_file(vfs: &Vfs, path: &Path, progress: &mut Progress, cancel: &AtomicBool,
+             report: &mut FnMut(&Progress)) -> io::Result<[u8; 16]>
+{
+    let mut input = try!(vfs.open_read(path));
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0; READ_CHUNK];
+    loop {
+        if cancelled(progress, cancel) {
+            return Ok([0; 16]);
+        }
+        let read = try!(read_full(&mut *input, &mut buffer));
+        if read == 0 {
+            break;
+        }
+        context.consume(&buffer[..read]);
+        progress.bytes_done += read as u64;
+        report(progress);
+    }
+    Ok(context.compute().0)
+}
+
+// Whether two files have the same bytes. Both are counted in the progress
+// as if read whole, so the totals add up when they differ early.
+pub fn same_content(left_fs: &Vfs, left: &Path, right_fs: &Vfs, right: &Path, size: u64, check: ContentCheck,
+                    progress: &mut Progress, cancel: &AtomicBool, report: &mut FnMut(&Progress)) -> io::Result<bool>
+{
+    let end = progress.bytes_done + 2 * size;
+    let same = match check {
+        ContentCheck::Off => true,
+        ContentCheck::Hash => {
+            let digest = try!(hash_file(left_fs, left, progress, cancel, report));
+            digest == try!(hash_file(right_fs, right, progress, cancel, report))
+        },
+        ContentCheck::Bytes => {
+            let mut left_input = try!(left_fs.open_read(left));
+            let mut right_input = try!(right_fs.open_read(right));
+            let (mut left_buffer, mut right_buffer) = (vec![0; READ_CHUNK], vec![0; READ_CHUNK]);
+            loop {
+                if cancelled(progress, cancel) {
+                    break true;
+                }
+                let read = try!(read_full(&mut *left_input, &mut left_buffer));
+                if try!(read_full(&mut *right_input, &mut right_buffer)) != read
+                    || left_buffer[..read] != right_buffer[..read] {
+                    break false;
+                }
+                if read == 0 {
+                    break true;
+                }
+                progress.bytes_done += 2 * read as u64;
+                report(progress);
+            }
+        },
+    };
+    progress.bytes_done = end;
+    Ok(same)
+}
+
+// How the entries of a pair differ, if they do; `left` and `right` are
+// their paths. Pairs of directories are the same here, their contents are
+// for the caller to compare.
+pub fn compare_items(left_fs: &Vfs, left: &Path, left_item: &Item, right_fs: &Vfs, right: &Path, right_item: &Item,
+                     options: &CompareOptions, progress: &mut Progress, cancel: &AtomicBool,
+                     report: &mut FnMut(&Progress)) -> io::Result<Option<Difference>>
+{
+    if left_item.is_dir || right_item.is_dir {
+        return Ok(if left_item.is_dir == right_item.is_dir { None } else { Some(Difference::Different) });
+    }
+    let left_newer = newer(left_item.modified, right_item.modified, options.time_tolerance);
+    let differ = if left_item.size != right_item.size {
+        true
+    } else if options.content != ContentCheck::Off {
+        progress.current = left.to_path_buf();
+        report(progress);
+        let same = try!(same_content(left_fs, left, right_fs, right, left_item.size, options.content,
+                                     progress, cancel, report).map_err(|e|
+            io::Error::new(e.kind(), format!("Cannot compare \"{}\": {}", left.display(), e))));
+        progress.files_done += 1;
+        !same
+    } else {
+        left_newer.is_some()
+    };
+    Ok(match left_newer {
+        _ if !differ => None,
+        Some(true) => Some(Difference::LeftNewer),
+        Some(false) => Some(Difference::RightNewer),
+        None => Some(Difference::Different),
+    })
+}
+
+// Compares the entries `left` of `left_dir` with the entries `right` of
+// `right_dir`. Only a content check reads anything, reporting progress as
+// a copy does; on cancel what was found so far is returned.
+pub fn compare(left_fs: &Vfs, left_dir: &Path, left: &[Item], right_fs: &Vfs, right_dir: &Path, right: &[Item],
+               options: &CompareOptions, cancel: &AtomicBool, report: &mut FnMut(&Progress))
+               -> io::Result<(Comparison, Progress)>
+{
+    let pairs = pair(left, right, left_fs.ignores_case() || right_fs.ignores_case());
+    let mut progress = Progress::default();
+    if options.content != ContentCheck::Off {
+        for pair in pairs.iter() {
+            if let (Some(l), Some(r)) = *pair {
+                if !l.is_dir && !r.is_dir && l.size == r.size {
+                    progress.files_total += 1;
+                    progress.bytes_total += 2 * l.size;
+                }
+            }
+        }
+    }
+    let mut comparison = Comparison::default();
+    for pair in pairs {
+        if cancelled(&mut progress, cancel) {
+            break;
+        }
+        let difference = match pair {
+            (Some(l), Some(r)) => try!(compare_items(
+                left_fs, &left_dir.join(&l.name), l, right_fs, &right_dir.join(&r.name), r,
+                options, &mut progress, cancel, report)),
+            (Some(_), None) => Some(Difference::LeftOnly),
+            (None, _) => Some(Difference::RightOnly),
+        };
+        match difference {
+            // cut short by a cancel
+            _ if progress.cancelled => {},
+            Some(difference) => comparison.differences.push(Found {
+                left: pair.0.map(|item| item.name.clone()),
+                right: pair.1.map(|item| item.name.clone()),
+                difference: difference,
+            }),
+            None => comparison.same += 1,
+        }
+    }
+    Ok((comparison, progress))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::vfs::MemoryFs;
+    use std::time::UNIX_EPOCH;
+
+    fn file(name: &str, size: u64, seconds: u64) -> Item
+    { Item { name: name.to_string(), is_dir: false, size: size, modified: Some(UNIX_EPOCH + Duration::from_secs(seconds)) } }
+
+    fn names<'a>(pairs: &[(Option<&'a Item>, Option<&'a Item>)]) -> Vec<(Option<&'a str>, Option<&'a str>)>
+    { pairs.iter().map(|&(l, r)| (l.map(|item| item.name.as_str()), r.map(|item| item.name.as_str()))).collect() }
+
+    #[test]
+    fn pairs_keep_every_item()
+    {
+        let left = [file("a", 1, 1), file("B", 1, 1), file("Readme", 1, 1), file("readme", 1, 1)];
+        let right = [file("b", 1, 1), file("c", 1, 1), file("README", 1, 1), file("readme", 1, 1)];
+        assert_eq!(names(&pair(&left, &right, false)), vec![
+            (Some("a"), None), (Some("B"), None), (Some("Readme"), None), (Some("readme"), Some("readme")),
+            (None, Some("b")), (None, Some("c")), (None, Some("README")),
+        ]);
+        // exact matches first, then one each by case
+        assert_eq!(names(&pair(&left, &right, true)), vec![
+            (Some("a"), None), (Some("B"), Some("b")), (Some("Readme"), Some("README")), (Some("readme"), Some("readme")),
+            (None, Some("c")),
+        ]);
+        let right = [file("FOO", 1, 1), file("foo", 1, 1)];
+        assert_eq!(names(&pair(&[file("Foo", 1, 1)], &right, true)), vec![(Some("Foo"), Some("FOO")), (None, Some("foo"))]);
+    }
+
+    #[test]
+    fn newer_beyond_the_tolerance()
+    {
+        let time = |seconds| Some(UNIX_EPOCH + Duration::from_secs(seconds));
+        let two = Duration::from_secs(2);
+        assert_eq!(newer(time(100), time(100), two), None);
+        assert_eq!(newer(time(102), time(100), two), None);
+        assert_eq!(newer(time(100), time(102), two), None);
+        assert_eq!(newer(time(103), time(100), two), Some(true));
+        assert_eq!(newer(time(100), time(103), two), Some(false));
+        assert_eq!(newer(time(101), time(100), Duration::from_secs(0)), Some(true));
+        assert_eq!(newer(None, time(100), two), None);
+        assert_eq!(newer(time(100), None, two), None);
+    }
+
+    fn found(left: Option<&str>, right: Option<&str>, difference: Difference) -> Found
+    { Found { left: left.map(String::from), right: right.map(String::from), difference: difference } }
+
+    #[test]
+    fn metadata_only()
+    {
+        let dir = |name: &str| Item { name: name.to_string(), is_dir: true, size: 0, modified: None };
+        let left = [file("same", 10, 100), file("newer", 10, 200), file("older", 10, 100), file("size", 10, 100),
+                    file("fat", 10, 101), file("only left", 1, 1), dir("d"), dir("was dir"), file("was file", 1, 1),
+                    Item { name: "no time".to_string(), is_dir: false, size: 3, modified: None }];
+        let right = [file("same", 10, 100), file("newer", 10, 100), file("older", 10, 200), file("size", 11, 100),
+                     file("fat", 10, 100), file("only right", 1, 1), dir("d"), file("was dir", 1, 1), dir("was file"),
+                     file("no time", 3, 50)];
+        let options = CompareOptions { content: ContentCheck::Off, time_tolerance: Duration::from_secs(2) };
+        // nothing is read, so the directories need not exist
+        let fs = MemoryFs::new();
+        let cancel = AtomicBool::new(false);
+        let (comparison, progress) = compare(&fs, Path::new("/l"), &left, &fs, Path::new("/r"), &right, &options,
+                                             &cancel, &mut |_| panic!("progress without a content check")).unwrap();
+        assert_eq!(progress.files_total, 0);
+        assert_eq!(comparison.same, 4);
+        assert_eq!(comparison.differences, vec![
+            found(Some("newer"), Some("newer"), Difference::LeftNewer),
+            found(Some("older"), Some("older"), Difference::RightNewer),
+            found(Some("size"), Some("size"), Difference::Different),
+            found(Some("only left"), None, Difference::LeftOnly),
+            found(Some("was dir"), Some("was dir"), Difference::Different),
+            found(Some("was file"), Some("was file"), Difference::Different),
+            found(None, Some("only right"), Difference::RightOnly),
+        ]);
+        assert_eq!(comparison.marked_left(), vec!["newer", "size", "only left", "was dir", "was file"]);
+        assert_eq!(comparison.marked_right(), vec!["older", "size", "was dir", "was file", "only right"]);
+
+        // without the tolerance the FAT time counts, and a newer file of
+        // another size is newer
+        let (comparison, _) = compare(&fs, Path::new("/l"), &[file("fat", 10, 101), file("grown", 20, 300)],
+                                      &fs, Path::new("/r"), &[file("fat", 10, 100), file("grown", 10, 100)],
+                                      &CompareOptions::default(), &cancel, &mut |_| {}).unwrap();
+        assert_eq!(comparison.differences, vec![found(Some("fat"), Some("fat"), Difference::LeftNewer),
+                                                found(Some("grown"), Some("grown"), Difference::LeftNewer)]);
+    }
+
+    // Two trees with files of the same size: the same bytes at different
+    // times, and different bytes at the same time or a later one.
+    fn trees() -> (MemoryFs, MemoryFs)
+    {
+        let (left, right) = (MemoryFs::new(), MemoryFs::new());
+        let big: Vec<u8> = (0..300000u32).map(|i| (i % 251) as u8).collect();
+        let mut changed = big.clone();
+        changed[250000] ^= 1;
+        let files: [(&str, &[u8], &[u8], u64, u64); 5] = [
+            ("same", &big, &big, 1000, 5000),
+            ("late change", &big, &changed, 1000, 1000),
+            ("first byte", b"abc", b"xbc", 1000, 9000),
+            ("empty", b"", b"", 1000, 1000),
+            ("sizes", b"ab", b"abc", 1000, 1000),
+        ];
+        for &(name, left_data, right_data, left_time, right_time) in files.iter() {
+            let path = Path::new("/d").join(name);
+            left.add_file(&path, left_data);
+            right.add_file(&path, right_data);
+            left.set_modified(&path, UNIX_EPOCH + Duration::from_secs(left_time)).unwrap();
+            right.set_modified(&path, UNIX_EPOCH + Duration::from_secs(right_time)).unwrap();
+        }
+        (left, right)
+    }
+
+    fn items(fs: &MemoryFs) -> Vec<Item>
+    { fs.list(Path::new("/d")).unwrap().iter().map(Item::from_entry).collect() }
+
+    fn by_content(check: ContentCheck)
+    {
+        let (left, right) = trees();
+        let options = CompareOptions { content: check, time_tolerance: Duration::from_secs(0) };
+        let cancel = AtomicBool::new(false);
+        let mut reports = 0;
+        let (mut comparison, progress) = compare(&left, Path::new("/d"), &items(&left), &right, Path::new("/d"), &items(&right),
+                                                 &options, &cancel, &mut |_| reports += 1).unwrap();
+        comparison.differences.sort_by(|a, b| a.left.cmp(&b.left));
+        assert_eq!(comparison.differences, vec![
+            found(Some("first byte"), Some("first byte"), Difference::RightNewer),
+            found(Some("late change"), Some("late change"), Difference::Different),
+            found(Some("sizes"), Some("sizes"), Difference::Different),
+        ]);
+        assert_eq!(comparison.same, 2);
+        assert_eq!((progress.files_done, progress.files_total), (4, 4));
+        assert_eq!(progress.bytes_total, 2 * (300000 + 300000 + 3));
+        assert_eq!(progress.bytes_done, progress.bytes_total);
+        assert!(reports > 4 && !progress.cancelled);
+
+        // one pair at a time
+        let (same, late) = (file("same", 300000, 1000), file("late change", 300000, 1000));
+        let mut progress = Progress::default();
+        let run = |name: &str, left_item: &Item, right_item: &Item, progress: &mut Progress| {
+            let path = Path::new("/d").join(name);
+            compare_items(&left, &path, left_item, &right, &path, right_item, &options, progress, &cancel, &mut |_| {})
+        };
+        assert_eq!(run("same", &same, &file("same", 300000, 5000), &mut progress).unwrap(), None);
+        assert_eq!(run("late change", &late, &late, &mut progress).unwrap(), Some(Difference::Different));
+        assert_eq!(progress.files_done, 2);
+        // a file against a directory is not read
+        let dir = Item { name: "same".to_string(), is_dir: true, size: 0, modified: None };
+        assert_eq!(run("same", &same, &dir, &mut progress).unwrap(), Some(Difference::Different));
+        assert_eq!(run("same", &dir, &same, &mut progress).unwrap(), Some(Difference::Different));
+        assert_eq!(run("same", &dir, &dir, &mut progress).unwrap(), None);
+        assert_eq!(progress.files_done, 2);
+        // a file that vanished is an error naming it
+        let err = run("gone", &file("gone", 5, 1), &file("gone", 5, 1), &mut progress).unwrap_err();
+        assert!(err.to_string().contains("gone"), "{}", err);
+
+        // a cancel stops at once and keeps nothing half done
+        let cancel = AtomicBool::new(true);
+        let (comparison, progress) = compare(&left, Path::new("/d"), &items(&left), &right, Path::new("/d"), &items(&right),
+                                             &options, &cancel, &mut |_| {}).unwrap();
+        assert!(progress.cancelled && comparison.differences.is_empty() && comparison.same == 0);
+    }
+
+    #[test]
+    fn content_compared_side_by_side()
+    { by_content(ContentCheck::Bytes); }
+
+    #[test]
+    fn content_compared_by_hash()
+    { by_content(ContentCheck::Hash); }
+}
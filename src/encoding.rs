@@ -0,0 +1,277 @@
+// Text encodings the viewer can decode, detection from a sample of the file,
+// and the shared decoder. On Windows legacy code pages go through
+// `MultiByteToWideChar` and `from_wstr` from `win_layer`.
+
+use std::str;
+
+#[cfg(windows)]
+use win_layer;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    // a Windows code page; 0 and 1 are the system ANSI and OEM pages
+    CodePage(u32),
+}
+
+pub const CP_ANSI: u32 = 0;
+pub const CP_OEM: u32 = 1;
+const CP_WINDOWS_1252: u32 = 1252;
+const CP_LATIN_1: u32 = 28591;
+const CP_SHIFT_JIS: u32 = 932;
+const CP_GBK: u32 = 936;
+
+const CODE_PAGES: &'static [(u32, &'static str)] = &[
+    (CP_ANSI, "ANSI (system default)"),
+    (CP_OEM, "OEM (system DOS code page)"),
+    (1250, "Central European (Windows-1250)"),
+    (1251, "Cyrillic (Windows-1251)"),
+    (CP_WINDOWS_1252, "Western (Windows-1252)"),
+    (866, "Cyrillic (DOS 866)"),
+    (20866, "Cyrillic (KOI8-R)"),
+    (CP_LATIN_1, "Western (ISO-8859-1)"),
+    (CP_SHIFT_JIS, "Japanese (Shift-JIS)"),
+    (CP_GBK, "Chinese Simplified (GBK)"),
+];
+
+// bytes looked at by `detect`
+pub const SAMPLE_SIZE: usize = 64 * 1024;
+
+impl Encoding {
+    // In the order shown in the viewer's encoding menu.
+    pub fn all() -> Vec<Encoding>
+    {
+        let mut rv = vec![Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be];
+        rv.extend(CODE_PAGES.iter().map(|&(cp, _)| Encoding::CodePage(cp)));
+        rv
+    }
+
+    pub fn name(&self) -> String
+    {
+        match *self {
+            Encoding::Utf8 => "UTF-8".to_string(),
+            Encoding::Utf16Le => "UTF-16LE".to_string(),
+            Encoding::Utf16Be => "UTF-16BE".to_string(),
+            Encoding::CodePage(cp) => CODE_PAGES.iter()
+                .find(|&&(known, _)| known == cp)
+                .map_or_else(|| format!("Code page {}", cp), |&(_, name)| name.to_string()),
+        }
+    }
+}
+
+pub fn bom_len(bytes: &[u8], encoding: Encoding) -> usize
+{
+    let bom: &[u8] = match encoding {
+        Encoding::Utf8 => b"\xef\xbb\xbf",
+        Encoding::Utf16Le => b"\xff\xfe",
+        Encoding::Utf16Be => b"\xfe\xff",
+        Encoding::CodePage(_) => b"",
+    };
+    if bytes.starts_with(bom) { bom.len() } else { 0 }
+}
+
+// Valid UTF-8 except possibly for a sequence cut off at the end of the sample.
+fn is_utf8(sample: &[u8]) -> bool
+{
+    match str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+// Guesses the encoding of a file from its first bytes: a BOM if there is one,
+// then the share of zero bytes at even and odd offsets (UTF-16 text that is
+// mostly ASCII), then UTF-8 validity. Anything else is the ANSI code page.
+pub fn detect(sample: &[u8]) -> Encoding
+{
+    for encoding in [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be].iter() {
+        if bom_len(sample, *encoding) > 0 {
+            return *encoding;
+        }
+    }
+    let pairs = sample.len() / 2;
+    if pairs >= 2 {
+        let zeros_even = sample.chunks(2).filter(|p| p.len() == 2 && p[0] == 0).count();
+        let zeros_odd = sample.chunks(2).filter(|p| p.len() == 2 && p[1] == 0).count();
+        if zeros_odd * 10 >= pairs * 3 && zeros_even * 10 < pairs {
+            return Encoding::Utf16Le;
+        }
+        if zeros_even * 10 >= pairs * 3 && zeros_odd * 10 < pairs {
+            return Encoding::Utf16Be;
+        }
+    }
+    if is_utf8(sample) { Encoding::Utf8 } else { Encoding::CodePage(CP_ANSI) }
+}
+
+fn utf16_units(bytes: &[u8], big_endian: bool) -> Vec<u16>
+{
+    bytes.chunks(2)
+        .filter(|p| p.len() == 2)
+        .map(|p| if big_endian { (p[0] as u16) << 8 | p[1] as u16 } else { (p[1] as u16) << 8 | p[0] as u16 })
+        .collect()
+}
+
+#[cfg(windows)]
+fn decode_utf16(units: &[u16]) -> String
+{ win_layer::from_wstr(units) }
+
+#[cfg(not(windows))]
+fn decode_utf16(units: &[u16]) -> String
+{ String::from_utf16_lossy(units) }
+
+// Whether `byte` starts a two-byte character in `code_page`.
+#[cfg(windows)]
+pub fn is_lead_byte(code_page: u32, byte: u8) -> bool
+{ win_layer::IsDBCSLeadByteEx(code_page, byte) }
+
+// Without Windows only the double-byte pages of the menu are known.
+#[cfg(not(windows))]
+pub fn is_lead_byte(code_page: u32, byte: u8) -> bool
+{
+    match code_page {
+        CP_SHIFT_JIS => (byte >= 0x81 && byte <= 0x9f) || (byte >= 0xe0 && byte <= 0xfc),
+        CP_GBK => byte >= 0x81 && byte <= 0xfe,
+        _ => false,
+    }
+}
+
+pub fn is_double_byte(code_page: u32) -> bool
+{ (0x80..0x100u32).any(|byte| is_lead_byte(code_page, byte as u8)) }
+
+#[cfg(windows)]
+fn decode_code_page(bytes: &[u8], code_page: u32) -> String
+{ win_layer::from_code_page(code_page, bytes) }
+
+// Without the Windows conversion functions only the Western pages are known;
+// other bytes above 0x7f show as U+FFFD, one for each character.
+#[cfg(not(windows))]
+fn decode_code_page(bytes: &[u8], code_page: u32) -> String
+{
+    if is_double_byte(code_page) {
+        let mut text = String::with_capacity(bytes.len());
+        let mut idx = 0;
+        while idx < bytes.len() {
+            let byte = bytes[idx];
+            text.push(if byte < 0x80 { byte as char } else { '\u{fffd}' });
+            idx += if is_lead_byte(code_page, byte) { 2 } else { 1 };
+        }
+        return text;
+    }
+
+    const WINDOWS_1252_80: [u16; 32] = [
+        0x20ac, 0xfffd, 0x201a, 0x0192, 0x201e, 0x2026, 0x2020, 0x2021,
+        0x02c6, 0x2030, 0x0160, 0x2039, 0x0152, 0xfffd, 0x017d, 0xfffd,
+        0xfffd, 0x2018, 0x2019, 0x201c, 0x201d, 0x2022, 0x2013, 0x2014,
+        0x02dc, 0x2122, 0x0161, 0x203a, 0x0153, 0xfffd, 0x017e, 0x0178,
+    ];
+    bytes.iter().map(|&b| match (code_page, b) {
+        (_, 0..=0x7f) => b as char,
+        (CP_LATIN_1, _) => b as char,
+        (CP_ANSI, 0x80..=0x9f) | (CP_WINDOWS_1252, 0x80..=0x9f) =>
+            ::std::char::from_u32(WINDOWS_1252_80[b as usize - 0x80] as u32).unwrap_or('\u{fffd}'),
+        (CP_ANSI, _) | (CP_WINDOWS_1252, _) => b as char,
+        _ => '\u{fffd}',
+    }).collect()
+}
+
+// Decodes `bytes`; incomplete or invalid sequences become U+FFFD and a BOM is dropped.
+pub fn decode(bytes: &[u8], encoding: Encoding) -> String
+{
+    let bytes = &bytes[bom_len(bytes, encoding)..];
+    match encoding {
+        Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        Encoding::Utf16Le => decode_utf16(&utf16_units(bytes, false)),
+        Encoding::Utf16Be => decode_utf16(&utf16_units(bytes, true)),
+        Encoding::CodePage(cp) => decode_code_page(bytes, cp),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boms_win_first()
+    {
+        assert_eq!(detect(b"\xef\xbb\xbfab"), Encoding::Utf8);
+        assert_eq!(detect(b"\xff\xfea\0b\0"), Encoding::Utf16Le);
+        assert_eq!(detect(b"\xfe\xff\0a\0b"), Encoding::Utf16Be);
+        // a BOM decides even when the rest would not fit
+        assert_eq!(detect(b"\xef\xbb\xbf\xff\xff"), Encoding::Utf8);
+
+        assert_eq!(bom_len(b"\xef\xbb\xbfx", Encoding::Utf8), 3);
+        assert_eq!(bom_len(b"\xff\xfex\0", Encoding::Utf16Le), 2);
+        assert_eq!(bom_len(b"\xff\xfex\0", Encoding::Utf16Be), 0);
+        assert_eq!(bom_len(b"\xef\xbb\xbfx", Encoding::CodePage(CP_ANSI)), 0);
+        assert_eq!(bom_len(b"\xef\xbb", Encoding::Utf8), 0);
+    }
+
+    #[test]
+    fn utf16_without_bom()
+    {
+        assert_eq!(detect(b"h\0e\0l\0l\0o\0"), Encoding::Utf16Le);
+        assert_eq!(detect(b"\0h\0e\0l\0l\0o"), Encoding::Utf16Be);
+        // zero bytes on both sides are binary, not UTF-16
+        assert_eq!(detect(b"\0\0\0\0a\0\0b"), Encoding::Utf8);
+        // a single pair is too short to tell
+        assert_eq!(detect(b"a\0"), Encoding::Utf8);
+        // 3 zeros in 10 pairs are enough, 2 are not
+        assert_eq!(detect(b"a\0b\0c\0dxefghijklmn"), Encoding::Utf16Le);
+        assert_eq!(detect(b"a\0b\0cxdxefghijklmn"), Encoding::Utf8);
+    }
+
+    #[test]
+    fn utf8_or_ansi()
+    {
+        assert_eq!(detect(b""), Encoding::Utf8);
+        assert_eq!(detect(b"plain ascii"), Encoding::Utf8);
+        assert_eq!(detect("na\u{ef}ve \u{20ac}".as_bytes()), Encoding::Utf8);
+        // the sample may end in the middle of a character
+        assert_eq!(detect(b"price \xe2\x82"), Encoding::Utf8);
+        // but not break one off in the middle
+        assert_eq!(detect(b"price \xe2\x82 ok"), Encoding::CodePage(CP_ANSI));
+        assert_eq!(detect(b"caf\xe9 au lait"), Encoding::CodePage(CP_ANSI));
+        assert_eq!(detect(b"\xc0\xaf"), Encoding::CodePage(CP_ANSI));
+    }
+
+    #[test]
+    fn decoding()
+    {
+        assert_eq!(decode(b"\xef\xbb\xbfa\xe2\x82", Encoding::Utf8), "a\u{fffd}");
+        assert_eq!(decode(b"\xff\xfeh\0i\0!", Encoding::Utf16Le), "hi");
+        assert_eq!(decode(b"\0h\0i", Encoding::Utf16Be), "hi");
+        assert_eq!(decode(b"\x3d\xd8", Encoding::Utf16Le), "\u{fffd}");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn code_pages()
+    {
+        assert_eq!(decode(b"\x80 caf\xe9", Encoding::CodePage(CP_ANSI)), "\u{20ac} caf\u{e9}");
+        assert_eq!(decode(b"\x81\x80", Encoding::CodePage(CP_WINDOWS_1252)), "\u{fffd}\u{20ac}");
+        assert_eq!(decode(b"\x80\xe9", Encoding::CodePage(CP_LATIN_1)), "\u{80}\u{e9}");
+        assert_eq!(decode(b"\xe9", Encoding::CodePage(866)), "\u{fffd}");
+        // one replacement for each two-byte character
+        assert_eq!(decode(b"a\x82\xa0b\xa1", Encoding::CodePage(CP_SHIFT_JIS)), "a\u{fffd}b\u{fffd}");
+
+        assert!(is_lead_byte(CP_SHIFT_JIS, 0x81) && is_lead_byte(CP_SHIFT_JIS, 0xfc));
+        assert!(!is_lead_byte(CP_SHIFT_JIS, 0xa0) && !is_lead_byte(CP_SHIFT_JIS, 0x80));
+        assert!(is_lead_byte(CP_GBK, 0xfe) && !is_lead_byte(CP_GBK, 0xff));
+        assert!(is_double_byte(CP_SHIFT_JIS) && is_double_byte(CP_GBK));
+        assert!(!is_double_byte(CP_ANSI) && !is_double_byte(CP_WINDOWS_1252));
+    }
+
+    #[test]
+    fn names()
+    {
+        assert_eq!(Encoding::Utf16Be.name(), "UTF-16BE");
+        assert_eq!(Encoding::CodePage(CP_OEM).name(), "OEM (system DOS code page)");
+        assert_eq!(Encoding::CodePage(437).name(), "Code page 437");
+        let all = Encoding::all();
+        assert_eq!(all.len(), 3 + CODE_PAGES.len());
+        assert_eq!(all[0], Encoding::Utf8);
+        assert!(all.contains(&Encoding::CodePage(CP_GBK)));
+    }
+}
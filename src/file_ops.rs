@@ -0,0 +1,719 @@
+// File operations started from the panels. Copying, deleting and packing may
+// take a while, so they report their progress and check for cancellation
+// between files and, when copying or packing, between chunks of a file.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ::vfs::{self, Metadata, Vfs, same_fs};
+use ::zip::ZipWriter;
+
+const COPY_CHUNK: usize = 1024 * 1024;
+
+#[cfg(windows)]
+const RESERVED_NAMES: &'static [&'static str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+fn invalid_name(name: &str, reason: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidInput,
+                   format!("Invalid directory name \"{}\": {}", name, reason))
+}
+
+#[cfg(windows)]
+fn check_component(name: &str) -> io::Result<()>
+{
+    if let Some(c) = name.chars().find(|c| "<>:\"|?*".contains(*c) || (*c as u32) < 32) {
+        return Err(invalid_name(name, &format!("the character {:?} is not allowed", c)));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err(invalid_name(name, "names cannot end with a dot or a space"));
+    }
+    let stem = name.split('.').next().unwrap_or("").trim_end().to_uppercase();
+    if RESERVED_NAMES.contains(&stem.as_str()) {
+        return Err(invalid_name(name, "the name is reserved by the system"));
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn check_component(name: &str) -> io::Result<()>
+{
+    if name.contains('\0') {
+        return Err(invalid_name(name, "the NUL character is not allowed"));
+    }
+    Ok(())
+}
+
+// Checks every new component of `input` and resolves it against `base`.
+pub fn parse_new_dir(base: &Path, input: &str) -> io::Result<PathBuf>
+{
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Directory name is empty"));
+    }
+    for component in Path::new(input).components() {
+        if let Component::Normal(name) = component {
+            try!(check_component(&name.to_string_lossy()));
+        }
+    }
+    Ok(base.join(input))
+}
+
+// Creates `input` relative to `base`, including missing intermediate directories,
+// and returns the name of the entry that appeared in `base` itself, if any,
+// so the panel can put the cursor on it.
+pub fn make_dir(vfs: &Vfs, base: &Path, input: &str) -> io::Result<Option<String>>
+{
+    let target = try!(parse_new_dir(base, input));
+    if vfs.stat(&target).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                  format!("\"{}\" already exists", target.display())));
+    }
+    let mut missing: Vec<&Path> = target.ancestors().take_while(|dir| vfs.stat(dir).is_err()).collect();
+    missing.reverse();
+    if let Err(e) = missing.into_iter().map(|dir| vfs.mkdir(dir)).collect::<io::Result<Vec<()>>>() {
+        let message = match e.kind() {
+            io::ErrorKind::PermissionDenied =>
+                format!("Permission denied, cannot create \"{}\"", target.display()),
+            _ => format!("Cannot create \"{}\": {}", target.display(), e),
+        };
+        return Err(io::Error::new(e.kind(), message));
+    }
+    let first = target.strip_prefix(base).ok()
+        .and_then(|rel| rel.components().next())
+        .and_then(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        });
+    Ok(first)
+}
+
+// Starts `argv` (a program and its arguments) in `dir` without waiting for it.
+pub fn launch(argv: &[String], dir: &Path) -> io::Result<()>
+{
+    let (program, args) = try!(argv.split_first().ok_or_else(||
+        io::Error::new(io::ErrorKind::InvalidInput, "No program to start")));
+    match Command::new(program).args(args).current_dir(dir).spawn() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(io::Error::new(e.kind(), format!("Cannot start \"{}\": {}", program, e))),
+    }
+}
+
+// Hands `file` to the program the system associates with it.
+#[cfg(windows)]
+pub fn open_with_default(file: &Path) -> io::Result<()>
+{
+    use ::win_layer::{ShellExecuteW, wstr};
+    let dir = file.parent().map(|d| wstr(&d.to_string_lossy()));
+    ShellExecuteW(None, &wstr("open"), &wstr(&file.to_string_lossy()), dir.as_ref().map(|d| &d[..]))
+        .map_err(|code| {
+            let e = io::Error::from_raw_os_error(code as i32);
+            io::Error::new(e.kind(), format!("Cannot open \"{}\": {}", file.display(), e))
+        })
+}
+
+#[cfg(not(windows))]
+pub fn open_with_default(file: &Path) -> io::Result<()>
+{
+    let argv = ["xdg-open".to_string(), file.to_string_lossy().into_owned()];
+    launch(&argv, file.parent().unwrap_or(Path::new(".")))
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Progress {
+    // the file being worked on
+    pub current: PathBuf,
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub cancelled: bool,
+}
+
+fn name_of(path: &Path) -> io::Result<&::std::ffi::OsStr>
+{
+    path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                                                  format!("Cannot copy \"{}\"", path.display())))
+}
+
+// Adds the files and bytes under `path` to the totals; links are not followed.
+fn measure(vfs: &Vfs, path: &Path, progress: &mut Progress, cancel: &AtomicBool) -> io::Result<()>
+{
+    if cancel.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let meta = try!(vfs.stat(path));
+    if meta.is_dir && !meta.is_link {
+        for entry in try!(vfs.list(path)) {
+            try!(measure(vfs, &path.join(&entry.name), progress, cancel));
+        }
+    } else if !meta.is_dir {
+        progress.files_total += 1;
+        progress.bytes_total += meta.size;
+    }
+    Ok(())
+}
+
+// Copies `input` to `output` and flushes it; false if cancelled on the way.
+fn copy_data(input: &mut Read, output: &mut Write, progress: &mut Progress, cancel: &AtomicBool,
+             report: &mut FnMut(&Progress)) -> io::Result<bool>
+{
+    let mut buffer = vec![0; COPY_CHUNK];
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        let read = try!(input.read(&mut buffer));
+        if read == 0 {
+            break;
+        }
+        try!(output.write_all(&buffer[..read]));
+        progress.bytes_done += read as u64;
+        report(progress);
+    }
+    try!(output.flush());
+    Ok(true)
+}
+
+fn copy_file(source_fs: &Vfs, source: &Path, target_fs: &Vfs, target: &Path, meta: &Metadata,
+             progress: &mut Progress, cancel: &AtomicBool, report: &mut FnMut(&Progress)) -> io::Result<()>
+{
+    let mut input = try!(source_fs.open_read(source));
+    let mut output = try!(target_fs.open_write(target));
+    let copied = copy_data(&mut *input, &mut *output, progress, cancel, report);
+    drop(output);
+    match copied {
+        Ok(true) => {},
+        Ok(false) | Err(_) => {
+            progress.cancelled = copied.is_ok();
+            // a partial copy is of no use
+            let _ = target_fs.remove(target);
+            return copied.map(|_| ());
+        },
+    }
+    if let Some(modified) = meta.modified {
+        let _ = target_fs.set_modified(target, modified);
+    }
+    if let (Some(from), Some(to)) = (source_fs.local_path(source), target_fs.local_path(target)) {
+        try!(fs::metadata(&from).and_then(|meta| fs::set_permissions(&to, meta.permissions())));
+    }
+    progress.files_done += 1;
+    Ok(())
+}
+
+// Copies the file or directory tree `source` to `target`, adding to `progress`.
+pub fn copy_entry(source_fs: &Vfs, source: &Path, target_fs: &Vfs, target: &Path, progress: &mut Progress,
+                  cancel: &AtomicBool, report: &mut FnMut(&Progress)) -> io::Result<()>
+{
+    if progress.cancelled || cancel.load(Ordering::Relaxed) {
+        progress.cancelled = true;
+        return Ok(());
+    }
+    progress.current = source.to_path_buf();
+    report(progress);
+    let meta = try!(source_fs.stat(source));
+    // links in archives and the like are made again as links where the target can have them
+    if meta.is_link && source_fs.local_path(source).is_none() && !target_fs.stat(target).is_ok() {
+        let linked = source_fs.read_link(source).and_then(|link| target_fs.symlink(&link, target, meta.is_dir));
+        if linked.is_ok() {
+            if !meta.is_dir {
+                progress.files_done += 1;
+                progress.bytes_done += meta.size;
+            }
+            return Ok(());
+        }
+    }
+    // links to directories are not followed, links to files are copied as files
+    if meta.is_dir {
+        if meta.is_link {
+            return Ok(());
+        }
+        // copying into an existing directory merges the two
+        if !target_fs.stat(target).map(|m| m.is_dir).unwrap_or(false) {
+            try!(target_fs.mkdir(target));
+        }
+        for entry in try!(source_fs.list(source)) {
+            try!(copy_entry(source_fs, &source.join(&entry.name), target_fs, &target.join(&entry.name),
+                            progress, cancel, report));
+        }
+        // after the entries, which change it
+        if let Some(modified) = meta.modified {
+            let _ = target_fs.set_modified(target, modified);
+        }
+        return Ok(());
+    }
+    if target_fs.stat(target).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                  format!("\"{}\" already exists", target.display())));
+    }
+    copy_file(source_fs, source, target_fs, target, &meta, progress, cancel, report).map_err(|e|
+        io::Error::new(e.kind(), format!("Cannot copy \"{}\": {}", source.display(), e)))
+}
+
+// Copies the file `source` over the file `target`. The copy is made next to
+// `target` under another name and only then takes its place, so a failed or
+// cancelled copy leaves `target` as it was.
+pub fn copy_over(source_fs: &Vfs, source: &Path, target_fs: &Vfs, target: &Path, progress: &mut Progress,
+                 cancel: &AtomicBool, report: &mut FnMut(&Progress)) -> io::Result<()>
+{
+    let partial = target.with_file_name(format!("{}.partial", try!(name_of(target)).to_string_lossy()));
+    try!(copy_entry(source_fs, source, target_fs, &partial, progress, cancel, report));
+    if progress.cancelled {
+        return Ok(());
+    }
+    let failed = |e: io::Error| io::Error::new(e.kind(), format!("Cannot replace \"{}\": {}", target.display(), e));
+    // not every file system renames over an existing file
+    if let Err(e) = target_fs.remove(target) {
+        let _ = target_fs.remove(&partial);
+        return Err(failed(e));
+    }
+    // the copy is kept if it cannot take the name, being all that is left
+    target_fs.rename(&partial, target).map_err(failed)
+}
+
+// Copies files and directory trees from `source_fs` into `target_dir` of
+// `target_fs`. Existing directories are merged, existing files are left alone
+// and stop the copy with an error.
+pub fn copy(source_fs: &Vfs, sources: &[PathBuf], target_fs: &Vfs, target_dir: &Path,
+            cancel: &AtomicBool, report: &mut FnMut(&Progress)) -> io::Result<Progress>
+{
+    let mut progress = Progress::default();
+    for source in sources {
+        if same_fs(source_fs, target_fs) {
+            let target = target_dir.join(try!(name_of(source)));
+            if target == *source {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("Cannot copy \"{}\" onto itself", source.display())));
+            }
+            if target_dir.starts_with(source) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("Cannot copy \"{}\" into itself", source.display())));
+            }
+        }
+        try!(measure(source_fs, source, &mut progress, cancel));
+    }
+    for source in sources {
+        let target = target_dir.join(try!(name_of(source)));
+        try!(copy_entry(source_fs, source, target_fs, &target, &mut progress, cancel, report));
+    }
+    Ok(progress)
+}
+
+fn delete_entry(vfs: &Vfs, path: &Path, progress: &mut Progress,
+                cancel: &AtomicBool, report: &mut FnMut(&Progress)) -> io::Result<()>
+{
+    if progress.cancelled || cancel.load(Ordering::Relaxed) {
+        progress.cancelled = true;
+        return Ok(());
+    }
+    progress.current = path.to_path_buf();
+    report(progress);
+    let meta = try!(vfs.stat(path));
+    if meta.is_dir && !meta.is_link {
+        for entry in try!(vfs.list(path)) {
+            try!(delete_entry(vfs, &path.join(&entry.name), progress, cancel, report));
+        }
+        if progress.cancelled {
+            return Ok(());
+        }
+    }
+    let rv = vfs.remove(path).map(|_| if !meta.is_dir {
+        progress.files_done += 1;
+        progress.bytes_done += meta.size;
+    });
+    rv.map_err(|e| io::Error::new(e.kind(), format!("Cannot delete \"{}\": {}", path.display(), e)))
+}
+
+// Deletes files and whole directory trees. Links are deleted, not followed.
+pub fn delete(vfs: &Vfs, paths: &[PathBuf], cancel: &AtomicBool,
+              report: &mut FnMut(&Progress)) -> io::Result<Progress>
+{
+    let mut progress = Progress::default();
+    for path in paths {
+        try!(measure(vfs, path, &mut progress, cancel));
+    }
+    for path in paths {
+        try!(delete_entry(vfs, path, &mut progress, cancel, report));
+    }
+    Ok(progress)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PackOptions {
+    // 0 stores the files, 1 to 9 deflates them
+    pub level: u32,
+    // entries keep their path below the directory the sources are in;
+    // otherwise every file goes to the root of the archive
+    pub relative_paths: bool,
+    // the sources are deleted once they are in the archive
+    pub move_files: bool,
+}
+
+fn pack_entry(source_fs: &Vfs, source: &Path, name: &str, zip: ZipWriter, options: &PackOptions,
+              progress: &mut Progress, cancel: &AtomicBool, report: &mut FnMut(&Progress))
+              -> io::Result<ZipWriter>
+{
+    if progress.cancelled || cancel.load(Ordering::Relaxed) {
+        progress.cancelled = true;
+        return Ok(zip);
+    }
+    progress.current = source.to_path_buf();
+    report(progress);
+    let meta = try!(source_fs.stat(source));
+    if meta.is_dir {
+        // links to directories are not followed
+        if meta.is_link {
+            return Ok(zip);
+        }
+        let mut zip = zip;
+        if options.relative_paths {
+            try!(zip.add_dir(name, meta.modified));
+        }
+        for entry in try!(source_fs.list(source)) {
+            let child = if options.relative_paths { format!("{}/{}", name, entry.name) } else { entry.name.clone() };
+            zip = try!(pack_entry(source_fs, &source.join(&entry.name), &child, zip, options, progress, cancel, report));
+        }
+        return Ok(zip);
+    }
+    let mut input = try!(source_fs.open_read(source).map_err(|e|
+        io::Error::new(e.kind(), format!("Cannot read \"{}\": {}", source.display(), e))));
+    let (zip, done) = try!(zip.add_file(name, meta.modified, options.level, Some(meta.size), &mut *input, cancel,
+                                        &mut |read| { progress.bytes_done += read; report(progress); }));
+    if done {
+        progress.files_done += 1;
+    } else {
+        progress.cancelled = true;
+    }
+    Ok(zip)
+}
+
+// Adds `sources` to the archive file `local`, which is made unless it `exists`.
+// If a file fails to go in, the writer is dropped, which leaves the archive
+// with the entries before it.
+fn pack_into(source_fs: &Vfs, sources: &[PathBuf], archive: &Path, local: &Path, exists: bool,
+             options: &PackOptions, progress: &mut Progress, cancel: &AtomicBool, report: &mut FnMut(&Progress))
+             -> io::Result<()>
+{
+    let mut zip = try!(if exists { ZipWriter::append(local) } else { ZipWriter::create(local) }.map_err(|e|
+        io::Error::new(e.kind(), format!("Cannot open \"{}\": {}", archive.display(), e))));
+    for source in sources {
+        let name = try!(name_of(source)).to_string_lossy().into_owned();
+        zip = try!(pack_entry(source_fs, source, &name, zip, options, progress, cancel, report));
+    }
+    zip.finish()
+}
+
+// Notes the archive names of the files under `path` packed without their
+// paths, failing on the second file to get a name, which would replace the
+// first in the archive.
+fn flat_names(vfs: &Vfs, path: &Path, name: &str, names: &mut HashMap<String, PathBuf>) -> io::Result<()>
+{
+    let meta = try!(vfs.stat(path));
+    if meta.is_dir {
+        if !meta.is_link {
+            for entry in try!(vfs.list(path)) {
+                try!(flat_names(vfs, &path.join(&entry.name), &entry.name, names));
+            }
+        }
+        return Ok(());
+    }
+    if let Some(first) = names.insert(name.to_string(), path.to_path_buf()) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                  format!("Cannot pack both \"{}\" and \"{}\" as \"{}\" without their paths",
+                                          first.display(), path.display(), name)));
+    }
+    Ok(())
+}
+
+// Packs files and directory trees into the ZIP archive `archive` of
+// `target_fs`, adding them to it if it exists. Archives that are not on the
+// disk are put together in a temporary file and copied over when complete.
+pub fn pack(source_fs: &Vfs, sources: &[PathBuf], target_fs: &Vfs, archive: &Path, options: &PackOptions,
+            cancel: &AtomicBool, report: &mut FnMut(&Progress)) -> io::Result<Progress>
+{
+    let mut progress = Progress::default();
+    for source in sources {
+        if same_fs(source_fs, target_fs) && archive.starts_with(source) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("Cannot pack \"{}\" into itself", source.display())));
+        }
+        try!(measure(source_fs, source, &mut progress, cancel));
+    }
+    if !options.relative_paths {
+        let mut names = HashMap::new();
+        for source in sources {
+            try!(flat_names(source_fs, source, &try!(name_of(source)).to_string_lossy(), &mut names));
+        }
+    }
+    let exists = target_fs.stat(archive).is_ok();
+    let local = match target_fs.local_path(archive) {
+        Some(local) => local,
+        None if exists => try!(vfs::local_copy(target_fs, archive)),
+        None => env::temp_dir().join(format!("trusty-commander-{}-{}", process::id(), try!(name_of(archive)).to_string_lossy())),
+    };
+    let packed = pack_into(source_fs, sources, archive, &local, exists, options, &mut progress, cancel, report);
+    // an existing archive keeps what was added before a cancel, and before a
+    // failure if it is on the disk; a new one is kept only when complete
+    let complete = packed.is_ok() && !(progress.cancelled && !exists);
+    let temporary = target_fs.local_path(archive).is_none();
+    let copied = if complete && temporary {
+        File::open(&local).and_then(|mut input| {
+            let mut output = try!(target_fs.open_write(archive));
+            try!(io::copy(&mut input, &mut output));
+            output.flush()
+        })
+    } else {
+        Ok(())
+    };
+    if temporary || (!complete && !exists) {
+        let _ = fs::remove_file(&local);
+    }
+    try!(packed);
+    try!(copied);
+    if options.move_files && !progress.cancelled {
+        let mut deleted = Progress::default();
+        for source in sources {
+            try!(delete_entry(source_fs, source, &mut deleted, cancel, &mut |_| {}));
+        }
+    }
+    Ok(progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::vfs::MemoryFs;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn paths(names: &[&str]) -> Vec<PathBuf>
+    { names.iter().map(PathBuf::from).collect() }
+
+    fn tree() -> MemoryFs
+    {
+        let fs = MemoryFs::new();
+        fs.add_file(Path::new("/src/a.txt"), b"alpha");
+        fs.add_file(Path::new("/src/dir/b.txt"), b"beta");
+        fs.add_file(Path::new("/src/dir/deeper/c.txt"), b"gamma!");
+        fs.mkdir(Path::new("/src/dir/empty")).unwrap();
+        fs.mkdir(Path::new("/dst")).unwrap();
+        fs
+    }
+
+    fn names(fs: &MemoryFs, dir: &str) -> Vec<String>
+    {
+        let mut names: Vec<String> = fs.list(Path::new(dir)).unwrap().into_iter().map(|e| e.name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn copy_between_file_systems()
+    {
+        let (source, target) = (tree(), MemoryFs::new());
+        target.mkdir(Path::new("/dst")).unwrap();
+        let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        source.set_modified(Path::new("/src/a.txt"), time).unwrap();
+        let mut reports = 0;
+        let progress = copy(&source, &paths(&["/src/a.txt", "/src/dir"]), &target, Path::new("/dst"),
+                            &AtomicBool::new(false), &mut |_| reports += 1).unwrap();
+        assert_eq!((progress.files_done, progress.files_total), (3, 3));
+        assert_eq!((progress.bytes_done, progress.bytes_total), (15, 15));
+        assert!(reports >= 3 && !progress.cancelled);
+        assert_eq!(target.read(Path::new("/dst/a.txt")), Some(b"alpha".to_vec()));
+        assert_eq!(target.read(Path::new("/dst/dir/deeper/c.txt")), Some(b"gamma!".to_vec()));
+        assert!(target.stat(Path::new("/dst/dir/empty")).unwrap().is_dir);
+        assert_eq!(target.stat(Path::new("/dst/a.txt")).unwrap().modified, Some(time));
+
+        // directories are merged, files already there stop the copy
+        source.add_file(Path::new("/src/dir/new.txt"), b"new");
+        let err = copy(&source, &paths(&["/src/dir"]), &target, Path::new("/dst"),
+                       &AtomicBool::new(false), &mut |_| {}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(target.read(Path::new("/dst/dir/b.txt")), Some(b"beta".to_vec()));
+    }
+
+    #[test]
+    fn copy_within_one_file_system()
+    {
+        let fs = tree();
+        copy(&fs, &paths(&["/src/dir"]), &fs, Path::new("/dst"), &AtomicBool::new(false), &mut |_| {}).unwrap();
+        assert_eq!(names(&fs, "/dst/dir"), vec!["b.txt", "deeper", "empty"]);
+        assert_eq!(names(&fs, "/src/dir"), vec!["b.txt", "deeper", "empty"]);
+        let onto = copy(&fs, &paths(&["/src/a.txt"]), &fs, Path::new("/src"), &AtomicBool::new(false), &mut |_| {});
+        assert!(onto.unwrap_err().to_string().contains("onto itself"));
+        let into = copy(&fs, &paths(&["/src"]), &fs, Path::new("/src/dir"), &AtomicBool::new(false), &mut |_| {});
+        assert!(into.unwrap_err().to_string().contains("into itself"));
+    }
+
+    #[test]
+    fn cancelled_copy_leaves_nothing_half_done()
+    {
+        let (source, target) = (tree(), MemoryFs::new());
+        let progress = copy(&source, &paths(&["/src/a.txt"]), &target, Path::new("/"),
+                            &AtomicBool::new(true), &mut |_| {}).unwrap();
+        assert!(progress.cancelled);
+        assert!(target.stat(Path::new("/a.txt")).is_err());
+    }
+
+    // Files that give a few bytes and then fail, like a share going away.
+    struct FailingFs(MemoryFs);
+
+    struct FailingReader(&'static [u8]);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+        {
+            if self.0.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "gone"));
+            }
+            let len = self.0.len().min(buf.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    impl Vfs for FailingFs {
+        fn list(&self, dir: &Path) -> io::Result<Vec<vfs::DirEntry>>
+        { self.0.list(dir) }
+        fn stat(&self, path: &Path) -> io::Result<Metadata>
+        { self.0.stat(path) }
+        fn open_read(&self, _path: &Path) -> io::Result<Box<Read + Send>>
+        { Ok(Box::new(FailingReader(b"partial"))) }
+        fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>
+        { self.0.open_write(path) }
+        fn mkdir(&self, path: &Path) -> io::Result<()>
+        { self.0.mkdir(path) }
+        fn remove(&self, path: &Path) -> io::Result<()>
+        { self.0.remove(path) }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()>
+        { self.0.rename(from, to) }
+    }
+
+    #[test]
+    fn failed_copy_leaves_nothing_half_done()
+    {
+        let (source, target) = (FailingFs(tree()), MemoryFs::new());
+        let mut progress = Progress::default();
+        let err = copy_entry(&source, Path::new("/src/a.txt"), &target, Path::new("/a.txt"), &mut progress,
+                             &AtomicBool::new(false), &mut |_| {}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(progress.bytes_done, 7);
+        assert!(!progress.cancelled && progress.files_done == 0);
+        assert!(target.stat(Path::new("/a.txt")).is_err());
+    }
+
+    #[test]
+    fn failed_pack_leaves_the_archive_as_it_was()
+    {
+        let (source, target) = (tree(), MemoryFs::new());
+        target.mkdir(Path::new("/dst")).unwrap();
+        let options = PackOptions { level: 6, relative_paths: true, move_files: true };
+        pack(&source, &paths(&["/src/a.txt"]), &target, Path::new("/dst/a.zip"), &options,
+             &AtomicBool::new(false), &mut |_| {}).unwrap();
+        let before = target.read(Path::new("/dst/a.zip")).unwrap();
+
+        let failing = FailingFs(tree());
+        for archive in &["/dst/a.zip", "/dst/new.zip"] {
+            let err = pack(&failing, &paths(&["/src/dir"]), &target, Path::new(archive), &options,
+                           &AtomicBool::new(false), &mut |_| {}).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        }
+        assert_eq!(target.read(Path::new("/dst/a.zip")), Some(before));
+        assert_eq!(names(&target, "/dst"), vec!["a.zip"]);
+        // nothing was moved
+        assert_eq!(names(&failing.0, "/src/dir"), vec!["b.txt", "deeper", "empty"]);
+    }
+
+    #[test]
+    fn copy_over_replaces_the_target()
+    {
+        let fs = tree();
+        fs.add_file(Path::new("/dst/a.txt"), b"old contents");
+        let mut progress = Progress::default();
+        copy_over(&fs, Path::new("/src/a.txt"), &fs, Path::new("/dst/a.txt"), &mut progress,
+                  &AtomicBool::new(false), &mut |_| {}).unwrap();
+        assert_eq!(fs.read(Path::new("/dst/a.txt")), Some(b"alpha".to_vec()));
+        assert_eq!(names(&fs, "/dst"), vec!["a.txt"]);
+        // a source that is gone leaves the target as it was
+        let missing = copy_over(&fs, Path::new("/src/gone"), &fs, Path::new("/dst/a.txt"), &mut progress,
+                                &AtomicBool::new(false), &mut |_| {});
+        assert!(missing.is_err());
+        assert_eq!(fs.read(Path::new("/dst/a.txt")), Some(b"alpha".to_vec()));
+    }
+
+    #[test]
+    fn delete_trees()
+    {
+        let fs = tree();
+        let progress = delete(&fs, &paths(&["/src/dir", "/src/a.txt"]), &AtomicBool::new(false), &mut |_| {}).unwrap();
+        assert_eq!((progress.files_done, progress.files_total, progress.bytes_done), (3, 3, 15));
+        assert!(names(&fs, "/src").is_empty());
+        assert!(delete(&fs, &paths(&["/src/dir"]), &AtomicBool::new(false), &mut |_| {}).is_err());
+
+        let fs = tree();
+        let progress = delete(&fs, &paths(&["/src"]), &AtomicBool::new(true), &mut |_| {}).unwrap();
+        assert!(progress.cancelled);
+        assert_eq!(names(&fs, "/src"), vec!["a.txt", "dir"]);
+    }
+
+    #[test]
+    fn make_dirs()
+    {
+        let fs = tree();
+        let base = Path::new("/dst");
+        assert_eq!(make_dir(&fs, base, " a/b/c ").unwrap(), Some("a".to_string()));
+        assert!(fs.stat(Path::new("/dst/a/b/c")).unwrap().is_dir);
+        assert_eq!(make_dir(&fs, base, "a/b/c").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(make_dir(&fs, base, "a/d").unwrap(), Some("a".to_string()));
+        assert_eq!(make_dir(&fs, base, "/elsewhere").unwrap(), None);
+        assert_eq!(make_dir(&fs, base, "  ").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        // a file in the way
+        assert!(make_dir(&fs, Path::new("/src"), "a.txt/sub").is_err());
+    }
+
+    fn archive_names(fs: &MemoryFs, archive: &str) -> Vec<String>
+    {
+        let data = fs.read(Path::new(archive)).unwrap();
+        let entries = ::zip::read_entries(&mut io::Cursor::new(data)).unwrap();
+        let mut names: Vec<String> = entries.into_iter().map(|e| e.name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn packing_without_paths_keeps_every_file()
+    {
+        let fs = tree();
+        fs.add_file(Path::new("/other/b.txt"), b"another beta");
+        let options = PackOptions { level: 6, relative_paths: false, move_files: true };
+        let sources = paths(&["/src/dir", "/other"]);
+        let err = pack(&fs, &sources, &fs, Path::new("/dst/flat.zip"), &options, &AtomicBool::new(false), &mut |_| {})
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(err.to_string().contains("dir") && err.to_string().contains("other"), "{}", err);
+        // nothing was written or moved
+        assert!(fs.stat(Path::new("/dst/flat.zip")).is_err());
+        assert_eq!(fs.read(Path::new("/other/b.txt")), Some(b"another beta".to_vec()));
+
+        let options = PackOptions { move_files: false, .. options };
+        let progress = pack(&fs, &paths(&["/src/dir", "/src/a.txt"]), &fs, Path::new("/dst/flat.zip"), &options,
+                            &AtomicBool::new(false), &mut |_| {}).unwrap();
+        assert_eq!(progress.files_done, 3);
+        assert_eq!(archive_names(&fs, "/dst/flat.zip"), vec!["a.txt", "b.txt", "c.txt"]);
+
+        let options = PackOptions { relative_paths: true, .. options };
+        pack(&fs, &sources, &fs, Path::new("/dst/tree.zip"), &options, &AtomicBool::new(false), &mut |_| {}).unwrap();
+        assert_eq!(archive_names(&fs, "/dst/tree.zip"),
+                   vec!["dir/", "dir/b.txt", "dir/deeper/", "dir/deeper/c.txt", "dir/empty/", "other/", "other/b.txt"]);
+    }
+}
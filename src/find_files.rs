@@ -0,0 +1,441 @@
+// Find Files (Alt+F7): walks a directory tree and reports every entry that
+// passes the name, directory, size, date and attribute filters and, if a
+// pattern is given, contains it. The walk runs on a worker thread; entries
+// are handed over as they are found and the walk checks for cancellation
+// between entries and while reading a file.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ::encoding::{self, Encoding};
+use ::mask;
+use ::search::{Outcome, SearchOptions, Searcher};
+use ::vfs;
+
+// bytes looked at to guess the encoding of a file searched for text
+const SAMPLE_BYTES: u64 = 4096;
+
+// An attribute an entry must have, must not have, or either.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Require {
+    Any,
+    Set,
+    Clear,
+}
+
+impl Require {
+    fn allows(&self, set: bool) -> bool
+    {
+        match *self {
+            Require::Any => true,
+            Require::Set => set,
+            Require::Clear => !set,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FindOptions {
+    pub root: PathBuf,
+    // `;` separated masks an entry's name has to match, and ones it must not
+    pub names: String,
+    pub exclude_names: String,
+    // subdirectories searched: those matching `dirs` (all if empty) and not `exclude_dirs`
+    pub dirs: String,
+    pub exclude_dirs: String,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    // modified at or after, and before
+    pub modified_from: Option<SystemTime>,
+    pub modified_to: Option<SystemTime>,
+    pub directory: Require,
+    pub hidden: Require,
+    pub read_only: Require,
+    // only files containing this are found
+    pub content: Option<SearchOptions>,
+    // levels of subdirectories searched, 0 for `root` alone; None for all
+    pub max_depth: Option<usize>,
+}
+
+impl FindOptions {
+    pub fn new(root: &Path) -> FindOptions
+    {
+        FindOptions {
+            root: root.to_path_buf(),
+            names: "*".to_string(),
+            exclude_names: String::new(),
+            dirs: String::new(),
+            exclude_dirs: String::new(),
+            min_size: None,
+            max_size: None,
+            modified_from: None,
+            modified_to: None,
+            directory: Require::Any,
+            hidden: Require::Any,
+            read_only: Require::Any,
+            content: None,
+            max_depth: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Found {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub hidden: bool,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Summary {
+    pub dirs: u64,
+    pub files: u64,
+    pub found: u64,
+    // directories and files that could not be read
+    pub unreadable: u64,
+    pub cancelled: bool,
+}
+
+// "1500", "64K", "1.5M", "2 GB": sizes with binary units.
+pub fn parse_size(text: &str) -> Option<u64>
+{
+    let text = text.trim().to_uppercase();
+    let text = text.trim_end_matches('B').trim_end_matches('I').trim_end();
+    let (number, unit) = match text.chars().last() {
+        Some('K') => (&text[..text.len() - 1], 1u64 << 10),
+        Some('M') => (&text[..text.len() - 1], 1 << 20),
+        Some('G') => (&text[..text.len() - 1], 1 << 30),
+        Some('T') => (&text[..text.len() - 1], 1 << 40),
+        _ => (text, 1),
+    };
+    let number: f64 = match number.trim().parse() {
+        Ok(number) if number >= 0.0 => number,
+        _ => return None,
+    };
+    let bytes = number * unit as f64;
+    if bytes < u64::max_value() as f64 { Some(bytes.round() as u64) } else { None }
+}
+
+// Midnight UTC of a `YYYY-MM-DD` date.
+pub fn parse_date(text: &str) -> Option<SystemTime>
+{
+    let parts: Vec<&str> = text.trim().split('-').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let year: i64 = match parts[0].parse() { Ok(y) if y >= 1970 && y < 10000 => y, _ => return None };
+    let month: i64 = match parts[1].parse() { Ok(m) if m >= 1 && m <= 12 => m, _ => return None };
+    let day: i64 = match parts[2].parse() { Ok(d) if d >= 1 && d <= 31 => d, _ => return None };
+    // days from the civil calendar, counting years from March so leap days come last
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Some(UNIX_EPOCH + Duration::from_secs(days as u64 * 86400))
+}
+
+// One compiled pattern per encoding met so far.
+struct ContentMatcher {
+    options: SearchOptions,
+    searchers: Vec<(Encoding, Searcher)>,
+}
+
+impl ContentMatcher {
+    fn new(options: &SearchOptions) -> io::Result<ContentMatcher>
+    {
+        // compiling once up front reports a bad pattern before the walk starts
+        let searcher = try!(Searcher::new(options, Encoding::Utf8));
+        Ok(ContentMatcher { options: options.clone(), searchers: vec![(Encoding::Utf8, searcher)] })
+    }
+
+    fn contains(&mut self, path: &Path, len: u64, cancel: &AtomicBool) -> io::Result<Outcome>
+    {
+        let mut file = try!(File::open(path));
+        let mut sample = Vec::new();
+        try!((&mut file).take(SAMPLE_BYTES).read_to_end(&mut sample));
+        let encoding = encoding::detect(&sample);
+        let idx = match self.searchers.iter().position(|&(e, _)| e == encoding) {
+            Some(idx) => idx,
+            None => {
+                let searcher = try!(Searcher::new(&self.options, encoding));
+                self.searchers.push((encoding, searcher));
+                self.searchers.len() - 1
+            },
+        };
+        self.searchers[idx].1.find(&mut file, len, 0, false, cancel, &mut |_| {})
+    }
+}
+
+fn name_of(path: &Path) -> String
+{ path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned()) }
+
+// Whether the walk goes into the subdirectory `name`.
+fn searches_dir(options: &FindOptions, name: &str) -> bool
+{
+    (mask::split(&options.dirs).is_empty() || mask::matches_any(&options.dirs, name))
+        && !mask::matches_any(&options.exclude_dirs, name)
+}
+
+// The filters that need nothing but the metadata.
+fn passes(options: &FindOptions, found: &Found, read_only: bool) -> bool
+{
+    let name = name_of(&found.path);
+    if !mask::matches_any(&options.names, &name) || mask::matches_any(&options.exclude_names, &name) {
+        return false;
+    }
+    if !options.directory.allows(found.is_dir)
+        || !options.hidden.allows(found.hidden)
+        || !options.read_only.allows(read_only) {
+        return false;
+    }
+    if found.is_dir && (options.min_size.is_some() || options.max_size.is_some() || options.content.is_some()) {
+        return false;
+    }
+    if options.min_size.map_or(false, |min| found.size < min) || options.max_size.map_or(false, |max| found.size > max) {
+        return false;
+    }
+    match found.modified {
+        Some(modified) => options.modified_from.map_or(true, |from| modified >= from)
+            && options.modified_to.map_or(true, |to| modified < to),
+        None => options.modified_from.is_none() && options.modified_to.is_none(),
+    }
+}
+
+// Walks `options.root` and calls `found` for each match. `searching` gets each
+// directory as the walk enters it. Symbolic links to directories are reported
+// but not followed.
+pub fn find(
+    options: &FindOptions,
+    cancel: &AtomicBool,
+    found: &mut FnMut(Found),
+    searching: &mut FnMut(&Path))
+    -> io::Result<Summary>
+{
+    let mut content = match options.content {
+        Some(ref content) => Some(try!(ContentMatcher::new(content))),
+        None => None,
+    };
+    let mut summary = Summary::default();
+    searching(&options.root);
+    let mut pending = vec![(try!(fs::read_dir(&options.root)), 0)];
+    while let Some((mut dir, depth)) = pending.pop() {
+        let entry = match dir.next() {
+            Some(entry) => entry,
+            None => continue,
+        };
+        pending.push((dir, depth));
+        if cancel.load(Ordering::Relaxed) {
+            summary.cancelled = true;
+            return Ok(summary);
+        }
+        let (path, link_meta) = match entry.and_then(|e| e.metadata().map(|m| (e.path(), m))) {
+            Ok(entry) => entry,
+            Err(_) => { summary.unreadable += 1; continue; },
+        };
+        let meta = fs::metadata(&path).unwrap_or_else(|_| link_meta.clone());
+        let name = name_of(&path);
+        let candidate = Found {
+            is_dir: meta.is_dir(),
+            size: if meta.is_dir() { 0 } else { meta.len() },
+            modified: meta.modified().ok(),
+            hidden: vfs::is_hidden(&name, &link_meta),
+            path: path,
+        };
+        if candidate.is_dir {
+            summary.dirs += 1;
+        } else {
+            summary.files += 1;
+        }
+        let descend = link_meta.is_dir()
+            && options.max_depth.map_or(true, |max| depth < max)
+            && searches_dir(options, &name);
+        if descend {
+            searching(&candidate.path);
+            match fs::read_dir(&candidate.path) {
+                Ok(sub) => pending.push((sub, depth + 1)),
+                Err(_) => summary.unreadable += 1,
+            }
+        }
+        if !passes(options, &candidate, meta.permissions().readonly()) {
+            continue;
+        }
+        if let Some(ref mut content) = content {
+            match content.contains(&candidate.path, candidate.size, cancel) {
+                Ok(Outcome::Found(..)) => {},
+                Ok(Outcome::NotFound) => continue,
+                Ok(Outcome::Cancelled) => { summary.cancelled = true; return Ok(summary); },
+                Err(_) => { summary.unreadable += 1; continue; },
+            }
+        }
+        summary.found += 1;
+        found(candidate);
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use ::search::PatternKind;
+
+    fn file(name: &str, size: u64, modified: Option<SystemTime>) -> Found
+    { Found { path: PathBuf::from("root").join(name), is_dir: false, size: size, modified: modified, hidden: false } }
+
+    fn day(date: &str) -> SystemTime
+    { parse_date(date).unwrap() }
+
+    #[test]
+    fn sizes()
+    {
+        assert_eq!(parse_size("1500"), Some(1500));
+        assert_eq!(parse_size(" 64K "), Some(64 << 10));
+        assert_eq!(parse_size("1.5M"), Some(3 << 19));
+        assert_eq!(parse_size("2 GB"), Some(2 << 30));
+        assert_eq!(parse_size("1 tib"), Some(1 << 40));
+        assert_eq!(parse_size("0"), Some(0));
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("-1K"), None);
+        assert_eq!(parse_size("12 apples"), None);
+        assert_eq!(parse_size("99999999999T"), None);
+    }
+
+    #[test]
+    fn dates()
+    {
+        assert_eq!(parse_date("1970-01-01"), Some(UNIX_EPOCH));
+        assert_eq!(parse_date("1970-01-02"), Some(UNIX_EPOCH + Duration::from_secs(86400)));
+        assert_eq!(parse_date("2000-02-29"), Some(UNIX_EPOCH + Duration::from_secs(951782400)));
+        assert_eq!(parse_date("2000-03-01"), Some(UNIX_EPOCH + Duration::from_secs(951868800)));
+        assert_eq!(parse_date(" 2024-12-31 "), Some(UNIX_EPOCH + Duration::from_secs(1735603200)));
+        assert_eq!(parse_date("1969-12-31"), None);
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-01-00"), None);
+        assert_eq!(parse_date("2024-01"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn name_masks()
+    {
+        let mut options = FindOptions::new(Path::new("root"));
+        options.names = "*.txt;*.md".to_string();
+        assert!(passes(&options, &file("a.txt", 1, None), false));
+        assert!(passes(&options, &file("README.MD", 1, None), false));
+        assert!(!passes(&options, &file("a.rs", 1, None), false));
+        options.exclude_names = "~*".to_string();
+        assert!(!passes(&options, &file("~a.txt", 1, None), false));
+
+        options.dirs = "src;doc*".to_string();
+        options.exclude_dirs = "docs_old".to_string();
+        assert!(searches_dir(&options, "src") && searches_dir(&options, "docs"));
+        assert!(!searches_dir(&options, "target") && !searches_dir(&options, "docs_old"));
+        options.dirs = String::new();
+        assert!(searches_dir(&options, "target"));
+    }
+
+    #[test]
+    fn size_and_date_ranges()
+    {
+        let mut options = FindOptions::new(Path::new("root"));
+        options.min_size = Some(10);
+        options.max_size = Some(20);
+        assert!(!passes(&options, &file("a", 9, None), false));
+        assert!(passes(&options, &file("a", 10, None), false));
+        assert!(passes(&options, &file("a", 20, None), false));
+        assert!(!passes(&options, &file("a", 21, None), false));
+        // directories have no size to compare
+        let dir = Found { is_dir: true, ..file("d", 0, None) };
+        assert!(!passes(&options, &dir, false));
+
+        let mut options = FindOptions::new(Path::new("root"));
+        options.modified_from = Some(day("2024-01-01"));
+        options.modified_to = Some(day("2024-02-01"));
+        assert!(!passes(&options, &file("a", 0, Some(day("2024-01-01") - Duration::from_secs(1))), false));
+        assert!(passes(&options, &file("a", 0, Some(day("2024-01-01"))), false));
+        assert!(passes(&options, &file("a", 0, Some(day("2024-01-31"))), false));
+        assert!(!passes(&options, &file("a", 0, Some(day("2024-02-01"))), false));
+        assert!(!passes(&options, &file("a", 0, None), false));
+        options.modified_from = None;
+        options.modified_to = None;
+        assert!(passes(&options, &file("a", 0, None), false));
+    }
+
+    #[test]
+    fn attributes()
+    {
+        let mut options = FindOptions::new(Path::new("root"));
+        let hidden = Found { hidden: true, ..file(".a", 0, None) };
+        options.hidden = Require::Clear;
+        assert!(!passes(&options, &hidden, false) && passes(&options, &file("a", 0, None), false));
+        options.hidden = Require::Set;
+        assert!(passes(&options, &hidden, false) && !passes(&options, &file("a", 0, None), false));
+        options.hidden = Require::Any;
+        options.read_only = Require::Set;
+        assert!(passes(&options, &file("a", 0, None), true) && !passes(&options, &file("a", 0, None), false));
+        options.directory = Require::Clear;
+        assert!(!passes(&options, &Found { is_dir: true, ..file("d", 0, None) }, true));
+    }
+
+    #[test]
+    fn walks_the_tree()
+    {
+        let root = env::temp_dir().join(format!("find_files_test_{}_walk", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a").join("b")).unwrap();
+        fs::create_dir_all(root.join("skip")).unwrap();
+        fs::write(root.join("one.txt"), "hello world").unwrap();
+        fs::write(root.join("a").join("two.TXT"), "Hello there").unwrap();
+        fs::write(root.join("a").join("b").join("three.txt"), "nothing").unwrap();
+        fs::write(root.join("skip").join("four.txt"), "hello").unwrap();
+        fs::write(root.join("a").join("big.bin"), vec![0u8; 5000]).unwrap();
+
+        let cancel = AtomicBool::new(false);
+        let run = |options: &FindOptions| {
+            let mut names = Vec::new();
+            let summary = find(options, &cancel, &mut |found| {
+                let relative = found.path.strip_prefix(&root).unwrap().to_path_buf();
+                names.push(relative.to_string_lossy().replace('\\', "/"))
+            }, &mut |_| {}).unwrap();
+            names.sort();
+            (names, summary)
+        };
+
+        let mut options = FindOptions::new(&root);
+        options.names = "*.txt".to_string();
+        let (names, summary) = run(&options);
+        assert_eq!(names, vec!["a/b/three.txt", "a/two.TXT", "one.txt", "skip/four.txt"]);
+        assert_eq!((summary.dirs, summary.files, summary.found), (3, 5, 4));
+        options.exclude_dirs = "sk*".to_string();
+        options.max_depth = Some(1);
+        assert_eq!(run(&options).0, vec!["a/two.TXT", "one.txt"]);
+        options.max_depth = None;
+        options.content = Some(SearchOptions {
+            pattern: "hello".to_string(),
+            kind: PatternKind::Text,
+            case_sensitive: false,
+            whole_word: false,
+        });
+        assert_eq!(run(&options).0, vec!["a/two.TXT", "one.txt"]);
+
+        let mut options = FindOptions::new(&root);
+        options.min_size = Some(1000);
+        assert_eq!(run(&options).0, vec!["a/big.bin"]);
+        options.min_size = None;
+        options.directory = Require::Set;
+        assert_eq!(run(&options).0, vec!["a", "a/b", "skip"]);
+
+        cancel.store(true, Ordering::Relaxed);
+        let (names, summary) = run(&options);
+        assert!(names.is_empty() && summary.cancelled);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,469 @@
+// Find Files window (Alt+F7): the search options on top and the list of what
+// was found below. `find_files::find` runs on a worker thread and leaves what
+// it finds in `Progress`; WM_FIND_UPDATE tells the window to pick it up, at
+// most one such message being queued at a time. Found files can be viewed,
+// shown in the panel the search was started from, or all fed to that panel.
+
+extern crate winapi;
+extern crate std;
+
+use ::find_files::{self, FindOptions, Found, Require, Summary};
+use ::search::{PatternKind, SearchOptions};
+use viewer::ViewerCls;
+use win_gdi::{WinCls, rcrc, with_panel, show_error, create_control, add_dialog, remove_dialog};
+use winapi::*;
+use win_layer::*;
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const WM_FIND_UPDATE: UINT = WM_APP + 1;
+const WIDTH: c_int = 640;
+const HEIGHT: c_int = 600;
+// top of the result list in the client area
+const RESULTS_TOP: c_int = 266;
+const STATUS_HEIGHT: c_int = 22;
+
+const IDC_NAMES: c_int = 100;
+const IDC_EXCLUDE_NAMES: c_int = 101;
+const IDC_ROOT: c_int = 102;
+const IDC_CONTENT: c_int = 103;
+const IDC_CASE: c_int = 104;
+const IDC_WHOLE_WORD: c_int = 105;
+const IDC_REGEX: c_int = 106;
+const IDC_HEX: c_int = 107;
+const IDC_DIRS: c_int = 108;
+const IDC_EXCLUDE_DIRS: c_int = 109;
+const IDC_MIN_SIZE: c_int = 110;
+const IDC_MAX_SIZE: c_int = 111;
+const IDC_DEPTH: c_int = 112;
+const IDC_FROM_DATE: c_int = 113;
+const IDC_TO_DATE: c_int = 114;
+const IDC_DIRECTORY: c_int = 115;
+const IDC_HIDDEN: c_int = 116;
+const IDC_READ_ONLY: c_int = 117;
+const IDC_VIEW: c_int = 120;
+const IDC_GO_TO: c_int = 121;
+const IDC_FEED: c_int = 122;
+const IDC_RESULTS: c_int = 130;
+const IDC_STATUS: c_int = 131;
+
+// What the worker has found since the window last looked.
+struct Progress {
+    found: Vec<Found>,
+    searching: PathBuf,
+    done: Option<io::Result<Summary>>,
+}
+
+struct RunningFind {
+    cancel: Arc<AtomicBool>,
+    // a WM_FIND_UPDATE is on its way
+    posted: Arc<AtomicBool>,
+    progress: Arc<Mutex<Progress>>,
+}
+
+pub struct FinderCls {
+    // the panel the window was opened from
+    panel: HWND,
+    controls: RefCell<HashMap<c_int, HWND>>,
+    // directory searched last, and what was found, in the order of the list
+    root: RefCell<PathBuf>,
+    found: RefCell<Vec<Found>>,
+    running: RefCell<Option<RunningFind>>,
+}
+
+fn invalid(message: String) -> io::Error
+{ io::Error::new(io::ErrorKind::InvalidInput, message) }
+
+// Empty text means no limit.
+fn parse_optional<T, F>(text: &str, parse: F, what: &str) -> io::Result<Option<T>>
+    where F: Fn(&str) -> Option<T>
+{
+    if text.is_empty() {
+        return Ok(None);
+    }
+    parse(text).map(Some).ok_or_else(|| invalid(format!("\"{}\" is not a valid {}", text, what)))
+}
+
+// Sets the flag and posts WM_FIND_UPDATE unless one is already queued.
+fn notify(target: usize, posted: &AtomicBool)
+{
+    if !posted.swap(true, Ordering::SeqCst) {
+        let _ = PostMessageW(target as HWND, WM_FIND_UPDATE, 0, 0);
+    }
+}
+
+impl FinderCls {
+    pub fn create(instance: HINSTANCE, panel: HWND, root: &Path) -> Result<HWND, u32>
+    {
+        let inst = FinderCls {
+            panel: panel,
+            controls: RefCell::new(HashMap::new()),
+            root: RefCell::new(root.to_path_buf()),
+            found: RefCell::new(Vec::new()),
+            running: RefCell::new(None),
+        };
+        let cls_id = try!(Self::get_cls_id());
+        let inst_rc = rcrc(inst);
+        let inst_ptr = Box::into_raw(Box::new(inst_rc.clone()) as Box<Any>);
+        let hwnd = try!(CreateWindowExW(
+            0,
+            cls_id,
+            Some(&wstr("Find Files")),
+            WS_OVERLAPPEDWINDOW,
+            CW_USEDEFAULT, CW_USEDEFAULT, WIDTH, HEIGHT,
+            None, None, instance, Some(inst_ptr as LPVOID)));
+
+        let root_text = root.to_string_lossy().into_owned();
+        let edits = [
+            ("File names:", IDC_NAMES, "*", (10, 10, 100, 230)),
+            ("Except:", IDC_EXCLUDE_NAMES, "", (350, 10, 60, 210)),
+            ("Search in:", IDC_ROOT, root_text.as_str(), (10, 38, 100, 510)),
+            ("Containing:", IDC_CONTENT, "", (10, 66, 100, 510)),
+            ("Only in dirs:", IDC_DIRS, "", (10, 122, 100, 230)),
+            ("Skip dirs:", IDC_EXCLUDE_DIRS, "", (350, 122, 60, 210)),
+            ("Size from:", IDC_MIN_SIZE, "", (10, 150, 100, 90)),
+            ("to:", IDC_MAX_SIZE, "", (210, 150, 30, 100)),
+            ("Depth:", IDC_DEPTH, "", (350, 150, 60, 60)),
+            ("Modified from:", IDC_FROM_DATE, "", (10, 178, 100, 90)),
+            ("to:", IDC_TO_DATE, "", (210, 178, 30, 100)),
+        ];
+        let checks = [
+            ("Case sensitive", IDC_CASE, (110, 94, 110)),
+            ("Whole words", IDC_WHOLE_WORD, (225, 94, 100)),
+            ("Regular expression", IDC_REGEX, (330, 94, 130)),
+            ("Hex bytes", IDC_HEX, (465, 94, 100)),
+        ];
+        let attributes = [
+            ("Directories", IDC_DIRECTORY, (110, 206, 100)),
+            ("Hidden", IDC_HIDDEN, (225, 206, 100)),
+            ("Read-only", IDC_READ_ONLY, (330, 206, 100)),
+        ];
+        let buttons = [
+            ("Start", IDOK, BS_DEFPUSHBUTTON, 10),
+            ("View", IDC_VIEW, BS_PUSHBUTTON, 110),
+            ("Go to file", IDC_GO_TO, BS_PUSHBUTTON, 210),
+            ("Feed to panel", IDC_FEED, BS_PUSHBUTTON, 310),
+            ("Close", IDCANCEL, BS_PUSHBUTTON, 530),
+        ];
+        {
+            let inst = inst_rc.borrow();
+            let mut controls = inst.controls.borrow_mut();
+            for &(label, id, text, (x, y, label_width, width)) in edits.iter() {
+                try!(create_control(hwnd, "STATIC", label, 0, (x, y + 3, label_width, 16), -1));
+                let edit = try!(create_control(
+                    hwnd, "EDIT", text, WS_BORDER | WS_TABSTOP | ES_AUTOHSCROLL,
+                    (x + label_width, y, width, 22), id));
+                controls.insert(id, edit);
+            }
+            try!(create_control(hwnd, "STATIC", "(64K, 1.5M)", 0, (420, 153, 100, 16), -1));
+            try!(create_control(hwnd, "STATIC", "(YYYY-MM-DD)", 0, (350, 181, 100, 16), -1));
+            for &(label, id, (x, y, width)) in checks.iter() {
+                let check = try!(create_control(
+                    hwnd, "BUTTON", label, WS_TABSTOP | BS_AUTOCHECKBOX, (x, y, width, 20), id));
+                controls.insert(id, check);
+            }
+            try!(create_control(hwnd, "STATIC", "Attributes:", 0, (10, 209, 100, 16), -1));
+            for &(label, id, (x, y, width)) in attributes.iter() {
+                let check = try!(create_control(
+                    hwnd, "BUTTON", label, WS_TABSTOP | BS_AUTO3STATE, (x, y, width, 20), id));
+                // grey: either way
+                SendMessageW(check, BM_SETCHECK, BST_INDETERMINATE, 0);
+                controls.insert(id, check);
+            }
+            for &(label, id, style, x) in buttons.iter() {
+                let button = try!(create_control(hwnd, "BUTTON", label, WS_TABSTOP | style, (x, 234, 90, 24), id));
+                controls.insert(id, button);
+            }
+            let results = try!(create_control(
+                hwnd, "LISTBOX", "",
+                WS_BORDER | WS_TABSTOP | WS_VSCROLL | LBS_NOTIFY | LBS_NOINTEGRALHEIGHT,
+                (10, RESULTS_TOP, 100, 100), IDC_RESULTS));
+            controls.insert(IDC_RESULTS, results);
+            let status = try!(create_control(hwnd, "STATIC", "", 0, (10, 0, 100, 16), IDC_STATUS));
+            controls.insert(IDC_STATUS, status);
+            try!(inst.layout(hwnd));
+        }
+        add_dialog(hwnd);
+        let _ = ShowWindow(hwnd, SW_SHOW);
+        let names = inst_rc.borrow().control(IDC_NAMES);
+        SendMessageW(names, EM_SETSEL as UINT, 0, -1);
+        let _ = SetFocus(names);
+        Ok(hwnd)
+    }
+
+    fn control(&self, id: c_int) -> HWND
+    { self.controls.borrow().get(&id).cloned().unwrap_or(0 as HWND) }
+
+    // The result list and the status line take what is left of the window.
+    fn layout(&self, hwnd: HWND) -> Result<(), u32>
+    {
+        let rect = try!(GetClientRect(hwnd));
+        let width = rect.right - 20;
+        let status_top = rect.bottom - STATUS_HEIGHT;
+        try!(MoveWindow(self.control(IDC_RESULTS), 10, RESULTS_TOP, width, status_top - RESULTS_TOP - 4, true));
+        MoveWindow(self.control(IDC_STATUS), 10, status_top + 2, width, 16, true)
+    }
+
+    fn set_status(&self, text: &str)
+    {
+        let _ = SetWindowTextW(self.control(IDC_STATUS), &wstr(text));
+    }
+
+    fn read_options(&self) -> io::Result<FindOptions>
+    {
+        let text = |id| GetWindowTextW(self.control(id)).trim().to_string();
+        let checked = |id| SendMessageW(self.control(id), BM_GETCHECK, 0, 0) as WPARAM;
+        let require = |id| match checked(id) {
+            BST_CHECKED => Require::Set,
+            BST_INDETERMINATE => Require::Any,
+            _ => Require::Clear,
+        };
+        let root = text(IDC_ROOT);
+        if root.is_empty() {
+            return Err(invalid("Choose a directory to search in".to_string()));
+        }
+        let mut options = FindOptions::new(Path::new(&root));
+        let names = text(IDC_NAMES);
+        if !names.is_empty() {
+            options.names = names;
+        }
+        options.exclude_names = text(IDC_EXCLUDE_NAMES);
+        options.dirs = text(IDC_DIRS);
+        options.exclude_dirs = text(IDC_EXCLUDE_DIRS);
+        options.min_size = try!(parse_optional(&text(IDC_MIN_SIZE), find_files::parse_size, "size"));
+        options.max_size = try!(parse_optional(&text(IDC_MAX_SIZE), find_files::parse_size, "size"));
+        options.modified_from = try!(parse_optional(&text(IDC_FROM_DATE), find_files::parse_date, "date"));
+        // the end date is included
+        let to = try!(parse_optional(&text(IDC_TO_DATE), find_files::parse_date, "date"));
+        options.modified_to = to.map(|to| to + Duration::from_secs(24 * 60 * 60));
+        options.max_depth = try!(parse_optional(&text(IDC_DEPTH), |t| t.parse().ok(), "depth"));
+        options.directory = require(IDC_DIRECTORY);
+        options.hidden = require(IDC_HIDDEN);
+        options.read_only = require(IDC_READ_ONLY);
+        let pattern = GetWindowTextW(self.control(IDC_CONTENT));
+        if !pattern.is_empty() {
+            let kind = match (checked(IDC_REGEX) == BST_CHECKED, checked(IDC_HEX) == BST_CHECKED) {
+                (false, false) => PatternKind::Text,
+                (true, false) => PatternKind::Regex,
+                (false, true) => PatternKind::Hex,
+                (true, true) => return Err(invalid(
+                    "Choose either a regular expression or hex bytes, not both".to_string())),
+            };
+            options.content = Some(SearchOptions {
+                pattern: pattern,
+                kind: kind,
+                case_sensitive: checked(IDC_CASE) == BST_CHECKED,
+                whole_word: checked(IDC_WHOLE_WORD) == BST_CHECKED,
+            });
+        }
+        Ok(options)
+    }
+
+    fn start(&self, hwnd: HWND)
+    {
+        if self.running.borrow().is_some() {
+            return;
+        }
+        let options = match self.read_options() {
+            Ok(options) => options,
+            Err(e) => return show_error(hwnd, "Find Files", &e),
+        };
+        SendMessageW(self.control(IDC_RESULTS), LB_RESETCONTENT, 0, 0);
+        self.found.borrow_mut().clear();
+        *self.root.borrow_mut() = options.root.clone();
+        let running = RunningFind {
+            cancel: Arc::new(AtomicBool::new(false)),
+            posted: Arc::new(AtomicBool::new(false)),
+            progress: Arc::new(Mutex::new(Progress {
+                found: Vec::new(),
+                searching: options.root.clone(),
+                done: None,
+            })),
+        };
+        let (cancel, posted, progress) = (running.cancel.clone(), running.posted.clone(), running.progress.clone());
+        // HWND is not Send
+        let target = hwnd as usize;
+        thread::spawn(move || {
+            let rv = find_files::find(
+                &options, &cancel,
+                &mut |found| {
+                    progress.lock().unwrap().found.push(found);
+                    notify(target, &posted);
+                },
+                &mut |dir| {
+                    progress.lock().unwrap().searching = dir.to_path_buf();
+                    notify(target, &posted);
+                });
+            progress.lock().unwrap().done = Some(rv);
+            let _ = PostMessageW(target as HWND, WM_FIND_UPDATE, 0, 0);
+        });
+        *self.running.borrow_mut() = Some(running);
+        let _ = SetWindowTextW(self.control(IDOK), &wstr("Stop"));
+        self.set_status("Searching...");
+    }
+
+    fn stop(&self)
+    {
+        if let Some(ref running) = *self.running.borrow() {
+            running.cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn on_update(&self, hwnd: HWND)
+    {
+        let (found, searching, done) = match *self.running.borrow() {
+            Some(ref running) => {
+                running.posted.store(false, Ordering::SeqCst);
+                let mut progress = running.progress.lock().unwrap();
+                (mem::replace(&mut progress.found, Vec::new()), progress.searching.clone(), progress.done.take())
+            },
+            None => return,
+        };
+        let list = self.control(IDC_RESULTS);
+        let first = self.found.borrow().is_empty() && !found.is_empty();
+        for item in &found {
+            let text = wstr(&item.path.to_string_lossy());
+            SendMessageW(list, LB_ADDSTRING, 0, text.as_ptr() as LPARAM);
+        }
+        if first {
+            SendMessageW(list, LB_SETCURSEL, 0, 0);
+        }
+        self.found.borrow_mut().extend(found);
+        let count = self.found.borrow().len();
+        let status = match done {
+            None => format!("{} found, searching {}", count, searching.display()),
+            Some(rv) => {
+                *self.running.borrow_mut() = None;
+                let _ = SetWindowTextW(self.control(IDOK), &wstr("Start"));
+                match rv {
+                    Ok(summary) => {
+                        let mut status = format!("{}{} found in {} files and {} directories",
+                                                 if summary.cancelled { "Stopped: " } else { "" },
+                                                 count, summary.files, summary.dirs);
+                        if summary.unreadable > 0 {
+                            status.push_str(&format!(", {} could not be read", summary.unreadable));
+                        }
+                        status
+                    },
+                    Err(e) => {
+                        show_error(hwnd, "Find Files", &e);
+                        format!("{} found, the search failed", count)
+                    },
+                }
+            },
+        };
+        self.set_status(&status);
+    }
+
+    fn selected(&self) -> Option<Found>
+    {
+        let idx = SendMessageW(self.control(IDC_RESULTS), LB_GETCURSEL, 0, 0);
+        if idx == LB_ERR {
+            return None;
+        }
+        self.found.borrow().get(idx as usize).cloned()
+    }
+
+    fn view(&self, hwnd: HWND)
+    {
+        match self.selected() {
+            Some(ref found) if found.is_dir => self.go_to(),
+            Some(found) => {
+                let instance = GetModuleHandleW(None).unwrap_or(0 as HINSTANCE);
+                if let Err(e) = ViewerCls::create(instance, &found.path) {
+                    show_error(hwnd, "Lister", &e);
+                }
+            },
+            None => {},
+        }
+    }
+
+    // Opens the file's directory in the panel with the cursor on the file.
+    fn go_to(&self)
+    {
+        if let Some(found) = self.selected() {
+            let panel = self.panel;
+            let _ = with_panel(panel, |p| { p.show_file(panel, &found.path); Ok(()) });
+            let _ = SetFocus(panel);
+        }
+    }
+
+    fn feed(&self)
+    {
+        let panel = self.panel;
+        let root = self.root.borrow().clone();
+        let _ = with_panel(panel, |p| { p.show_found(panel, &root, &self.found.borrow()); Ok(()) });
+        let _ = SetFocus(panel);
+    }
+}
+
+impl WinCls for FinderCls {
+    fn wnd_proc(
+        &self,
+        hwnd: HWND, msg: UINT,
+        param: WPARAM, _para: LPARAM)
+        -> Option<LRESULT>
+    {
+        match msg {
+            WM_COMMAND => {
+                let (id, code) = (LOWORD(param as DWORD) as c_int, HIWORD(param as DWORD));
+                match id {
+                    // Enter: a found file in the list, the search otherwise
+                    IDOK if GetFocus() == Some(self.control(IDC_RESULTS)) => self.go_to(),
+                    IDOK if self.running.borrow().is_some() => self.stop(),
+                    IDOK => self.start(hwnd),
+                    IDCANCEL if self.running.borrow().is_some() => self.stop(),
+                    IDCANCEL => { let _ = DestroyWindow(hwnd); },
+                    IDC_VIEW => self.view(hwnd),
+                    IDC_GO_TO => self.go_to(),
+                    IDC_FEED => self.feed(),
+                    IDC_RESULTS if code == LBN_DBLCLK => self.go_to(),
+                    _ => return None,
+                }
+                Some(0)
+            },
+            WM_FIND_UPDATE => {
+                self.on_update(hwnd);
+                Some(0)
+            },
+            WM_SIZE => {
+                let _ = self.layout(hwnd);
+                Some(0)
+            },
+            WM_DESTROY => {
+                self.stop();
+                remove_dialog(hwnd);
+                Some(0)
+            },
+            _ => None,
+        }
+    }
+
+    fn register () -> Result<ATOM, u32>
+    {
+        let wnd_cls = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as UINT,
+            style: CS_HREDRAW | CS_VREDRAW,
+            lpfnWndProc: Some(Self::wnd_proc_raw),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: 0 as HINSTANCE,
+            hIcon: try!(LoadIconW(0 as HINSTANCE, RC_IDI_APPLICATION)),
+            hCursor: try!(LoadCursorW(0 as HINSTANCE, RC_IDC_ARROW)),
+            hbrBackground: (COLOR_BTNFACE + 1) as HBRUSH,
+            lpszMenuName: 0 as *const u16,
+            lpszClassName: wstr("FinderCls").as_ptr(),
+            hIconSm: try!(LoadIconW(0 as HINSTANCE, RC_IDI_APPLICATION)),
+        };
+
+        RegisterClassExW(&wnd_cls)
+    }
+}
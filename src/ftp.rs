@@ -0,0 +1,1154 @@
+// FTP sites shown in a panel, at `\\ftp\<site>\`. Directories are listed with
+// MLSD when the server offers it, and with LIST otherwise, reading both the
+// UNIX `ls -l` and the DOS formats. Data connections are passive (EPSV, then
+// PASV) or active (PORT, EPRT for IPv6) as the site says.
+//
+// Logged in connections are kept for reuse; a transfer has one to itself
+// until it is complete, so a panel can list while files are copied. Listings
+// are kept too: `stat` looks entries up in the listing of their directory.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ::remote::{self, Pool, TIMEOUT, civil_from_days, is_lost, join, month_number, time_of};
+use ::sites::Site;
+use ::vfs::{DirEntry, Metadata, Vfs};
+
+// A line from the server: the code and the text of all its lines.
+#[derive(Debug)]
+struct Reply {
+    code: u32,
+    text: String,
+}
+
+impl Reply {
+    fn is_ok(&self) -> bool
+    { self.code < 400 }
+}
+
+fn reply_error(reply: &Reply) -> io::Error
+{
+    let text = reply.text.to_lowercase();
+    let kind = match reply.code {
+        421 => io::ErrorKind::ConnectionAborted,
+        530 | 532 => io::ErrorKind::PermissionDenied,
+        450 | 550 if text.contains("permission") || text.contains("denied") => io::ErrorKind::PermissionDenied,
+        450 | 550 if text.contains("exists") => io::ErrorKind::AlreadyExists,
+        450 | 550 => io::ErrorKind::NotFound,
+        553 => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("{} {}", reply.code, reply.text))
+}
+
+// The port of a `229 Entering Extended Passive Mode (|||port|)` reply.
+pub fn parse_epsv(text: &str) -> Option<u16>
+{
+    let start = try_opt!(text.find('('));
+    let end = try_opt!(text[start..].find(')')) + start;
+    let fields: Vec<&str> = text[start + 1..end].split('|').collect();
+    if fields.len() != 5 {
+        return None;
+    }
+    fields[3].parse().ok()
+}
+
+// The address of a `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)` reply.
+pub fn parse_pasv(text: &str) -> Option<SocketAddr>
+{
+    let start = try_opt!(text.find(|c: char| c.is_digit(10)));
+    let numbers: Vec<u8> = text[start..].split(',')
+        .map(|field| field.trim_matches(|c: char| !c.is_digit(10)))
+        .take(6)
+        .filter_map(|field| field.parse().ok())
+        .collect();
+    if numbers.len() != 6 {
+        return None;
+    }
+    let ip = IpAddr::from([numbers[0], numbers[1], numbers[2], numbers[3]]);
+    Some(SocketAddr::new(ip, (numbers[4] as u16) << 8 | numbers[5] as u16))
+}
+
+// The whitespace separated words of `line` with the byte offset each starts at.
+fn words(line: &str) -> Vec<(usize, &str)>
+{
+    let mut rv = Vec::new();
+    let mut start = None;
+    for (idx, c) in line.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => { rv.push((s, &line[s..idx])); start = None; },
+            (false, None) => start = Some(idx),
+            _ => {},
+        }
+    }
+    if let Some(s) = start {
+        rv.push((s, &line[s..]));
+    }
+    rv
+}
+
+// A line of `ls -l` output:
+// `drwxr-xr-x  2 user group  4096 Mar  5 12:34 name`. The owner or the group
+// may be missing, so the date is looked for instead of counting fields. Dates
+// without a year are within the last year from `now`; all are taken as UTC.
+fn parse_unix_line(line: &str, now: SystemTime) -> Option<DirEntry>
+{
+    let kind = try_opt!(line.chars().next());
+    if !"-dlbcps".contains(kind) {
+        return None;
+    }
+    let words = words(line);
+    let month_at = try_opt!((2..words.len().saturating_sub(3)).find(|&idx|
+        month_number(words[idx].1).is_some() && words[idx - 1].1.parse::<u64>().is_ok()));
+    let size: u64 = try_opt!(words[month_at - 1].1.parse().ok());
+    let month = try_opt!(month_number(words[month_at].1));
+    let day: i64 = try_opt!(words[month_at + 1].1.parse().ok());
+    let (when_at, when) = words[month_at + 2];
+    let modified = match when.find(':') {
+        Some(colon) => {
+            let hour: u64 = try_opt!(when[..colon].parse().ok());
+            let minute: u64 = try_opt!(when[colon + 1..].parse().ok());
+            let now_secs = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let (year, _, _) = civil_from_days((now_secs / 86400) as i64);
+            let this_year = try_opt!(time_of(year, month, day, hour, minute, 0));
+            // a day ahead is allowed for servers in other time zones
+            if this_year > now + Duration::from_secs(86400) { time_of(year - 1, month, day, hour, minute, 0) } else { Some(this_year) }
+        },
+        None => time_of(try_opt!(when.parse().ok()), month, day, 0, 0, 0),
+    };
+    let mut name = line[when_at + when.len()..].trim_start();
+    if kind == 'l' {
+        if let Some(arrow) = name.find(" -> ") {
+            name = &name[..arrow];
+        }
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+    let mode = permission_bits(&line[..words[0].1.len()]);
+    Some(DirEntry {
+        name: name.to_string(),
+        meta: Metadata {
+            is_dir: kind == 'd',
+            size: if kind == 'd' { 0 } else { size },
+            modified: modified,
+            hidden: name.starts_with('.'),
+            read_only: mode.map_or(false, |mode| mode & 0o200 == 0),
+            is_link: kind == 'l',
+            mode: mode,
+        },
+    })
+}
+
+// The bits of `drwxr-sr-x` and the like; the type letter is left out.
+fn permission_bits(text: &str) -> Option<u32>
+{
+    let chars: Vec<char> = text.chars().skip(1).take(9).collect();
+    if chars.len() != 9 {
+        return None;
+    }
+    let mut mode = 0;
+    for (idx, &c) in chars.iter().enumerate() {
+        let bit = 1 << (8 - idx);
+        mode |= match (idx % 3, c) {
+            (_, '-') => 0,
+            (0, 'r') | (1, 'w') | (2, 'x') => bit,
+            (2, 's') | (2, 't') => bit | 0o1000 << (2 - idx / 3),
+            (2, 'S') | (2, 'T') => 0o1000 << (2 - idx / 3),
+            _ => return None,
+        };
+    }
+    Some(mode)
+}
+
+// A line of a Windows server's listing:
+// `03-15-21  02:30PM       <DIR>          name` or the size instead of `<DIR>`.
+fn parse_dos_line(line: &str) -> Option<DirEntry>
+{
+    let words = words(line);
+    if words.len() < 4 {
+        return None;
+    }
+    let date: Vec<&str> = words[0].1.split('-').collect();
+    if date.len() != 3 {
+        return None;
+    }
+    let month: i64 = try_opt!(date[0].parse().ok());
+    let day: i64 = try_opt!(date[1].parse().ok());
+    let year: i64 = match try_opt!(date[2].parse().ok()) {
+        year if year < 70 => 2000 + year,
+        year if year < 100 => 1900 + year,
+        year => year,
+    };
+    let time = words[1].1.to_uppercase();
+    let (clock, afternoon) = match (time.ends_with("AM"), time.ends_with("PM")) {
+        (true, _) => (&time[..time.len() - 2], Some(false)),
+        (_, true) => (&time[..time.len() - 2], Some(true)),
+        _ => (&time[..], None),
+    };
+    let colon = try_opt!(clock.find(':'));
+    let hour: u64 = try_opt!(clock[..colon].parse().ok());
+    let minute: u64 = try_opt!(clock[colon + 1..].parse().ok());
+    let hour = match afternoon {
+        Some(pm) => hour % 12 + if pm { 12 } else { 0 },
+        None => hour,
+    };
+    let is_dir = words[2].1.eq_ignore_ascii_case("<DIR>");
+    let size: u64 = if is_dir { 0 } else { try_opt!(words[2].1.parse().ok()) };
+    let name = line[words[3].0..].trim_end();
+    if name == "." || name == ".." {
+        return None;
+    }
+    Some(DirEntry {
+        name: name.to_string(),
+        meta: Metadata {
+            is_dir: is_dir,
+            size: size,
+            modified: time_of(year, month, day, hour, minute, 0),
+            hidden: false,
+            read_only: false,
+            is_link: false,
+            mode: None,
+        },
+    })
+}
+
+// A line of `LIST` output in either format.
+pub fn parse_list_line(line: &str, now: SystemTime) -> Option<DirEntry>
+{ parse_unix_line(line, now).or_else(|| parse_dos_line(line)) }
+
+// `YYYYMMDDHHMMSS[.sss]`, always UTC.
+fn parse_mlsd_time(text: &str) -> Option<SystemTime>
+{
+    if text.len() < 14 || !text.is_char_boundary(14) || !text[..14].bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let field = |from: usize, to: usize| text[from..to].parse::<u64>().unwrap_or(0);
+    time_of(field(0, 4) as i64, field(4, 6) as i64, field(6, 8) as i64, field(8, 10), field(10, 12), field(12, 14))
+}
+
+fn to_mlsd_time(time: SystemTime) -> String
+{
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let in_day = seconds % 86400;
+    format!("{:04}{:02}{:02}{:02}{:02}{:02}", year, month, day, in_day / 3600, in_day % 3600 / 60, in_day % 60)
+}
+
+// A line of `MLSD` output: `type=file;size=12;modify=20210315143000; name`.
+pub fn parse_mlsd_line(line: &str) -> Option<DirEntry>
+{
+    let space = try_opt!(line.find(' '));
+    let name = &line[space + 1..];
+    let mut meta = Metadata { is_dir: false, size: 0, modified: None, hidden: name.starts_with('.'),
+                              read_only: false, is_link: false, mode: None };
+    for fact in line[..space].split(';').filter(|fact| !fact.is_empty()) {
+        let eq = try_opt!(fact.find('='));
+        let (key, value) = (fact[..eq].to_lowercase(), &fact[eq + 1..]);
+        match key.as_str() {
+            "type" => {
+                let value = value.to_lowercase();
+                match value.as_str() {
+                    "cdir" | "pdir" => return None,
+                    "dir" => meta.is_dir = true,
+                    _ => meta.is_link = value.starts_with("os.unix=slink") || value.starts_with("os.unix=symlink"),
+                }
+            },
+            "size" => meta.size = value.parse().unwrap_or(0),
+            "modify" => meta.modified = parse_mlsd_time(value),
+            // files without `w` cannot be written, directories without `c` cannot be added to
+            "perm" => meta.read_only = !value.contains(|c| c == 'w' || c == 'c'),
+            "unix.mode" => meta.mode = u32::from_str_radix(value, 8).ok().map(|mode| mode & 0o7777),
+            _ => {},
+        }
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+    if meta.is_dir {
+        meta.size = 0;
+    }
+    Some(DirEntry { name: name.to_string(), meta: meta })
+}
+
+struct Connection {
+    control: BufReader<TcpStream>,
+    passive: bool,
+    // false once the server refused EPSV
+    extended: bool,
+    mlsd: bool,
+    mfmt: bool,
+}
+
+impl Connection {
+    fn open(site: &Site, password: &str) -> io::Result<Connection>
+    {
+        let stream = try!(remote::connect(site));
+        let mut conn = Connection {
+            control: BufReader::new(stream),
+            passive: site.passive,
+            extended: true,
+            mlsd: false,
+            mfmt: false,
+        };
+        let mut greeting = try!(conn.read_reply());
+        // "120 ready in a few minutes" comes before the real greeting
+        while greeting.code < 200 {
+            greeting = try!(conn.read_reply());
+        }
+        if !greeting.is_ok() {
+            return Err(reply_error(&greeting));
+        }
+        try!(conn.log_in(site, password));
+        let features = try!(conn.command("FEAT"));
+        if features.code == 211 {
+            for feature in features.text.lines().map(|line| line.trim().to_uppercase()) {
+                conn.mlsd |= feature.starts_with("MLST");
+                conn.mfmt |= feature == "MFMT";
+                if feature == "UTF8" {
+                    let _ = try!(conn.command("OPTS UTF8 ON"));
+                }
+            }
+        }
+        try!(conn.expect("TYPE I"));
+        Ok(conn)
+    }
+
+    fn log_in(&mut self, site: &Site, password: &str) -> io::Result<()>
+    {
+        let (user, password) = match (site.user.as_str(), password) {
+            ("", "") | ("anonymous", "") => ("anonymous", "anonymous@"),
+            ("", password) => ("anonymous", password),
+            (user, password) => (user, password),
+        };
+        let mut reply = try!(self.command(&format!("USER {}", user)));
+        if reply.code == 331 {
+            reply = try!(self.command(&format!("PASS {}", password)));
+        }
+        if reply.code >= 300 {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      format!("Cannot log in to {} as {}: {} {}", site.host, user, reply.code, reply.text)));
+        }
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<String>
+    {
+        let mut line = Vec::new();
+        if try!(self.control.read_until(b'\n', &mut line)) == 0 {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "The server closed the connection"));
+        }
+        let line = String::from_utf8_lossy(&line);
+        Ok(line.trim_end_matches(|c| c == '\r' || c == '\n').to_string())
+    }
+
+    // Reads a reply; those of several lines end with a line starting with
+    // the code and a space.
+    fn read_reply(&mut self) -> io::Result<Reply>
+    {
+        let first = try!(self.read_line());
+        let code: u32 = match first.get(..3).and_then(|code| code.parse().ok()) {
+            Some(code) => code,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Not an FTP reply: \"{}\"", first))),
+        };
+        let mut text = first[3..].trim_start_matches('-').trim().to_string();
+        if first[3..].starts_with('-') {
+            let end = format!("{} ", code);
+            loop {
+                let line = try!(self.read_line());
+                if line.starts_with(&end) || line == end.trim() {
+                    let last = line[3..].trim();
+                    if !last.is_empty() {
+                        text.push('\n');
+                        text.push_str(last);
+                    }
+                    break;
+                }
+                text.push('\n');
+                text.push_str(&line);
+            }
+        }
+        let reply = Reply { code: code, text: text };
+        if reply.code == 421 {
+            return Err(reply_error(&reply));
+        }
+        Ok(reply)
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()>
+    {
+        if command.contains(|c| c == '\r' || c == '\n') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Names cannot contain line breaks on FTP"));
+        }
+        let stream = self.control.get_mut();
+        try!(stream.write_all(format!("{}\r\n", command).as_bytes()));
+        stream.flush()
+    }
+
+    fn command(&mut self, command: &str) -> io::Result<Reply>
+    {
+        try!(self.send(command));
+        self.read_reply()
+    }
+
+    // `command`, failing unless the server agreed.
+    fn expect(&mut self, command: &str) -> io::Result<Reply>
+    {
+        let reply = try!(self.command(command));
+        if !reply.is_ok() {
+            return Err(reply_error(&reply));
+        }
+        Ok(reply)
+    }
+
+    // Sends `command` once the server is ready to transfer over a data
+    // connection, and returns that connection.
+    fn open_data(&mut self, command: &str) -> io::Result<TcpStream>
+    {
+        let stream = if self.passive {
+            let address = try!(self.passive_address());
+            let stream = try!(TcpStream::connect_timeout(&address, TIMEOUT));
+            try!(self.start_transfer(command));
+            stream
+        } else {
+            let listener = try!(self.announce_port());
+            try!(self.start_transfer(command));
+            try!(accept(&listener))
+        };
+        try!(stream.set_read_timeout(Some(TIMEOUT)));
+        try!(stream.set_write_timeout(Some(TIMEOUT)));
+        Ok(stream)
+    }
+
+    // The data comes from the server the control connection goes to: the
+    // address in a PASV reply is ignored, as servers behind NAT often give
+    // one that cannot be reached.
+    fn passive_address(&mut self) -> io::Result<SocketAddr>
+    {
+        let peer = try!(self.control.get_ref().peer_addr());
+        if self.extended {
+            let reply = try!(self.command("EPSV"));
+            match parse_epsv(&reply.text) {
+                Some(port) if reply.code == 229 => return Ok(SocketAddr::new(peer.ip(), port)),
+                _ => self.extended = false,
+            }
+        }
+        let reply = try!(self.expect("PASV"));
+        match parse_pasv(&reply.text) {
+            Some(address) => Ok(SocketAddr::new(peer.ip(), address.port())),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Cannot read the PASV reply \"{}\"", reply.text))),
+        }
+    }
+
+    fn announce_port(&mut self) -> io::Result<TcpListener>
+    {
+        let local = try!(self.control.get_ref().local_addr());
+        let listener = try!(TcpListener::bind(SocketAddr::new(local.ip(), 0)));
+        let port = try!(listener.local_addr()).port();
+        let command = match local.ip() {
+            IpAddr::V4(ip) => {
+                let o = ip.octets();
+                format!("PORT {},{},{},{},{},{}", o[0], o[1], o[2], o[3], port >> 8, port & 0xff)
+            },
+            IpAddr::V6(ip) => format!("EPRT |2|{}|{}|", ip, port),
+        };
+        try!(self.expect(&command));
+        Ok(listener)
+    }
+
+    fn start_transfer(&mut self, command: &str) -> io::Result<()>
+    {
+        let reply = try!(self.command(command));
+        if reply.code >= 200 {
+            // a server agreeing without a preliminary reply sends no data
+            return Err(if reply.is_ok() {
+                io::Error::new(io::ErrorKind::InvalidData, format!("No data from \"{}\": {} {}", command, reply.code, reply.text))
+            } else {
+                reply_error(&reply)
+            });
+        }
+        Ok(())
+    }
+
+    // The reply after the data connection was closed.
+    fn finish_transfer(&mut self) -> io::Result<()>
+    {
+        let reply = try!(self.read_reply());
+        if !reply.is_ok() {
+            return Err(reply_error(&reply));
+        }
+        Ok(())
+    }
+
+    // Entries of the remote directory `dir`. LIST is given no arguments, as
+    // servers take those for `ls` options or globs, so the directory is
+    // changed to first.
+    fn list(&mut self, dir: &str) -> io::Result<Vec<DirEntry>>
+    {
+        try!(self.expect(&format!("CWD {}", dir)));
+        let command = if self.mlsd { "MLSD" } else { "LIST" };
+        let mut data = Vec::new();
+        {
+            let mut stream = try!(self.open_data(command));
+            try!(stream.read_to_end(&mut data));
+        }
+        try!(self.finish_transfer());
+        let now = SystemTime::now();
+        let text = String::from_utf8_lossy(&data);
+        let lines = text.lines().map(|line| line.trim_end_matches('\r'));
+        let mut entries: Vec<DirEntry> = if self.mlsd {
+            lines.filter_map(parse_mlsd_line).collect()
+        } else {
+            lines.filter_map(|line| parse_list_line(line, now)).collect()
+        };
+        // links to directories can be changed to
+        for entry in entries.iter_mut().filter(|entry| entry.meta.is_link && !entry.meta.is_dir) {
+            let reply = try!(self.command(&format!("CWD {}", join(dir, &entry.name))));
+            entry.meta.is_dir = reply.code == 250;
+        }
+        Ok(entries)
+    }
+}
+
+fn accept(listener: &TcpListener) -> io::Result<TcpStream>
+{
+    try!(listener.set_nonblocking(true));
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                try!(stream.set_nonblocking(false));
+                return Ok(stream);
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline =>
+                thread::sleep(Duration::from_millis(10)),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// The directory and the name of a remote path other than `/`.
+fn split(path: &str) -> (&str, &str)
+{
+    match path.rfind('/') {
+        Some(0) => ("/", &path[1..]),
+        Some(slash) => (&path[..slash], &path[slash + 1..]),
+        None => ("/", path),
+    }
+}
+
+// What the file system and its transfers share.
+struct Shared {
+    site: Site,
+    password: String,
+    pool: Pool<Connection>,
+    // entries of the remote directories listed, until they change
+    listed: Mutex<HashMap<String, Vec<DirEntry>>>,
+}
+
+impl Shared {
+    fn forget(&self, dir: &str)
+    { self.listed.lock().unwrap().remove(dir); }
+}
+
+pub struct FtpFs {
+    shared: Arc<Shared>,
+    // the path the site's top directory is shown at
+    root: PathBuf,
+}
+
+impl FtpFs {
+    // Logs in to `site`, to be shown at `root`. The connection is kept for
+    // what the panel does next.
+    pub fn connect(site: &Site, password: &str, root: &Path) -> io::Result<FtpFs>
+    {
+        let conn = try!(Connection::open(site, password));
+        let shared = Shared {
+            site: site.clone(),
+            password: password.to_string(),
+            pool: Pool::new(vec![conn]),
+            listed: Mutex::new(HashMap::new()),
+        };
+        Ok(FtpFs { shared: Arc::new(shared), root: root.to_path_buf() })
+    }
+
+    // Where the panel starts: the site's directory.
+    pub fn start_path(&self) -> PathBuf
+    {
+        let mut path = self.root.clone();
+        for name in self.shared.site.directory.split('/').filter(|name| !name.is_empty()) {
+            path.push(name);
+        }
+        path
+    }
+
+    // `path` on the server.
+    fn remote(&self, path: &Path) -> io::Result<String>
+    {
+        let rest = try!(path.strip_prefix(&self.root).map_err(|_|
+            io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not in \"{}\"", path.display(), self.root.display()))));
+        let names: Vec<String> = rest.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        Ok(format!("/{}", names.join("/")))
+    }
+
+    // Runs `f` on a connection of its own, an idle one if there is any. A
+    // connection the server closed while idle is replaced once.
+    fn take<T, F>(&self, f: F) -> io::Result<(Connection, T)>
+        where F: FnMut(&mut Connection) -> io::Result<T>
+    {
+        let shared = &self.shared;
+        let (conn, rv) = try!(shared.pool.run(|| Connection::open(&shared.site, &shared.password), f));
+        match rv {
+            Ok(value) => Ok((conn, value)),
+            Err(e) => {
+                if !is_lost(&e) {
+                    shared.pool.give_back(conn);
+                }
+                Err(e)
+            },
+        }
+    }
+
+    fn run<T, F>(&self, f: F) -> io::Result<T>
+        where F: FnMut(&mut Connection) -> io::Result<T>
+    {
+        let (conn, value) = try!(self.take(f));
+        self.shared.pool.give_back(conn);
+        Ok(value)
+    }
+
+    fn list_remote(&self, dir: &str) -> io::Result<Vec<DirEntry>>
+    {
+        let entries = try!(self.run(|conn| conn.list(dir)));
+        self.shared.listed.lock().unwrap().insert(dir.to_string(), entries.clone());
+        Ok(entries)
+    }
+
+    fn transfer(&self, command: &str, changes: Option<&str>) -> io::Result<Transfer>
+    {
+        let (conn, data) = try!(self.take(|conn| conn.open_data(command)));
+        Ok(Transfer {
+            shared: self.shared.clone(),
+            conn: Some(conn),
+            data: Some(data),
+            changes: changes.map(|dir| dir.to_string()),
+        })
+    }
+}
+
+// A file being downloaded or uploaded. A download is complete once read to
+// the end, an upload once flushed or dropped.
+struct Transfer {
+    shared: Arc<Shared>,
+    conn: Option<Connection>,
+    data: Option<TcpStream>,
+    // the directory an upload goes to
+    changes: Option<String>,
+}
+
+impl Transfer {
+    fn finish(&mut self) -> io::Result<()>
+    {
+        if let Some(data) = self.data.take() {
+            let _ = data.shutdown(Shutdown::Write);
+        }
+        if let Some(ref dir) = self.changes {
+            self.shared.forget(dir);
+        }
+        match self.conn.take() {
+            Some(mut conn) => {
+                let rv = conn.finish_transfer();
+                if rv.as_ref().err().map_or(true, |e| !is_lost(e)) {
+                    self.shared.pool.give_back(conn);
+                }
+                rv
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Read for Transfer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        let read = match self.data {
+            Some(ref mut data) => try!(data.read(buf)),
+            None => return Ok(0),
+        };
+        if read == 0 && !buf.is_empty() {
+            try!(self.finish());
+        }
+        Ok(read)
+    }
+}
+
+impl Write for Transfer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        match self.data {
+            Some(ref mut data) => data.write(buf),
+            None => Err(io::Error::new(io::ErrorKind::Other, "The file is already complete")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    { self.finish() }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self)
+    {
+        if self.changes.is_some() {
+            let _ = self.finish();
+        }
+        // a download left half way still has a reply coming, its connection
+        // is closed rather than reused
+    }
+}
+
+impl Vfs for FtpFs {
+    fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>>
+    { self.list_remote(&try!(self.remote(dir))) }
+
+    fn stat(&self, path: &Path) -> io::Result<Metadata>
+    {
+        let remote = try!(self.remote(path));
+        if remote == "/" {
+            return Ok(Metadata { is_dir: true, size: 0, modified: None, hidden: false, read_only: false, is_link: false,
+                                 mode: None });
+        }
+        let (dir, name) = split(&remote);
+        let cached = self.shared.listed.lock().unwrap().get(dir).cloned();
+        let entries = match cached {
+            Some(entries) => entries,
+            None => try!(self.list_remote(dir)),
+        };
+        entries.into_iter().find(|entry| entry.name == name).map(|entry| entry.meta).ok_or_else(||
+            io::Error::new(io::ErrorKind::NotFound, format!("\"{}\" not found", path.display())))
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>>
+    {
+        let transfer = try!(self.transfer(&format!("RETR {}", try!(self.remote(path))), None));
+        Ok(Box::new(transfer))
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>
+    {
+        let remote = try!(self.remote(path));
+        let transfer = try!(self.transfer(&format!("STOR {}", remote), Some(split(&remote).0)));
+        Ok(Box::new(transfer))
+    }
+
+    fn mkdir(&self, path: &Path) -> io::Result<()>
+    {
+        let remote = try!(self.remote(path));
+        self.shared.forget(split(&remote).0);
+        self.run(|conn| conn.expect(&format!("MKD {}", remote))).map(|_| ())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()>
+    {
+        let meta = try!(self.stat(path));
+        let remote = try!(self.remote(path));
+        let command = if meta.is_dir && !meta.is_link { "RMD" } else { "DELE" };
+        self.shared.forget(split(&remote).0);
+        self.shared.forget(&remote);
+        self.run(|conn| conn.expect(&format!("{} {}", command, remote))).map(|_| ())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>
+    {
+        let (from, to) = (try!(self.remote(from)), try!(self.remote(to)));
+        self.shared.forget(split(&from).0);
+        self.shared.forget(split(&to).0);
+        self.shared.forget(&from);
+        self.run(|conn| {
+            let reply = try!(conn.command(&format!("RNFR {}", from)));
+            if reply.code != 350 {
+                return Err(reply_error(&reply));
+            }
+            conn.expect(&format!("RNTO {}", to))
+        }).map(|_| ())
+    }
+
+    // Servers without MFMT keep the time of the upload.
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()>
+    {
+        let remote = try!(self.remote(path));
+        self.shared.forget(split(&remote).0);
+        self.run(|conn| if conn.mfmt {
+            conn.expect(&format!("MFMT {} {}", to_mlsd_time(time), remote)).map(|_| ())
+        } else {
+            Ok(())
+        })
+    }
+
+    fn mount_point(&self) -> Option<PathBuf>
+    { Some(self.root.clone()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Listing { Unix, Dos, Mlsd }
+
+    // 2020-09-13 12:26:40 UTC, the time of everything on the stand-in
+    const TIME: u64 = 1_600_000_000;
+
+    // An FTP server holding its files in memory: paths map to their data,
+    // or to None for directories.
+    struct Server {
+        port: u16,
+        files: Arc<Mutex<BTreeMap<String, Option<Vec<u8>>>>>,
+        logins: Arc<AtomicUsize>,
+        commands: Arc<Mutex<Vec<String>>>,
+        // the next command closes the connection instead
+        drop_next: Arc<AtomicBool>,
+    }
+
+    fn parent(path: &str) -> &str
+    { split(path).0 }
+
+    fn resolve(cwd: &str, name: &str) -> String
+    { if name.starts_with('/') { name.to_string() } else { join(cwd, name) } }
+
+    fn list_line(listing: Listing, name: &str, data: &Option<Vec<u8>>) -> String
+    {
+        let size = data.as_ref().map_or(0, |data| data.len());
+        match listing {
+            Listing::Unix if name == "link" => "lrwxrwxrwx 1 ftp ftp 3 Sep 13  2020 link -> sub".to_string(),
+            Listing::Unix => format!("{}rw-r--r-- 1 owner group {:>8} Sep 13  2020 {}",
+                                     if data.is_none() { 'd' } else { '-' }, size, name),
+            Listing::Dos if data.is_none() => format!("09-13-20  12:26PM       <DIR>          {}", name),
+            Listing::Dos => format!("09-13-20  12:26PM {:>20} {}", size, name),
+            Listing::Mlsd => format!("type={};size={};modify=20200913122640;perm=rw; {}",
+                                     if data.is_none() { "dir" } else { "file" }, size, name),
+        }
+    }
+
+    fn serve(stream: TcpStream, listing: Listing, epsv: bool, files: Arc<Mutex<BTreeMap<String, Option<Vec<u8>>>>>,
+             logins: Arc<AtomicUsize>, commands: Arc<Mutex<Vec<String>>>, drop_next: Arc<AtomicBool>)
+    {
+        stream.set_nodelay(true).unwrap();
+        let mut out = stream.try_clone().unwrap();
+        let mut input = BufReader::new(stream);
+        let (mut cwd, mut user, mut logged_in) = ("/".to_string(), String::new(), false);
+        let (mut passive, mut active, mut rename_from) = (None::<TcpListener>, None::<SocketAddr>, None::<String>);
+        write!(out, "220-Welcome\r\n220 ready\r\n").unwrap();
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line).unwrap_or(0) == 0 || drop_next.swap(false, Ordering::SeqCst) {
+                return;
+            }
+            let line = line.trim_end().to_string();
+            commands.lock().unwrap().push(line.clone());
+            let (command, arg) = match line.find(' ') {
+                Some(space) => (line[..space].to_uppercase(), line[space + 1..].to_string()),
+                None => (line.to_uppercase(), String::new()),
+            };
+            let mut open_data = |out: &mut TcpStream| {
+                write!(out, "150 Opening data connection\r\n").unwrap();
+                match passive.take() {
+                    Some(listener) => listener.accept().unwrap().0,
+                    None => TcpStream::connect(active.take().unwrap()).unwrap(),
+                }
+            };
+            if !logged_in && command != "USER" && command != "PASS" {
+                write!(out, "530 Not logged in\r\n").unwrap();
+                continue;
+            }
+            let path = resolve(&cwd, &arg);
+            let reply = match command.as_str() {
+                "USER" => { user = arg; "331 Password please".to_string() },
+                "PASS" if user == "me" && arg == "secret" => {
+                    logged_in = true;
+                    logins.fetch_add(1, Ordering::SeqCst);
+                    "230 Logged in".to_string()
+                },
+                "PASS" => "530 Login incorrect".to_string(),
+                "FEAT" if listing == Listing::Mlsd => "211-Features:\r\n MLST type*;size*;modify*;\r\n UTF8\r\n211 End".to_string(),
+                "FEAT" => "211-Features:\r\n UTF8\r\n211 End".to_string(),
+                "OPTS" | "TYPE" => "200 OK".to_string(),
+                "CWD" if path == "/" || path.ends_with("/link") || files.lock().unwrap().get(&path) == Some(&None) => {
+                    cwd = path;
+                    "250 OK".to_string()
+                },
+                "CWD" => "550 No such directory".to_string(),
+                "EPSV" if epsv => {
+                    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                    let port = listener.local_addr().unwrap().port();
+                    passive = Some(listener);
+                    format!("229 Entering Extended Passive Mode (|||{}|)", port)
+                },
+                "PASV" => {
+                    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                    let port = listener.local_addr().unwrap().port();
+                    passive = Some(listener);
+                    // an address behind NAT, which the client has to ignore
+                    format!("227 Entering Passive Mode (10,255,255,1,{},{})", port >> 8, port & 0xff)
+                },
+                "PORT" => {
+                    let n: Vec<u16> = arg.split(',').map(|n| n.parse().unwrap()).collect();
+                    active = Some(format!("{}.{}.{}.{}:{}", n[0], n[1], n[2], n[3], n[4] << 8 | n[5]).parse().unwrap());
+                    "200 PORT OK".to_string()
+                },
+                "LIST" | "MLSD" => {
+                    let mut text = String::new();
+                    for (path, data) in files.lock().unwrap().iter().filter(|&(path, _)| parent(path) == cwd) {
+                        text.push_str(&list_line(listing, split(path).1, data));
+                        text.push_str("\r\n");
+                    }
+                    if listing == Listing::Unix {
+                        text = format!("total 3\r\n{}", text);
+                    }
+                    open_data(&mut out).write_all(text.as_bytes()).unwrap();
+                    "226 Transfer complete".to_string()
+                },
+                "RETR" => {
+                    let data = files.lock().unwrap().get(&path).cloned();
+                    match data {
+                        Some(Some(data)) => {
+                            let _ = open_data(&mut out).write_all(&data);
+                            "226 Transfer complete".to_string()
+                        },
+                        _ => "550 No such file".to_string(),
+                    }
+                },
+                "STOR" if files.lock().unwrap().get(parent(&path)) == Some(&None) || parent(&path) == "/" => {
+                    let mut data = Vec::new();
+                    open_data(&mut out).read_to_end(&mut data).unwrap();
+                    files.lock().unwrap().insert(path, Some(data));
+                    "226 Transfer complete".to_string()
+                },
+                "STOR" => "553 Cannot create file".to_string(),
+                "MKD" if files.lock().unwrap().contains_key(&path) => "550 File exists".to_string(),
+                "MKD" => {
+                    files.lock().unwrap().insert(path.clone(), None);
+                    format!("257 \"{}\" created", path)
+                },
+                "RMD" | "DELE" => {
+                    let mut files = files.lock().unwrap();
+                    let is_dir = files.get(&path).map(|data| data.is_none());
+                    let empty = !files.keys().any(|other| parent(other) == path);
+                    match is_dir {
+                        Some(is_dir) if is_dir == (command == "RMD") && empty => {
+                            files.remove(&path);
+                            "250 Removed".to_string()
+                        },
+                        Some(_) => "550 Permission denied".to_string(),
+                        None => "550 No such file or directory".to_string(),
+                    }
+                },
+                "RNFR" if files.lock().unwrap().contains_key(&path) => {
+                    rename_from = Some(path);
+                    "350 Ready for RNTO".to_string()
+                },
+                "RNFR" => "550 No such file".to_string(),
+                "RNTO" => {
+                    let from = rename_from.take().unwrap();
+                    let mut files = files.lock().unwrap();
+                    let moved: Vec<String> = files.keys()
+                        .filter(|other| **other == from || other.starts_with(&format!("{}/", from)))
+                        .cloned().collect();
+                    for old in moved {
+                        let data = files.remove(&old).unwrap();
+                        files.insert(format!("{}{}", path, &old[from.len()..]), data);
+                    }
+                    "250 Renamed".to_string()
+                },
+                _ => "502 Not implemented".to_string(),
+            };
+            out.write_all(format!("{}\r\n", reply).as_bytes()).unwrap();
+        }
+    }
+
+    fn start(listing: Listing, epsv: bool) -> Server
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut files = BTreeMap::new();
+        files.insert("/pub".to_string(), None);
+        files.insert("/pub/sub".to_string(), None);
+        files.insert("/pub/read me.txt".to_string(), Some(b"hello ftp".to_vec()));
+        files.insert("/pub/sub/big.bin".to_string(), Some((0..200000u32).map(|i| (i % 251) as u8).collect()));
+        if listing == Listing::Unix {
+            files.insert("/pub/link".to_string(), Some(Vec::new()));
+        }
+        let server = Server {
+            port: listener.local_addr().unwrap().port(),
+            files: Arc::new(Mutex::new(files)),
+            logins: Arc::new(AtomicUsize::new(0)),
+            commands: Arc::new(Mutex::new(Vec::new())),
+            drop_next: Arc::new(AtomicBool::new(false)),
+        };
+        let shared = (server.files.clone(), server.logins.clone(), server.commands.clone(), server.drop_next.clone());
+        thread::spawn(move || for stream in listener.incoming() {
+            let (files, logins, commands, drop_next) = (shared.0.clone(), shared.1.clone(), shared.2.clone(), shared.3.clone());
+            let stream = stream.unwrap();
+            thread::spawn(move || serve(stream, listing, epsv, files, logins, commands, drop_next));
+        });
+        server
+    }
+
+    fn names(fs: &FtpFs, dir: &Path) -> Vec<String>
+    {
+        let mut names: Vec<String> = fs.list(dir).unwrap().into_iter().map(|entry| entry.name).collect();
+        names.sort();
+        names
+    }
+
+    fn round_trip(listing: Listing, epsv: bool, passive: bool)
+    {
+        let server = start(listing, epsv);
+        let mut site = Site::new("test");
+        site.set_address(&format!("ftp://me@127.0.0.1:{}/pub", server.port)).unwrap();
+        site.passive = passive;
+        let root = PathBuf::from("/ftp/test");
+        assert_eq!(FtpFs::connect(&site, "wrong", &root).err().unwrap().kind(), io::ErrorKind::PermissionDenied);
+        let fs = FtpFs::connect(&site, "secret", &root).unwrap();
+        let start = fs.start_path();
+        assert_eq!(start, root.join("pub"));
+
+        // list
+        let mut expected = vec!["read me.txt", "sub"];
+        if listing == Listing::Unix {
+            expected.insert(0, "link");
+            let link = fs.stat(&start.join("link")).unwrap();
+            assert!(link.is_link && link.is_dir);
+        }
+        assert_eq!(names(&fs, &start), expected);
+        let meta = fs.stat(&start.join("read me.txt")).unwrap();
+        assert_eq!((meta.size, meta.is_dir), (9, false));
+        let time = match listing {
+            Listing::Unix => TIME / 86400 * 86400,
+            Listing::Dos => TIME / 60 * 60,
+            Listing::Mlsd => TIME,
+        };
+        assert_eq!(meta.modified, Some(UNIX_EPOCH + Duration::from_secs(time)));
+        assert!(fs.stat(&start.join("sub")).unwrap().is_dir);
+        assert_eq!(fs.stat(&start.join("missing")).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        // download, listing on another connection while it is open
+        let mut text = String::new();
+        fs.open_read(&start.join("read me.txt")).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "hello ftp");
+        assert!(fs.open_read(&start.join("missing")).is_err());
+        let mut reader = fs.open_read(&start.join("sub/big.bin")).unwrap();
+        assert_eq!(names(&fs, &start.join("sub")), vec!["big.bin"]);
+        let mut big = Vec::new();
+        reader.read_to_end(&mut big).unwrap();
+        assert_eq!(big.len(), 200000);
+        assert_eq!(big[1000], (1000 % 251) as u8);
+        drop(reader);
+
+        // upload, completed by a flush or on drop
+        fs.mkdir(&start.join("new")).unwrap();
+        assert_eq!(fs.mkdir(&start.join("new")).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        {
+            let mut output = fs.open_write(&start.join("new/up.txt")).unwrap();
+            output.write_all(b"uploaded").unwrap();
+            output.flush().unwrap();
+        }
+        fs.open_write(&start.join("new/dropped.txt")).unwrap().write_all(b"abc").unwrap();
+        assert_eq!(fs.stat(&start.join("new/up.txt")).unwrap().size, 8);
+        assert_eq!(server.files.lock().unwrap().get("/pub/new/dropped.txt"), Some(&Some(b"abc".to_vec())));
+
+        // rename and delete
+        fs.rename(&start.join("new"), &start.join("renamed")).unwrap();
+        assert_eq!(names(&fs, &start.join("renamed")), vec!["dropped.txt", "up.txt"]);
+        assert!(fs.stat(&start.join("new")).is_err());
+        assert!(fs.remove(&start.join("renamed")).is_err());
+        fs.remove(&start.join("renamed/up.txt")).unwrap();
+        fs.remove(&start.join("renamed/dropped.txt")).unwrap();
+        fs.remove(&start.join("renamed")).unwrap();
+        assert!(!server.files.lock().unwrap().keys().any(|path| path.starts_with("/pub/renamed")));
+
+        // an idle connection the server dropped is replaced, once
+        let logins = server.logins.load(Ordering::SeqCst);
+        server.drop_next.store(true, Ordering::SeqCst);
+        assert_eq!(names(&fs, &start.join("sub")), vec!["big.bin"]);
+        assert_eq!(names(&fs, &start.join("sub")), vec!["big.bin"]);
+        assert_eq!(server.logins.load(Ordering::SeqCst), logins + 1);
+
+        let commands = server.commands.lock().unwrap();
+        let data_command = if !passive { "PORT " } else if epsv { "EPSV" } else { "PASV" };
+        assert!(commands.iter().any(|command| command.starts_with(data_command)), "{:?}", *commands);
+    }
+
+    #[test]
+    fn unix_listing_over_epsv()
+    { round_trip(Listing::Unix, true, true); }
+
+    #[test]
+    fn dos_listing_over_pasv()
+    { round_trip(Listing::Dos, false, true); }
+
+    #[test]
+    fn mlsd_over_port()
+    { round_trip(Listing::Mlsd, true, false); }
+
+    #[test]
+    fn unix_lines()
+    {
+        // 2023-11-14, so December without a year is last year's
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let entry = parse_list_line("-rw-r--r--    1 1000     1000         1234 Dec 24 18:00 xmas  file.txt", now).unwrap();
+        assert_eq!((entry.name.as_str(), entry.meta.size), ("xmas  file.txt", 1234));
+        assert_eq!(entry.meta.modified, Some(UNIX_EPOCH + Duration::from_secs(1_671_904_800)));
+        assert_eq!(entry.meta.mode, Some(0o644));
+        let entry = parse_list_line("-r--r--r-- 1 ftp 77 Nov 10 08:00 no group", now).unwrap();
+        assert_eq!((entry.name.as_str(), entry.meta.size, entry.meta.read_only), ("no group", 77, true));
+        let entry = parse_list_line("lrwxrwxrwx 1 root root 7 Jan  1  2020 bin -> usr/bin", now).unwrap();
+        assert_eq!(entry.name, "bin");
+        assert!(entry.meta.is_link);
+        // a name that looks like a month
+        let entry = parse_list_line("drwxr-xr-x 2 a b 4096 Mar 5 2021 Jan", now).unwrap();
+        assert_eq!((entry.name.as_str(), entry.meta.is_dir, entry.meta.size), ("Jan", true, 0));
+        assert!(parse_list_line("total 42", now).is_none());
+        assert!(parse_list_line("drwxr-xr-x 2 a b 4096 Mar 5 2021 ..", now).is_none());
+    }
+
+    #[test]
+    fn dos_lines()
+    {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let entry = parse_list_line("03-15-21  02:30PM       <DIR>          My Folder", now).unwrap();
+        assert_eq!((entry.name.as_str(), entry.meta.is_dir), ("My Folder", true));
+        assert_eq!(entry.meta.modified, Some(UNIX_EPOCH + Duration::from_secs(1_615_818_600)));
+        let entry = parse_list_line("12-01-2019  12:05AM             99 a.txt", now).unwrap();
+        assert_eq!((entry.name.as_str(), entry.meta.size), ("a.txt", 99));
+        assert_eq!(entry.meta.modified, Some(UNIX_EPOCH + Duration::from_secs(1_575_158_700)));
+    }
+
+    #[test]
+    fn mlsd_lines()
+    {
+        let entry = parse_mlsd_line("Type=file;Size=3;Modify=20200101000000.123;UNIX.mode=0640; a b").unwrap();
+        assert_eq!((entry.name.as_str(), entry.meta.size, entry.meta.mode), ("a b", 3, Some(0o640)));
+        assert_eq!(entry.meta.modified, Some(UNIX_EPOCH + Duration::from_secs(1_577_836_800)));
+        let entry = parse_mlsd_line("type=OS.unix=slink:/x;size=3; l").unwrap();
+        assert!(entry.meta.is_link);
+        assert!(parse_mlsd_line("type=dir;size=4096; d").unwrap().meta.is_dir);
+        assert!(parse_mlsd_line("type=cdir; .").is_none());
+        assert!(parse_mlsd_line("type=pdir; ..").is_none());
+    }
+
+    #[test]
+    fn passive_replies()
+    {
+        assert_eq!(parse_epsv("Entering Extended Passive Mode (|||6446|)"), Some(6446));
+        assert_eq!(parse_epsv("Entering Extended Passive Mode (6446)"), None);
+        assert_eq!(parse_pasv("Entering Passive Mode (192,168,1,2,19,137)").unwrap().to_string(), "192.168.1.2:5001");
+        assert_eq!(parse_pasv("=192,168,1,2,19,137").unwrap().port(), 5001);
+        assert_eq!(parse_pasv("Entering Passive Mode (192,168,1,2,19)"), None);
+    }
+}
@@ -0,0 +1,266 @@
+// Hex mode of the viewer: row formatting, offsets, and the byte cursor with
+// its selection. Rows look like
+// `00000010  48 65 6C 6C 6F 2C 20 77  6F 72 6C 64 0A 00 01 02  Hello, world....`
+
+use std::cmp;
+
+pub const ROW_BYTES: usize = 16;
+// extra space between the two halves of the hex column
+const GROUP_BYTES: usize = 8;
+
+// Hex digits needed for offsets in a file of `len` bytes, at least 8.
+pub fn offset_digits(len: u64) -> usize
+{
+    let mut digits = 8;
+    while digits < 16 && len > (1u64 << (4 * digits as u32)) - 1 {
+        digits += 1;
+    }
+    digits
+}
+
+fn ascii_char(byte: u8) -> char
+{ if byte >= 0x20 && byte < 0x7f { byte as char } else { '.' } }
+
+// Formats one row of at most `ROW_BYTES` bytes starting at `offset`. A short
+// last row is padded so its ASCII column lines up with the full rows.
+pub fn format_row(offset: u64, bytes: &[u8], digits: usize) -> String
+{
+    let bytes = &bytes[..cmp::min(bytes.len(), ROW_BYTES)];
+    let mut rv = format!("{:01$X}  ", offset, digits);
+    for idx in 0..ROW_BYTES {
+        if idx == GROUP_BYTES {
+            rv.push(' ');
+        }
+        match bytes.get(idx) {
+            Some(byte) => rv.push_str(&format!("{:02X} ", byte)),
+            None => rv.push_str("   "),
+        }
+    }
+    rv.push(' ');
+    rv.extend(bytes.iter().map(|b| ascii_char(*b)));
+    rv
+}
+
+// Character column where byte `idx` of a row starts in the hex part.
+pub fn hex_column(idx: usize, digits: usize) -> usize
+{ digits + 2 + idx * 3 + if idx >= GROUP_BYTES { 1 } else { 0 } }
+
+pub fn ascii_column(idx: usize, digits: usize) -> usize
+{ hex_column(ROW_BYTES, digits) + 1 + idx }
+
+// Byte of the row shown at character `column`, in either the hex or the ASCII part.
+pub fn byte_at_column(column: usize, digits: usize) -> Option<usize>
+{
+    if column >= ascii_column(0, digits) {
+        let idx = column - ascii_column(0, digits);
+        return if idx < ROW_BYTES { Some(idx) } else { None };
+    }
+    (0..ROW_BYTES).find(|idx| column >= hex_column(*idx, digits) && column < hex_column(*idx, digits) + 3)
+}
+
+// "48 65 6C" for copying a selection.
+pub fn hex_string(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+// Goto offset input: `0x1F`, `$1F` or `1Fh` are hexadecimal, plain digits decimal.
+pub fn parse_offset(input: &str) -> Option<u64>
+{
+    let input = input.trim();
+    let lower = input.to_lowercase();
+    if lower.starts_with("0x") {
+        u64::from_str_radix(&input[2..], 16).ok()
+    } else if lower.starts_with('$') {
+        u64::from_str_radix(&input[1..], 16).ok()
+    } else if lower.ends_with('h') {
+        u64::from_str_radix(&input[..input.len() - 1], 16).ok()
+    } else {
+        input.parse().ok()
+    }
+}
+
+// Byte cursor, selection and scroll position of the hex mode.
+pub struct HexView {
+    pub len: u64,
+    // offset of the first visible row, a multiple of ROW_BYTES
+    pub top: u64,
+    pub cursor: u64,
+    // the other end of the selection; the cursor byte is included in it
+    pub anchor: Option<u64>,
+}
+
+impl HexView {
+    pub fn new(len: u64) -> HexView
+    {
+        HexView { len: len, top: 0, cursor: 0, anchor: None }
+    }
+
+    fn last_row(&self) -> u64
+    { self.len.saturating_sub(1) / ROW_BYTES as u64 * ROW_BYTES as u64 }
+
+    // Inclusive start, exclusive end.
+    pub fn selection(&self) -> Option<(u64, u64)>
+    {
+        if self.len == 0 {
+            return None;
+        }
+        self.anchor.map(|anchor| (cmp::min(anchor, self.cursor), cmp::max(anchor, self.cursor) + 1))
+    }
+
+    pub fn is_selected(&self, offset: u64) -> bool
+    { self.selection().map_or(false, |(start, end)| offset >= start && offset < end) }
+
+    // Moves the cursor, extending the selection if `extend`, else dropping it.
+    pub fn set_cursor(&mut self, offset: u64, extend: bool)
+    {
+        if extend {
+            if self.anchor.is_none() {
+                self.anchor = Some(self.cursor);
+            }
+        } else {
+            self.anchor = None;
+        }
+        self.cursor = cmp::min(offset, self.len.saturating_sub(1));
+    }
+
+    pub fn move_cursor(&mut self, delta: i64, extend: bool)
+    {
+        let target = self.cursor as i64 + delta;
+        let target = if target < 0 { 0 } else { target as u64 };
+        self.set_cursor(target, extend);
+    }
+
+    // Adjusts `top` so the cursor row is within `rows` visible rows.
+    pub fn scroll_into_view(&mut self, rows: usize)
+    {
+        let row_bytes = ROW_BYTES as u64;
+        let page = cmp::max(rows, 1) as u64 * row_bytes;
+        let cursor_row = self.cursor / row_bytes * row_bytes;
+        if cursor_row < self.top {
+            self.top = cursor_row;
+        } else if cursor_row >= self.top + page {
+            self.top = cursor_row + row_bytes - page;
+        }
+    }
+
+    // Scrolls by whole rows without moving the cursor.
+    pub fn scroll(&mut self, rows: i64)
+    {
+        let top = self.top as i64 + rows * ROW_BYTES as i64;
+        let top = if top < 0 { 0 } else { top as u64 };
+        self.top = cmp::min(top, self.last_row());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The rows of `len` bytes counting up from 0x41 ('A'), as the viewer shows them.
+    fn dump(len: usize) -> Vec<String>
+    {
+        let data: Vec<u8> = (0..len).map(|idx| 0x41 + (idx % 64) as u8).collect();
+        data.chunks(ROW_BYTES)
+            .enumerate()
+            .map(|(row, bytes)| format_row((row * ROW_BYTES) as u64, bytes, offset_digits(len as u64)))
+            .collect()
+    }
+
+    // Checks every row of a dump of `len` bytes: the hex column padded out
+    // to full width, then one ASCII character for each byte in the row.
+    fn check_dump(len: usize)
+    {
+        let rows = dump(len);
+        assert_eq!(rows.len(), (len + ROW_BYTES - 1) / ROW_BYTES, "{} bytes", len);
+        let ascii = ascii_column(0, 8);
+        for (row, text) in rows.iter().enumerate() {
+            let count = cmp::min(ROW_BYTES, len - row * ROW_BYTES);
+            assert_eq!(&text[..10], &format!("{:08X}  ", row * ROW_BYTES)[..]);
+            assert_eq!(text.len(), ascii + count, "row {} of {} bytes", row, len);
+            for idx in 0..ROW_BYTES {
+                let cell = &text[hex_column(idx, 8)..hex_column(idx, 8) + 2];
+                if idx < count {
+                    assert_eq!(cell, format!("{:02X}", 0x41 + (row * ROW_BYTES + idx) % 64));
+                } else {
+                    assert_eq!(cell, "  ");
+                }
+            }
+            let expect: String = (0..count).map(|idx| ascii_char(0x41 + ((row * ROW_BYTES + idx) % 64) as u8)).collect();
+            assert_eq!(&text[ascii - 1..], &format!(" {}", expect)[..]);
+        }
+    }
+
+    #[test]
+    fn rows_of_any_length()
+    {
+        for &len in [0, 1, 15, 16, 17, ROW_BYTES * 3, ROW_BYTES * 3 + 5, ROW_BYTES * 7 + 15].iter() {
+            check_dump(len);
+        }
+        assert!(dump(0).is_empty());
+        assert_eq!(dump(1), vec![format!("00000000  41{}  A", " ".repeat(3 * ROW_BYTES - 2))]);
+        let seventeen = dump(17);
+        assert_eq!(seventeen[0], "00000000  41 42 43 44 45 46 47 48  49 4A 4B 4C 4D 4E 4F 50  ABCDEFGHIJKLMNOP");
+        assert_eq!(seventeen[1], format!("00000010  51{}  Q", " ".repeat(3 * ROW_BYTES - 2)));
+        let fifteen = dump(15);
+        assert_eq!(fifteen[0], "00000000  41 42 43 44 45 46 47 48  49 4A 4B 4C 4D 4E 4F     ABCDEFGHIJKLMNO");
+    }
+
+    #[test]
+    fn control_and_high_bytes_show_as_dots()
+    {
+        let row = format_row(0x10, b"Hello, world\n\x00\x7f\xff", 8);
+        assert_eq!(row, "00000010  48 65 6C 6C 6F 2C 20 77  6F 72 6C 64 0A 00 7F FF  Hello, world....");
+        // only ROW_BYTES are taken
+        assert_eq!(format_row(0, &[0x20; 40], 8).len(), ascii_column(ROW_BYTES, 8));
+    }
+
+    #[test]
+    fn columns()
+    {
+        assert_eq!(offset_digits(0), 8);
+        assert_eq!(offset_digits(0xffff_ffff), 8);
+        assert_eq!(offset_digits(0x1_0000_0000), 9);
+        assert_eq!(byte_at_column(hex_column(9, 8) + 1, 8), Some(9));
+        assert_eq!(byte_at_column(ascii_column(15, 8), 8), Some(15));
+        assert_eq!(byte_at_column(ascii_column(16, 8), 8), None);
+        // the gap between the halves
+        assert_eq!(byte_at_column(hex_column(8, 8) - 1, 8), None);
+        assert_eq!(hex_string(b"He\x00"), "48 65 00");
+        assert_eq!(hex_string(b""), "");
+    }
+
+    #[test]
+    fn offsets()
+    {
+        assert_eq!(parse_offset("0x1f"), Some(31));
+        assert_eq!(parse_offset("0X1F"), Some(31));
+        assert_eq!(parse_offset("$10"), Some(16));
+        assert_eq!(parse_offset("10h"), Some(16));
+        assert_eq!(parse_offset(" 10 "), Some(10));
+        assert_eq!(parse_offset("1f"), None);
+        assert_eq!(parse_offset("0x"), None);
+        assert_eq!(parse_offset(""), None);
+    }
+
+    #[test]
+    fn cursor_and_selection()
+    {
+        let mut view = HexView::new(100);
+        view.set_cursor(10, false);
+        view.move_cursor(-5, true);
+        assert_eq!(view.selection(), Some((5, 11)));
+        assert!(view.is_selected(10) && !view.is_selected(11));
+        view.move_cursor(-50, false);
+        assert_eq!((view.cursor, view.selection()), (0, None));
+        view.set_cursor(1000, false);
+        assert_eq!(view.cursor, 99);
+        view.scroll_into_view(2);
+        assert_eq!(view.top, 80);
+        view.scroll(10);
+        assert_eq!(view.top, 96);
+        view.scroll(-10);
+        assert_eq!(view.top, 0);
+        assert_eq!(HexView::new(0).selection(), None);
+    }
+}
@@ -0,0 +1,457 @@
+// Image mode of the viewer: decoding BMP, PNG and JPEG files into RGBA pixels,
+// and the zoom and pan of the picture in the window. BMP is read here; PNG
+// and JPEG go through the `png` and `jpeg-decoder` crates.
+
+extern crate png;
+extern crate jpeg_decoder;
+
+use std::cmp;
+use std::io;
+
+// larger images are refused rather than decoded into a few gigabytes
+pub const MAX_PIXELS: u64 = 1 << 26;
+pub const MIN_ZOOM: f64 = 1.0 / 32.0;
+pub const MAX_ZOOM: f64 = 32.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Bmp,
+    Png,
+    Jpeg,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            Format::Bmp => "BMP",
+            Format::Png => "PNG",
+            Format::Jpeg => "JPEG",
+        }
+    }
+}
+
+// Pixels are RGBA, row by row from the top.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+fn invalid(message: &str) -> io::Error
+{ io::Error::new(io::ErrorKind::InvalidData, message.to_string()) }
+
+// Recognizes an image by its first bytes.
+pub fn sniff(header: &[u8]) -> Option<Format>
+{
+    if header.starts_with(b"BM") && header.len() >= 18 {
+        Some(Format::Bmp)
+    } else if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(Format::Png)
+    } else if header.starts_with(b"\xff\xd8\xff") {
+        Some(Format::Jpeg)
+    } else {
+        None
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> io::Result<Image>
+{
+    match sniff(bytes) {
+        Some(Format::Bmp) => decode_bmp(bytes),
+        Some(Format::Png) => decode_png(bytes),
+        Some(Format::Jpeg) => decode_jpeg(bytes),
+        None => Err(invalid("Not a BMP, PNG or JPEG image")),
+    }
+}
+
+fn check_size(width: u64, height: u64) -> io::Result<()>
+{
+    if width == 0 || height == 0 {
+        return Err(invalid("The image is empty"));
+    }
+    if width * height > MAX_PIXELS {
+        return Err(invalid("The image is too large to show"));
+    }
+    Ok(())
+}
+
+fn u16_at(bytes: &[u8], pos: usize) -> io::Result<u16>
+{
+    match bytes.get(pos..pos + 2) {
+        Some(b) => Ok(b[0] as u16 | (b[1] as u16) << 8),
+        None => Err(invalid("The BMP file is truncated")),
+    }
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> io::Result<u32>
+{
+    Ok(try!(u16_at(bytes, pos)) as u32 | (try!(u16_at(bytes, pos + 2)) as u32) << 16)
+}
+
+// A BI_BITFIELDS channel: where it is in the pixel and how many bits it has.
+#[derive(Clone, Copy)]
+struct Channel {
+    shift: u32,
+    bits: u32,
+}
+
+impl Channel {
+    fn from_mask(mask: u32) -> Channel
+    {
+        if mask == 0 {
+            return Channel { shift: 0, bits: 0 };
+        }
+        let shift = mask.trailing_zeros();
+        Channel { shift: shift, bits: cmp::min((mask >> shift).trailing_ones(), 8) }
+    }
+
+    // The channel scaled to 0..=255, or `missing` if the mask was empty.
+    fn extract(&self, pixel: u32, missing: u8) -> u8
+    {
+        if self.bits == 0 {
+            return missing;
+        }
+        let max = (1u32 << self.bits) - 1;
+        ((pixel >> self.shift & max) * 255 / max) as u8
+    }
+}
+
+// Uncompressed and bitfield BMPs with 1, 4, 8, 16, 24 or 32 bits per pixel,
+// including OS/2 headers. RLE compression is not supported.
+pub fn decode_bmp(bytes: &[u8]) -> io::Result<Image>
+{
+    let data_offset = try!(u32_at(bytes, 10)) as usize;
+    let header_size = try!(u32_at(bytes, 14)) as usize;
+    let (width, height, bits, compression, colors_used) = if header_size == 12 {
+        (try!(u16_at(bytes, 18)) as i64, try!(u16_at(bytes, 20)) as i64, try!(u16_at(bytes, 24)), 0, 0)
+    } else if header_size >= 40 {
+        (try!(u32_at(bytes, 18)) as i32 as i64, try!(u32_at(bytes, 22)) as i32 as i64,
+         try!(u16_at(bytes, 28)), try!(u32_at(bytes, 30)), try!(u32_at(bytes, 46)))
+    } else {
+        return Err(invalid("Unknown BMP header"));
+    };
+    // rows are stored from the bottom unless the height is negative
+    let bottom_up = height > 0;
+    let (width, height) = (width.abs() as u64, height.abs() as u64);
+    try!(check_size(width, height));
+
+    const BI_RGB: u32 = 0;
+    const BI_BITFIELDS: u32 = 3;
+    const BI_ALPHABITFIELDS: u32 = 6;
+    let masks = match (compression, bits) {
+        (BI_RGB, 16) => Some([0x7c00, 0x03e0, 0x001f, 0]),
+        (BI_RGB, _) => None,
+        (BI_BITFIELDS, 16) | (BI_BITFIELDS, 32) | (BI_ALPHABITFIELDS, 16) | (BI_ALPHABITFIELDS, 32) => {
+            // right after a 40 byte header, or inside the larger ones
+            let pos = 14 + 40;
+            let alpha = if compression == BI_ALPHABITFIELDS || header_size >= 56 {
+                try!(u32_at(bytes, pos + 12))
+            } else { 0 };
+            Some([try!(u32_at(bytes, pos)), try!(u32_at(bytes, pos + 4)), try!(u32_at(bytes, pos + 8)), alpha])
+        },
+        _ => return Err(invalid("Unsupported BMP compression")),
+    };
+    let palette = if bits <= 8 {
+        let entry = if header_size == 12 { 3 } else { 4 };
+        let count = if colors_used == 0 { 1usize << bits } else { cmp::min(colors_used as usize, 256) };
+        let start = 14 + header_size;
+        let table = try!(bytes.get(start..start + count * entry).ok_or_else(|| invalid("The BMP file is truncated")));
+        table.chunks(entry).map(|c| [c[2], c[1], c[0]]).collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+    let channels = masks.map(|m| [Channel::from_mask(m[0]), Channel::from_mask(m[1]),
+                                  Channel::from_mask(m[2]), Channel::from_mask(m[3])]);
+
+    let (width, height) = (width as usize, height as usize);
+    let stride = (width * bits as usize + 31) / 32 * 4;
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let row = if bottom_up { height - 1 - y } else { y };
+        let start = data_offset + row * stride;
+        let line = try!(bytes.get(start..start + stride).ok_or_else(|| invalid("The BMP file is truncated")));
+        for x in 0..width {
+            let rgba = match bits {
+                1 | 4 | 8 => {
+                    let bit = x * bits as usize;
+                    let index = (line[bit / 8] >> (8 - bits as usize - bit % 8)) as usize & ((1 << bits) - 1);
+                    let c = try!(palette.get(index).ok_or_else(|| invalid("Bad BMP palette index")));
+                    [c[0], c[1], c[2], 255]
+                },
+                24 => [line[x * 3 + 2], line[x * 3 + 1], line[x * 3], 255],
+                16 | 32 => {
+                    let size = bits as usize / 8;
+                    let pixel = line[x * size..x * size + size].iter().rev().fold(0u32, |acc, b| acc << 8 | *b as u32);
+                    match channels {
+                        Some(ref c) => [c[0].extract(pixel, 0), c[1].extract(pixel, 0),
+                                        c[2].extract(pixel, 0), c[3].extract(pixel, 255)],
+                        // 32-bit BI_RGB: the fourth byte is unused
+                        None => [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8, 255],
+                    }
+                },
+                _ => return Err(invalid("Unsupported BMP pixel depth")),
+            };
+            pixels.extend_from_slice(&rgba);
+        }
+    }
+    Ok(Image { width: width as u32, height: height as u32, pixels: pixels })
+}
+
+pub fn decode_png(bytes: &[u8]) -> io::Result<Image>
+{
+    let to_io = |e: png::DecodingError| invalid(&format!("Cannot decode the PNG image: {}", e));
+    let mut decoder = png::Decoder::new(bytes);
+    // palettes, transparency chunks and low or 16-bit depths all become 8-bit channels
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = try!(decoder.read_info().map_err(&to_io));
+    {
+        let info = reader.info();
+        try!(check_size(info.width as u64, info.height as u64));
+    }
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = try!(reader.next_frame(&mut buffer).map_err(&to_io));
+    let count = frame.width as usize * frame.height as usize;
+    let mut pixels = Vec::with_capacity(count * 4);
+    for y in 0..frame.height as usize {
+        let line = &buffer[y * frame.line_size..(y + 1) * frame.line_size];
+        match frame.color_type {
+            png::ColorType::Rgba => pixels.extend_from_slice(&line[..frame.width as usize * 4]),
+            png::ColorType::Rgb => for c in line.chunks(3).take(frame.width as usize) {
+                pixels.extend_from_slice(&[c[0], c[1], c[2], 255]);
+            },
+            png::ColorType::GrayscaleAlpha => for c in line.chunks(2).take(frame.width as usize) {
+                pixels.extend_from_slice(&[c[0], c[0], c[0], c[1]]);
+            },
+            png::ColorType::Grayscale => for c in line.iter().take(frame.width as usize) {
+                pixels.extend_from_slice(&[*c, *c, *c, 255]);
+            },
+            png::ColorType::Indexed => return Err(invalid("Cannot decode the PNG palette")),
+        }
+    }
+    Ok(Image { width: frame.width, height: frame.height, pixels: pixels })
+}
+
+pub fn decode_jpeg(bytes: &[u8]) -> io::Result<Image>
+{
+    let to_io = |e: jpeg_decoder::Error| invalid(&format!("Cannot decode the JPEG image: {}", e));
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    try!(decoder.read_info().map_err(&to_io));
+    let info = try!(decoder.info().ok_or_else(|| invalid("Cannot decode the JPEG image")));
+    try!(check_size(info.width as u64, info.height as u64));
+    let data = try!(decoder.decode().map_err(&to_io));
+    let count = info.width as usize * info.height as usize;
+    let mut pixels = Vec::with_capacity(count * 4);
+    match info.pixel_format {
+        jpeg_decoder::PixelFormat::RGB24 => for c in data.chunks(3).take(count) {
+            pixels.extend_from_slice(&[c[0], c[1], c[2], 255]);
+        },
+        jpeg_decoder::PixelFormat::L8 => for c in data.iter().take(count) {
+            pixels.extend_from_slice(&[*c, *c, *c, 255]);
+        },
+        // big-endian samples; the high byte is enough for the screen
+        jpeg_decoder::PixelFormat::L16 => for c in data.chunks(2).take(count) {
+            pixels.extend_from_slice(&[c[0], c[0], c[0], 255]);
+        },
+        // the decoder gives inverted CMYK
+        jpeg_decoder::PixelFormat::CMYK32 => for c in data.chunks(4).take(count) {
+            let k = c[3] as u32;
+            let channel = |v: u8| (v as u32 * k / 255) as u8;
+            pixels.extend_from_slice(&[channel(c[0]), channel(c[1]), channel(c[2]), 255]);
+        },
+    }
+    if pixels.len() != count * 4 {
+        return Err(invalid("The JPEG image is truncated"));
+    }
+    Ok(Image { width: info.width as u32, height: info.height as u32, pixels: pixels })
+}
+
+impl Image {
+    // 32-bit BGRX pixels for GDI, with transparency blended onto `background` (RGB).
+    pub fn to_bgrx(&self, background: [u8; 3]) -> Vec<u8>
+    {
+        let blend = |c: u8, b: u8, a: u8| ((c as u32 * a as u32 + b as u32 * (255 - a as u32) + 127) / 255) as u8;
+        let mut rv = Vec::with_capacity(self.pixels.len());
+        for p in self.pixels.chunks(4) {
+            rv.extend_from_slice(&[blend(p[2], background[2], p[3]), blend(p[1], background[1], p[3]),
+                                   blend(p[0], background[0], p[3]), 0]);
+        }
+        rv
+    }
+}
+
+// Zoom and pan of an image in a viewport; all sizes are in screen pixels.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageView {
+    pub width: u32,
+    pub height: u32,
+    // None: fit into the viewport, never enlarging
+    pub zoom: Option<f64>,
+    // point of the image, in image pixels, shown at the center of the viewport
+    pub center: (f64, f64),
+}
+
+impl ImageView {
+    pub fn new(width: u32, height: u32) -> ImageView
+    {
+        ImageView { width: width, height: height, zoom: None, center: (width as f64 / 2.0, height as f64 / 2.0) }
+    }
+
+    pub fn scale(&self, viewport: (i32, i32)) -> f64
+    {
+        match self.zoom {
+            Some(zoom) => zoom,
+            None => {
+                let fit_x = cmp::max(viewport.0, 1) as f64 / self.width as f64;
+                let fit_y = cmp::max(viewport.1, 1) as f64 / self.height as f64;
+                fit_x.min(fit_y).min(1.0)
+            },
+        }
+    }
+
+    // Keeps the image covering as much of the viewport as it can; an image
+    // smaller than the viewport stays centered.
+    fn clamp(&mut self, viewport: (i32, i32))
+    {
+        let scale = self.scale(viewport);
+        let clamp_axis = |center: f64, size: u32, view: i32| {
+            let half = view as f64 / 2.0 / scale;
+            if size as f64 <= 2.0 * half {
+                size as f64 / 2.0
+            } else {
+                center.max(half).min(size as f64 - half)
+            }
+        };
+        self.center = (clamp_axis(self.center.0, self.width, viewport.0),
+                       clamp_axis(self.center.1, self.height, viewport.1));
+    }
+
+    // Where the whole image goes: left, top, width and height in the viewport.
+    pub fn placement(&self, viewport: (i32, i32)) -> (i32, i32, i32, i32)
+    {
+        let scale = self.scale(viewport);
+        let width = cmp::max((self.width as f64 * scale).round() as i32, 1);
+        let height = cmp::max((self.height as f64 * scale).round() as i32, 1);
+        let left = (viewport.0 as f64 / 2.0 - self.center.0 * scale).round() as i32;
+        let top = (viewport.1 as f64 / 2.0 - self.center.1 * scale).round() as i32;
+        (left, top, width, height)
+    }
+
+    // Multiplies the scale by `factor`, keeping the center where it is.
+    pub fn zoom_by(&mut self, factor: f64, viewport: (i32, i32))
+    {
+        let zoom = (self.scale(viewport) * factor).max(MIN_ZOOM).min(MAX_ZOOM);
+        self.set_zoom(Some(zoom), viewport);
+    }
+
+    pub fn set_zoom(&mut self, zoom: Option<f64>, viewport: (i32, i32))
+    {
+        self.zoom = zoom;
+        self.clamp(viewport);
+    }
+
+    // Moves the image by `dx`, `dy` screen pixels.
+    pub fn pan(&mut self, dx: i32, dy: i32, viewport: (i32, i32))
+    {
+        let scale = self.scale(viewport);
+        self.center = (self.center.0 - dx as f64 / scale, self.center.1 - dy as f64 / scale);
+        self.clamp(viewport);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small images in tests/images, with the pixels they hold as RGBA rows.
+    const BOTTOM_UP_24: &'static [u8] = include_bytes!("../tests/images/bottom_up_24.bmp");
+    const TOP_DOWN_8: &'static [u8] = include_bytes!("../tests/images/top_down_8.bmp");
+    const ALPHA_32: &'static [u8] = include_bytes!("../tests/images/alpha_32.bmp");
+    const RGBA_PNG: &'static [u8] = include_bytes!("../tests/images/rgba.png");
+    const INDEXED_PNG: &'static [u8] = include_bytes!("../tests/images/indexed.png");
+    const TWO_COLORS_JPG: &'static [u8] = include_bytes!("../tests/images/two_colors.jpg");
+    const GRAY_JPG: &'static [u8] = include_bytes!("../tests/images/gray.jpg");
+
+    fn decoded(bytes: &[u8], width: u32, height: u32) -> Vec<u8>
+    {
+        let image = decode(bytes).unwrap();
+        assert_eq!((image.width, image.height), (width, height));
+        assert_eq!(image.pixels.len(), (width * height * 4) as usize);
+        image.pixels
+    }
+
+    // JPEG is lossy: every pixel within a few steps of the expected color.
+    fn assert_close(pixels: &[u8], expect: &Fn(usize) -> [u8; 3])
+    {
+        for (idx, pixel) in pixels.chunks(4).enumerate() {
+            let color = expect(idx);
+            for channel in 0..3 {
+                assert!((pixel[channel] as i32 - color[channel] as i32).abs() <= 8,
+                        "pixel {}: {:?} for {:?}", idx, pixel, color);
+            }
+            assert_eq!(pixel[3], 255);
+        }
+    }
+
+    #[test]
+    fn bottom_up_bmp()
+    {
+        assert_eq!(sniff(BOTTOM_UP_24), Some(Format::Bmp));
+        assert_eq!(decoded(BOTTOM_UP_24, 3, 2), vec![
+            3, 2, 1, 255, 6, 5, 4, 255, 9, 8, 7, 255,
+            0, 0, 255, 255, 0, 255, 0, 255, 255, 0, 0, 255,
+        ]);
+    }
+
+    #[test]
+    fn top_down_bmps()
+    {
+        assert_eq!(decoded(TOP_DOWN_8, 3, 2), vec![
+            0, 0, 0, 255, 30, 20, 10, 255, 255, 255, 255, 255,
+            128, 0, 0, 255, 255, 255, 255, 255, 30, 20, 10, 255,
+        ]);
+        assert_eq!(decoded(ALPHA_32, 2, 1), vec![1, 2, 3, 128, 255, 0, 0, 0]);
+    }
+
+    #[test]
+    fn truncated_bmp()
+    {
+        assert!(decode(&BOTTOM_UP_24[..BOTTOM_UP_24.len() - 4]).is_err());
+        assert!(decode(&BOTTOM_UP_24[..20]).is_err());
+    }
+
+    #[test]
+    fn png()
+    {
+        assert_eq!(sniff(RGBA_PNG), Some(Format::Png));
+        assert_eq!(decoded(RGBA_PNG, 3, 2), vec![
+            255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255,
+            0, 0, 0, 0, 255, 255, 255, 128, 10, 20, 30, 40,
+        ]);
+        // palette entries with and without transparency
+        assert_eq!(decoded(INDEXED_PNG, 3, 1), vec![4, 5, 6, 255, 1, 2, 3, 0, 4, 5, 6, 255]);
+        assert!(decode(&RGBA_PNG[..RGBA_PNG.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn jpeg()
+    {
+        assert_eq!(sniff(TWO_COLORS_JPG), Some(Format::Jpeg));
+        // left half red, right half blue
+        let pixels = decoded(TWO_COLORS_JPG, 16, 8);
+        assert_close(&pixels, &|idx| if idx % 16 < 8 { [200, 30, 30] } else { [20, 40, 220] });
+        // top half dark, bottom half light
+        let pixels = decoded(GRAY_JPG, 8, 8);
+        assert_close(&pixels, &|idx| if idx < 32 { [50; 3] } else { [200; 3] });
+        assert!(decode(&TWO_COLORS_JPG[..TWO_COLORS_JPG.len() / 3]).is_err());
+        assert!(decode(b"hello").is_err());
+    }
+
+    #[test]
+    fn transparency_is_blended()
+    {
+        let image = Image { width: 2, height: 1, pixels: vec![255, 0, 0, 128, 0, 255, 0, 255] };
+        assert_eq!(image.to_bgrx([0, 0, 255]), vec![127, 0, 128, 0, 0, 255, 0, 0]);
+    }
+}
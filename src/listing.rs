@@ -0,0 +1,517 @@
+// Directory listing shown by a file panel: entries of the current directory,
+// their selection, the cursor and the scroll position. Directories are read
+// through a `Vfs`, the local disk unless the panel shows something else.
+
+use std::cmp::Ordering;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use ::archive;
+use ::find_files::Found;
+use ::vfs::{LocalFs, Vfs};
+
+pub const PARENT_NAME: &'static str = "..";
+
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub selected: bool,
+    // hidden or system attribute on Windows, dot-file elsewhere
+    pub hidden: bool,
+    // UNIX permission bits, where the file system has them
+    pub mode: Option<u32>,
+    // where the entry is when that is not in the listed directory, as with
+    // search results
+    pub path: Option<PathBuf>,
+}
+
+impl Entry {
+    pub fn is_parent(&self) -> bool
+    { self.is_dir && self.name == PARENT_NAME }
+}
+
+// Numbers for the panel status line; directories are not counted.
+#[derive(Clone, Copy, Default)]
+pub struct SelectionSummary {
+    pub selected_files: usize,
+    pub total_files: usize,
+    pub selected_bytes: u64,
+    pub total_bytes: u64,
+}
+
+pub struct Listing {
+    // the file system `path` is in and, for an archive, the ones it was
+    // opened from, innermost last
+    pub vfs: Arc<Vfs>,
+    outer: Vec<Arc<Vfs>>,
+    pub path: PathBuf,
+    pub entries: Vec<Entry>,
+    pub cursor: usize,
+    pub top: usize,
+    pub show_hidden: bool,
+    // Find Files results fed to the panel: the entries carry their own paths,
+    // `path` is the directory searched and going up returns there
+    pub search_results: bool,
+}
+
+fn compare_entries(a: &Entry, b: &Entry) -> Ordering
+{
+    b.is_parent().cmp(&a.is_parent())
+        .then(b.is_dir.cmp(&a.is_dir))
+        .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        .then_with(|| a.name.cmp(&b.name))
+}
+
+fn parent_entry() -> Entry
+{
+    Entry {
+        name: PARENT_NAME.to_string(),
+        is_dir: true,
+        size: 0,
+        modified: None,
+        selected: false,
+        hidden: false,
+        mode: None,
+        path: None,
+    }
+}
+
+fn read_entries(vfs: &Vfs, path: &Path, show_hidden: bool) -> io::Result<Vec<Entry>>
+{
+    let mut entries = Vec::new();
+    if path.parent().is_some() {
+        entries.push(parent_entry());
+    }
+    for dir_entry in try!(vfs.list(path)) {
+        if dir_entry.meta.hidden && !show_hidden {
+            continue;
+        }
+        entries.push(Entry {
+            name: dir_entry.name,
+            is_dir: dir_entry.meta.is_dir,
+            size: dir_entry.meta.size,
+            modified: dir_entry.meta.modified,
+            selected: false,
+            hidden: dir_entry.meta.hidden,
+            mode: dir_entry.meta.mode,
+            path: None,
+        });
+    }
+    entries.sort_by(compare_entries);
+    Ok(entries)
+}
+
+impl Listing {
+    pub fn new() -> Listing
+    {
+        Listing {
+            vfs: Arc::new(LocalFs),
+            outer: Vec::new(),
+            path: PathBuf::new(),
+            entries: Vec::new(),
+            cursor: 0,
+            top: 0,
+            show_hidden: false,
+            search_results: false,
+        }
+    }
+
+    // The file system `path` is in, with the ones below it: the current one
+    // unless `path` is outside the archives shown.
+    fn file_systems_for(&self, path: &Path) -> (Arc<Vfs>, Vec<Arc<Vfs>>)
+    {
+        let (mut vfs, mut outer) = (self.vfs.clone(), self.outer.clone());
+        while let Some(mount_point) = vfs.mount_point() {
+            if path.starts_with(&mount_point) {
+                break;
+            }
+            match outer.pop() {
+                Some(below) => vfs = below,
+                // leaving a file system shown on its own, like a plugin's
+                None => { vfs = Arc::new(LocalFs); break; },
+            }
+        }
+        (vfs, outer)
+    }
+
+    pub fn vfs_for(&self, path: &Path) -> Arc<Vfs>
+    { self.file_systems_for(path).0 }
+
+    // Switches to `path`, leaving archives `path` is not in; on error the
+    // current listing stays intact.
+    pub fn navigate(&mut self, path: &Path) -> io::Result<()>
+    {
+        let (vfs, outer) = self.file_systems_for(path);
+        let entries = try!(read_entries(&*vfs, path, self.show_hidden));
+        self.vfs = vfs;
+        self.outer = outer;
+        self.path = path.to_path_buf();
+        self.entries = entries;
+        self.cursor = 0;
+        self.top = 0;
+        self.search_results = false;
+        Ok(())
+    }
+
+    // Switches to `path` in another file system.
+    pub fn set_vfs(&mut self, vfs: Arc<Vfs>, path: &Path) -> io::Result<()>
+    {
+        let entries = try!(read_entries(&*vfs, path, self.show_hidden));
+        self.vfs = vfs;
+        self.outer.clear();
+        self.path = path.to_path_buf();
+        self.entries = entries;
+        self.cursor = 0;
+        self.top = 0;
+        self.search_results = false;
+        Ok(())
+    }
+
+    // Shows the root of `vfs`, an archive opened from the current file system.
+    pub fn mount(&mut self, vfs: Arc<Vfs>) -> io::Result<()>
+    {
+        let root = try!(vfs.mount_point().ok_or_else(||
+            io::Error::new(io::ErrorKind::InvalidInput, "Not an archive")));
+        let entries = try!(read_entries(&*vfs, &root, self.show_hidden));
+        self.outer.push(self.vfs.clone());
+        self.vfs = vfs;
+        self.path = root;
+        self.entries = entries;
+        self.cursor = 0;
+        self.top = 0;
+        self.search_results = false;
+        Ok(())
+    }
+
+    // Shows what Find Files found in place of a directory. Entries under
+    // `root` are named by their path relative to it, others by their full path.
+    pub fn show_found(&mut self, root: &Path, found: &[Found])
+    {
+        let mut entries = vec![parent_entry()];
+        for item in found {
+            let name = item.path.strip_prefix(root).unwrap_or(&item.path);
+            entries.push(Entry {
+                name: name.to_string_lossy().into_owned(),
+                is_dir: item.is_dir,
+                size: item.size,
+                modified: item.modified,
+                selected: false,
+                hidden: item.hidden,
+                mode: None,
+                path: Some(item.path.clone()),
+            });
+        }
+        entries.sort_by(compare_entries);
+        // Find Files searches the disk
+        self.vfs = Arc::new(LocalFs);
+        self.outer.clear();
+        self.path = root.to_path_buf();
+        self.entries = entries;
+        self.cursor = 0;
+        self.top = 0;
+        self.search_results = true;
+    }
+
+    // Search results are looked up again one by one; those gone are dropped.
+    fn refresh_found(&mut self)
+    {
+        let vfs = self.vfs.clone();
+        self.entries.retain(|entry| entry.path.as_ref().map_or(true, |p| vfs.stat(p).is_ok()));
+        for entry in self.entries.iter_mut() {
+            if let Some(meta) = entry.path.as_ref().and_then(|p| vfs.stat(p).ok()) {
+                entry.size = meta.size;
+                entry.modified = meta.modified;
+            }
+        }
+        let cursor = self.cursor;
+        self.set_cursor(cursor);
+    }
+
+    // Re-reads the current directory keeping the cursor and the selection
+    // on the entries that survived.
+    pub fn refresh(&mut self) -> io::Result<()>
+    {
+        if self.search_results {
+            self.refresh_found();
+            return Ok(());
+        }
+        let focused = self.focused().map(|e| e.name.clone());
+        let selected: Vec<String> = self.entries.iter()
+            .filter(|e| e.selected)
+            .map(|e| e.name.clone())
+            .collect();
+        let cursor = self.cursor;
+        let top = self.top;
+        let path = self.path.clone();
+        try!(self.navigate(&path));
+        self.top = top;
+        for entry in self.entries.iter_mut() {
+            entry.selected = selected.contains(&entry.name);
+        }
+        let found = focused.map_or(false, |name| self.select_name(&name));
+        if !found {
+            self.set_cursor(cursor);
+        }
+        Ok(())
+    }
+
+    pub fn set_show_hidden(&mut self, show: bool) -> io::Result<()>
+    {
+        if self.show_hidden == show {
+            return Ok(());
+        }
+        self.show_hidden = show;
+        self.refresh()
+    }
+
+    pub fn focused(&self) -> Option<&Entry>
+    { self.entries.get(self.cursor) }
+
+    pub fn entry_path(&self, entry: &Entry) -> PathBuf
+    { entry.path.clone().unwrap_or_else(|| self.path.join(&entry.name)) }
+
+    // What a file operation works on: the selected entries, or else the
+    // focused one.
+    pub fn chosen_paths(&self) -> Vec<PathBuf>
+    {
+        let selected: Vec<PathBuf> = self.entries.iter()
+            .filter(|e| e.selected)
+            .map(|e| self.entry_path(e))
+            .collect();
+        match self.focused() {
+            _ if !selected.is_empty() => selected,
+            Some(entry) if !entry.is_parent() => vec![self.entry_path(entry)],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn toggle_selection(&mut self)
+    {
+        if let Some(entry) = self.entries.get_mut(self.cursor) {
+            if !entry.is_parent() {
+                entry.selected = !entry.selected;
+            }
+        }
+    }
+
+    // Selects the entries named and clears the rest.
+    pub fn select_names(&mut self, names: &[String])
+    {
+        for entry in self.entries.iter_mut() {
+            entry.selected = !entry.is_parent() && names.contains(&entry.name);
+        }
+    }
+
+    pub fn selection_summary(&self) -> SelectionSummary
+    {
+        let mut summary = SelectionSummary::default();
+        for entry in self.entries.iter().filter(|e| !e.is_dir) {
+            summary.total_files += 1;
+            summary.total_bytes += entry.size;
+            if entry.selected {
+                summary.selected_files += 1;
+                summary.selected_bytes += entry.size;
+            }
+        }
+        summary
+    }
+
+    pub fn set_cursor(&mut self, index: usize)
+    {
+        let last = self.entries.len().saturating_sub(1);
+        self.cursor = ::std::cmp::min(index, last);
+    }
+
+    pub fn move_cursor(&mut self, delta: isize)
+    {
+        let target = self.cursor as isize + delta;
+        self.set_cursor(if target < 0 { 0 } else { target as usize });
+    }
+
+    pub fn select_name(&mut self, name: &str) -> bool
+    {
+        match self.entries.iter().position(|e| e.name == name) {
+            Some(idx) => { self.cursor = idx; true },
+            None => false,
+        }
+    }
+
+    // Adjusts `top` so the cursor is within `rows` visible lines.
+    pub fn scroll_into_view(&mut self, rows: usize)
+    {
+        let rows = ::std::cmp::max(rows, 1);
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + rows {
+            self.top = self.cursor + 1 - rows;
+        }
+    }
+
+    // Goes one level up, leaving the cursor on the directory we came from.
+    // From search results it goes back to the directory searched.
+    pub fn go_up(&mut self) -> io::Result<()>
+    {
+        if self.search_results {
+            let path = self.path.clone();
+            return self.navigate(&path);
+        }
+        let (parent, came_from) = match (self.path.parent(), self.path.file_name()) {
+            (Some(parent), Some(name)) => (parent.to_path_buf(), name.to_string_lossy().into_owned()),
+            _ => return Ok(()),
+        };
+        try!(self.navigate(&parent));
+        self.select_name(&came_from);
+        Ok(())
+    }
+
+    // Enters the focused directory or archive. Returns false when the focused
+    // entry is neither.
+    pub fn enter(&mut self) -> io::Result<bool>
+    {
+        let target = match self.focused() {
+            Some(e) if e.is_parent() => None,
+            Some(e) if e.is_dir => Some(self.entry_path(e)),
+            Some(e) if archive::is_archive(&e.name) => {
+                let archive = try!(archive::open(&*self.vfs, &self.entry_path(e)));
+                try!(self.mount(archive));
+                return Ok(true);
+            },
+            _ => return Ok(false),
+        };
+        match target {
+            Some(path) => try!(self.navigate(&path)),
+            None => try!(self.go_up()),
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::vfs::{DirEntry, MemoryFs, Metadata};
+    use std::io::{Read, Write};
+
+    // A MemoryFs shown inside another file system at `mount_point`, as an
+    // archive is.
+    struct Mounted(MemoryFs, PathBuf);
+
+    impl Vfs for Mounted {
+        fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>>
+        { self.0.list(dir) }
+        fn stat(&self, path: &Path) -> io::Result<Metadata>
+        { self.0.stat(path) }
+        fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>>
+        { self.0.open_read(path) }
+        fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>
+        { self.0.open_write(path) }
+        fn mkdir(&self, path: &Path) -> io::Result<()>
+        { self.0.mkdir(path) }
+        fn remove(&self, path: &Path) -> io::Result<()>
+        { self.0.remove(path) }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()>
+        { self.0.rename(from, to) }
+        fn mount_point(&self) -> Option<PathBuf>
+        { Some(self.1.clone()) }
+    }
+
+    fn memory() -> Arc<Vfs>
+    {
+        let fs = MemoryFs::new();
+        fs.add_file(Path::new("/data/b.txt"), b"bb");
+        fs.add_file(Path::new("/data/A.txt"), b"a");
+        fs.add_file(Path::new("/data/.hidden"), b"h");
+        fs.add_file(Path::new("/data/sub/c.txt"), b"ccc");
+        fs.add_file(Path::new("/data/pack.zip"), b"not read");
+        Arc::new(fs)
+    }
+
+    fn archive() -> Arc<Vfs>
+    {
+        let fs = MemoryFs::new();
+        fs.add_file(Path::new("/data/pack.zip/inner/x.txt"), b"x");
+        fs.add_file(Path::new("/data/pack.zip/top.txt"), b"top");
+        Arc::new(Mounted(fs, PathBuf::from("/data/pack.zip")))
+    }
+
+    fn names(listing: &Listing) -> Vec<&str>
+    { listing.entries.iter().map(|e| e.name.as_str()).collect() }
+
+    #[test]
+    fn navigate_sorts_and_hides()
+    {
+        let mut listing = Listing::new();
+        listing.set_vfs(memory(), Path::new("/data")).unwrap();
+        assert_eq!(names(&listing), vec!["..", "sub", "A.txt", "b.txt", "pack.zip"]);
+        listing.set_show_hidden(true).unwrap();
+        assert_eq!(names(&listing), vec!["..", "sub", ".hidden", "A.txt", "b.txt", "pack.zip"]);
+        listing.select_names(&["b.txt".to_string(), "A.txt".to_string(), "..".to_string()]);
+        let summary = listing.selection_summary();
+        assert_eq!((summary.selected_files, summary.total_files, summary.selected_bytes), (2, 4, 3));
+
+        // a directory that is not there keeps the listing as it was
+        assert!(listing.navigate(Path::new("/data/nope")).is_err());
+        assert_eq!(listing.path, Path::new("/data"));
+        assert_eq!(listing.chosen_paths(), vec![PathBuf::from("/data/A.txt"), PathBuf::from("/data/b.txt")]);
+
+        listing.select_name("sub");
+        assert!(listing.enter().unwrap());
+        assert_eq!(listing.path, Path::new("/data/sub"));
+        assert_eq!(names(&listing), vec!["..", "c.txt"]);
+        listing.set_cursor(1);
+        assert!(!listing.enter().unwrap());
+    }
+
+    #[test]
+    fn go_up_returns_to_where_it_came_from()
+    {
+        let mut listing = Listing::new();
+        listing.set_vfs(memory(), Path::new("/data/sub")).unwrap();
+        listing.go_up().unwrap();
+        assert_eq!(listing.path, Path::new("/data"));
+        assert_eq!(listing.focused().unwrap().name, "sub");
+        listing.go_up().unwrap();
+        assert_eq!(listing.path, Path::new("/"));
+        assert_eq!(listing.focused().unwrap().name, "data");
+        // the root has no parent entry and nowhere to go
+        assert_eq!(names(&listing), vec!["data"]);
+        listing.go_up().unwrap();
+        assert_eq!(listing.path, Path::new("/"));
+    }
+
+    #[test]
+    fn mounted_file_systems_are_left_on_the_way_up()
+    {
+        let mut listing = Listing::new();
+        let outer = memory();
+        listing.set_vfs(outer.clone(), Path::new("/data")).unwrap();
+        assert!(listing.mount(outer.clone()).is_err());
+        listing.mount(archive()).unwrap();
+        assert_eq!(listing.path, Path::new("/data/pack.zip"));
+        assert_eq!(names(&listing), vec!["..", "inner", "top.txt"]);
+        listing.select_name("inner");
+        listing.enter().unwrap();
+        assert_eq!(names(&listing), vec!["..", "x.txt"]);
+        assert!(listing.vfs.mount_point().is_some());
+
+        listing.go_up().unwrap();
+        assert_eq!(listing.path, Path::new("/data/pack.zip"));
+        assert!(listing.vfs.mount_point().is_some());
+        // out of the archive, back in the file system it was opened from
+        listing.go_up().unwrap();
+        assert_eq!(listing.path, Path::new("/data"));
+        assert!(listing.vfs.mount_point().is_none());
+        assert_eq!(listing.focused().unwrap().name, "pack.zip");
+        assert_eq!(&*listing.vfs as *const Vfs as *const u8, &*outer as *const Vfs as *const u8);
+
+        // navigating away from inside the archive leaves it as well
+        listing.mount(archive()).unwrap();
+        listing.navigate(Path::new("/data/sub")).unwrap();
+        assert!(listing.vfs.mount_point().is_none());
+        assert_eq!(names(&listing), vec!["..", "c.txt"]);
+    }
+}
@@ -6,6 +6,40 @@ use winapi::*;
 mod win_layer;
 mod win_gdi;
 mod messages;
+mod commands;
+mod listing;
+mod roots;
+mod breadcrumb;
+mod size_format;
+mod settings;
+mod file_ops;
+mod command_template;
+mod mask;
+mod associations;
+mod encoding;
+mod pager;
+mod hexdump;
+mod search;
+mod image;
+mod quick_view;
+mod find_files;
+mod viewer;
+mod finder;
+mod operation;
+mod vfs;
+mod zip;
+mod tar;
+mod plugins;
+mod sites;
+#[macro_use]
+mod remote;
+mod ftp;
+mod sftp;
+mod webdav;
+mod compare;
+mod sync_dirs;
+mod syncer;
+mod archive;
 use win_layer::*;
 
 #[no_mangle]
@@ -54,8 +88,11 @@ fn our_main(instance: HINSTANCE) -> Result<(), u32>
     */
 
     for rmsg in blocking_msg_loop(None) {
-        let msg = try!(rmsg);
+        let mut msg = try!(rmsg);
         //println!("msg, 0x{:04x}", msg.message);
+        if win_gdi::dialog_message(&mut msg) {
+            continue;
+        }
         TranslateMessage(&msg);
         DispatchMessageW(&msg);
     }
@@ -0,0 +1,46 @@
+// File name masks as typed by users: `*.txt`, `report-??.doc`, or several of
+// them separated by `;`. Matching ignores case, as Windows file names do.
+
+// Whether `name` matches `mask`, where `*` stands for any run of characters and
+// `?` for exactly one.
+pub fn matches(mask: &str, name: &str) -> bool
+{
+    let mask: Vec<char> = mask.chars().flat_map(|c| c.to_lowercase()).collect();
+    let name: Vec<char> = name.chars().flat_map(|c| c.to_lowercase()).collect();
+    let (mut m, mut n) = (0, 0);
+    // position after the last `*` and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match mask.get(m) {
+            Some(&'*') => {
+                m += 1;
+                backtrack = Some((m, n));
+            },
+            Some(&c) if c == '?' || c == name[n] => {
+                m += 1;
+                n += 1;
+            },
+            _ => match backtrack {
+                // let the last `*` take one more character
+                Some((star_m, star_n)) => {
+                    m = star_m;
+                    n = star_n + 1;
+                    backtrack = Some((star_m, star_n + 1));
+                },
+                None => return false,
+            },
+        }
+    }
+    mask[m..].iter().all(|c| *c == '*')
+}
+
+// Splits `*.txt; *.log` into its masks.
+pub fn split(masks: &str) -> Vec<&str>
+{
+    masks.split(';').map(|m| m.trim()).filter(|m| !m.is_empty()).collect()
+}
+
+pub fn matches_any(masks: &str, name: &str) -> bool
+{
+    split(masks).iter().any(|mask| matches(mask, name))
+}
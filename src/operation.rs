@@ -0,0 +1,206 @@
+// Progress window of a file operation (copy, delete). The operation runs on a
+// worker thread, reporting through `file_ops::Progress`; the window shows the
+// file being worked on and a progress bar, and Cancel or Esc stops the work
+// at the next file or chunk. When it is over the window closes and sends
+// CM_OPERATION_DONE to its owner so the panels are read again.
+
+extern crate winapi;
+extern crate std;
+
+use ::commands;
+use ::file_ops::Progress;
+use ::size_format::{SizeFormat, format_size};
+use win_gdi::{WinCls, rcrc, text_out, invalidate, show_error, create_control, add_dialog, remove_dialog};
+use winapi::*;
+use win_layer::*;
+use std::any::Any;
+use std::cell::RefCell;
+use std::cmp;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+const WM_OPERATION_UPDATE: UINT = WM_APP + 1;
+const WIDTH: c_int = 480;
+const HEIGHT: c_int = 170;
+const BAR_TOP: c_int = 56;
+const BAR_HEIGHT: c_int = 18;
+
+pub type Work = Box<FnOnce(&AtomicBool, &mut FnMut(&Progress)) -> io::Result<Progress> + Send>;
+
+// The latest progress and, once the work is over, its result.
+struct Shared {
+    progress: Progress,
+    done: Option<io::Result<Progress>>,
+}
+
+pub struct OperationCls {
+    owner: HWND,
+    title: String,
+    cancel: Arc<AtomicBool>,
+    // a WM_OPERATION_UPDATE is on its way
+    posted: Arc<AtomicBool>,
+    shared: Arc<Mutex<Shared>>,
+    progress: RefCell<Progress>,
+}
+
+// Runs `work` on a worker thread with a progress window over `owner`.
+pub fn start(owner: HWND, title: &str, work: Work) -> Result<HWND, u32>
+{
+    let inst = OperationCls {
+        owner: owner,
+        title: title.to_string(),
+        cancel: Arc::new(AtomicBool::new(false)),
+        posted: Arc::new(AtomicBool::new(false)),
+        shared: Arc::new(Mutex::new(Shared { progress: Progress::default(), done: None })),
+        progress: RefCell::new(Progress::default()),
+    };
+    let (cancel, posted, shared) = (inst.cancel.clone(), inst.posted.clone(), inst.shared.clone());
+    let cls_id = try!(OperationCls::get_cls_id());
+    let inst_ptr = Box::into_raw(Box::new(rcrc(inst)) as Box<Any>);
+    let owner_rect = try!(GetWindowRect(owner));
+    let x = (owner_rect.left + owner_rect.right - WIDTH) / 2;
+    let y = (owner_rect.top + owner_rect.bottom - HEIGHT) / 2;
+    let instance = try!(GetModuleHandleW(None));
+    let hwnd = try!(CreateWindowExW(
+        WS_EX_DLGMODALFRAME,
+        cls_id,
+        Some(&wstr(title)),
+        WS_POPUP | WS_CAPTION | WS_SYSMENU,
+        x, y, WIDTH, HEIGHT,
+        Some(owner), None, instance, Some(inst_ptr as LPVOID)));
+    let client = try!(GetClientRect(hwnd));
+    let cancel_button = try!(create_control(
+        hwnd, "BUTTON", "Cancel", WS_TABSTOP | BS_DEFPUSHBUTTON,
+        ((client.right - 90) / 2, client.bottom - 34, 90, 24), IDCANCEL));
+    add_dialog(hwnd);
+    let _ = ShowWindow(hwnd, SW_SHOW);
+    let _ = SetFocus(cancel_button);
+
+    // HWND is not Send
+    let target = hwnd as usize;
+    thread::spawn(move || {
+        let rv = work(&cancel, &mut |progress| {
+            shared.lock().unwrap().progress = progress.clone();
+            if !posted.swap(true, Ordering::SeqCst) {
+                let _ = PostMessageW(target as HWND, WM_OPERATION_UPDATE, 0, 0);
+            }
+        });
+        shared.lock().unwrap().done = Some(rv);
+        let _ = PostMessageW(target as HWND, WM_OPERATION_UPDATE, 0, 0);
+    });
+    Ok(hwnd)
+}
+
+impl OperationCls {
+    fn on_update(&self, hwnd: HWND)
+    {
+        self.posted.store(false, Ordering::SeqCst);
+        let done = {
+            let mut shared = self.shared.lock().unwrap();
+            *self.progress.borrow_mut() = shared.progress.clone();
+            shared.done.take()
+        };
+        let done = match done {
+            Some(done) => done,
+            None => { let _ = invalidate(hwnd); return; },
+        };
+        let _ = DestroyWindow(hwnd);
+        if let Err(e) = done {
+            show_error(self.owner, &self.title, &e);
+        }
+        SendMessageW(self.owner, WM_COMMAND, commands::CM_OPERATION_DONE as WPARAM, hwnd as LPARAM);
+    }
+
+    fn paint(&self, hdc: HDC, rect: &RECT) -> Result<(), u32>
+    {
+        let progress = self.progress.borrow();
+        let cancelling = self.cancel.load(Ordering::Relaxed);
+        let current = if cancelling { "Cancelling...".to_string() } else { progress.current.to_string_lossy().into_owned() };
+        try!(text_out(hdc, 10, 10, &current));
+        let counts = format!(
+            "{} of {} files, {} of {}",
+            progress.files_done, progress.files_total,
+            format_size(progress.bytes_done, SizeFormat::Binary),
+            format_size(progress.bytes_total, SizeFormat::Binary));
+        try!(text_out(hdc, 10, 30, &counts));
+        let bar = RECT { left: 10, right: rect.right - 10, top: BAR_TOP, bottom: BAR_TOP + BAR_HEIGHT };
+        try!(FillRect(hdc, &bar, (COLOR_WINDOW + 1) as HBRUSH));
+        // bytes, or files when there are no bytes to speak of
+        let (done, total) = if progress.bytes_total > 0 { (progress.bytes_done, progress.bytes_total) }
+                            else { (progress.files_done, progress.files_total) };
+        if total > 0 {
+            let width = (bar.right - bar.left) as u64 * cmp::min(done, total) / total;
+            let filled = RECT { right: bar.left + width as c_int, .. bar };
+            try!(FillRect(hdc, &filled, (COLOR_HIGHLIGHT + 1) as HBRUSH));
+        }
+        Ok(())
+    }
+}
+
+impl WinCls for OperationCls {
+    fn wnd_proc(
+        &self,
+        hwnd: HWND, msg: UINT,
+        param: WPARAM, _para: LPARAM)
+        -> Option<LRESULT>
+    {
+        match msg {
+            WM_PAINT => {
+                let rv = BeginPaint(hwnd).and_then(|(ps, hdc)| {
+                    let rect = try!(GetClientRect(hwnd));
+                    try!(FillRect(hdc, &rect, (COLOR_BTNFACE + 1) as HBRUSH));
+                    try!(SelectObject(hdc, try!(GetStockObject(DEFAULT_GUI_FONT))));
+                    try!(SetBkMode(hdc, TRANSPARENT));
+                    try!(self.paint(hdc, &rect));
+                    EndPaint(hwnd, &ps)
+                });
+                match rv {
+                    Ok(_) => Some(1),
+                    Err(x) => Some(x as LRESULT),
+                }
+            },
+            WM_COMMAND if LOWORD(param as DWORD) as c_int == IDCANCEL => {
+                self.cancel.store(true, Ordering::Relaxed);
+                let _ = invalidate(hwnd);
+                Some(0)
+            },
+            // the window stays until the worker has stopped
+            WM_CLOSE => {
+                self.cancel.store(true, Ordering::Relaxed);
+                let _ = invalidate(hwnd);
+                Some(0)
+            },
+            WM_OPERATION_UPDATE => {
+                self.on_update(hwnd);
+                Some(0)
+            },
+            WM_DESTROY => {
+                remove_dialog(hwnd);
+                Some(0)
+            },
+            _ => None,
+        }
+    }
+
+    fn register () -> Result<ATOM, u32>
+    {
+        let wnd_cls = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as UINT,
+            style: CS_HREDRAW | CS_VREDRAW,
+            lpfnWndProc: Some(Self::wnd_proc_raw),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: 0 as HINSTANCE,
+            hIcon: 0 as HICON,
+            hCursor: try!(LoadCursorW(0 as HINSTANCE, RC_IDC_ARROW)),
+            hbrBackground: (COLOR_BTNFACE + 1) as HBRUSH,
+            lpszMenuName: 0 as *const u16,
+            lpszClassName: wstr("OperationCls").as_ptr(),
+            hIconSm: 0 as HICON,
+        };
+
+        RegisterClassExW(&wnd_cls)
+    }
+}
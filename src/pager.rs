@@ -0,0 +1,666 @@
+// Paging engine of the file viewer. The file is read through a small cache of
+// fixed-size blocks and line boundaries are found around the current offset
+// only, so opening and scrolling a 10 GiB log costs the same as a 1 KiB file.
+// Line numbers come from a sparse index that is built on demand. Text is
+// decoded with `encoding::decode`, detected from the start of the file.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use ::encoding::{self, Encoding};
+
+pub const BLOCK_SIZE: u64 = 64 * 1024;
+const CACHED_BLOCKS: usize = 16;
+// A line longer than this is broken at the next multiple of MAX_LINE_BYTES,
+// so files without newlines can still be paged. An offset that is a multiple
+// of MAX_LINE_BYTES starts a line when the MAX_LINE_BYTES before it contain
+// no newline; this keeps the breaks the same whether scrolling up or down.
+pub const MAX_LINE_BYTES: u64 = 16 * 1024;
+// Every INDEX_STEP-th line start is remembered by the line index.
+const INDEX_STEP: u64 = 1024;
+pub const TAB_WIDTH: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GotoTarget {
+    // 1-based
+    Line(u64),
+    Percent(f64),
+}
+
+// "120" goes to line 120, "50%" to the middle of the file.
+pub fn parse_goto(input: &str) -> Option<GotoTarget>
+{
+    let input = input.trim();
+    if input.ends_with('%') {
+        return input[..input.len() - 1].trim().parse::<f64>().ok()
+            .filter(|p| *p >= 0.0 && *p <= 100.0)
+            .map(GotoTarget::Percent);
+    }
+    input.parse::<u64>().ok().filter(|n| *n > 0).map(GotoTarget::Line)
+}
+
+// Replaces tabs with spaces up to the next tab stop and control characters with dots.
+pub fn expand_tabs(text: &str) -> String
+{
+    let mut rv = String::with_capacity(text.len());
+    let mut column = 0;
+    for c in text.chars() {
+        if c == '\t' {
+            let next = (column / TAB_WIDTH + 1) * TAB_WIDTH;
+            while column < next { rv.push(' '); column += 1; }
+            continue;
+        }
+        rv.push(if c.is_control() { '.' } else { c });
+        column += 1;
+    }
+    rv
+}
+
+fn row_count(text: &str, columns: usize) -> usize
+{
+    let chars = text.chars().count();
+    cmp::max(1, (chars + columns - 1) / cmp::max(columns, 1))
+}
+
+fn row_text(text: &str, row: usize, columns: usize) -> String
+{ text.chars().skip(row * columns).take(columns).collect() }
+
+pub struct Pager<R> {
+    source: R,
+    len: u64,
+    // (block number, data), most recently used last
+    blocks: Vec<(u64, Vec<u8>)>,
+    // checkpoints[k] is the offset of line k * INDEX_STEP (0-based)
+    checkpoints: Vec<u64>,
+    indexed_lines: u64,
+    // offset of line `indexed_lines`
+    indexed_to: u64,
+    top: u64,
+    // with wrapping, the first visible row of the line at `top`
+    top_row: usize,
+    // without wrapping, the first visible column
+    left: usize,
+    wrap: bool,
+    encoding: Encoding,
+}
+
+impl Pager<File> {
+    pub fn open(path: &Path) -> io::Result<Pager<File>>
+    { File::open(path).and_then(Pager::new) }
+}
+
+impl<R: Read + Seek> Pager<R> {
+    pub fn new(mut source: R) -> io::Result<Pager<R>>
+    {
+        let len = try!(source.seek(SeekFrom::End(0)));
+        let mut pager = Pager {
+            source: source,
+            len: len,
+            blocks: Vec::with_capacity(CACHED_BLOCKS),
+            checkpoints: vec![0],
+            indexed_lines: 0,
+            indexed_to: 0,
+            top: 0,
+            top_row: 0,
+            left: 0,
+            wrap: false,
+            encoding: Encoding::Utf8,
+        };
+        let sample = try!(pager.read_range(0, encoding::SAMPLE_SIZE as u64));
+        pager.encoding = encoding::detect(&sample);
+        Ok(pager)
+    }
+
+    pub fn len(&self) -> u64
+    { self.len }
+
+    pub fn top(&self) -> u64
+    { self.top }
+
+    pub fn wrap(&self) -> bool
+    { self.wrap }
+
+    pub fn set_wrap(&mut self, wrap: bool)
+    {
+        self.wrap = wrap;
+        self.top_row = 0;
+        self.left = 0;
+    }
+
+    pub fn encoding(&self) -> Encoding
+    { self.encoding }
+
+    // Line boundaries depend on the encoding, so the line index starts over.
+    pub fn set_encoding(&mut self, encoding: Encoding) -> io::Result<()>
+    {
+        self.encoding = encoding;
+        self.checkpoints = vec![0];
+        self.indexed_lines = 0;
+        self.indexed_to = 0;
+        let top = self.top;
+        self.goto_offset(top)
+    }
+
+    pub fn percent(&self) -> u32
+    {
+        if self.len == 0 { 100 }
+        else { (self.top as f64 * 100.0 / self.len as f64).round() as u32 }
+    }
+
+    fn block(&mut self, number: u64) -> io::Result<&[u8]>
+    {
+        match self.blocks.iter().position(|&(n, _)| n == number) {
+            Some(idx) => {
+                let block = self.blocks.remove(idx);
+                self.blocks.push(block);
+            },
+            None => {
+                let start = number * BLOCK_SIZE;
+                let size = cmp::min(BLOCK_SIZE, self.len.saturating_sub(start));
+                let mut data = Vec::with_capacity(size as usize);
+                try!(self.source.seek(SeekFrom::Start(start)));
+                // a file that shrank while open gives a short block instead of an error
+                try!((&mut self.source).take(size).read_to_end(&mut data));
+                if self.blocks.len() >= CACHED_BLOCKS {
+                    self.blocks.remove(0);
+                }
+                self.blocks.push((number, data));
+            },
+        }
+        Ok(&self.blocks[self.blocks.len() - 1].1)
+    }
+
+    // Bytes of [from, to), shorter if the file ends early.
+    pub fn read_range(&mut self, from: u64, to: u64) -> io::Result<Vec<u8>>
+    {
+        let to = cmp::min(to, self.len);
+        let mut rv = Vec::with_capacity(to.saturating_sub(from) as usize);
+        let mut pos = from;
+        while pos < to {
+            let number = pos / BLOCK_SIZE;
+            let block_start = number * BLOCK_SIZE;
+            let data = try!(self.block(number));
+            let begin = (pos - block_start) as usize;
+            let end = cmp::min((to - block_start) as usize, data.len());
+            if begin >= end { break; }
+            rv.extend_from_slice(&data[begin..end]);
+            pos = block_start + end as u64;
+        }
+        Ok(rv)
+    }
+
+    // First occurrence of `byte` in [from, to).
+    fn find_byte(&mut self, from: u64, to: u64, byte: u8) -> io::Result<Option<u64>>
+    {
+        let to = cmp::min(to, self.len);
+        let mut pos = from;
+        while pos < to {
+            let number = pos / BLOCK_SIZE;
+            let block_start = number * BLOCK_SIZE;
+            let data = try!(self.block(number));
+            let begin = (pos - block_start) as usize;
+            let end = cmp::min((to - block_start) as usize, data.len());
+            if begin >= end { break; }
+            if let Some(idx) = data[begin..end].iter().position(|b| *b == byte) {
+                return Ok(Some(pos + idx as u64));
+            }
+            pos = block_start + end as u64;
+        }
+        Ok(None)
+    }
+
+    // Last occurrence of `byte` in [from, to).
+    fn rfind_byte(&mut self, from: u64, to: u64, byte: u8) -> io::Result<Option<u64>>
+    {
+        let mut end_pos = cmp::min(to, self.len);
+        while end_pos > from {
+            let number = (end_pos - 1) / BLOCK_SIZE;
+            let block_start = number * BLOCK_SIZE;
+            let data = try!(self.block(number));
+            let begin = (cmp::max(from, block_start) - block_start) as usize;
+            let end = cmp::min((end_pos - block_start) as usize, data.len());
+            if begin >= end { break; }
+            if let Some(idx) = data[begin..end].iter().rposition(|b| *b == byte) {
+                return Ok(Some(block_start + (begin + idx) as u64));
+            }
+            end_pos = block_start + begin as u64;
+        }
+        Ok(None)
+    }
+
+    fn byte_at(&mut self, pos: u64) -> io::Result<Option<u8>>
+    {
+        let data = try!(self.block(pos / BLOCK_SIZE));
+        Ok(data.get((pos % BLOCK_SIZE) as usize).cloned())
+    }
+
+    // Given a `\n` byte at `pos`, where the line after it starts, or `None`
+    // if the byte is part of another UTF-16 code unit.
+    fn newline_end(&mut self, pos: u64) -> io::Result<Option<u64>>
+    {
+        match self.encoding {
+            Encoding::Utf16Le => {
+                let aligned = pos % 2 == 0 && try!(self.byte_at(pos + 1)) == Some(0);
+                Ok(if aligned { Some(pos + 2) } else { None })
+            },
+            Encoding::Utf16Be => {
+                let aligned = pos % 2 == 1 && try!(self.byte_at(pos - 1)) == Some(0);
+                Ok(if aligned { Some(pos + 1) } else { None })
+            },
+            _ => Ok(Some(pos + 1)),
+        }
+    }
+
+    // End of the first newline whose `\n` byte is in [from, to).
+    fn find_newline(&mut self, from: u64, to: u64) -> io::Result<Option<u64>>
+    {
+        let mut pos = from;
+        while let Some(nl) = try!(self.find_byte(pos, to, b'\n')) {
+            if let Some(end) = try!(self.newline_end(nl)) {
+                return Ok(Some(end));
+            }
+            pos = nl + 1;
+        }
+        Ok(None)
+    }
+
+    // End of the last newline whose `\n` byte is in [from, to) and that ends at or before `limit`.
+    fn rfind_newline(&mut self, from: u64, to: u64, limit: u64) -> io::Result<Option<u64>>
+    {
+        let mut to = to;
+        while let Some(nl) = try!(self.rfind_byte(from, to, b'\n')) {
+            match try!(self.newline_end(nl)) {
+                Some(end) if end <= limit => return Ok(Some(end)),
+                _ => to = nl,
+            }
+        }
+        Ok(None)
+    }
+
+    fn is_forced_break(&mut self, offset: u64) -> io::Result<bool>
+    {
+        if offset == 0 || offset % MAX_LINE_BYTES != 0 || offset >= self.len {
+            return Ok(false);
+        }
+        self.rfind_newline(offset - MAX_LINE_BYTES, offset, offset).map(|nl| nl.is_none())
+    }
+
+    // Start of the line containing `pos`.
+    pub fn line_start_at(&mut self, pos: u64) -> io::Result<u64>
+    {
+        if self.len == 0 {
+            return Ok(0);
+        }
+        let pos = cmp::min(pos, self.len - 1);
+        let boundary = pos / MAX_LINE_BYTES * MAX_LINE_BYTES;
+        if let Some(end) = try!(self.rfind_newline(boundary, pos, pos)) {
+            return Ok(end);
+        }
+        if boundary == 0 {
+            return Ok(0);
+        }
+        match try!(self.rfind_newline(boundary - MAX_LINE_BYTES, boundary, boundary)) {
+            Some(end) => Ok(end),
+            None => Ok(boundary),
+        }
+    }
+
+    // Start of the line after the one starting at `start`; `len()` for the last line.
+    pub fn next_line_start(&mut self, start: u64) -> io::Result<u64>
+    {
+        let mut pos = start;
+        while pos < self.len {
+            let boundary = (pos / MAX_LINE_BYTES + 1) * MAX_LINE_BYTES;
+            let limit = cmp::min(boundary, self.len);
+            if let Some(end) = try!(self.find_newline(pos, limit)) {
+                return Ok(end);
+            }
+            if try!(self.is_forced_break(boundary)) {
+                return Ok(boundary);
+            }
+            pos = limit;
+        }
+        Ok(self.len)
+    }
+
+    // Display text of the line starting at `start`, and where the next line starts.
+    pub fn read_line(&mut self, start: u64) -> io::Result<(String, u64)>
+    {
+        let next = try!(self.next_line_start(start));
+        let bytes = try!(self.read_range(start, next));
+        let mut text = encoding::decode(&bytes, self.encoding);
+        if text.ends_with('\n') { text.pop(); }
+        if text.ends_with('\r') { text.pop(); }
+        Ok((expand_tabs(&text), next))
+    }
+
+    // Text rows for a window of `rows` by `columns` characters, with the offset
+    // of the line each row belongs to and, when wrapping, which row of the line it is.
+    pub fn visible(&mut self, rows: usize, columns: usize) -> io::Result<Vec<(u64, usize, String)>>
+    {
+        let columns = cmp::max(columns, 1);
+        let mut rv = Vec::with_capacity(rows);
+        let mut line = self.top;
+        let mut first_row = self.top_row;
+        while rv.len() < rows && (line < self.len || (line == 0 && rv.is_empty())) {
+            let (text, next) = try!(self.read_line(line));
+            if self.wrap {
+                for row in first_row..row_count(&text, columns) {
+                    if rv.len() == rows { break; }
+                    rv.push((line, row, row_text(&text, row, columns)));
+                }
+            } else {
+                rv.push((line, 0, text.chars().skip(self.left).take(columns).collect()));
+            }
+            first_row = 0;
+            if next <= line { break; }
+            line = next;
+        }
+        Ok(rv)
+    }
+
+    // Moves the view by `delta` rows: display rows when wrapping, lines otherwise.
+    pub fn scroll(&mut self, delta: i64, columns: usize) -> io::Result<()>
+    {
+        let columns = cmp::max(columns, 1);
+        if delta > 0 {
+            for _ in 0..delta {
+                let (text, next) = try!(self.read_line(self.top));
+                if self.wrap && self.top_row + 1 < row_count(&text, columns) {
+                    self.top_row += 1;
+                    continue;
+                }
+                if next >= self.len { break; }
+                self.top = next;
+                self.top_row = 0;
+            }
+        } else {
+            for _ in 0..-delta {
+                if self.wrap && self.top_row > 0 {
+                    self.top_row -= 1;
+                    continue;
+                }
+                if self.top == 0 { break; }
+                self.top = try!(self.line_start_at(self.top - 1));
+                self.top_row = if self.wrap {
+                    let (text, _) = try!(self.read_line(self.top));
+                    row_count(&text, columns) - 1
+                } else { 0 };
+            }
+        }
+        Ok(())
+    }
+
+    pub fn left(&self) -> usize
+    { self.left }
+
+    // Display column of the byte at `offset` in the line starting at `line`.
+    pub fn column_at(&mut self, line: u64, offset: u64) -> io::Result<usize>
+    {
+        let bytes = try!(self.read_range(line, offset));
+        Ok(expand_tabs(&encoding::decode(&bytes, self.encoding)).chars().count())
+    }
+
+    // Scrolls so the byte at `offset` is on the first row and within the visible columns.
+    pub fn show_offset(&mut self, offset: u64, columns: usize) -> io::Result<()>
+    {
+        let columns = cmp::max(columns, 1);
+        try!(self.goto_offset(offset));
+        let top = self.top;
+        let column = try!(self.column_at(top, offset));
+        if self.wrap {
+            self.top_row = column / columns;
+        } else if column < self.left || column >= self.left + columns {
+            self.left = column.saturating_sub(columns / 4);
+        }
+        Ok(())
+    }
+
+    pub fn scroll_horizontal(&mut self, delta: i64)
+    {
+        if self.wrap { return; }
+        let left = self.left as i64 + delta;
+        self.left = if left < 0 { 0 } else { left as usize };
+    }
+
+    // Puts the line containing `offset` at the top.
+    pub fn goto_offset(&mut self, offset: u64) -> io::Result<()>
+    {
+        self.top = try!(self.line_start_at(offset));
+        self.top_row = 0;
+        Ok(())
+    }
+
+    pub fn home(&mut self)
+    {
+        self.top = 0;
+        self.top_row = 0;
+    }
+
+    // Shows the last page.
+    pub fn end(&mut self, rows: usize, columns: usize) -> io::Result<()>
+    {
+        try!(self.goto_offset(self.len.saturating_sub(1)));
+        if self.wrap {
+            let (text, _) = try!(self.read_line(self.top));
+            self.top_row = row_count(&text, cmp::max(columns, 1)) - 1;
+        }
+        self.scroll(1 - cmp::max(rows, 1) as i64, columns)
+    }
+
+    fn extend_index(&mut self, until_line: u64, until_offset: u64) -> io::Result<()>
+    {
+        while self.indexed_lines < until_line && self.indexed_to < until_offset {
+            let next = try!(self.next_line_start(self.indexed_to));
+            if next >= self.len { break; }
+            self.indexed_lines += 1;
+            self.indexed_to = next;
+            if self.indexed_lines % INDEX_STEP == 0 {
+                self.checkpoints.push(next);
+            }
+        }
+        Ok(())
+    }
+
+    // Goes to the 1-based `line`, or the last line if the file is shorter.
+    pub fn goto_line(&mut self, line: u64) -> io::Result<()>
+    {
+        let target = line.saturating_sub(1);
+        try!(self.extend_index(target, u64::max_value()));
+        let target = cmp::min(target, self.indexed_lines);
+        let checkpoint = target / INDEX_STEP;
+        let mut offset = self.checkpoints[checkpoint as usize];
+        for _ in 0..target - checkpoint * INDEX_STEP {
+            offset = try!(self.next_line_start(offset));
+        }
+        self.top = offset;
+        self.top_row = 0;
+        Ok(())
+    }
+
+    pub fn goto(&mut self, target: GotoTarget) -> io::Result<()>
+    {
+        match target {
+            GotoTarget::Line(line) => self.goto_line(line),
+            GotoTarget::Percent(percent) => {
+                let offset = (self.len as f64 * percent / 100.0) as u64;
+                self.goto_offset(offset)
+            },
+        }
+    }
+
+    // 1-based number of the line starting at `offset`. The index is extended by
+    // at most `max_scan` bytes; `None` if that is not enough.
+    pub fn line_number(&mut self, offset: u64, max_scan: u64) -> io::Result<Option<u64>>
+    {
+        if offset > self.indexed_to {
+            if offset - self.indexed_to > max_scan {
+                return Ok(None);
+            }
+            try!(self.extend_index(u64::max_value(), offset));
+        }
+        if offset > self.indexed_to {
+            return Ok(None);
+        }
+        let checkpoint = match self.checkpoints.binary_search(&offset) {
+            Ok(idx) => idx,
+            Err(idx) => idx - 1,
+        };
+        let mut line = checkpoint as u64 * INDEX_STEP;
+        let mut pos = self.checkpoints[checkpoint];
+        while pos < offset {
+            pos = try!(self.next_line_start(pos));
+            line += 1;
+        }
+        Ok(Some(line + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::process;
+
+    // A few MB of short lines with some far longer than MAX_LINE_BYTES, one
+    // of them ending the file without a newline.
+    fn generated() -> Vec<u8>
+    {
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move |below: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % below
+        };
+        let mut data = Vec::new();
+        while data.len() < 5_000_000 {
+            let len = if random(1000) < 3 { 2 * MAX_LINE_BYTES + random(3 * MAX_LINE_BYTES) } else { random(200) };
+            for _ in 0..len {
+                data.push(b'a' + random(26) as u8);
+            }
+            data.push(b'\n');
+        }
+        data.extend((0..3 * MAX_LINE_BYTES + 5).map(|i| b'0' + (i % 10) as u8));
+        data
+    }
+
+    // Line starts as documented: after each newline, and at multiples of
+    // MAX_LINE_BYTES with no newline in the MAX_LINE_BYTES before.
+    fn line_starts(data: &[u8]) -> Vec<u64>
+    {
+        let max = MAX_LINE_BYTES as usize;
+        let mut starts = vec![0];
+        for pos in 1..data.len() {
+            if data[pos - 1] == b'\n' || (pos % max == 0 && !data[pos - max..pos].contains(&b'\n')) {
+                starts.push(pos as u64);
+            }
+        }
+        starts
+    }
+
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf
+    {
+        let path = env::temp_dir().join(format!("pager_test_{}_{}", process::id(), name));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8>
+    {
+        text.encode_utf16()
+            .flat_map(|unit| if big_endian { vec![(unit >> 8) as u8, unit as u8] } else { vec![unit as u8, (unit >> 8) as u8] })
+            .collect()
+    }
+
+    fn lines<R: Read + Seek>(pager: &mut Pager<R>) -> Vec<String>
+    { pager.visible(100, 200).unwrap().into_iter().map(|(_, _, text)| text).collect() }
+
+    #[test]
+    fn both_directions_agree_on_a_large_file()
+    {
+        let data = generated();
+        let starts = line_starts(&data);
+        let forced = starts.iter().filter(|&&start| start > 0 && data[start as usize - 1] != b'\n').count();
+        assert!(forced > 100, "{} forced breaks", forced);
+        let path = temp_file("large", &data);
+        let mut pager = Pager::open(&path).unwrap();
+        assert_eq!(pager.len(), data.len() as u64);
+
+        let mut next_starts = Vec::new();
+        let mut pos = 0;
+        while pos < pager.len() {
+            next_starts.push(pos);
+            pos = pager.next_line_start(pos).unwrap();
+        }
+        assert_eq!(next_starts, starts);
+        for (idx, &start) in starts.iter().enumerate() {
+            let end = starts.get(idx + 1).cloned().unwrap_or(data.len() as u64);
+            for &pos in [start, start + (end - start) / 2, end - 1].iter() {
+                assert_eq!(pager.line_start_at(pos).unwrap(), start, "at {}", pos);
+            }
+        }
+        assert_eq!(pager.line_start_at(u64::max_value()).unwrap(), *starts.last().unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn line_numbers_across_index_steps()
+    {
+        let data = generated();
+        let starts = line_starts(&data);
+        let mut pager = Pager::new(Cursor::new(data.clone())).unwrap();
+        // nothing indexed yet, and too far to scan
+        assert_eq!(pager.line_number(starts[3000], 1000).unwrap(), None);
+        for &line in [1, 2, 1023, 1024, 1025, 1026, 2048, 2049, 2050, 3073, 1025, 5].iter() {
+            pager.goto_line(line).unwrap();
+            assert_eq!(pager.top(), starts[line as usize - 1], "line {}", line);
+            assert_eq!(pager.line_number(pager.top(), u64::max_value()).unwrap(), Some(line));
+        }
+        let last = starts.len() as u64;
+        pager.goto_line(last + 1000).unwrap();
+        assert_eq!(pager.top(), starts[last as usize - 1]);
+        assert_eq!(pager.line_number(pager.top(), 0).unwrap(), Some(last));
+
+        // a fresh index built by line_number alone matches as well
+        let mut pager = Pager::new(Cursor::new(data)).unwrap();
+        for &line in [4097, 1024, 1025, 1].iter() {
+            assert_eq!(pager.line_number(starts[line - 1], u64::max_value()).unwrap(), Some(line as u64));
+        }
+    }
+
+    #[test]
+    fn utf16_newlines_must_be_aligned()
+    {
+        // U+0A41 puts a 0x0A byte at an odd offset, followed by the 0x00 of U+0100
+        let mut pager = Pager::new(Cursor::new(utf16("one\u{a41}\u{100}\ntwo\u{a0a}\nthree", false))).unwrap();
+        pager.set_encoding(Encoding::Utf16Le).unwrap();
+        assert_eq!(lines(&mut pager), vec!["one\u{a41}\u{100}", "two\u{a0a}", "three"]);
+        assert_eq!(pager.next_line_start(0).unwrap(), 12);
+        assert_eq!(pager.line_start_at(13).unwrap(), 12);
+        assert_eq!(pager.line_start_at(11).unwrap(), 0);
+
+        // U+0A00 after U+0100 gives 0x00 0x0A at an even offset
+        let mut pager = Pager::new(Cursor::new(utf16("\u{100}\u{a00}x\ny\n", true))).unwrap();
+        pager.set_encoding(Encoding::Utf16Be).unwrap();
+        assert_eq!(lines(&mut pager), vec!["\u{100}\u{a00}x", "y"]);
+        assert_eq!(pager.next_line_start(0).unwrap(), 8);
+        pager.goto_line(2).unwrap();
+        assert_eq!(pager.top(), 8);
+        assert_eq!(pager.line_number(8, 0).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn goto_input()
+    {
+        assert_eq!(parse_goto(" 12 "), Some(GotoTarget::Line(12)));
+        assert_eq!(parse_goto("50%"), Some(GotoTarget::Percent(50.0)));
+        assert_eq!(parse_goto("0"), None);
+        assert_eq!(expand_tabs("a\tb"), "a       b");
+    }
+}
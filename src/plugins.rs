@@ -0,0 +1,179 @@
+// Plugins adding archive formats and file systems, loaded from the `plugins`
+// directory next to the executable the first time they are asked for. See
+// `plugins/api` for the interface and `plugins/example` for a plugin.
+//
+// Archive plugins open files matching their masks, which are shown at the
+// archive's path like ZIP files. A plugin file system is shown at
+// `\\plugins\<name>\` and offered in the drive menu.
+
+extern crate trusty_commander_plugin as api;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use self::api::loader::{EntryInfo, Plugin, Session, Writer};
+use self::api::{CAP_ARCHIVE, CAP_FILESYSTEM, CAP_RENAME, CAP_WRITE};
+
+use ::mask;
+use ::vfs::{DirEntry, Metadata, Vfs};
+
+static PLUGINS: OnceLock<Vec<Arc<Plugin>>> = OnceLock::new();
+
+// Libraries that fail to load, or are not plugins, are left out.
+fn load_all() -> Vec<Arc<Plugin>>
+{
+    let dir = match env::current_exe() {
+        Ok(exe) => exe.with_file_name("plugins"),
+        Err(_) => return Vec::new(),
+    };
+    let mut paths: Vec<PathBuf> = match fs::read_dir(&dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path())
+            .filter(|p| p.extension().map_or(false, |ext| ext.eq_ignore_ascii_case(env::consts::DLL_EXTENSION)))
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+    paths.sort();
+    paths.iter().filter_map(|path| Plugin::load(path).ok()).map(Arc::new).collect()
+}
+
+pub fn loaded() -> &'static [Arc<Plugin>]
+{ PLUGINS.get_or_init(load_all) }
+
+// The plugin opening archives called `name`.
+pub fn archive_plugin(name: &str) -> Option<Arc<Plugin>>
+{
+    loaded().iter()
+        .find(|plugin| plugin.has(CAP_ARCHIVE) && mask::matches_any(plugin.masks(), name))
+        .cloned()
+}
+
+// Plugins with a file system of their own, and where it is shown.
+pub fn file_systems() -> Vec<(Arc<Plugin>, PathBuf)>
+{
+    loaded().iter()
+        .filter(|plugin| plugin.has(CAP_FILESYSTEM))
+        .map(|plugin| (plugin.clone(), PathBuf::from(format!("\\\\plugins\\{}\\", plugin.name()))))
+        .collect()
+}
+
+pub struct PluginFs {
+    session: Session,
+    // the path the archive or file system is shown at
+    root: PathBuf,
+    // false for a copy of an archive that is not on the disk
+    writable: bool,
+}
+
+impl PluginFs {
+    // Opens `archive`, a file on the disk shown at `root`, or the plugin's
+    // file system if None.
+    pub fn open(plugin: &Arc<Plugin>, archive: Option<&Path>, root: &Path, writable: bool) -> io::Result<PluginFs>
+    {
+        let session = try!(Plugin::open(plugin, archive));
+        Ok(PluginFs { session: session, root: root.to_path_buf(), writable: writable })
+    }
+
+    // `path` as the plugin names it.
+    fn inner(&self, path: &Path) -> io::Result<String>
+    {
+        let rest = try!(path.strip_prefix(&self.root).map_err(|_|
+            io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not in \"{}\"", path.display(), self.root.display()))));
+        let names: Vec<String> = rest.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        Ok(names.join("/"))
+    }
+
+    fn check_writable(&self, path: &Path, capability: u32) -> io::Result<()>
+    {
+        if !self.writable || !self.session.plugin().has(capability) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      format!("Cannot change \"{}\": {} is read-only", path.display(), self.session.plugin().name())));
+        }
+        Ok(())
+    }
+}
+
+fn metadata(info: &EntryInfo) -> Metadata
+{
+    Metadata {
+        is_dir: info.is_dir,
+        size: info.size,
+        modified: info.modified,
+        hidden: info.hidden,
+        read_only: info.read_only,
+        is_link: info.is_link,
+        mode: None,
+    }
+}
+
+// A file being written; it is complete once flushed or dropped.
+struct PluginWriter {
+    writer: Option<Writer>,
+}
+
+impl Write for PluginWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        match self.writer {
+            Some(ref mut writer) => writer.write(buf),
+            None => Err(io::Error::new(io::ErrorKind::Other, "The file is already complete")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    { self.writer.take().map_or(Ok(()), |writer| writer.close()) }
+}
+
+impl Vfs for PluginFs {
+    fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>>
+    {
+        let entries = try!(self.session.list(&try!(self.inner(dir))));
+        Ok(entries.iter().map(|info| DirEntry { name: info.name.clone(), meta: metadata(info) }).collect())
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Metadata>
+    {
+        let inner = try!(self.inner(path));
+        if inner.is_empty() {
+            return Ok(Metadata { is_dir: true, size: 0, modified: None, hidden: false, read_only: false, is_link: false,
+                                 mode: None });
+        }
+        self.session.stat(&inner).map(|info| metadata(&info))
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>>
+    {
+        let reader = try!(self.session.open_read(&try!(self.inner(path))));
+        Ok(Box::new(reader))
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>
+    {
+        try!(self.check_writable(path, CAP_WRITE));
+        let writer = try!(self.session.open_write(&try!(self.inner(path))));
+        Ok(Box::new(PluginWriter { writer: Some(writer) }))
+    }
+
+    fn mkdir(&self, path: &Path) -> io::Result<()>
+    {
+        try!(self.check_writable(path, CAP_WRITE));
+        self.session.mkdir(&try!(self.inner(path)))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()>
+    {
+        try!(self.check_writable(path, CAP_WRITE));
+        self.session.remove(&try!(self.inner(path)))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>
+    {
+        try!(self.check_writable(from, CAP_RENAME));
+        self.session.rename(&try!(self.inner(from)), &try!(self.inner(to)))
+    }
+
+    fn mount_point(&self) -> Option<PathBuf>
+    { Some(self.root.clone()) }
+}
@@ -0,0 +1,247 @@
+// Quick view (Ctrl+Q): the inactive panel previews the file focused in the
+// active one. This decides how a file is shown and counts what is in a
+// directory; the latter walks the whole tree, so the panel runs it on a worker
+// thread and shows the counts as they grow.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use ::encoding::{self, Encoding};
+use ::image;
+
+// the preview is loaded once the cursor has rested this long
+pub const DEBOUNCE_MS: u32 = 200;
+// entries counted between reports of a directory summary
+const REPORT_EVERY: u64 = 1000;
+
+// The file a quick view pane shows and the one the cursor has moved to since.
+// A new file only becomes the one shown once the cursor has rested on it for
+// DEBOUNCE_MS; moving back to the file on screen drops the pending one.
+#[derive(Default, Debug)]
+pub struct Target {
+    shown: Option<PathBuf>,
+    // the file waiting to be shown, None for nothing, and since when
+    pending: Option<(Option<PathBuf>, Instant)>,
+}
+
+impl Target {
+    // The file on screen; None before the first load and on "..".
+    pub fn shown(&self) -> Option<&Path>
+    { self.shown.as_ref().map(|p| p.as_path()) }
+
+    pub fn is_pending(&self) -> bool
+    { self.pending.is_some() }
+
+    // The cursor has moved to `path`, None for "..". Returns whether a file
+    // is now waiting to be shown, that is whether the timer should run.
+    pub fn focus(&mut self, path: Option<PathBuf>, now: Instant) -> bool
+    {
+        if path == self.shown {
+            self.pending = None;
+        } else if self.pending.as_ref().map_or(true, |&(ref pending, _)| *pending != path) {
+            self.pending = Some((path, now));
+        }
+        self.is_pending()
+    }
+
+    // Makes the pending file the one shown if the cursor has rested on it
+    // long enough; true if the preview is to be loaded again.
+    pub fn due(&mut self, now: Instant) -> bool
+    {
+        let rested = match self.pending {
+            Some((_, since)) => now >= since + Duration::from_millis(DEBOUNCE_MS as u64),
+            None => false,
+        };
+        if rested {
+            self.shown = self.pending.take().and_then(|(path, _)| path);
+        }
+        rested
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Text,
+    Hex,
+    Image,
+    Directory,
+}
+
+// Control characters other than the usual whitespace, escape and form feed.
+fn is_binary_byte(byte: u8) -> bool
+{ byte < 0x20 && !b"\t\n\r\x0c\x1b".contains(&byte) || byte == 0x7f }
+
+// Picks the preview from the first bytes of a file: images by their
+// signature, text unless more than a few percent of the bytes are control
+// characters, which UTF-16 text is allowed to have as zero bytes.
+pub fn kind_of(is_dir: bool, sample: &[u8]) -> Kind
+{
+    if is_dir {
+        return Kind::Directory;
+    }
+    if image::sniff(sample).is_some() {
+        return Kind::Image;
+    }
+    match encoding::detect(sample) {
+        Encoding::Utf16Le | Encoding::Utf16Be => Kind::Text,
+        _ => {
+            let binary = sample.iter().filter(|b| is_binary_byte(**b)).count();
+            if binary * 20 > sample.len() { Kind::Hex } else { Kind::Text }
+        },
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct DirSummary {
+    pub files: u64,
+    pub dirs: u64,
+    pub bytes: u64,
+    // entries and subdirectories that could not be read
+    pub unreadable: u64,
+    // false while counting, or if the count was cancelled
+    pub complete: bool,
+}
+
+// Counts the files, subdirectories and bytes under `path`. Symbolic links are
+// counted but not followed. `report` gets the counts so far every now and then.
+pub fn summarize_dir(path: &Path, cancel: &AtomicBool, report: &mut FnMut(&DirSummary)) -> io::Result<DirSummary>
+{
+    let mut summary = DirSummary::default();
+    let mut pending = vec![try!(fs::read_dir(path))];
+    let mut seen = 0;
+    while let Some(mut dir) = pending.pop() {
+        let entry = match dir.next() {
+            Some(entry) => entry,
+            None => continue,
+        };
+        pending.push(dir);
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(summary);
+        }
+        seen += 1;
+        if seen % REPORT_EVERY == 0 {
+            report(&summary);
+        }
+        let meta = match entry.and_then(|e| e.metadata().map(|m| (e.path(), m))) {
+            Ok(meta) => meta,
+            Err(_) => { summary.unreadable += 1; continue; },
+        };
+        match meta {
+            (ref sub, ref meta) if meta.is_dir() => {
+                summary.dirs += 1;
+                match fs::read_dir(sub) {
+                    Ok(sub) => pending.push(sub),
+                    Err(_) => summary.unreadable += 1,
+                }
+            },
+            (_, ref meta) => {
+                summary.files += 1;
+                summary.bytes += meta.len();
+            },
+        }
+    }
+    summary.complete = true;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn later(start: Instant, ms: u32) -> Instant
+    { start + Duration::from_millis(ms as u64) }
+
+    #[test]
+    fn loads_once_the_cursor_rests()
+    {
+        let start = Instant::now();
+        let mut target = Target::default();
+        assert!(!target.due(start));
+        assert!(target.focus(Some(PathBuf::from("a")), start));
+        assert!(!target.due(later(start, DEBOUNCE_MS - 1)));
+        assert_eq!(target.shown(), None);
+        assert!(target.due(later(start, DEBOUNCE_MS)));
+        assert_eq!(target.shown(), Some(Path::new("a")));
+        assert!(!target.is_pending());
+        assert!(!target.due(later(start, 2 * DEBOUNCE_MS)));
+    }
+
+    #[test]
+    fn scrolling_restarts_the_wait()
+    {
+        let start = Instant::now();
+        let mut target = Target::default();
+        target.focus(Some(PathBuf::from("a")), start);
+        target.focus(Some(PathBuf::from("b")), later(start, DEBOUNCE_MS / 2));
+        target.focus(Some(PathBuf::from("c")), later(start, DEBOUNCE_MS));
+        assert!(!target.due(later(start, DEBOUNCE_MS + DEBOUNCE_MS / 2)));
+        // the same file again does not restart it
+        assert!(target.focus(Some(PathBuf::from("c")), later(start, 2 * DEBOUNCE_MS - 1)));
+        assert!(target.due(later(start, 2 * DEBOUNCE_MS)));
+        assert_eq!(target.shown(), Some(Path::new("c")));
+    }
+
+    #[test]
+    fn back_to_the_shown_file_cancels()
+    {
+        let start = Instant::now();
+        let mut target = Target::default();
+        target.focus(Some(PathBuf::from("a")), start);
+        assert!(target.due(later(start, DEBOUNCE_MS)));
+        assert!(target.focus(Some(PathBuf::from("b")), later(start, DEBOUNCE_MS)));
+        assert!(!target.focus(Some(PathBuf::from("a")), later(start, DEBOUNCE_MS + 1)));
+        assert!(!target.due(later(start, 3 * DEBOUNCE_MS)));
+        assert_eq!(target.shown(), Some(Path::new("a")));
+
+        // ".." clears the pane once it is due
+        assert!(target.focus(None, later(start, 3 * DEBOUNCE_MS)));
+        assert!(target.due(later(start, 4 * DEBOUNCE_MS)));
+        assert_eq!(target.shown(), None);
+        assert!(!target.focus(None, later(start, 4 * DEBOUNCE_MS)));
+    }
+
+    #[test]
+    fn kinds()
+    {
+        assert_eq!(kind_of(true, b"\x89PNG\r\n\x1a\n"), Kind::Directory);
+        assert_eq!(kind_of(false, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Kind::Image);
+        assert_eq!(kind_of(false, b"\xff\xd8\xff\xe0\0\x10JFIF"), Kind::Image);
+        assert_eq!(kind_of(false, b""), Kind::Text);
+        assert_eq!(kind_of(false, b"line\r\n\tindented\x0cpage \x1b[1m"), Kind::Text);
+        assert_eq!(kind_of(false, b"h\0e\0l\0l\0o\0"), Kind::Text);
+        assert_eq!(kind_of(false, b"MZ\x90\0\x03\0\0\0\x04\0\0\0\xff\xff\0\0"), Kind::Hex);
+        // one control character in twenty is still text, two are not
+        let mut sample = vec![b'x'; 40];
+        sample[0] = 1;
+        sample[1] = 2;
+        assert_eq!(kind_of(false, &sample), Kind::Text);
+        sample[2] = 0x7f;
+        assert_eq!(kind_of(false, &sample), Kind::Hex);
+    }
+
+    #[test]
+    fn directory_summary()
+    {
+        let dir = env::temp_dir().join(format!("quick_view_test_{}_summary", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a").join("b")).unwrap();
+        fs::write(dir.join("x"), b"12345").unwrap();
+        fs::write(dir.join("a").join("y"), b"12").unwrap();
+        fs::write(dir.join("a").join("b").join("z"), b"1").unwrap();
+
+        let cancel = AtomicBool::new(false);
+        let summary = summarize_dir(&dir, &cancel, &mut |_| {}).unwrap();
+        assert_eq!(summary, DirSummary { files: 3, dirs: 2, bytes: 8, unreadable: 0, complete: true });
+
+        cancel.store(true, Ordering::Relaxed);
+        let summary = summarize_dir(&dir, &cancel, &mut |_| {}).unwrap();
+        assert!(!summary.complete);
+        assert!(summarize_dir(&dir.join("none"), &cancel, &mut |_| {}).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
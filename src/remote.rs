@@ -0,0 +1,142 @@
+// What the FTP, SFTP and WebDAV file systems share: where their sites are
+// shown, how they connect, a pool of logged in connections kept for reuse,
+// and the dates in their listings. ZIP archives read their DOS times with
+// the same civil date arithmetic.
+
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ::sites::{Protocol, Site};
+
+// `try!` for functions returning an Option.
+macro_rules! try_opt { ($e:expr) => (match $e { Some(x) => x, None => return None }) }
+
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+// connections kept for reuse, the others are closed
+pub const MAX_IDLE: usize = 4;
+const MONTHS: [&'static str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+// Where the files of `site` are shown.
+pub fn root_of(site: &Site) -> PathBuf
+{
+    let scheme = match site.protocol {
+        Protocol::Ftp => "ftp",
+        Protocol::Sftp => "sftp",
+        Protocol::WebDav | Protocol::WebDavs => "webdav",
+    };
+    PathBuf::from(format!("\\\\{}\\{}\\", scheme, site.name))
+}
+
+// A connection to the first address of `site` that answers, reads and
+// writes on it giving up after `TIMEOUT`.
+pub fn connect(site: &Site) -> io::Result<TcpStream>
+{
+    let mut stream = Err(io::Error::new(io::ErrorKind::NotFound, format!("Cannot find \"{}\"", site.host)));
+    for address in try!((site.host.as_str(), site.port).to_socket_addrs()) {
+        stream = TcpStream::connect_timeout(&address, TIMEOUT);
+        if stream.is_ok() {
+            break;
+        }
+    }
+    let stream = try!(stream);
+    try!(stream.set_read_timeout(Some(TIMEOUT)));
+    try!(stream.set_write_timeout(Some(TIMEOUT)));
+    Ok(stream)
+}
+
+// `name` in the remote directory `dir`.
+pub fn join(dir: &str, name: &str) -> String
+{
+    if dir.ends_with('/') { format!("{}{}", dir, name) } else { format!("{}/{}", dir, name) }
+}
+
+// Errors after which the connection is of no more use.
+pub fn is_lost(e: &io::Error) -> bool
+{
+    match e.kind() {
+        io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe |
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => true,
+        _ => false,
+    }
+}
+
+// Idle connections, up to `MAX_IDLE` of them.
+pub struct Pool<T> {
+    idle: Mutex<Vec<T>>,
+}
+
+impl<T> Pool<T> {
+    pub fn new(idle: Vec<T>) -> Pool<T>
+    { Pool { idle: Mutex::new(idle) } }
+
+    pub fn give_back(&self, conn: T)
+    {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE {
+            idle.push(conn);
+        }
+    }
+
+    // Runs `f` on an idle connection, or on one from `open` if there is
+    // none. An idle connection the server closed is replaced once. The
+    // connection comes back with what `f` returned, for the caller to keep
+    // or give back.
+    pub fn run<R, O, F>(&self, open: O, mut f: F) -> io::Result<(T, io::Result<R>)>
+        where O: Fn() -> io::Result<T>, F: FnMut(&mut T) -> io::Result<R>
+    {
+        let idle = self.idle.lock().unwrap().pop();
+        let reused = idle.is_some();
+        let mut conn = match idle {
+            Some(conn) => conn,
+            None => try!(open()),
+        };
+        let mut rv = f(&mut conn);
+        if reused && rv.as_ref().err().map_or(false, is_lost) {
+            conn = try!(open());
+            rv = f(&mut conn);
+        }
+        Ok((conn, rv))
+    }
+}
+
+// The number of a month from its English name or its first three letters.
+pub fn month_number(name: &str) -> Option<i64>
+{ MONTHS.iter().position(|&m| name.eq_ignore_ascii_case(m)).map(|m| m as i64 + 1) }
+
+// Days since 1970-01-01 of a civil date.
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64
+{
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The civil date of a day counted from 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64)
+{
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (year_of_era + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+// A UTC time from its fields, if they make one from 1970 on.
+pub fn time_of(year: i64, month: i64, day: i64, hour: u64, minute: u64, second: u64) -> Option<SystemTime>
+{
+    if year < 1970 || month < 1 || month > 12 || day < 1 || day > 31 || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day) as u64;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second))
+}
@@ -0,0 +1,271 @@
+// Filesystem roots shown in the drive bar and the Alt+F1/Alt+F2 menus:
+// drive letters on Windows, mount points on Linux.
+
+#[cfg(unix)]
+extern crate libc;
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct Root {
+    pub path: PathBuf,
+    pub label: String,
+    pub free_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+}
+
+impl Root {
+    // Short name for the drive bar: "c" for `C:\`, the last component for mount points.
+    pub fn short_name(&self) -> String
+    {
+        let full = self.path.to_string_lossy();
+        let trimmed = full.trim_end_matches(|c| c == '\\' || c == '/');
+        if trimmed.is_empty() {
+            return "/".to_string();
+        }
+        if trimmed.len() == 2 && trimmed.ends_with(':') {
+            return trimmed[..1].to_lowercase();
+        }
+        self.path.file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| trimmed.to_string())
+    }
+}
+
+pub trait RootSource {
+    fn roots(&self) -> io::Result<Vec<Root>>;
+    // Free and total bytes of the volume holding `path`.
+    fn space(&self, path: &Path) -> io::Result<(u64, u64)>;
+}
+
+// Index of the root containing `path`; the longest matching root wins,
+// so `/home` is preferred over `/` for `/home/user`.
+pub fn root_of(roots: &[Root], path: &Path) -> Option<usize>
+{
+    roots.iter()
+        .enumerate()
+        .filter(|&(_, root)| path.starts_with(&root.path))
+        .max_by_key(|&(_, root)| root.path.components().count())
+        .map(|(idx, _)| idx)
+}
+
+pub struct SystemRoots;
+
+#[cfg(windows)]
+impl RootSource for SystemRoots {
+    fn roots(&self) -> io::Result<Vec<Root>>
+    {
+        use win_layer::{wstr, GetLogicalDrives, GetDriveTypeW,
+                        GetVolumeInformationW, GetDiskFreeSpaceExW};
+        use winapi::DRIVE_NO_ROOT_DIR;
+
+        let mask = try!(GetLogicalDrives().map_err(|e| io::Error::from_raw_os_error(e as i32)));
+        let mut roots = Vec::new();
+        for letter in 0..26u8 {
+            if mask & (1 << letter) == 0 { continue; }
+            let path = format!("{}:\\", (b'A' + letter) as char);
+            let wpath = wstr(&path);
+            if GetDriveTypeW(&wpath) == DRIVE_NO_ROOT_DIR { continue; }
+            // removable drives without media fail both calls, they are still listed
+            let label = GetVolumeInformationW(&wpath).map(|(label, _)| label).unwrap_or_default();
+            let space = GetDiskFreeSpaceExW(&wpath).ok();
+            roots.push(Root {
+                path: PathBuf::from(path),
+                label: label,
+                free_bytes: space.map(|(free, _)| free),
+                total_bytes: space.map(|(_, total)| total),
+            });
+        }
+        Ok(roots)
+    }
+
+    fn space(&self, path: &Path) -> io::Result<(u64, u64)>
+    {
+        use win_layer::{wstr, GetDiskFreeSpaceExW};
+
+        let wpath = wstr(&path.to_string_lossy());
+        GetDiskFreeSpaceExW(&wpath).map_err(|e| io::Error::from_raw_os_error(e as i32))
+    }
+}
+
+#[cfg(unix)]
+impl RootSource for SystemRoots {
+    fn roots(&self) -> io::Result<Vec<Root>>
+    {
+        use std::fs::File;
+        use std::io::Read;
+
+        let mut text = String::new();
+        try!(File::open("/proc/mounts").and_then(|mut f| f.read_to_string(&mut text)));
+        let roots = parse_mounts(&text).into_iter().map(|(path, device)| {
+            let space = statvfs(&path).ok();
+            Root {
+                label: device,
+                path: path,
+                free_bytes: space.map(|(free, _)| free),
+                total_bytes: space.map(|(_, total)| total),
+            }
+        }).collect();
+        Ok(roots)
+    }
+
+    fn space(&self, path: &Path) -> io::Result<(u64, u64)>
+    { statvfs(path) }
+}
+
+#[cfg(unix)]
+fn statvfs(path: &Path) -> io::Result<(u64, u64)>
+{
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let cpath = try!(CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)));
+    let mut st: libc::statvfs = unsafe { ::std::mem::zeroed() };
+    let result = unsafe { libc::statvfs(cpath.as_ptr(), &mut st) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let block = st.f_frsize as u64;
+    Ok((st.f_bavail as u64 * block, st.f_blocks as u64 * block))
+}
+
+// Filesystem types worth showing even though they are not backed by a block device.
+const LISTED_FS_TYPES: &'static [&'static str] = &[
+    "nfs", "nfs4", "cifs", "smbfs", "smb3", "fuse.sshfs", "9p", "zfs", "btrfs",
+];
+
+// Picks user-visible mount points out of `/proc/mounts`, returning (mount point, device).
+// Pseudo filesystems (proc, sysfs, cgroup, ...) are skipped, `/` is always kept.
+pub fn parse_mounts(text: &str) -> Vec<(PathBuf, String)>
+{
+    let mut rv: Vec<(PathBuf, String)> = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let (device, mount_point, fs_type) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(d), Some(m), Some(t)) => (unescape_mount(d), unescape_mount(m), t),
+            _ => continue,
+        };
+        let visible = mount_point == "/"
+            || device.starts_with("/dev/")
+            || LISTED_FS_TYPES.contains(&fs_type);
+        if !visible { continue; }
+        // bind mounts and snap loops show up several times, keep the first
+        if rv.iter().any(|&(ref p, _)| p.as_os_str() == mount_point.as_str()) { continue; }
+        if mount_point.starts_with("/snap/") || mount_point.starts_with("/boot/efi") { continue; }
+        rv.push((PathBuf::from(mount_point), device));
+    }
+    rv.sort_by(|a, b| a.0.cmp(&b.0));
+    rv
+}
+
+// `/proc/mounts` escapes space, tab, newline and backslash as three-digit octal.
+fn unescape_mount(field: &str) -> String
+{
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let oct = ::std::str::from_utf8(&bytes[i + 1..i + 4]).unwrap_or("");
+            if let Ok(value) = u8::from_str_radix(oct, 8) {
+                out.push(value);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Roots as a machine would report them, with space for each one.
+    struct FakeRoots(Vec<(&'static str, &'static str, u64, u64)>);
+
+    impl RootSource for FakeRoots {
+        fn roots(&self) -> io::Result<Vec<Root>>
+        {
+            Ok(self.0.iter().map(|&(path, label, free, total)| Root {
+                path: PathBuf::from(path),
+                label: label.to_string(),
+                free_bytes: Some(free),
+                total_bytes: Some(total),
+            }).collect())
+        }
+
+        fn space(&self, path: &Path) -> io::Result<(u64, u64)>
+        { Err(io::Error::new(io::ErrorKind::Other, format!("no space for \"{}\" in a fake", path.display()))) }
+    }
+
+    fn names(source: &RootSource) -> Vec<String>
+    { source.roots().unwrap().iter().map(Root::short_name).collect() }
+
+    #[test]
+    fn mounts_are_filtered_and_unescaped()
+    {
+        let text = "/dev/sda1 / ext4 rw 0 0\n\
+                    proc /proc proc rw,nosuid 0 0\n\
+                    tmpfs /run tmpfs rw 0 0\n\
+                    /dev/sdb1 /mnt/my\\040disk ext4 rw 0 0\n\
+                    server:/export /mnt/nfs nfs4 rw 0 0\n\
+                    /dev/sda1 /mnt/bind ext4 rw 0 0\n\
+                    /dev/sda1 / ext4 rw 0 0\n\
+                    /dev/loop3 /snap/core/1 squashfs ro 0 0\n\
+                    /dev/sdc1 /media/tab\\011and\\134slash vfat rw 0 0\n\
+                    broken line\n";
+        let mounts = parse_mounts(text);
+        let paths: Vec<&str> = mounts.iter().map(|&(ref path, _)| path.to_str().unwrap()).collect();
+        assert_eq!(paths, vec!["/", "/media/tab\tand\\slash", "/mnt/bind", "/mnt/my disk", "/mnt/nfs"]);
+        assert_eq!(mounts[3].1, "/dev/sdb1");
+        assert_eq!(mounts[4].1, "server:/export");
+    }
+
+    #[test]
+    fn escapes_that_are_not_octal_are_kept()
+    {
+        assert_eq!(unescape_mount("a\\040b"), "a b");
+        assert_eq!(unescape_mount("a\\09x"), "a\\09x");
+        assert_eq!(unescape_mount("end\\04"), "end\\04");
+        assert_eq!(unescape_mount("\\134\\134"), "\\\\");
+    }
+
+    #[test]
+    fn short_names()
+    {
+        let windows = FakeRoots(vec![("C:\\", "System", 1, 2), ("d:", "", 0, 0)]);
+        assert_eq!(names(&windows), vec!["c", "d"]);
+        let unix = FakeRoots(vec![("/", "/dev/sda1", 1, 2), ("/mnt/my disk/", "/dev/sdb1", 3, 4), ("/home", "", 0, 0)]);
+        assert_eq!(names(&unix), vec!["/", "my disk", "home"]);
+    }
+
+    #[test]
+    fn the_longest_root_wins()
+    {
+        let source = FakeRoots(vec![("/", "/dev/sda1", 10, 100), ("/home", "/dev/sda2", 20, 200),
+                                    ("/home/user/mnt", "/dev/sdb1", 30, 300)]);
+        let roots = source.roots().unwrap();
+        assert_eq!(root_of(&roots, Path::new("/etc")), Some(0));
+        assert_eq!(root_of(&roots, Path::new("/home")), Some(1));
+        assert_eq!(root_of(&roots, Path::new("/home/user/docs")), Some(1));
+        assert_eq!(root_of(&roots, Path::new("/home/user/mnt/a")), Some(2));
+        // whole components only
+        assert_eq!(root_of(&roots, Path::new("/homer")), Some(0));
+        assert_eq!(root_of(&roots, Path::new("relative")), None);
+        assert_eq!(root_of(&[], Path::new("/")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn space_of_the_volume_holding_a_path()
+    {
+        let (free, total) = SystemRoots.space(&::std::env::temp_dir()).unwrap();
+        assert!(total > 0 && free <= total);
+        assert_eq!(SystemRoots.space(Path::new("/no/such/dir")).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(SystemRoots.space(Path::new("nul\0byte")).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
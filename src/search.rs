@@ -0,0 +1,412 @@
+// Search engine of the viewer. The file is read in chunks so a search never
+// holds more than a few megabytes, and it can be cancelled between chunks,
+// which lets the viewer run it on a worker thread.
+//
+// Every kind of pattern becomes a `regex::bytes::Regex`. UTF-8 files and hex
+// patterns are matched against the raw bytes; text in other encodings is
+// converted to UTF-8 first, keeping the file offset of each character. In
+// double-byte code pages the second byte of a character can look like ASCII,
+// so characters are found with the lead bytes before they are decoded.
+
+extern crate regex;
+
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use self::regex::bytes::{Regex, RegexBuilder};
+use ::encoding::{self, Encoding};
+
+pub const CHUNK_SIZE: u64 = 1024 * 1024;
+// Chunks overlap by this much; longer matches may be missed where chunks meet.
+pub const MAX_MATCH_BYTES: u64 = 64 * 1024;
+// read before a chunk so `\b` and look-behind see the preceding characters
+const CONTEXT_BYTES: u64 = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PatternKind {
+    Text,
+    Regex,
+    // bytes written as hex, e.g. `4D 5A 90 00`
+    Hex,
+}
+
+#[derive(Clone)]
+pub struct SearchOptions {
+    pub pattern: String,
+    pub kind: PatternKind,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+    // start and end offset of the match
+    Found(u64, u64),
+    NotFound,
+    Cancelled,
+}
+
+fn invalid_pattern(message: String) -> io::Error
+{ io::Error::new(io::ErrorKind::InvalidInput, message) }
+
+// "4D 5A 90", "4d5a90" and "0x4D, 0x5A" all give the same bytes.
+pub fn parse_hex_bytes(input: &str) -> Option<Vec<u8>>
+{
+    let mut digits = String::new();
+    for token in input.split(|c: char| c.is_whitespace() || c == ',').filter(|t| !t.is_empty()) {
+        let token = if token.starts_with("0x") || token.starts_with("0X") { &token[2..] } else { token };
+        if token.len() % 2 != 0 {
+            return None;
+        }
+        digits.push_str(token);
+    }
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(16)) {
+        return None;
+    }
+    (0..digits.len() / 2).map(|i| u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).ok()).collect()
+}
+
+fn read_at<R: Read + Seek>(source: &mut R, from: u64, to: u64) -> io::Result<Vec<u8>>
+{
+    let mut data = Vec::with_capacity(to.saturating_sub(from) as usize);
+    try!(source.seek(SeekFrom::Start(from)));
+    try!(source.take(to.saturating_sub(from)).read_to_end(&mut data));
+    Ok(data)
+}
+
+pub struct Searcher {
+    regex: Regex,
+    encoding: Encoding,
+    // match against the file bytes as they are
+    raw: bool,
+    // characters of single-byte code pages
+    code_page_chars: Vec<char>,
+}
+
+impl Searcher {
+    pub fn new(options: &SearchOptions, encoding: Encoding) -> io::Result<Searcher>
+    {
+        if options.pattern.is_empty() {
+            return Err(invalid_pattern("Nothing to search for".to_string()));
+        }
+        let mut source = match options.kind {
+            PatternKind::Text => regex::escape(&options.pattern),
+            PatternKind::Regex => options.pattern.clone(),
+            PatternKind::Hex => {
+                let bytes = try!(parse_hex_bytes(&options.pattern).ok_or_else(||
+                    invalid_pattern(format!("\"{}\" is not a list of hex bytes", options.pattern))));
+                let escaped: Vec<String> = bytes.iter().map(|b| format!("\\x{:02x}", b)).collect();
+                format!("(?-u){}", escaped.concat())
+            },
+        };
+        let hex = options.kind == PatternKind::Hex;
+        if options.whole_word && !hex {
+            source = format!(r"\b(?:{})\b", source);
+        }
+        let regex = try!(RegexBuilder::new(&source)
+            .case_insensitive(!options.case_sensitive && !hex)
+            .multi_line(true)
+            .build()
+            .map_err(|e| invalid_pattern(format!("Invalid regular expression: {}", e))));
+        let code_page_chars = match encoding {
+            Encoding::CodePage(code_page) if !encoding::is_double_byte(code_page) => (0..256u32)
+                .map(|b| encoding::decode(&[b as u8], encoding).chars().next().unwrap_or('\u{fffd}'))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(Searcher {
+            regex: regex,
+            encoding: encoding,
+            raw: hex || encoding == Encoding::Utf8,
+            code_page_chars: code_page_chars,
+        })
+    }
+
+    // Chunks of UTF-16 text start at even offsets.
+    fn align(&self, pos: u64) -> u64
+    {
+        match self.encoding {
+            Encoding::Utf16Le | Encoding::Utf16Be if !self.raw => pos & !1,
+            _ => pos,
+        }
+    }
+
+    // UTF-8 text of `bytes`, and for each of its bytes the offset in `bytes`
+    // of the character it belongs to, plus one entry for the end.
+    fn transcode(&self, bytes: &[u8]) -> (String, Vec<u64>)
+    {
+        let mut text = String::with_capacity(bytes.len());
+        let mut offsets = Vec::with_capacity(bytes.len() + 1);
+        let mut push = |c: char, offset: usize| {
+            text.push(c);
+            for _ in 0..c.len_utf8() { offsets.push(offset as u64); }
+        };
+        match self.encoding {
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let big_endian = self.encoding == Encoding::Utf16Be;
+                let unit = |i: usize| -> u32 {
+                    let (a, b) = (bytes[2 * i] as u32, bytes[2 * i + 1] as u32);
+                    if big_endian { a << 8 | b } else { b << 8 | a }
+                };
+                let units = bytes.len() / 2;
+                let mut i = 0;
+                while i < units {
+                    let first = unit(i);
+                    let pair = if first >= 0xd800 && first < 0xdc00 && i + 1 < units {
+                        let second = unit(i + 1);
+                        if second >= 0xdc00 && second < 0xe000 {
+                            ::std::char::from_u32(0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00))
+                        } else { None }
+                    } else { None };
+                    match pair {
+                        Some(c) => { push(c, 2 * i); i += 2; },
+                        None => {
+                            push(::std::char::from_u32(first).unwrap_or('\u{fffd}'), 2 * i);
+                            i += 1;
+                        },
+                    }
+                }
+            },
+            Encoding::CodePage(_) if !self.code_page_chars.is_empty() => {
+                for (idx, b) in bytes.iter().enumerate() {
+                    push(self.code_page_chars[*b as usize], idx);
+                }
+            },
+            Encoding::CodePage(code_page) => {
+                // Characters are found with the lead bytes. A chunk may start
+                // inside one; the pairs come right again at the first byte
+                // below 0x40, which is never the second of a pair.
+                let mut starts = Vec::with_capacity(bytes.len());
+                let mut idx = 0;
+                while idx < bytes.len() {
+                    starts.push(idx);
+                    idx += if encoding::is_lead_byte(code_page, bytes[idx]) { 2 } else { 1 };
+                }
+                // decoded at once when that gives one character for each,
+                // else one by one
+                let whole: Vec<char> = encoding::decode(bytes, self.encoding).chars().collect();
+                if whole.len() == starts.len() {
+                    for (c, start) in whole.into_iter().zip(starts) {
+                        push(c, start);
+                    }
+                } else {
+                    for (n, &start) in starts.iter().enumerate() {
+                        let end = starts.get(n + 1).cloned().unwrap_or(bytes.len());
+                        for c in encoding::decode(&bytes[start..end], self.encoding).chars() {
+                            push(c, start);
+                        }
+                    }
+                }
+            },
+            Encoding::Utf8 => {},
+        }
+        offsets.push(bytes.len() as u64);
+        (text, offsets)
+    }
+
+    // First match, or the last one if `last`, that starts in [from, limit).
+    // `bytes` were read from `base`.
+    fn search_window(&self, bytes: &[u8], base: u64, from: u64, limit: u64, last: bool) -> Option<(u64, u64)>
+    {
+        let transcoded;
+        let (haystack, offsets): (&[u8], Option<&[u64]>) = if self.raw {
+            (bytes, None)
+        } else {
+            transcoded = self.transcode(bytes);
+            (transcoded.0.as_bytes(), Some(&transcoded.1))
+        };
+        let to_offset = |idx: usize| base + offsets.map_or(idx as u64, |o| o[idx]);
+        let rel = from - base;
+        let mut idx = match offsets {
+            Some(o) => o.iter().position(|x| *x >= rel).unwrap_or(haystack.len()),
+            None => rel as usize,
+        };
+        let mut rv = None;
+        while idx <= haystack.len() {
+            let m = match self.regex.find_at(haystack, idx) {
+                Some(m) => m,
+                None => break,
+            };
+            let (start, end) = (to_offset(m.start()), to_offset(m.end()));
+            if start >= limit {
+                break;
+            }
+            if m.start() == m.end() {
+                // empty matches are of no use, step over one character
+                idx = m.end() + 1;
+                while offsets.is_some() && idx < haystack.len() && haystack[idx] & 0xc0 == 0x80 { idx += 1; }
+                continue;
+            }
+            rv = Some((start, end));
+            if !last {
+                break;
+            }
+            idx = m.end();
+        }
+        rv
+    }
+
+    // Searches forward for a match starting at or after `from`, or backward
+    // for one starting before it. `progress` gets the offset reached.
+    pub fn find<R: Read + Seek>(
+        &self,
+        source: &mut R,
+        len: u64,
+        from: u64,
+        backwards: bool,
+        cancel: &AtomicBool,
+        progress: &mut FnMut(u64))
+        -> io::Result<Outcome>
+    {
+        if backwards {
+            let mut end = cmp::min(from, len);
+            while end > 0 {
+                if cancel.load(Ordering::Relaxed) {
+                    return Ok(Outcome::Cancelled);
+                }
+                progress(end);
+                let window_start = self.align(end.saturating_sub(CHUNK_SIZE));
+                let read_start = self.align(window_start.saturating_sub(CONTEXT_BYTES));
+                let read_end = cmp::min(len, end + MAX_MATCH_BYTES);
+                let bytes = try!(read_at(source, read_start, read_end));
+                if let Some((start, end)) = self.search_window(&bytes, read_start, window_start, end, true) {
+                    return Ok(Outcome::Found(start, end));
+                }
+                end = window_start;
+            }
+        } else {
+            let mut pos = from;
+            while pos < len {
+                if cancel.load(Ordering::Relaxed) {
+                    return Ok(Outcome::Cancelled);
+                }
+                progress(pos);
+                let chunk_end = cmp::min(len, pos + CHUNK_SIZE);
+                let read_start = self.align(pos.saturating_sub(CONTEXT_BYTES));
+                let read_end = cmp::min(len, chunk_end + MAX_MATCH_BYTES);
+                let bytes = try!(read_at(source, read_start, read_end));
+                if let Some((start, end)) = self.search_window(&bytes, read_start, pos, chunk_end, false) {
+                    return Ok(Outcome::Found(start, end));
+                }
+                pos = chunk_end;
+            }
+        }
+        Ok(Outcome::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn options(pattern: &str, kind: PatternKind) -> SearchOptions
+    {
+        SearchOptions { pattern: pattern.to_string(), kind: kind, case_sensitive: false, whole_word: false }
+    }
+
+    fn find_in(data: &[u8], options: &SearchOptions, encoding: Encoding, from: u64, backwards: bool) -> Outcome
+    {
+        let searcher = Searcher::new(options, encoding).unwrap();
+        searcher.find(&mut Cursor::new(data), data.len() as u64, from, backwards, &AtomicBool::new(false), &mut |_| {})
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_across_chunks()
+    {
+        let mut data = vec![b'x'; 3 * CHUNK_SIZE as usize / 2];
+        data[10..15].copy_from_slice(b" Cat ");
+        data[CHUNK_SIZE as usize - 2..CHUNK_SIZE as usize + 3].copy_from_slice(b"catxx");
+        let len = data.len() as u64;
+        let word = SearchOptions { whole_word: true, .. options("cat", PatternKind::Text) };
+        assert_eq!(find_in(&data, &word, Encoding::Utf8, 0, false), Outcome::Found(11, 14));
+        assert_eq!(find_in(&data, &word, Encoding::Utf8, 12, false), Outcome::NotFound);
+        assert_eq!(find_in(&data, &word, Encoding::Utf8, len, true), Outcome::Found(11, 14));
+        let text = options("cat", PatternKind::Text);
+        assert_eq!(find_in(&data, &text, Encoding::Utf8, 12, false), Outcome::Found(CHUNK_SIZE - 2, CHUNK_SIZE + 1));
+        let hex = options("63 61 74 78", PatternKind::Hex);
+        assert_eq!(find_in(&data, &hex, Encoding::Utf8, 0, false), Outcome::Found(CHUNK_SIZE - 2, CHUNK_SIZE + 2));
+        let regex = options("c.t", PatternKind::Regex);
+        assert_eq!(find_in(&data, &regex, Encoding::Utf8, len, true), Outcome::Found(CHUNK_SIZE - 2, CHUNK_SIZE + 1));
+        assert!(Searcher::new(&options("(", PatternKind::Regex), Encoding::Utf8).is_err());
+        assert!(Searcher::new(&options("", PatternKind::Text), Encoding::Utf8).is_err());
+
+        let cancel = AtomicBool::new(true);
+        let searcher = Searcher::new(&text, Encoding::Utf8).unwrap();
+        assert_eq!(searcher.find(&mut Cursor::new(&data), len, 0, false, &cancel, &mut |_| {}).unwrap(), Outcome::Cancelled);
+    }
+
+    #[test]
+    fn hex_input()
+    {
+        assert_eq!(parse_hex_bytes("0x4D, 5a90"), Some(vec![0x4d, 0x5a, 0x90]));
+        assert_eq!(parse_hex_bytes("4D 5A 90"), Some(vec![0x4d, 0x5a, 0x90]));
+        assert_eq!(parse_hex_bytes("4"), None);
+        assert_eq!(parse_hex_bytes("zz"), None);
+        assert_eq!(parse_hex_bytes(" "), None);
+    }
+
+    #[test]
+    fn utf16_offsets()
+    {
+        let mut data = vec![0xff, 0xfe];
+        for unit in "héllo Wörld wörld".encode_utf16() {
+            data.push(unit as u8);
+            data.push((unit >> 8) as u8);
+        }
+        let pattern = options("WÖRLD", PatternKind::Text);
+        assert_eq!(find_in(&data, &pattern, Encoding::Utf16Le, 0, false), Outcome::Found(14, 24));
+        assert_eq!(find_in(&data, &pattern, Encoding::Utf16Le, 15, false), Outcome::Found(26, 36));
+        assert_eq!(find_in(&data, &pattern, Encoding::Utf16Le, data.len() as u64, true), Outcome::Found(26, 36));
+    }
+
+    #[test]
+    fn single_byte_code_pages()
+    {
+        let pattern = SearchOptions { case_sensitive: true, .. options("é€", PatternKind::Text) };
+        assert_eq!(find_in(b"abc\xe9\x80", &pattern, Encoding::CodePage(1252), 0, false), Outcome::Found(3, 5));
+    }
+
+    #[test]
+    fn double_byte_code_pages()
+    {
+        // U+8868 is 0x95 0x5C in Shift-JIS, the second byte that of `\`;
+        // 0x88 0x9F has a second byte that is also a first one
+        let data = b"ab\x95\x5cx\x88\x9f\x95\x5c\\";
+        let shift_jis = Encoding::CodePage(932);
+        let backslash = options("\\", PatternKind::Text);
+        assert_eq!(find_in(data, &backslash, shift_jis, 0, false), Outcome::Found(9, 10));
+        assert_eq!(find_in(data, &backslash, shift_jis, 9, true), Outcome::NotFound);
+        // a match of several two-byte characters
+        let kanji = encoding::decode(b"\x95\x5c", shift_jis);
+        let pair = options(&format!("{}x{}", kanji, encoding::decode(b"\x88\x9f", shift_jis)), PatternKind::Text);
+        assert_eq!(find_in(data, &pair, shift_jis, 0, false), Outcome::Found(2, 7));
+        assert_eq!(find_in(data, &pair, shift_jis, 10, true), Outcome::Found(2, 7));
+        let regex = options(&format!("{}\\\\", kanji), PatternKind::Regex);
+        assert_eq!(find_in(data, &regex, shift_jis, 0, false), Outcome::Found(7, 10));
+
+        // GBK second bytes start at 0x40, `@`
+        let gbk = Encoding::CodePage(936);
+        assert_eq!(find_in(b"\xd6\x40\xd6\x40@", &options("@", PatternKind::Text), gbk, 0, false), Outcome::Found(4, 5));
+        // hex patterns still match the bytes as they are
+        assert_eq!(find_in(data, &options("5C", PatternKind::Hex), shift_jis, 0, false), Outcome::Found(3, 4));
+    }
+
+    #[test]
+    fn double_byte_text_across_chunks()
+    {
+        // a line of two-byte characters over the chunk boundary, so the
+        // second chunk starts inside one, then a line with a `\`
+        let mut data = b"x".to_vec();
+        while data.len() < CHUNK_SIZE as usize + 100 {
+            data.extend_from_slice(b"\x88\x9f");
+        }
+        data.extend_from_slice(b"\n\x95\x5c\\");
+        let backslash = options("\\", PatternKind::Text);
+        let end = data.len() as u64;
+        assert_eq!(find_in(&data, &backslash, Encoding::CodePage(932), 0, false), Outcome::Found(end - 1, end));
+        assert_eq!(find_in(&data, &backslash, Encoding::CodePage(932), CHUNK_SIZE + 1, false), Outcome::Found(end - 1, end));
+    }
+}
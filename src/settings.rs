@@ -0,0 +1,137 @@
+// User settings, stored as an ini-style text file:
+// `%APPDATA%\trusty-commander\settings.ini` on Windows,
+// `$XDG_CONFIG_HOME/trusty-commander/settings.ini` elsewhere.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use ::size_format::SizeFormat;
+use ::command_template::EditorSettings;
+use ::associations::Associations;
+
+pub struct Settings {
+    pub size_format: SizeFormat,
+    pub show_hidden: bool,
+    // F4; `[editor]` holds `command=` and `ext1,ext2=` overrides
+    pub editor: EditorSettings,
+    // Enter; `[associations]` holds `mask;mask=command` lines
+    pub associations: Associations,
+}
+
+// (section, key, value) triples in file order; blank lines and `;`/`#` comments are skipped.
+pub fn parse_ini(text: &str) -> Vec<(String, String, String)>
+{
+    let mut rv = Vec::new();
+    let mut section = String::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+        if let Some(eq) = line.find('=') {
+            let key = line[..eq].trim().to_string();
+            let value = line[eq + 1..].trim().to_string();
+            rv.push((section.clone(), key, value));
+        }
+    }
+    rv
+}
+
+pub fn parse_bool(value: &str) -> Option<bool>
+{
+    match value {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+impl Settings {
+    pub fn new() -> Settings
+    {
+        Settings {
+            size_format: SizeFormat::Binary,
+            show_hidden: false,
+            editor: EditorSettings::new(),
+            associations: Associations::new(),
+        }
+    }
+
+    // Unknown keys and malformed values are ignored, keeping the defaults.
+    pub fn parse(text: &str) -> Settings
+    {
+        let mut settings = Settings::new();
+        for (section, key, value) in parse_ini(text) {
+            match (section.as_str(), key.as_str()) {
+                ("panels", "size_format") => {
+                    if let Some(format) = SizeFormat::from_name(&value) {
+                        settings.size_format = format;
+                    }
+                },
+                ("panels", "show_hidden") => {
+                    if let Some(show) = parse_bool(&value) {
+                        settings.show_hidden = show;
+                    }
+                },
+                ("editor", "command") => settings.editor.command = value,
+                ("editor", extensions) => settings.editor.set_override(extensions, &value),
+                ("associations", masks) => settings.associations.add(masks, &value),
+                _ => {},
+            }
+        }
+        settings
+    }
+
+    pub fn to_text(&self) -> String
+    {
+        let mut text = String::new();
+        text.push_str("[panels]\n");
+        text.push_str(&format!("size_format={}\n", self.size_format.name()));
+        text.push_str(&format!("show_hidden={}\n", if self.show_hidden { 1 } else { 0 }));
+        text.push_str("\n[editor]\n");
+        text.push_str(&format!("command={}\n", self.editor.command));
+        for &(ref extensions, ref template) in &self.editor.overrides {
+            text.push_str(&format!("{}={}\n", extensions.join(","), template));
+        }
+        text.push_str("\n[associations]\n");
+        for association in &self.associations.entries {
+            text.push_str(&format!("{}={}\n", association.masks, association.command));
+        }
+        text
+    }
+
+    pub fn path() -> Option<PathBuf>
+    {
+        let base = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        };
+        base.map(|dir| dir.join("trusty-commander").join("settings.ini"))
+    }
+
+    // A missing or unreadable file gives the defaults.
+    pub fn load() -> Settings
+    {
+        Settings::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map_or_else(Settings::new, |text| Settings::parse(&text))
+    }
+
+    pub fn save(&self) -> io::Result<()>
+    {
+        let path = try!(Settings::path().ok_or_else(||
+            io::Error::new(io::ErrorKind::NotFound, "No configuration directory")));
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        fs::write(path, self.to_text())
+    }
+}
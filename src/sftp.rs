@@ -0,0 +1,1571 @@
+// SFTP sites shown in a panel, at `\\sftp\<site>\`. libssh2 makes the SSH
+// connection and logs in, with a password or a private key; the SFTP protocol
+// itself (version 3, as OpenSSH speaks it) runs over its channel here. The
+// server's host key is looked up in the user's `known_hosts`: an unknown key
+// is shown to the user to accept, a changed one refuses the connection.
+//
+// The file system and its transfers share one connection, a request at a
+// time. A lost connection is opened again, and transfers go on from where
+// they were, as each read and write names its offset in the file.
+
+extern crate base64;
+extern crate ssh2;
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use self::base64::Engine;
+use self::base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use self::ssh2::{CheckResult, HashType, HostKeyType, KnownHostFileKind, KnownHosts, Session};
+
+use ::remote::{self, TIMEOUT, is_lost, join};
+use ::sites::Site;
+use ::vfs::{DirEntry, Metadata, Vfs};
+
+// a request the connection was lost during is sent again on a new one, this
+// many times in all, when sending it twice does no harm
+const ATTEMPTS: usize = 3;
+// bytes asked for or sent in a read or write request; servers take 32K at least
+const CHUNK: usize = 32768;
+// replies longer than this are taken for garbage
+const MAX_PACKET: usize = 1 << 20;
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_LSTAT: u8 = 7;
+const FXP_SETSTAT: u8 = 9;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_REMOVE: u8 = 13;
+const FXP_MKDIR: u8 = 14;
+const FXP_RMDIR: u8 = 15;
+const FXP_REALPATH: u8 = 16;
+const FXP_STAT: u8 = 17;
+const FXP_RENAME: u8 = 18;
+const FXP_READLINK: u8 = 19;
+const FXP_SYMLINK: u8 = 20;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+const ATTR_SIZE: u32 = 0x1;
+const ATTR_UIDGID: u32 = 0x2;
+const ATTR_PERMISSIONS: u32 = 0x4;
+const ATTR_ACMODTIME: u32 = 0x8;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+const OPEN_READ: u32 = 0x1;
+const OPEN_WRITE: u32 = 0x2;
+const OPEN_CREATE: u32 = 0x8;
+const OPEN_TRUNCATE: u32 = 0x10;
+
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+const FX_NO_SUCH_FILE: u32 = 2;
+const FX_PERMISSION_DENIED: u32 = 3;
+const FX_NO_CONNECTION: u32 = 6;
+const FX_CONNECTION_LOST: u32 = 7;
+const FX_OP_UNSUPPORTED: u32 = 8;
+
+const TYPE_MASK: u32 = 0o170000;
+const TYPE_DIR: u32 = 0o040000;
+const TYPE_LINK: u32 = 0o120000;
+
+// What SFTP runs over: an SSH channel, or anything else speaking it.
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+// Opens a new connection to the server, whenever one is needed.
+pub type Connector = Box<Fn() -> io::Result<Box<Stream>> + Send + Sync>;
+
+fn lost(e: io::Error) -> io::Error
+{ io::Error::new(io::ErrorKind::ConnectionAborted, format!("The connection to the server was lost: {}", e)) }
+
+fn bad_reply() -> io::Error
+{ io::Error::new(io::ErrorKind::InvalidData, "The server sent a reply that cannot be read") }
+
+// A request being put together.
+struct Packet(Vec<u8>);
+
+impl Packet {
+    fn new(kind: u8) -> Packet
+    { Packet(vec![kind]) }
+
+    fn u32(mut self, value: u32) -> Packet
+    {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn u64(mut self, value: u64) -> Packet
+    {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn bytes(self, value: &[u8]) -> Packet
+    {
+        let mut packet = self.u32(value.len() as u32);
+        packet.0.extend_from_slice(value);
+        packet
+    }
+
+    fn string(self, value: &str) -> Packet
+    { self.bytes(value.as_bytes()) }
+}
+
+// A reply being read, past its type and request id.
+struct Reply {
+    kind: u8,
+    data: Vec<u8>,
+    at: usize,
+}
+
+impl Reply {
+    fn take(&mut self, len: usize) -> io::Result<&[u8]>
+    {
+        if self.data.len() - self.at < len {
+            return Err(bad_reply());
+        }
+        self.at += len;
+        Ok(&self.data[self.at - len..self.at])
+    }
+
+    fn u32(&mut self) -> io::Result<u32>
+    {
+        let bytes = try!(self.take(4));
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> io::Result<u64>
+    { Ok((try!(self.u32()) as u64) << 32 | try!(self.u32()) as u64) }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>>
+    {
+        let len = try!(self.u32()) as usize;
+        self.take(len).map(|bytes| bytes.to_vec())
+    }
+
+    fn string(&mut self) -> io::Result<String>
+    { self.bytes().map(|bytes| String::from_utf8_lossy(&bytes).into_owned()) }
+
+    fn attrs(&mut self) -> io::Result<Attrs>
+    {
+        let flags = try!(self.u32());
+        let mut attrs = Attrs { size: None, permissions: None, modified: None };
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(try!(self.u64()));
+        }
+        if flags & ATTR_UIDGID != 0 {
+            try!(self.take(8));
+        }
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(try!(self.u32()));
+        }
+        if flags & ATTR_ACMODTIME != 0 {
+            try!(self.u32());
+            attrs.modified = Some(try!(self.u32()));
+        }
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..try!(self.u32()) {
+                try!(self.bytes());
+                try!(self.bytes());
+            }
+        }
+        Ok(attrs)
+    }
+
+    // A STATUS reply of success, the error it tells of otherwise.
+    fn status(&mut self, path: &str) -> io::Result<()>
+    {
+        if self.kind != FXP_STATUS {
+            return Err(bad_reply());
+        }
+        let code = try!(self.u32());
+        // SFTP 3 servers may leave the message out
+        let message = self.string().unwrap_or_default();
+        let kind = match code {
+            FX_OK => return Ok(()),
+            FX_EOF => io::ErrorKind::UnexpectedEof,
+            FX_NO_SUCH_FILE => io::ErrorKind::NotFound,
+            FX_PERMISSION_DENIED => io::ErrorKind::PermissionDenied,
+            FX_NO_CONNECTION | FX_CONNECTION_LOST => io::ErrorKind::ConnectionAborted,
+            FX_OP_UNSUPPORTED => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::Other,
+        };
+        let message = if message.is_empty() { format!("error {}", code) } else { message };
+        Err(io::Error::new(kind, format!("\"{}\": {}", path, message)))
+    }
+
+    // A reply of `kind`, or else the error in a STATUS one.
+    fn expect(mut self, kind: u8, path: &str) -> io::Result<Reply>
+    {
+        if self.kind == kind {
+            return Ok(self);
+        }
+        try!(self.status(path));
+        Err(bad_reply())
+    }
+}
+
+// What a file or directory has on the server; anything may be missing.
+struct Attrs {
+    size: Option<u64>,
+    permissions: Option<u32>,
+    modified: Option<u32>,
+}
+
+impl Attrs {
+    fn has_type(&self, kind: u32) -> bool
+    { self.permissions.map_or(false, |mode| mode & TYPE_MASK == kind) }
+
+    fn metadata(&self, name: &str) -> Metadata
+    {
+        let is_dir = self.has_type(TYPE_DIR);
+        Metadata {
+            is_dir: is_dir,
+            size: if is_dir { 0 } else { self.size.unwrap_or(0) },
+            modified: self.modified.map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds as u64)),
+            hidden: name.starts_with('.'),
+            read_only: self.permissions.map_or(false, |mode| mode & 0o200 == 0),
+            is_link: self.has_type(TYPE_LINK),
+            mode: self.permissions.map(|mode| mode & 0o7777),
+        }
+    }
+}
+
+// An SFTP session over a connection.
+struct Client {
+    stream: Box<Stream>,
+    next_id: u32,
+}
+
+impl Client {
+    fn start(stream: Box<Stream>) -> io::Result<Client>
+    {
+        let mut client = Client { stream: stream, next_id: 1 };
+        try!(client.send(Packet::new(FXP_INIT).u32(3)));
+        let (kind, _) = try!(client.receive());
+        if kind != FXP_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "The server does not speak SFTP"));
+        }
+        Ok(client)
+    }
+
+    fn send(&mut self, packet: Packet) -> io::Result<()>
+    {
+        let len = (packet.0.len() as u32).to_be_bytes();
+        try!(self.stream.write_all(&len).map_err(lost));
+        try!(self.stream.write_all(&packet.0).map_err(lost));
+        self.stream.flush().map_err(lost)
+    }
+
+    // The type and the rest of a packet from the server.
+    fn receive(&mut self) -> io::Result<(u8, Vec<u8>)>
+    {
+        let mut len = [0; 4];
+        try!(self.stream.read_exact(&mut len).map_err(lost));
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 || len > MAX_PACKET {
+            return Err(lost(bad_reply()));
+        }
+        let mut data = vec![0; len];
+        try!(self.stream.read_exact(&mut data).map_err(lost));
+        let kind = data.remove(0);
+        Ok((kind, data))
+    }
+
+    // A request of `kind` with a fresh id; the rest is added by the caller.
+    fn request(&mut self, kind: u8) -> Packet
+    {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        Packet::new(kind).u32(id)
+    }
+
+    // Sends a request and waits for its reply; requests are not pipelined,
+    // so a reply to anything else is the connection gone wrong.
+    fn call(&mut self, packet: Packet) -> io::Result<Reply>
+    {
+        let id = [packet.0[1], packet.0[2], packet.0[3], packet.0[4]];
+        try!(self.send(packet));
+        let (kind, data) = try!(self.receive());
+        if data.len() < 4 || data[..4] != id {
+            return Err(lost(bad_reply()));
+        }
+        Ok(Reply { kind: kind, data: data, at: 4 })
+    }
+
+    fn simple(&mut self, packet: Packet, path: &str) -> io::Result<()>
+    { try!(self.call(packet)).status(path) }
+
+    fn realpath(&mut self, path: &str) -> io::Result<String>
+    {
+        let packet = self.request(FXP_REALPATH).string(path);
+        let mut reply = try!(try!(self.call(packet)).expect(FXP_NAME, path));
+        if try!(reply.u32()) == 0 {
+            return Err(bad_reply());
+        }
+        reply.string()
+    }
+
+    fn stat(&mut self, path: &str, follow: bool) -> io::Result<Attrs>
+    {
+        let packet = self.request(if follow { FXP_STAT } else { FXP_LSTAT }).string(path);
+        try!(try!(self.call(packet)).expect(FXP_ATTRS, path)).attrs()
+    }
+
+    fn handle(&mut self, packet: Packet, path: &str) -> io::Result<Vec<u8>>
+    { try!(try!(self.call(packet)).expect(FXP_HANDLE, path)).bytes() }
+
+    fn close(&mut self, handle: &[u8], path: &str) -> io::Result<()>
+    {
+        let packet = self.request(FXP_CLOSE).bytes(handle);
+        self.simple(packet, path)
+    }
+
+    // Names and attributes in the directory `dir`, without `.` and `..`.
+    fn list(&mut self, dir: &str) -> io::Result<Vec<(String, Attrs)>>
+    {
+        let packet = self.request(FXP_OPENDIR).string(dir);
+        let handle = try!(self.handle(packet, dir));
+        let mut entries = Vec::new();
+        loop {
+            let packet = self.request(FXP_READDIR).bytes(&handle);
+            let mut reply = try!(self.call(packet));
+            if reply.kind != FXP_NAME {
+                match reply.status(dir) {
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                    Err(e) => return Err(e),
+                    Ok(()) => return Err(bad_reply()),
+                }
+            }
+            for _ in 0..try!(reply.u32()) {
+                let name = try!(reply.string());
+                // the `ls -l` line
+                try!(reply.bytes());
+                let attrs = try!(reply.attrs());
+                if name != "." && name != ".." {
+                    entries.push((name, attrs));
+                }
+            }
+        }
+        try!(self.close(&handle, dir));
+        Ok(entries)
+    }
+
+    fn open(&mut self, path: &str, flags: u32) -> io::Result<Vec<u8>>
+    {
+        let packet = self.request(FXP_OPEN).string(path).u32(flags).u32(0);
+        self.handle(packet, path)
+    }
+
+    // Up to `len` bytes at `offset`, none at the end of the file.
+    fn read(&mut self, handle: &[u8], offset: u64, len: usize, path: &str) -> io::Result<Option<Vec<u8>>>
+    {
+        let packet = self.request(FXP_READ).bytes(handle).u64(offset).u32(len as u32);
+        let mut reply = try!(self.call(packet));
+        if reply.kind == FXP_DATA {
+            return reply.bytes().map(Some);
+        }
+        match reply.status(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+            Ok(()) => Err(bad_reply()),
+        }
+    }
+
+    fn write(&mut self, handle: &[u8], offset: u64, data: &[u8], path: &str) -> io::Result<()>
+    {
+        let packet = self.request(FXP_WRITE).bytes(handle).u64(offset).bytes(data);
+        self.simple(packet, path)
+    }
+
+    fn mkdir(&mut self, path: &str) -> io::Result<()>
+    {
+        let packet = self.request(FXP_MKDIR).string(path).u32(0);
+        self.simple(packet, path)
+    }
+
+    fn rmdir(&mut self, path: &str) -> io::Result<()>
+    {
+        let packet = self.request(FXP_RMDIR).string(path);
+        self.simple(packet, path)
+    }
+
+    fn remove(&mut self, path: &str) -> io::Result<()>
+    {
+        let packet = self.request(FXP_REMOVE).string(path);
+        self.simple(packet, path)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> io::Result<()>
+    {
+        let packet = self.request(FXP_RENAME).string(from).string(to);
+        self.simple(packet, from)
+    }
+
+    fn set_modified(&mut self, path: &str, seconds: u32) -> io::Result<()>
+    {
+        let packet = self.request(FXP_SETSTAT).string(path).u32(ATTR_ACMODTIME).u32(seconds).u32(seconds);
+        self.simple(packet, path)
+    }
+
+    fn read_link(&mut self, path: &str) -> io::Result<String>
+    {
+        let packet = self.request(FXP_READLINK).string(path);
+        let mut reply = try!(try!(self.call(packet)).expect(FXP_NAME, path));
+        if try!(reply.u32()) == 0 {
+            return Err(bad_reply());
+        }
+        reply.string()
+    }
+
+    // OpenSSH takes the target first, against what the draft says, and every
+    // server follows it.
+    fn symlink(&mut self, target: &str, path: &str) -> io::Result<()>
+    {
+        let packet = self.request(FXP_SYMLINK).string(target).string(path);
+        self.simple(packet, path)
+    }
+}
+
+struct State {
+    client: Option<Client>,
+    // counts the connections made, so handles from a lost one are not used
+    generation: u64,
+}
+
+// What the file system and its transfers share.
+struct Shared {
+    connector: Connector,
+    state: Mutex<State>,
+}
+
+impl Shared {
+    // Runs `f` on the connection, with its generation, connecting first when
+    // there is none. A lost connection is replaced and `f` run again: `f`
+    // only reads, opens, stats, or writes at an offset.
+    fn call<T, F>(&self, f: F) -> io::Result<T>
+        where F: FnMut(&mut Client, u64) -> io::Result<T>
+    { self.attempt(ATTEMPTS, f) }
+
+    // Runs `f`, which changes the server's files, once: sent again, it could
+    // fail for having been done already. When the connection is lost on the
+    // way, `done` looks on a new one for whether it went through; if it
+    // cannot tell, the lost connection is reported.
+    fn change<F, D>(&self, f: F, mut done: D) -> io::Result<()>
+        where F: FnMut(&mut Client, u64) -> io::Result<()>, D: FnMut(&mut Client) -> io::Result<bool>
+    {
+        let rv = self.attempt(1, f);
+        if rv.as_ref().err().map_or(false, is_lost) && self.call(|client, _| done(client)).unwrap_or(false) {
+            return Ok(());
+        }
+        rv
+    }
+
+    fn attempt<T, F>(&self, tries: usize, mut f: F) -> io::Result<T>
+        where F: FnMut(&mut Client, u64) -> io::Result<T>
+    {
+        let mut state = self.state.lock().unwrap();
+        let mut attempts = 0;
+        loop {
+            if state.client.is_none() {
+                let stream = try!((self.connector)());
+                state.client = Some(try!(Client::start(stream)));
+                state.generation += 1;
+            }
+            let generation = state.generation;
+            let rv = f(state.client.as_mut().unwrap(), generation);
+            let gone = rv.as_ref().err().map_or(false, is_lost);
+            if gone {
+                state.client = None;
+            }
+            attempts += 1;
+            if !gone || attempts == tries {
+                return rv;
+            }
+        }
+    }
+}
+
+pub struct SftpFs {
+    shared: Arc<Shared>,
+    // the path the server's `/` is shown at
+    root: PathBuf,
+    // the remote directory the panel starts in
+    start: String,
+}
+
+impl SftpFs {
+    // Connects through `connector`, to be shown at `root`. An empty
+    // `directory` starts at the user's home directory.
+    pub fn open(connector: Connector, directory: &str, root: &Path) -> io::Result<SftpFs>
+    {
+        let shared = Arc::new(Shared {
+            connector: connector,
+            state: Mutex::new(State { client: None, generation: 0 }),
+        });
+        let start = if directory.is_empty() {
+            try!(shared.call(|client, _| client.realpath(".")))
+        } else {
+            directory.to_string()
+        };
+        Ok(SftpFs { shared: shared, root: root.to_path_buf(), start: start })
+    }
+
+    // Where the panel starts.
+    pub fn start_path(&self) -> PathBuf
+    {
+        let mut path = self.root.clone();
+        for name in self.start.split('/').filter(|name| !name.is_empty()) {
+            path.push(name);
+        }
+        path
+    }
+
+    // `path` on the server.
+    fn remote(&self, path: &Path) -> io::Result<String>
+    {
+        let rest = try!(path.strip_prefix(&self.root).map_err(|_|
+            io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not in \"{}\"", path.display(), self.root.display()))));
+        let names: Vec<String> = rest.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        Ok(format!("/{}", names.join("/")))
+    }
+
+    fn transfer(&self, path: &Path, writing: bool) -> io::Result<Transfer>
+    {
+        let mut transfer = Transfer {
+            shared: self.shared.clone(),
+            path: try!(self.remote(path)),
+            writing: writing,
+            handle: None,
+            offset: 0,
+            done: false,
+        };
+        // opened right away, for a missing file to fail here and an empty
+        // upload to leave a file
+        try!(transfer.with_handle(|_, _| Ok(())));
+        Ok(transfer)
+    }
+}
+
+// Links are shown as what they point to, a link to a directory as one.
+fn entry_metadata(client: &mut Client, path: &str, name: &str, attrs: &Attrs) -> io::Result<Metadata>
+{
+    let mut meta = attrs.metadata(name);
+    if meta.is_link {
+        match client.stat(path, true) {
+            Ok(target) => {
+                meta.is_dir = target.has_type(TYPE_DIR);
+                meta.size = if meta.is_dir { 0 } else { target.size.unwrap_or(0) };
+            },
+            // a dangling link is a file
+            Err(ref e) if !is_lost(e) => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(meta)
+}
+
+// Whether there is anything at `path`, a link itself rather than what it
+// points to.
+fn exists(client: &mut Client, path: &str) -> io::Result<bool>
+{
+    match client.stat(path, false) {
+        Ok(_) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// A file being downloaded or uploaded. A download is complete once read to
+// the end, an upload once flushed or dropped.
+struct Transfer {
+    shared: Arc<Shared>,
+    path: String,
+    writing: bool,
+    // the remote file's handle and the generation of the connection it is on
+    handle: Option<(Vec<u8>, u64)>,
+    offset: u64,
+    done: bool,
+}
+
+impl Transfer {
+    // Runs `f` with the file's handle. On a new connection the file is opened
+    // again; an upload is then not truncated, to go on at `offset`.
+    fn with_handle<T, F>(&mut self, mut f: F) -> io::Result<T>
+        where F: FnMut(&mut Client, &[u8]) -> io::Result<T>
+    {
+        let flags = match (self.writing, self.offset) {
+            (false, _) => OPEN_READ,
+            (true, 0) => OPEN_WRITE | OPEN_CREATE | OPEN_TRUNCATE,
+            (true, _) => OPEN_WRITE | OPEN_CREATE,
+        };
+        let (path, handle) = (&self.path, &mut self.handle);
+        self.shared.call(|client, generation| {
+            let current = match *handle {
+                Some((ref current, opened_on)) if opened_on == generation => current.clone(),
+                _ => {
+                    let opened = try!(client.open(path, flags));
+                    *handle = Some((opened.clone(), generation));
+                    opened
+                },
+            };
+            f(client, &current)
+        })
+    }
+
+    fn finish(&mut self) -> io::Result<()>
+    {
+        if self.done {
+            return Ok(());
+        }
+        self.done = true;
+        match self.handle.take() {
+            // a handle on a lost connection is gone with it
+            Some((handle, opened_on)) => {
+                let path = &self.path;
+                self.shared.call(|client, generation|
+                    if generation == opened_on { client.close(&handle, path) } else { Ok(()) })
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Read for Transfer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        let (offset, len) = (self.offset, ::std::cmp::min(buf.len(), CHUNK));
+        let path = self.path.clone();
+        match try!(self.with_handle(|client, handle| client.read(handle, offset, len, &path))) {
+            Some(ref data) if !data.is_empty() => {
+                let read = ::std::cmp::min(data.len(), len);
+                buf[..read].copy_from_slice(&data[..read]);
+                self.offset += read as u64;
+                Ok(read)
+            },
+            _ => {
+                try!(self.finish());
+                Ok(0)
+            },
+        }
+    }
+}
+
+impl Write for Transfer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        if self.done {
+            return Err(io::Error::new(io::ErrorKind::Other, "The file is already complete"));
+        }
+        let (offset, len) = (self.offset, ::std::cmp::min(buf.len(), CHUNK));
+        let path = self.path.clone();
+        try!(self.with_handle(|client, handle| client.write(handle, offset, &buf[..len], &path)));
+        self.offset += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        if self.writing { self.finish() } else { Ok(()) }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self)
+    { let _ = self.finish(); }
+}
+
+impl Vfs for SftpFs {
+    fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>>
+    {
+        let remote = try!(self.remote(dir));
+        self.shared.call(|client, _| {
+            let mut entries = Vec::new();
+            for (name, attrs) in try!(client.list(&remote)) {
+                let meta = try!(entry_metadata(client, &join(&remote, &name), &name, &attrs));
+                entries.push(DirEntry { name: name, meta: meta });
+            }
+            Ok(entries)
+        })
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Metadata>
+    {
+        let remote = try!(self.remote(path));
+        let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        self.shared.call(|client, _| {
+            let attrs = try!(client.stat(&remote, false));
+            entry_metadata(client, &remote, &name, &attrs)
+        })
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>>
+    {
+        let transfer = try!(self.transfer(path, false));
+        Ok(Box::new(transfer))
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>
+    {
+        let transfer = try!(self.transfer(path, true));
+        Ok(Box::new(transfer))
+    }
+
+    fn mkdir(&self, path: &Path) -> io::Result<()>
+    {
+        let remote = try!(self.remote(path));
+        self.shared.change(|client, _| client.mkdir(&remote),
+                           |client| client.stat(&remote, true).map(|attrs| attrs.has_type(TYPE_DIR)))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()>
+    {
+        let remote = try!(self.remote(path));
+        self.shared.change(|client, _| {
+            let attrs = try!(client.stat(&remote, false));
+            if attrs.has_type(TYPE_DIR) { client.rmdir(&remote) } else { client.remove(&remote) }
+        }, |client| exists(client, &remote).map(|exists| !exists))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>
+    {
+        let (from, to) = (try!(self.remote(from)), try!(self.remote(to)));
+        self.shared.change(|client, _| client.rename(&from, &to),
+                           |client| Ok(!try!(exists(client, &from)) && try!(exists(client, &to))))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>
+    {
+        let remote = try!(self.remote(path));
+        self.shared.call(|client, _| client.read_link(&remote)).map(PathBuf::from)
+    }
+
+    // Targets are written with forward slashes, as the server reads them.
+    fn symlink(&self, target: &Path, path: &Path, _is_dir: bool) -> io::Result<()>
+    {
+        let remote = try!(self.remote(path));
+        let target = target.to_string_lossy().replace('\\', "/");
+        self.shared.change(|client, _| client.symlink(&target, &remote),
+                           |client| client.read_link(&remote).map(|found| found == target))
+    }
+
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()>
+    {
+        let remote = try!(self.remote(path));
+        let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as u32;
+        self.shared.call(|client, _| client.set_modified(&remote, seconds))
+    }
+
+    fn mount_point(&self) -> Option<PathBuf>
+    { Some(self.root.clone()) }
+}
+
+pub enum Login {
+    Password(String),
+    // a private key file and its passphrase, empty if there is none
+    Key(PathBuf, String),
+}
+
+// A host key not in `known_hosts`, for the user to accept or not.
+pub struct HostKey {
+    pub host: String,
+    // as `known_hosts` names it: `ssh-ed25519` and the like
+    pub kind: &'static str,
+    // `SHA256:` and the hash in base64, as `ssh-keygen -l` shows it
+    pub fingerprint: String,
+}
+
+// The user's `known_hosts`, which OpenSSH reads too.
+pub fn known_hosts_path() -> Option<PathBuf>
+{
+    let home = env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" });
+    home.map(|home| PathBuf::from(home).join(".ssh").join("known_hosts"))
+}
+
+fn key_type_name(kind: HostKeyType) -> Option<&'static str>
+{
+    match kind {
+        HostKeyType::Rsa => Some("ssh-rsa"),
+        HostKeyType::Dss => Some("ssh-dss"),
+        HostKeyType::Ecdsa256 => Some("ecdsa-sha2-nistp256"),
+        HostKeyType::Ecdsa384 => Some("ecdsa-sha2-nistp384"),
+        HostKeyType::Ecdsa521 => Some("ecdsa-sha2-nistp521"),
+        HostKeyType::Ed25519 => Some("ssh-ed25519"),
+        HostKeyType::Unknown => None,
+    }
+}
+
+// How `known_hosts` names the server: `[host]:port` when not on port 22.
+fn known_host_name(site: &Site) -> String
+{
+    if site.port == 22 { site.host.clone() } else { format!("[{}]:{}", site.host, site.port) }
+}
+
+fn changed_key(site: &Site) -> io::Error
+{
+    io::Error::new(io::ErrorKind::PermissionDenied,
+                   format!("The host key of {} has changed; it may be another server posing as it", site.host))
+}
+
+fn open_session(site: &Site) -> io::Result<Session>
+{
+    let stream = try!(remote::connect(site));
+    let mut session = try!(Session::new());
+    session.set_tcp_stream(stream);
+    session.set_timeout(TIMEOUT.as_secs() as u32 * 1000);
+    try!(session.handshake());
+    Ok(session)
+}
+
+// Adds the line for a host to `known_hosts`, leaving the rest of it as it is.
+fn remember(path: &Path, line: &str) -> io::Result<()>
+{
+    if let Some(dir) = path.parent() {
+        try!(fs::create_dir_all(dir));
+    }
+    let ends_line = fs::read(path).map_or(true, |data| data.is_empty() || data.ends_with(b"\n"));
+    let mut file = try!(OpenOptions::new().create(true).append(true).open(path));
+    file.write_all(format!("{}{}\n", if ends_line { "" } else { "\n" }, line).as_bytes())
+}
+
+// The server's host key once it is known or accepted.
+fn check_host_key(session: &Session, site: &Site, known_hosts: Option<&Path>,
+                  trust: &mut FnMut(&HostKey) -> bool) -> io::Result<Vec<u8>>
+{
+    let (key, kind) = try!(session.host_key().ok_or_else(||
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} sent no host key", site.host))));
+    let hash = session.host_key_hash(HashType::Sha256).unwrap_or(&[]);
+    check_key(try!(session.known_hosts()), key, kind, hash, site, known_hosts, trust)
+}
+
+// `key`, of type `kind` and with the SHA-256 `hash`, once it is in
+// `known_hosts` or accepted.
+fn check_key(mut hosts: KnownHosts, key: &[u8], kind: HostKeyType, hash: &[u8], site: &Site,
+             known_hosts: Option<&Path>, trust: &mut FnMut(&HostKey) -> bool) -> io::Result<Vec<u8>>
+{
+    if let Some(path) = known_hosts.filter(|path| path.exists()) {
+        try!(hosts.read_file(path, KnownHostFileKind::OpenSSH));
+    }
+    match hosts.check_port(&site.host, site.port, key) {
+        CheckResult::Match => return Ok(key.to_vec()),
+        CheckResult::Mismatch => return Err(changed_key(site)),
+        CheckResult::Failure =>
+            return Err(io::Error::new(io::ErrorKind::Other, format!("The host key of {} could not be checked", site.host))),
+        CheckResult::NotFound => {},
+    }
+    let name = try!(key_type_name(kind).ok_or_else(||
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} sent a host key of an unknown type", site.host))));
+    let host_key = HostKey {
+        host: site.host.clone(),
+        kind: name,
+        fingerprint: format!("SHA256:{}", STANDARD_NO_PAD.encode(hash)),
+    };
+    if !trust(&host_key) {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("The host key of {} was not accepted", site.host)));
+    }
+    // the connection goes ahead when the key cannot be saved; it is asked
+    // about again next time
+    if let Some(path) = known_hosts {
+        let _ = remember(path, &format!("{} {} {}", known_host_name(site), name, STANDARD.encode(key)));
+    }
+    Ok(key.to_vec())
+}
+
+// The ways of logging in to a session; errors are the server's reasons.
+trait UserAuth {
+    fn password(&self, user: &str, password: &str) -> Result<(), String>;
+    fn key_file(&self, user: &str, key_file: &Path, passphrase: Option<&str>) -> Result<(), String>;
+    fn authenticated(&self) -> bool;
+}
+
+impl UserAuth for Session {
+    fn password(&self, user: &str, password: &str) -> Result<(), String>
+    { self.userauth_password(user, password).map_err(|e| e.message().to_string()) }
+
+    fn key_file(&self, user: &str, key_file: &Path, passphrase: Option<&str>) -> Result<(), String>
+    { self.userauth_pubkey_file(user, None, key_file, passphrase).map_err(|e| e.message().to_string()) }
+
+    fn authenticated(&self) -> bool
+    { Session::authenticated(self) }
+}
+
+fn log_in(auth: &UserAuth, site: &Site, login: &Login) -> io::Result<()>
+{
+    if site.user.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No user name to log in to {} with", site.host)));
+    }
+    let rv = match *login {
+        Login::Password(ref password) => auth.password(&site.user, password),
+        Login::Key(ref key_file, ref passphrase) => {
+            let passphrase = if passphrase.is_empty() { None } else { Some(passphrase.as_str()) };
+            auth.key_file(&site.user, key_file, passphrase)
+        },
+    };
+    let refused = match rv {
+        Err(reason) => Some(reason),
+        Ok(()) if !auth.authenticated() => Some("the server refused".to_string()),
+        Ok(()) => None,
+    };
+    match refused {
+        Some(reason) => Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                           format!("Cannot log in to {} as {}: {}", site.host, site.user, reason))),
+        None => Ok(()),
+    }
+}
+
+fn start_sftp(session: Session, site: &Site, login: &Login) -> io::Result<Box<Stream>>
+{
+    try!(log_in(&session, site, login));
+    let mut channel = try!(session.channel_session());
+    try!(channel.subsystem("sftp"));
+    Ok(Box::new(channel))
+}
+
+// Connects to `site` over SSH, checking the host key against `known_hosts`
+// and asking `trust` about a key not there, and logs in. The connector
+// returned gives this connection first; later ones must come from the same
+// key.
+pub fn ssh_connector(site: &Site, login: Login, known_hosts: Option<&Path>,
+                     trust: &mut FnMut(&HostKey) -> bool) -> io::Result<Connector>
+{
+    let session = try!(open_session(site));
+    let key = try!(check_host_key(&session, site, known_hosts, trust));
+    let first = Mutex::new(Some(try!(start_sftp(session, site, &login))));
+    let site = site.clone();
+    Ok(Box::new(move || {
+        if let Some(stream) = first.lock().unwrap().take() {
+            return Ok(stream);
+        }
+        let session = try!(open_session(&site));
+        if session.host_key().map(|(current, _)| current) != Some(&key[..]) {
+            return Err(changed_key(&site));
+        }
+        start_sftp(session, &site, &login)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, HashMap};
+    use std::net::{TcpListener, TcpStream};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    // two ed25519 host keys, as `known_hosts` has them
+    const KEY: &'static str = "AAAAC3NzaC1lZDI1NTE5AAAAIMFhtdmR6kwTZAFJlQRFwRu7F+ToVbldKS3W1mb2WOnh";
+    const OTHER_KEY: &'static str = "AAAAC3NzaC1lZDI1NTE5AAAAIEJFp9f3HoVct6StqlKzaRhnYfIFSpdIkZQZCAzFRwBL";
+    const FX_FAILURE: u32 = 4;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Node {
+        File(Vec<u8>, u32),
+        Dir,
+        Link(String),
+    }
+
+    type Files = Arc<Mutex<BTreeMap<String, Node>>>;
+
+    // An SFTP version 3 server keeping its files in memory, with `/home`
+    // as the user's home directory.
+    struct Server {
+        port: u16,
+        files: Files,
+        // reads and writes each new connection serves before it is dropped,
+        // none if 0
+        drop_after: Arc<AtomicUsize>,
+        connections: Arc<AtomicUsize>,
+        // the paths opened and their flags
+        opens: Arc<Mutex<Vec<(String, u32)>>>,
+        // a kind of request the connection is dropped at, once: with it done
+        // or not
+        lose: Lose,
+    }
+
+    type Lose = Arc<Mutex<Option<(u8, bool)>>>;
+
+    enum Handle {
+        File(String),
+        Dir(Vec<(String, Node)>),
+    }
+
+    fn parent(path: &str) -> &str
+    { path.rfind('/').map_or("/", |slash| if slash == 0 { "/" } else { &path[..slash] }) }
+
+    // Where a link at `path` leads, and what is there.
+    fn follow(files: &BTreeMap<String, Node>, path: &str) -> Option<Node>
+    {
+        match files.get(path) {
+            Some(&Node::Link(ref target)) if target.starts_with('/') => follow(files, target),
+            Some(&Node::Link(ref target)) => follow(files, &join(parent(path), target)),
+            node => node.cloned(),
+        }
+    }
+
+    // `packet` with the attributes of `node` added.
+    fn attrs(packet: Packet, node: &Node) -> Packet
+    {
+        let (size, mode, time) = match *node {
+            Node::File(ref data, time) => (data.len() as u64, 0o100644, time),
+            Node::Dir => (0, 0o040755, 0),
+            Node::Link(ref target) => (target.len() as u64, 0o120777, 0),
+        };
+        packet.u32(ATTR_SIZE | ATTR_PERMISSIONS | ATTR_ACMODTIME).u64(size).u32(mode).u32(time).u32(time)
+    }
+
+    fn status(id: u32, code: u32) -> Packet
+    { Packet::new(FXP_STATUS).u32(id).u32(code).string(if code == FX_OK { "" } else { "failed" }).string("") }
+
+    fn serve(mut stream: TcpStream, files: Files, drop_after: usize, opens: Arc<Mutex<Vec<(String, u32)>>>, lose: Lose)
+    {
+        let mut handles = HashMap::new();
+        let (mut next_handle, mut transfers) = (0u32, 0);
+        loop {
+            let mut len = [0; 4];
+            if stream.read_exact(&mut len).is_err() {
+                return;
+            }
+            let mut data = vec![0; u32::from_be_bytes(len) as usize];
+            stream.read_exact(&mut data).unwrap();
+            let kind = data[0];
+            let mut request = Reply { kind: kind, data: data, at: 1 };
+            if kind == FXP_INIT {
+                let reply = Packet::new(FXP_VERSION).u32(3);
+                stream.write_all(&Packet(Vec::new()).bytes(&reply.0).0).unwrap();
+                continue;
+            }
+            let id = request.u32().unwrap();
+            let lost = {
+                let mut lose = lose.lock().unwrap();
+                match *lose {
+                    Some((at, done)) if at == kind => { *lose = None; Some(done) },
+                    _ => None,
+                }
+            };
+            if lost == Some(false) {
+                return;
+            }
+            if kind == FXP_READ || kind == FXP_WRITE {
+                transfers += 1;
+                if drop_after > 0 && transfers > drop_after {
+                    return;
+                }
+            }
+            let mut files = files.lock().unwrap();
+            let mut new_handle = |handle: Handle| {
+                next_handle += 1;
+                handles.insert(next_handle, handle);
+                Packet::new(FXP_HANDLE).u32(id).bytes(&next_handle.to_be_bytes())
+            };
+            let reply = match kind {
+                FXP_OPEN => {
+                    let (path, flags) = (request.string().unwrap(), request.u32().unwrap());
+                    opens.lock().unwrap().push((path.clone(), flags));
+                    match (follow(&files, &path), flags & OPEN_CREATE != 0) {
+                        (Some(Node::Dir), _) => status(id, FX_PERMISSION_DENIED),
+                        (None, false) => status(id, FX_NO_SUCH_FILE),
+                        (None, true) if follow(&files, parent(&path)) != Some(Node::Dir) => status(id, FX_NO_SUCH_FILE),
+                        (node, _) => {
+                            if node.is_none() || flags & OPEN_TRUNCATE != 0 {
+                                files.insert(path.clone(), Node::File(Vec::new(), 0));
+                            }
+                            new_handle(Handle::File(path))
+                        },
+                    }
+                },
+                FXP_OPENDIR => {
+                    let path = request.string().unwrap();
+                    match follow(&files, &path) {
+                        Some(Node::Dir) => {
+                            let mut entries = vec![(".".to_string(), Node::Dir), ("..".to_string(), Node::Dir)];
+                            entries.extend(files.iter()
+                                .filter(|&(other, _)| other != "/" && parent(other) == path)
+                                .map(|(other, node)| (other[other.rfind('/').unwrap() + 1..].to_string(), node.clone())));
+                            new_handle(Handle::Dir(entries))
+                        },
+                        _ => status(id, FX_NO_SUCH_FILE),
+                    }
+                },
+                FXP_CLOSE => {
+                    let handle = request.bytes().unwrap();
+                    handles.remove(&u32::from_be_bytes([handle[0], handle[1], handle[2], handle[3]]));
+                    status(id, FX_OK)
+                },
+                FXP_READ | FXP_WRITE | FXP_READDIR => {
+                    let handle = request.bytes().unwrap();
+                    match handles.get_mut(&u32::from_be_bytes([handle[0], handle[1], handle[2], handle[3]])) {
+                        Some(&mut Handle::File(ref path)) if kind == FXP_READ => {
+                            let (offset, len) = (request.u64().unwrap() as usize, request.u32().unwrap() as usize);
+                            match files.get(path) {
+                                Some(&Node::File(ref data, _)) if offset < data.len() => {
+                                    let end = ::std::cmp::min(data.len(), offset + len);
+                                    Packet::new(FXP_DATA).u32(id).bytes(&data[offset..end])
+                                },
+                                _ => status(id, FX_EOF),
+                            }
+                        },
+                        Some(&mut Handle::File(ref path)) if kind == FXP_WRITE => {
+                            let (offset, written) = (request.u64().unwrap() as usize, request.bytes().unwrap());
+                            if let Some(&mut Node::File(ref mut data, _)) = files.get_mut(path) {
+                                if data.len() < offset + written.len() {
+                                    data.resize(offset + written.len(), 0);
+                                }
+                                data[offset..offset + written.len()].copy_from_slice(&written);
+                            }
+                            status(id, FX_OK)
+                        },
+                        Some(&mut Handle::Dir(ref mut entries)) if !entries.is_empty() => {
+                            let mut reply = Packet::new(FXP_NAME).u32(id).u32(entries.len() as u32);
+                            for (name, node) in entries.drain(..) {
+                                reply = attrs(reply.string(&name).string("-rw-r--r-- 1 me me"), &node);
+                            }
+                            reply
+                        },
+                        Some(&mut Handle::Dir(_)) => status(id, FX_EOF),
+                        _ => status(id, FX_FAILURE),
+                    }
+                },
+                FXP_STAT | FXP_LSTAT => {
+                    let path = request.string().unwrap();
+                    let node = if kind == FXP_STAT { follow(&files, &path) } else { files.get(&path).cloned() };
+                    match node {
+                        Some(node) => attrs(Packet::new(FXP_ATTRS).u32(id), &node),
+                        None => status(id, FX_NO_SUCH_FILE),
+                    }
+                },
+                FXP_SETSTAT => {
+                    let path = request.string().unwrap();
+                    let modified = request.attrs().unwrap().modified.unwrap();
+                    match files.get_mut(&path) {
+                        Some(&mut Node::File(_, ref mut time)) => { *time = modified; status(id, FX_OK) },
+                        _ => status(id, FX_NO_SUCH_FILE),
+                    }
+                },
+                FXP_REMOVE | FXP_RMDIR => {
+                    let path = request.string().unwrap();
+                    let empty = !files.keys().any(|other| other != &path && parent(other) == path);
+                    match files.get(&path) {
+                        Some(&Node::Dir) if kind == FXP_RMDIR && empty => { files.remove(&path); status(id, FX_OK) },
+                        Some(&Node::Dir) => status(id, FX_FAILURE),
+                        Some(_) if kind == FXP_REMOVE => { files.remove(&path); status(id, FX_OK) },
+                        _ => status(id, FX_NO_SUCH_FILE),
+                    }
+                },
+                FXP_MKDIR => {
+                    let path = request.string().unwrap();
+                    if files.contains_key(&path) {
+                        status(id, FX_FAILURE)
+                    } else {
+                        files.insert(path, Node::Dir);
+                        status(id, FX_OK)
+                    }
+                },
+                FXP_REALPATH => {
+                    let path = request.string().unwrap();
+                    let path = if path == "." { "/home".to_string() } else { path };
+                    Packet::new(FXP_NAME).u32(id).u32(1).string(&path).string("").u32(0)
+                },
+                FXP_RENAME => {
+                    let (from, to) = (request.string().unwrap(), request.string().unwrap());
+                    match files.remove(&from) {
+                        Some(node) => { files.insert(to, node); status(id, FX_OK) },
+                        None => status(id, FX_NO_SUCH_FILE),
+                    }
+                },
+                FXP_READLINK => {
+                    let path = request.string().unwrap();
+                    match files.get(&path) {
+                        Some(&Node::Link(ref target)) => Packet::new(FXP_NAME).u32(id).u32(1).string(target).string("").u32(0),
+                        _ => status(id, FX_NO_SUCH_FILE),
+                    }
+                },
+                FXP_SYMLINK => {
+                    let (target, path) = (request.string().unwrap(), request.string().unwrap());
+                    files.insert(path, Node::Link(target));
+                    status(id, FX_OK)
+                },
+                _ => status(id, FX_OP_UNSUPPORTED),
+            };
+            if lost == Some(true) {
+                return;
+            }
+            if stream.write_all(&Packet(Vec::new()).bytes(&reply.0).0).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn start() -> Server
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut files = BTreeMap::new();
+        for dir in &["/", "/home", "/home/sub"] {
+            files.insert(dir.to_string(), Node::Dir);
+        }
+        let server = Server {
+            port: listener.local_addr().unwrap().port(),
+            files: Arc::new(Mutex::new(files)),
+            drop_after: Arc::new(AtomicUsize::new(0)),
+            connections: Arc::new(AtomicUsize::new(0)),
+            opens: Arc::new(Mutex::new(Vec::new())),
+            lose: Arc::new(Mutex::new(None)),
+        };
+        let (files, drop_after, connections, opens, lose) = (server.files.clone(), server.drop_after.clone(),
+                                                             server.connections.clone(), server.opens.clone(),
+                                                             server.lose.clone());
+        thread::spawn(move || for stream in listener.incoming() {
+            connections.fetch_add(1, Ordering::SeqCst);
+            let (stream, files, opens, lose) = (stream.unwrap(), files.clone(), opens.clone(), lose.clone());
+            let drop_after = drop_after.load(Ordering::SeqCst);
+            thread::spawn(move || serve(stream, files, drop_after, opens, lose));
+        });
+        server
+    }
+
+    fn connector(server: &Server) -> Connector
+    {
+        let port = server.port;
+        Box::new(move || {
+            let stream = try!(TcpStream::connect(("127.0.0.1", port)));
+            try!(stream.set_nodelay(true));
+            Ok(Box::new(stream) as Box<Stream>)
+        })
+    }
+
+    fn read_all(fs: &SftpFs, path: &Path) -> Vec<u8>
+    {
+        let mut data = Vec::new();
+        fs.open_read(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn browse_and_change()
+    {
+        let server = start();
+        {
+            let mut files = server.files.lock().unwrap();
+            files.insert("/home/a.txt".to_string(), Node::File(b"hello".to_vec(), 1_000_000_000));
+            files.insert("/home/.hidden".to_string(), Node::File(Vec::new(), 0));
+            files.insert("/home/link".to_string(), Node::Link("sub".to_string()));
+            files.insert("/home/dangling".to_string(), Node::Link("nowhere".to_string()));
+        }
+        let root = PathBuf::from("/sftp/test");
+        let fs = SftpFs::open(connector(&server), "", &root).unwrap();
+        let start = fs.start_path();
+        assert_eq!(start, root.join("home"));
+
+        let mut entries = fs.list(&start).unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, [".hidden", "a.txt", "dangling", "link", "sub"]);
+        assert!(entries[0].meta.hidden);
+        assert_eq!((entries[1].meta.size, entries[1].meta.mode, entries[1].meta.is_dir), (5, Some(0o644), false));
+        assert_eq!(entries[1].meta.modified, Some(UNIX_EPOCH + Duration::from_secs(1_000_000_000)));
+        assert!(entries[2].meta.is_link && !entries[2].meta.is_dir);
+        assert!(entries[3].meta.is_link && entries[3].meta.is_dir);
+        assert!(entries[4].meta.is_dir && !entries[4].meta.is_link);
+        assert_eq!(fs.stat(&start.join("nope")).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(fs.open_read(&start.join("nope")).err().unwrap().kind(), io::ErrorKind::NotFound);
+        assert_eq!(read_all(&fs, &start.join("a.txt")), b"hello");
+        assert_eq!(fs.read_link(&start.join("link")).unwrap(), PathBuf::from("sub"));
+
+        {
+            let mut output = fs.open_write(&start.join("up.bin")).unwrap();
+            output.write_all(&vec![7; 100000]).unwrap();
+            output.flush().unwrap();
+        }
+        // an empty upload still leaves a file
+        drop(fs.open_write(&start.join("empty")).unwrap());
+        fs.mkdir(&start.join("new")).unwrap();
+        fs.rename(&start.join("up.bin"), &start.join("new").join("up2.bin")).unwrap();
+        let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        fs.set_modified(&start.join("a.txt"), time).unwrap();
+        assert_eq!(fs.stat(&start.join("a.txt")).unwrap().modified, Some(time));
+        fs.symlink(Path::new("a.txt"), &start.join("l2"), false).unwrap();
+        {
+            let files = server.files.lock().unwrap();
+            assert_eq!(files.get("/home/new/up2.bin"), Some(&Node::File(vec![7; 100000], 0)));
+            assert_eq!(files.get("/home/empty"), Some(&Node::File(Vec::new(), 0)));
+            assert_eq!(files.get("/home/l2"), Some(&Node::Link("a.txt".to_string())));
+        }
+
+        // a link is removed, not what it points to
+        fs.remove(&start.join("link")).unwrap();
+        assert!(fs.stat(&start.join("sub")).unwrap().is_dir);
+        assert!(fs.remove(&start.join("new")).is_err());
+        fs.remove(&start.join("new").join("up2.bin")).unwrap();
+        fs.remove(&start.join("new")).unwrap();
+        assert!(!server.files.lock().unwrap().contains_key("/home/new"));
+    }
+
+    #[test]
+    fn transfers_go_on_after_a_lost_connection()
+    {
+        let server = start();
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        server.files.lock().unwrap().insert("/home/big".to_string(), Node::File(data.clone(), 0));
+        let root = PathBuf::from("/sftp/test");
+        let fs = SftpFs::open(connector(&server), "/home", &root).unwrap();
+        let start = fs.start_path();
+        assert_eq!(start, root.join("home"));
+        // connections from now on are dropped after three reads or writes
+        server.drop_after.store(3, Ordering::SeqCst);
+        let before = server.connections.load(Ordering::SeqCst);
+
+        assert!(read_all(&fs, &start.join("big")) == data);
+        {
+            let mut output = fs.open_write(&start.join("copy")).unwrap();
+            output.write_all(&data).unwrap();
+            output.flush().unwrap();
+        }
+        assert!(server.files.lock().unwrap().get("/home/copy") == Some(&Node::File(data.clone(), 0)));
+        assert!(server.connections.load(Ordering::SeqCst) - before >= 4);
+
+        // the upload was opened again on each new connection, and truncated
+        // only while nothing of it was written
+        let opens: Vec<u32> = server.opens.lock().unwrap().iter()
+            .filter(|&&(ref path, _)| path == "/home/copy")
+            .map(|&(_, flags)| flags)
+            .collect();
+        let truncated = opens.iter().take_while(|&&flags| flags == OPEN_WRITE | OPEN_CREATE | OPEN_TRUNCATE).count();
+        assert!(truncated >= 1 && truncated < opens.len(), "{:?}", opens);
+        assert!(opens[truncated..].iter().all(|&flags| flags == OPEN_WRITE | OPEN_CREATE), "{:?}", opens);
+        assert!(fs.list(&start).unwrap().iter().any(|entry| entry.name == "copy"));
+    }
+
+    #[test]
+    fn changes_are_sent_once()
+    {
+        let server = start();
+        server.files.lock().unwrap().insert("/home/a.txt".to_string(), Node::File(b"hello".to_vec(), 0));
+        let root = PathBuf::from("/sftp/test");
+        let fs = SftpFs::open(connector(&server), "/home", &root).unwrap();
+        let start = fs.start_path();
+        let lose = |kind, done| *server.lose.lock().unwrap() = Some((kind, done));
+
+        // done before the connection was lost: found done on the next one,
+        // where sending them again would have failed
+        lose(FXP_MKDIR, true);
+        fs.mkdir(&start.join("new")).unwrap();
+        lose(FXP_RENAME, true);
+        fs.rename(&start.join("a.txt"), &start.join("new").join("b.txt")).unwrap();
+        lose(FXP_SYMLINK, true);
+        fs.symlink(Path::new("new/b.txt"), &start.join("link"), false).unwrap();
+        lose(FXP_REMOVE, true);
+        fs.remove(&start.join("link")).unwrap();
+        {
+            let files = server.files.lock().unwrap();
+            assert_eq!(files.get("/home/new"), Some(&Node::Dir));
+            assert_eq!(files.get("/home/new/b.txt"), Some(&Node::File(b"hello".to_vec(), 0)));
+            assert!(!files.contains_key("/home/a.txt") && !files.contains_key("/home/link"));
+        }
+
+        // not done: the lost connection is reported, not sent again
+        let before = server.connections.load(Ordering::SeqCst);
+        lose(FXP_RMDIR, false);
+        server.files.lock().unwrap().insert("/home/empty".to_string(), Node::Dir);
+        assert_eq!(fs.remove(&start.join("empty")).unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+        assert_eq!(server.files.lock().unwrap().get("/home/empty"), Some(&Node::Dir));
+        lose(FXP_RENAME, false);
+        assert_eq!(fs.rename(&start.join("new"), &start.join("old")).unwrap_err().kind(),
+                   io::ErrorKind::ConnectionAborted);
+        assert!(server.files.lock().unwrap().contains_key("/home/new"));
+        // one new connection each, to look
+        assert_eq!(server.connections.load(Ordering::SeqCst) - before, 2);
+
+        // reads are sent again
+        lose(FXP_LSTAT, false);
+        assert!(fs.stat(&start.join("new")).unwrap().is_dir);
+    }
+
+    #[test]
+    fn failed_key_checks_are_errors()
+    {
+        // libssh2 cannot look up host names this long
+        let mut site = site_on(2222);
+        site.host = "h".repeat(300);
+        let hosts = Session::new().unwrap().known_hosts().unwrap();
+        let err = check_key(hosts, &key(KEY), HostKeyType::Ed25519, &[0xab; 32], &site, None, &mut |_| panic!("asked"))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn connector_failures_are_reported()
+    {
+        let connector: Connector = Box::new(|| Err(io::Error::new(io::ErrorKind::PermissionDenied, "no")));
+        let fs = SftpFs::open(connector, "", Path::new("/sftp/test"));
+        assert_eq!(fs.err().unwrap().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    // Logs in when the password or the key is the one expected.
+    struct FakeAuth {
+        accepted: &'static str,
+        logged_in: RefCell<bool>,
+        tried: RefCell<Vec<String>>,
+    }
+
+    impl FakeAuth {
+        fn new(accepted: &'static str) -> FakeAuth
+        { FakeAuth { accepted: accepted, logged_in: RefCell::new(false), tried: RefCell::new(Vec::new()) } }
+
+        fn answer(&self, tried: String, secret: &str) -> Result<(), String>
+        {
+            self.tried.borrow_mut().push(tried);
+            if secret == self.accepted {
+                *self.logged_in.borrow_mut() = true;
+                Ok(())
+            } else {
+                Err("Authentication failed".to_string())
+            }
+        }
+    }
+
+    impl UserAuth for FakeAuth {
+        fn password(&self, user: &str, password: &str) -> Result<(), String>
+        { self.answer(format!("password {} {}", user, password), password) }
+
+        fn key_file(&self, user: &str, key_file: &Path, passphrase: Option<&str>) -> Result<(), String>
+        {
+            let key_file = key_file.to_string_lossy();
+            self.answer(format!("key {} {} {:?}", user, key_file, passphrase), &key_file)
+        }
+
+        fn authenticated(&self) -> bool
+        { *self.logged_in.borrow() }
+    }
+
+    fn site_on(port: u16) -> Site
+    {
+        let mut site = Site::new("test");
+        site.set_address(&format!("sftp://me@127.0.0.1:{}", port)).unwrap();
+        site
+    }
+
+    #[test]
+    fn password_and_key_logins()
+    {
+        let site = site_on(22);
+        let auth = FakeAuth::new("secret");
+        log_in(&auth, &site, &Login::Password("secret".to_string())).unwrap();
+        let auth = FakeAuth::new("secret");
+        let err = log_in(&auth, &site, &Login::Password("wrong".to_string())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("Authentication failed"), "{}", err);
+
+        let auth = FakeAuth::new("id_ed25519");
+        log_in(&auth, &site, &Login::Key(PathBuf::from("id_ed25519"), String::new())).unwrap();
+        log_in(&auth, &site, &Login::Key(PathBuf::from("id_ed25519"), "words".to_string())).unwrap();
+        assert_eq!(*auth.tried.borrow(), vec!["key me id_ed25519 None", "key me id_ed25519 Some(\"words\")"]);
+
+        // a login the server takes without authenticating the session
+        struct Partial;
+        impl UserAuth for Partial {
+            fn password(&self, _: &str, _: &str) -> Result<(), String> { Ok(()) }
+            fn key_file(&self, _: &str, _: &Path, _: Option<&str>) -> Result<(), String> { Ok(()) }
+            fn authenticated(&self) -> bool { false }
+        }
+        let err = log_in(&Partial, &site, &Login::Password("secret".to_string())).unwrap_err();
+        assert!(err.to_string().contains("the server refused"), "{}", err);
+
+        let mut nobody = site.clone();
+        nobody.user.clear();
+        let auth = FakeAuth::new("secret");
+        assert_eq!(log_in(&auth, &nobody, &Login::Password("secret".to_string())).unwrap_err().kind(),
+                   io::ErrorKind::InvalidInput);
+        assert!(auth.tried.borrow().is_empty());
+    }
+
+    fn temp_path(name: &str) -> PathBuf
+    {
+        let dir = env::temp_dir().join(format!("sftp_test_{}_{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join(".ssh").join("known_hosts")
+    }
+
+    fn key(text: &str) -> Vec<u8>
+    { STANDARD.decode(text).unwrap() }
+
+    fn check(site: &Site, key_text: &str, known_hosts: &Path, trust: &mut FnMut(&HostKey) -> bool) -> io::Result<Vec<u8>>
+    {
+        let hosts = Session::new().unwrap().known_hosts().unwrap();
+        check_key(hosts, &key(key_text), HostKeyType::Ed25519, &[0xab; 32], site, Some(known_hosts), trust)
+    }
+
+    #[test]
+    fn unknown_keys_are_asked_about_and_remembered()
+    {
+        let path = temp_path("unknown");
+        let site = site_on(2222);
+        let err = check(&site, KEY, &path, &mut |_| false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!path.exists());
+
+        let mut asked = Vec::new();
+        assert_eq!(check(&site, KEY, &path, &mut |key| { asked.push((key.kind, key.fingerprint.clone())); true }).unwrap(),
+                   key(KEY));
+        assert_eq!(asked, vec![("ssh-ed25519", format!("SHA256:{}", STANDARD_NO_PAD.encode(&[0xab; 32])))]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("[127.0.0.1]:2222 ssh-ed25519 {}\n", KEY));
+        // known from then on
+        assert_eq!(check(&site, KEY, &path, &mut |_| panic!("asked again")).unwrap(), key(KEY));
+        // the same host on the default port is another entry
+        check(&site_on(22), KEY, &path, &mut |_| true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(),
+                   format!("[127.0.0.1]:2222 ssh-ed25519 {}\n127.0.0.1 ssh-ed25519 {}\n", KEY, KEY));
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn changed_keys_are_refused()
+    {
+        let path = temp_path("changed");
+        let site = site_on(2222);
+        check(&site, KEY, &path, &mut |_| true).unwrap();
+        let err = check(&site, OTHER_KEY, &path, &mut |_| panic!("asked about a changed key")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("has changed"), "{}", err);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn known_host_names()
+    {
+        assert_eq!(known_host_name(&site_on(22)), "127.0.0.1");
+        assert_eq!(known_host_name(&site_on(2222)), "[127.0.0.1]:2222");
+    }
+
+    #[test]
+    fn remembering_keeps_what_is_there()
+    {
+        let path = temp_path("remember");
+        remember(&path, "first key").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first key\n");
+        // a last line without its line break gets one
+        fs::write(&path, "# by hand").unwrap();
+        remember(&path, "second key").unwrap();
+        remember(&path, "third key").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# by hand\nsecond key\nthird key\n");
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+}
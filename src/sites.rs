@@ -0,0 +1,234 @@
+// Remote sites saved in the connection manager (Ctrl+F), kept next to the
+// settings in `sites.ini` with a section per site:
+//
+//     [Build server]
+//     protocol=sftp
+//     host=build.example.com
+//     port=22
+//     user=deploy
+//     key=C:\Users\me\.ssh\id_ed25519
+//     directory=/srv/builds
+//
+// The protocol is `ftp`, `sftp`, or `webdav` and `webdavs` for WebDAV over
+// HTTP and HTTPS. `passive` is for FTP sites, `key` for SFTP ones, which log
+// in with a password when it is empty. Passwords and passphrases are not
+// stored; they are asked for when connecting.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use ::settings::{self, Settings};
+
+pub const FTP_PORT: u16 = 21;
+pub const SSH_PORT: u16 = 22;
+pub const HTTP_PORT: u16 = 80;
+pub const HTTPS_PORT: u16 = 443;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Ftp,
+    Sftp,
+    WebDav,
+    // WebDAV over HTTPS
+    WebDavs,
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            Protocol::Ftp => "ftp",
+            Protocol::Sftp => "sftp",
+            Protocol::WebDav => "webdav",
+            Protocol::WebDavs => "webdavs",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Protocol>
+    {
+        match name.to_lowercase().as_str() {
+            "ftp" => Some(Protocol::Ftp),
+            "sftp" => Some(Protocol::Sftp),
+            // addresses pasted from a browser
+            "webdav" | "http" => Some(Protocol::WebDav),
+            "webdavs" | "https" => Some(Protocol::WebDavs),
+            _ => None,
+        }
+    }
+
+    pub fn default_port(&self) -> u16
+    {
+        match *self {
+            Protocol::Ftp => FTP_PORT,
+            Protocol::Sftp => SSH_PORT,
+            Protocol::WebDav => HTTP_PORT,
+            Protocol::WebDavs => HTTPS_PORT,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Site {
+    pub name: String,
+    pub protocol: Protocol,
+    pub host: String,
+    pub port: u16,
+    // empty for anonymous logins
+    pub user: String,
+    pub passive: bool,
+    // private key file for SFTP
+    pub key_file: String,
+    // where the panel starts; the top directory on FTP and the home
+    // directory on SFTP if empty. On WebDAV, the share, shown as the top
+    pub directory: String,
+}
+
+impl Site {
+    pub fn new(name: &str) -> Site
+    {
+        Site {
+            name: name.to_string(),
+            protocol: Protocol::Ftp,
+            host: String::new(),
+            port: FTP_PORT,
+            user: String::new(),
+            passive: true,
+            key_file: String::new(),
+            directory: String::new(),
+        }
+    }
+
+    // Reads `[protocol://][user@]host[:port][/directory]` into the site; the
+    // protocol stays as it is when not given.
+    pub fn set_address(&mut self, address: &str) -> Result<(), String>
+    {
+        let address = address.trim();
+        let protocol = match address.find("://") {
+            Some(end) => try!(Protocol::from_name(&address[..end]).ok_or_else(||
+                format!("Unknown protocol \"{}\"", &address[..end]))),
+            None => self.protocol,
+        };
+        let address = address.find("://").map_or(address, |end| &address[end + 3..]);
+        let (login, directory) = match address.find('/') {
+            Some(slash) => (&address[..slash], &address[slash..]),
+            None => (address, ""),
+        };
+        let (user, server) = match login.rfind('@') {
+            Some(at) => (&login[..at], &login[at + 1..]),
+            None => ("", login),
+        };
+        // `[::1]:21` keeps the colons of an IPv6 address apart from the port
+        let (host, port) = match (server.starts_with('['), server.rfind(']'), server.rfind(':')) {
+            (true, Some(end), colon) => (&server[1..end], colon.filter(|&c| c > end).map(|c| &server[c + 1..])),
+            (false, _, Some(colon)) => (&server[..colon], Some(&server[colon + 1..])),
+            _ => (server, None),
+        };
+        if host.is_empty() {
+            return Err(format!("No host name in \"{}\"", address));
+        }
+        self.port = match port {
+            Some(port) => try!(port.parse().map_err(|_| format!("\"{}\" is not a port number", port))),
+            None => protocol.default_port(),
+        };
+        self.protocol = protocol;
+        self.host = host.to_string();
+        self.user = user.to_string();
+        self.directory = directory.to_string();
+        Ok(())
+    }
+
+    // The address as `set_address` reads it.
+    pub fn address(&self) -> String
+    {
+        let mut address = format!("{}://", self.protocol.name());
+        if !self.user.is_empty() {
+            address.push_str(&self.user);
+            address.push('@');
+        }
+        if self.host.contains(':') {
+            address.push_str(&format!("[{}]", self.host));
+        } else {
+            address.push_str(&self.host);
+        }
+        if self.port != self.protocol.default_port() {
+            address.push_str(&format!(":{}", self.port));
+        }
+        address.push_str(&self.directory);
+        address
+    }
+}
+
+// Sites without a host are dropped, as are unknown keys.
+pub fn parse(text: &str) -> Vec<Site>
+{
+    let mut sites: Vec<Site> = Vec::new();
+    for (section, key, value) in settings::parse_ini(text) {
+        if sites.last().map_or(true, |site| site.name != section) {
+            // the port follows the protocol unless given
+            sites.push(Site { port: 0, .. Site::new(&section) });
+        }
+        let site = sites.last_mut().unwrap();
+        match key.as_str() {
+            "protocol" => {
+                if let Some(protocol) = Protocol::from_name(&value) {
+                    site.protocol = protocol;
+                }
+            },
+            "host" => site.host = value,
+            "port" => site.port = value.parse().unwrap_or(0),
+            "user" => site.user = value,
+            "passive" => site.passive = settings::parse_bool(&value).unwrap_or(true),
+            "key" => site.key_file = value,
+            "directory" => site.directory = value,
+            _ => {},
+        }
+    }
+    for site in sites.iter_mut().filter(|site| site.port == 0) {
+        site.port = site.protocol.default_port();
+    }
+    sites.retain(|site| !site.host.is_empty() && !site.name.is_empty());
+    sites
+}
+
+pub fn to_text(sites: &[Site]) -> String
+{
+    let mut text = String::new();
+    for site in sites {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&format!("[{}]\n", site.name));
+        text.push_str(&format!("protocol={}\n", site.protocol.name()));
+        text.push_str(&format!("host={}\n", site.host));
+        text.push_str(&format!("port={}\n", site.port));
+        text.push_str(&format!("user={}\n", site.user));
+        match site.protocol {
+            Protocol::Ftp => text.push_str(&format!("passive={}\n", if site.passive { 1 } else { 0 })),
+            Protocol::Sftp => text.push_str(&format!("key={}\n", site.key_file)),
+            Protocol::WebDav | Protocol::WebDavs => {},
+        }
+        text.push_str(&format!("directory={}\n", site.directory));
+    }
+    text
+}
+
+pub fn path() -> Option<PathBuf>
+{ Settings::path().map(|path| path.with_file_name("sites.ini")) }
+
+pub fn load() -> Vec<Site>
+{
+    path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map_or_else(Vec::new, |text| parse(&text))
+}
+
+pub fn save(sites: &[Site]) -> io::Result<()>
+{
+    let path = try!(path().ok_or_else(||
+        io::Error::new(io::ErrorKind::NotFound, "No configuration directory")));
+    if let Some(dir) = path.parent() {
+        try!(fs::create_dir_all(dir));
+    }
+    fs::write(path, to_text(sites))
+}
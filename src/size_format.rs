@@ -0,0 +1,130 @@
+// Formatting of byte counts for the file list, the status line and the drive menus.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SizeFormat {
+    // 1234567 B
+    Bytes,
+    // 1.18 MiB
+    Binary,
+    // 1,234,567 B
+    Exact,
+}
+
+impl SizeFormat {
+    pub fn all() -> &'static [SizeFormat]
+    {
+        const ALL: &'static [SizeFormat] = &[SizeFormat::Bytes, SizeFormat::Binary, SizeFormat::Exact];
+        ALL
+    }
+
+    pub fn name(&self) -> &'static str
+    {
+        match *self {
+            SizeFormat::Bytes => "bytes",
+            SizeFormat::Binary => "binary",
+            SizeFormat::Exact => "exact",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SizeFormat>
+    { SizeFormat::all().iter().cloned().find(|f| f.name() == name) }
+
+    pub fn description(&self) -> &'static str
+    {
+        match *self {
+            SizeFormat::Bytes => "Bytes",
+            SizeFormat::Binary => "KiB, MiB, GiB",
+            SizeFormat::Exact => "Exact, with separators",
+        }
+    }
+}
+
+const UNITS: &'static [&'static str] = &["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+pub fn format_size(bytes: u64, format: SizeFormat) -> String
+{
+    match format {
+        SizeFormat::Bytes => format!("{} B", bytes),
+        SizeFormat::Exact => format!("{} B", group_thousands(bytes)),
+        SizeFormat::Binary => {
+            if bytes < 1024 {
+                return format!("{} B", bytes);
+            }
+            let mut value = bytes as f64 / 1024.0;
+            let mut unit = 0;
+            // what would round to 1024 goes on to the next unit
+            while value >= 1023.5 && unit + 1 < UNITS.len() {
+                value /= 1024.0;
+                unit += 1;
+            }
+            // three significant digits: 1.18 MiB, 11.8 MiB, 118 MiB, the
+            // boundaries as they round
+            if value < 9.995 { format!("{:.2} {}", value, UNITS[unit]) }
+            else if value < 99.95 { format!("{:.1} {}", value, UNITS[unit]) }
+            else { format!("{:.0} {}", value, UNITS[unit]) }
+        },
+    }
+}
+
+pub fn group_thousands(value: u64) -> String
+{
+    let digits = value.to_string();
+    let mut rv = String::with_capacity(digits.len() + digits.len() / 3);
+    for (idx, c) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx) % 3 == 0 {
+            rv.push(',');
+        }
+        rv.push(c);
+    }
+    rv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(bytes: u64) -> String
+    { format_size(bytes, SizeFormat::Binary) }
+
+    #[test]
+    fn binary_units_and_rounding()
+    {
+        assert_eq!(binary(0), "0 B");
+        assert_eq!(binary(1023), "1023 B");
+        assert_eq!(binary(1024), "1.00 KiB");
+        assert_eq!(binary(1234567), "1.18 MiB");
+        assert_eq!(binary(12345678), "11.8 MiB");
+        assert_eq!(binary(123456789), "118 MiB");
+        // values that round up into the next digit or the next unit
+        assert_eq!(binary(10229), "9.99 KiB");
+        assert_eq!(binary(10238), "10.0 KiB");
+        assert_eq!(binary(102348), "99.9 KiB");
+        assert_eq!(binary(102390), "100 KiB");
+        assert_eq!(binary(1047551), "1023 KiB");
+        assert_eq!(binary(1048575), "1.00 MiB");
+        assert_eq!(binary(1 << 30), "1.00 GiB");
+        assert_eq!(binary(1 << 40), "1.00 TiB");
+        assert_eq!(binary(u64::max_value()), "16.0 EiB");
+    }
+
+    #[test]
+    fn bytes_and_exact()
+    {
+        assert_eq!(format_size(1234567, SizeFormat::Bytes), "1234567 B");
+        assert_eq!(format_size(1234567, SizeFormat::Exact), "1,234,567 B");
+        assert_eq!(group_thousands(0), "0");
+        assert_eq!(group_thousands(999), "999");
+        assert_eq!(group_thousands(1000), "1,000");
+        assert_eq!(group_thousands(100000), "100,000");
+        assert_eq!(group_thousands(u64::max_value()), "18,446,744,073,709,551,615");
+    }
+
+    #[test]
+    fn names_round_trip()
+    {
+        for &format in SizeFormat::all() {
+            assert_eq!(SizeFormat::from_name(format.name()), Some(format));
+        }
+        assert_eq!(SizeFormat::from_name("kilobytes"), None);
+    }
+}
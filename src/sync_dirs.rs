@@ -0,0 +1,668 @@
+// Synchronize directories: compares two directory trees, each on its own
+// file system, into a list of tasks, one for each file or directory that
+// differs, with what to do about it. The list is shown for the user to
+// change before `apply` carries it out with the copy of the file operations.
+//
+// Directories on one side only are listed with everything in them, so that
+// the filters apply there too and single files can be left out. Copying a
+// file makes the directories it goes into; deleting a directory only works
+// once everything in it is deleted.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ::compare::{self, CompareOptions, ContentCheck, Difference, Item};
+use ::file_ops::{self, Progress};
+use ::mask;
+use ::vfs::Vfs;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncMode {
+    // newer files and those on one side only are copied to the other
+    BothWays,
+    // the right side is updated from the left; nothing is deleted
+    LeftToRight,
+    // the right side is made the same as the left, deleting what is not there
+    Mirror,
+}
+
+#[derive(Clone, Debug)]
+pub struct SyncOptions {
+    pub mode: SyncMode,
+    pub compare: CompareOptions,
+    // `;` separated masks files have to match, all if empty, and ones that
+    // files and directories must not match
+    pub names: String,
+    pub exclude_names: String,
+}
+
+impl Default for SyncOptions {
+    fn default() -> SyncOptions
+    {
+        SyncOptions {
+            mode: SyncMode::BothWays,
+            compare: CompareOptions::default(),
+            names: String::new(),
+            exclude_names: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    CopyRight,
+    CopyLeft,
+    // on the side the entry is on; entries on both sides are not deleted
+    Delete,
+    Skip,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Task {
+    // below the compared directories, as each side names the entry; on the
+    // side it is missing from, where a copy goes
+    pub left_path: PathBuf,
+    pub right_path: PathBuf,
+    pub left: Option<Item>,
+    pub right: Option<Item>,
+    pub difference: Difference,
+    pub action: Action,
+}
+
+impl Task {
+    // The path to show: the left one, unless the entry is on the right only.
+    pub fn path(&self) -> &Path
+    { if self.left.is_some() { &self.left_path } else { &self.right_path } }
+
+    pub fn is_dir(&self) -> bool
+    { self.left.as_ref().or(self.right.as_ref()).map_or(false, |item| item.is_dir) }
+
+    // A file on one side and a directory on the other, which is left alone.
+    pub fn is_conflict(&self) -> bool
+    {
+        match (self.left.as_ref(), self.right.as_ref()) {
+            (Some(left), Some(right)) => left.is_dir != right.is_dir,
+            _ => false,
+        }
+    }
+
+    pub fn allows(&self, action: Action) -> bool
+    {
+        match (action, self.left.is_some(), self.right.is_some()) {
+            _ if self.is_conflict() => action == Action::Skip,
+            (Action::Skip, _, _) => true,
+            (Action::CopyRight, left, _) => left,
+            (Action::CopyLeft, _, right) => right,
+            (Action::Delete, left, right) => left != right,
+        }
+    }
+}
+
+pub fn default_action(mode: SyncMode, difference: Difference) -> Action
+{
+    match (mode, difference) {
+        (_, Difference::LeftOnly) | (_, Difference::LeftNewer) => Action::CopyRight,
+        (SyncMode::BothWays, Difference::RightOnly) | (SyncMode::BothWays, Difference::RightNewer) => Action::CopyLeft,
+        (SyncMode::Mirror, Difference::RightOnly) => Action::Delete,
+        (SyncMode::Mirror, _) => Action::CopyRight,
+        // neither is newer, or the left side is the older one
+        _ => Action::Skip,
+    }
+}
+
+// What a sync did, for the report at the end.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    pub copied_right: usize,
+    pub copied_left: usize,
+    pub deleted: usize,
+    pub skipped: usize,
+    pub bytes: u64,
+    // one line for each task that failed; the others are carried out anyway
+    pub errors: Vec<String>,
+    pub cancelled: bool,
+}
+
+struct Side<'a> {
+    vfs: &'a Vfs,
+    root: &'a Path,
+}
+
+fn wanted(options: &SyncOptions, item: &Item) -> bool
+{
+    !mask::matches_any(&options.exclude_names, &item.name)
+        && (item.is_dir || options.names.trim().is_empty() || mask::matches_any(&options.names, &item.name))
+}
+
+// The entries of `relative` under `side` that pass the filters; links to
+// directories are not followed.
+fn list(side: &Side, relative: &Path, options: &SyncOptions) -> io::Result<Vec<Item>>
+{
+    let dir = side.root.join(relative);
+    let entries = try!(side.vfs.list(&dir).map_err(|e|
+        io::Error::new(e.kind(), format!("Cannot read \"{}\": {}", dir.display(), e))));
+    let mut items: Vec<Item> = entries.iter()
+        .filter(|entry| !(entry.meta.is_dir && entry.meta.is_link))
+        .map(Item::from_entry)
+        .filter(|item| wanted(options, item))
+        .collect();
+    items.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase())));
+    Ok(items)
+}
+
+// Plans the directory at `left_relative` on the left and `right_relative` on
+// the right: the same one, though a side that ignores case may have paired
+// names that differ in it.
+fn plan_dir(left: Option<&Side>, left_relative: &Path, right: Option<&Side>, right_relative: &Path, options: &SyncOptions,
+            tasks: &mut Vec<Task>, progress: &mut Progress, cancel: &AtomicBool,
+            report: &mut FnMut(&Progress)) -> io::Result<()>
+{
+    if progress.cancelled || cancel.load(Ordering::Relaxed) {
+        progress.cancelled = true;
+        return Ok(());
+    }
+    progress.current = match (left, right) {
+        (Some(side), _) => side.root.join(left_relative),
+        (None, Some(side)) => side.root.join(right_relative),
+        (None, None) => PathBuf::new(),
+    };
+    report(progress);
+    let left_items = match left {
+        Some(side) => try!(list(side, left_relative, options)),
+        None => Vec::new(),
+    };
+    let right_items = match right {
+        Some(side) => try!(list(side, right_relative, options)),
+        None => Vec::new(),
+    };
+    let ignore_case = left.into_iter().chain(right).any(|side| side.vfs.ignores_case());
+    for pair in compare::pair(&left_items, &right_items, ignore_case) {
+        if progress.cancelled {
+            break;
+        }
+        let either = pair.0.or(pair.1).unwrap();
+        let left_path = left_relative.join(&pair.0.unwrap_or(either).name);
+        let right_path = right_relative.join(&pair.1.unwrap_or(either).name);
+        let difference = match pair {
+            (Some(l), Some(r)) if l.is_dir && r.is_dir => {
+                try!(plan_dir(left, &left_path, right, &right_path, options, tasks, progress, cancel, report));
+                continue;
+            },
+            (Some(l), Some(r)) => {
+                let (left, right) = (left.unwrap(), right.unwrap());
+                let reads = options.compare.content != ContentCheck::Off && !l.is_dir && !r.is_dir && l.size == r.size;
+                progress.files_total += 1;
+                if reads {
+                    progress.bytes_total += 2 * l.size;
+                } else {
+                    progress.files_done += 1;
+                }
+                try!(compare::compare_items(left.vfs, &left.root.join(&left_path), l, right.vfs,
+                                            &right.root.join(&right_path), r, &options.compare, progress, cancel,
+                                            report))
+            },
+            (Some(_), None) => Some(Difference::LeftOnly),
+            (None, _) => Some(Difference::RightOnly),
+        };
+        let difference = match difference {
+            Some(_) if progress.cancelled => break,
+            Some(difference) => difference,
+            None => continue,
+        };
+        let mut task = Task {
+            left_path: left_path.clone(),
+            right_path: right_path.clone(),
+            left: pair.0.cloned(),
+            right: pair.1.cloned(),
+            difference: difference,
+            action: default_action(options.mode, difference),
+        };
+        if task.is_conflict() {
+            task.action = Action::Skip;
+        }
+        tasks.push(task);
+        // what is in a directory on one side only is listed too
+        match pair {
+            (Some(l), None) if l.is_dir =>
+                try!(plan_dir(left, &left_path, None, &right_path, options, tasks, progress, cancel, report)),
+            (None, Some(r)) if r.is_dir =>
+                try!(plan_dir(None, &left_path, right, &right_path, options, tasks, progress, cancel, report)),
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+// Compares the trees under `left_root` and `right_root` into tasks, parents
+// before what is in them. Reports progress as a file operation does, with a
+// file counted for each pair compared; on cancel the tasks found so far are
+// returned.
+pub fn plan(left_fs: &Vfs, left_root: &Path, right_fs: &Vfs, right_root: &Path, options: &SyncOptions,
+            cancel: &AtomicBool, report: &mut FnMut(&Progress)) -> io::Result<(Vec<Task>, Progress)>
+{
+    let left = Side { vfs: left_fs, root: left_root };
+    let right = Side { vfs: right_fs, root: right_root };
+    let mut tasks = Vec::new();
+    let mut progress = Progress::default();
+    try!(plan_dir(Some(&left), Path::new(""), Some(&right), Path::new(""), options, &mut tasks, &mut progress, cancel,
+                  report));
+    Ok((tasks, progress))
+}
+
+// Makes `dir` and the directories above it that are missing.
+fn make_dirs(vfs: &Vfs, dir: &Path) -> io::Result<()>
+{
+    if vfs.stat(dir).map(|meta| meta.is_dir).unwrap_or(false) {
+        return Ok(());
+    }
+    if let Some(parent) = dir.parent() {
+        try!(make_dirs(vfs, parent));
+    }
+    vfs.mkdir(dir).map_err(|e| io::Error::new(e.kind(), format!("Cannot create \"{}\": {}", dir.display(), e)))
+}
+
+// Copies `source` below `from` to `target` below `to`, or makes the
+// directory there.
+fn copy_task(from: &Side, source: &Path, to: &Side, target: &Path, is_dir: bool, progress: &mut Progress,
+             cancel: &AtomicBool, report: &mut FnMut(&Progress)) -> io::Result<()>
+{
+    let (source, target) = (from.root.join(source), to.root.join(target));
+    if is_dir {
+        progress.current = source;
+        report(progress);
+        return make_dirs(to.vfs, &target);
+    }
+    if let Some(parent) = target.parent() {
+        try!(make_dirs(to.vfs, parent));
+    }
+    if to.vfs.stat(&target).is_ok() {
+        file_ops::copy_over(from.vfs, &source, to.vfs, &target, progress, cancel, report)
+    } else {
+        file_ops::copy_entry(from.vfs, &source, to.vfs, &target, progress, cancel, report)
+    }
+}
+
+// Carries out `tasks`: the copies in their order, then the deletions from
+// the last one back, so directories are emptied before they are deleted.
+// A task that fails is noted in the summary and the rest go on; only a
+// cancel stops early.
+pub fn apply(left_fs: &Vfs, left_root: &Path, right_fs: &Vfs, right_root: &Path, tasks: &[Task],
+             cancel: &AtomicBool, report: &mut FnMut(&Progress)) -> (Summary, Progress)
+{
+    let left = Side { vfs: left_fs, root: left_root };
+    let right = Side { vfs: right_fs, root: right_root };
+    let mut summary = Summary::default();
+    let mut progress = Progress::default();
+    for task in tasks.iter().filter(|task| !task.is_dir()) {
+        match task.action {
+            Action::CopyRight => progress.bytes_total += task.left.as_ref().map_or(0, |item| item.size),
+            Action::CopyLeft => progress.bytes_total += task.right.as_ref().map_or(0, |item| item.size),
+            Action::Delete => {},
+            Action::Skip => continue,
+        }
+        progress.files_total += 1;
+    }
+    for task in tasks.iter() {
+        if progress.cancelled || cancel.load(Ordering::Relaxed) {
+            progress.cancelled = true;
+            break;
+        }
+        let ((from, source), (to, target)) = match task.action {
+            Action::CopyRight => ((&left, &task.left_path), (&right, &task.right_path)),
+            Action::CopyLeft => ((&right, &task.right_path), (&left, &task.left_path)),
+            Action::Skip => { summary.skipped += 1; continue; },
+            Action::Delete => continue,
+        };
+        let bytes = progress.bytes_done;
+        match copy_task(from, source, to, target, task.is_dir(), &mut progress, cancel, report) {
+            Ok(_) if progress.cancelled => {},
+            Ok(_) => {
+                summary.bytes += progress.bytes_done - bytes;
+                if task.action == Action::CopyRight {
+                    summary.copied_right += 1;
+                } else {
+                    summary.copied_left += 1;
+                }
+            },
+            Err(e) => summary.errors.push(e.to_string()),
+        }
+    }
+    for task in tasks.iter().rev().filter(|task| task.action == Action::Delete) {
+        if progress.cancelled || cancel.load(Ordering::Relaxed) {
+            progress.cancelled = true;
+            break;
+        }
+        let side = if task.left.is_some() { &left } else { &right };
+        let path = side.root.join(task.path());
+        progress.current = path.clone();
+        report(&progress);
+        match side.vfs.remove(&path) {
+            Ok(_) => summary.deleted += 1,
+            Err(e) => summary.errors.push(format!("Cannot delete \"{}\": {}", path.display(), e)),
+        }
+        if !task.is_dir() {
+            progress.files_done += 1;
+        }
+    }
+    summary.cancelled = progress.cancelled;
+    (summary, progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::vfs::{DirEntry, MemoryFs, Metadata};
+    use std::io::{Read, Write};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn write(fs: &MemoryFs, path: &str, data: &str, seconds: u64)
+    {
+        let path = Path::new("/d").join(path);
+        fs.add_file(&path, data.as_bytes());
+        fs.set_modified(&path, UNIX_EPOCH + Duration::from_secs(seconds)).unwrap();
+    }
+
+    // Two trees with something of each kind of difference.
+    fn trees() -> (MemoryFs, MemoryFs)
+    {
+        let (left, right) = (MemoryFs::new(), MemoryFs::new());
+        write(&left, "same.txt", "same", 1000);
+        write(&right, "same.txt", "same", 1000);
+        write(&left, "newer left.txt", "new", 2000);
+        write(&right, "newer left.txt", "old", 1000);
+        write(&left, "sub/newer right.txt", "old", 1000);
+        write(&right, "sub/newer right.txt", "newer", 2000);
+        write(&left, "sub/deep/left only.txt", "L", 1000);
+        write(&left, "only left dir/a.txt", "a", 1000);
+        write(&left, "only left dir/inner/b.log", "b", 1000);
+        write(&right, "only right dir/c.txt", "c", 1000);
+        write(&right, "right only.txt", "R", 1000);
+        write(&left, "size.txt", "abc", 1000);
+        write(&right, "size.txt", "abcd", 1000);
+        write(&left, "conflict", "file", 1000);
+        right.mkdir(Path::new("/d/conflict")).unwrap();
+        left.mkdir(Path::new("/d/empty dir")).unwrap();
+        (left, right)
+    }
+
+    fn plan_of(left: &MemoryFs, right: &MemoryFs, options: &SyncOptions) -> Vec<Task>
+    {
+        let root = Path::new("/d");
+        let (tasks, progress) = plan(left, root, right, root, options, &AtomicBool::new(false), &mut |_| {}).unwrap();
+        assert!(!progress.cancelled);
+        tasks
+    }
+
+    fn apply_to(left: &MemoryFs, right: &MemoryFs, tasks: &[Task]) -> (Summary, Progress)
+    { apply(left, Path::new("/d"), right, Path::new("/d"), tasks, &AtomicBool::new(false), &mut |_| {}) }
+
+    fn summary(tasks: &[Task]) -> Vec<(String, Difference, Action)>
+    { tasks.iter().map(|task| (task.path().to_string_lossy().replace('\\', "/"), task.difference, task.action)).collect() }
+
+    fn task<'a>(tasks: &'a [Task], path: &str) -> &'a Task
+    { tasks.iter().find(|task| task.path() == Path::new(path)).unwrap() }
+
+    // The paths under `/d`, with the text of the files.
+    fn contents(fs: &MemoryFs) -> Vec<(String, String)>
+    {
+        fn walk(fs: &MemoryFs, dir: &Path, rv: &mut Vec<(String, String)>)
+        {
+            for entry in fs.list(dir).unwrap() {
+                let path = dir.join(&entry.name);
+                let name = path.strip_prefix("/d").unwrap().to_string_lossy().replace('\\', "/");
+                if entry.meta.is_dir {
+                    rv.push((name + "/", String::new()));
+                    walk(fs, &path, rv);
+                } else {
+                    rv.push((name, String::from_utf8(fs.read(&path).unwrap()).unwrap()));
+                }
+            }
+        }
+        let mut rv = Vec::new();
+        walk(fs, Path::new("/d"), &mut rv);
+        rv.sort();
+        rv
+    }
+
+    #[test]
+    fn default_actions()
+    {
+        let differences = [Difference::LeftOnly, Difference::RightOnly, Difference::LeftNewer, Difference::RightNewer,
+                           Difference::Different];
+        let actions = |mode| differences.iter().map(|&difference| default_action(mode, difference)).collect::<Vec<_>>();
+        assert_eq!(actions(SyncMode::BothWays),
+                   [Action::CopyRight, Action::CopyLeft, Action::CopyRight, Action::CopyLeft, Action::Skip]);
+        assert_eq!(actions(SyncMode::LeftToRight),
+                   [Action::CopyRight, Action::Skip, Action::CopyRight, Action::Skip, Action::Skip]);
+        assert_eq!(actions(SyncMode::Mirror),
+                   [Action::CopyRight, Action::Delete, Action::CopyRight, Action::CopyRight, Action::CopyRight]);
+    }
+
+    #[test]
+    fn allowed_actions()
+    {
+        let item = |is_dir| Some(Item { name: "x".to_string(), is_dir: is_dir, size: 1, modified: None });
+        let task = |left, right| Task {
+            left_path: PathBuf::from("x"),
+            right_path: PathBuf::from("x"),
+            left: left,
+            right: right,
+            difference: Difference::Different,
+            action: Action::Skip,
+        };
+        let allowed = |task: &Task| [Action::CopyRight, Action::CopyLeft, Action::Delete, Action::Skip].iter()
+            .map(|&action| task.allows(action))
+            .collect::<Vec<_>>();
+        assert_eq!(allowed(&task(item(false), item(false))), [true, true, false, true]);
+        assert_eq!(allowed(&task(item(false), None)), [true, false, true, true]);
+        assert_eq!(allowed(&task(None, item(true))), [false, true, true, true]);
+        // a file against a directory is only skipped
+        let conflict = task(item(false), item(true));
+        assert!(conflict.is_conflict() && !conflict.is_dir());
+        assert_eq!(allowed(&conflict), [false, false, false, true]);
+    }
+
+    #[test]
+    fn filters()
+    {
+        let item = |name: &str, is_dir| Item { name: name.to_string(), is_dir: is_dir, size: 1, modified: None };
+        let options = SyncOptions { names: "*.txt; *.md".to_string(), exclude_names: "secret*; build".to_string(),
+                                    .. SyncOptions::default() };
+        assert!(wanted(&options, &item("a.txt", false)));
+        assert!(wanted(&options, &item("B.MD", false)));
+        assert!(!wanted(&options, &item("a.log", false)));
+        assert!(!wanted(&options, &item("secret.txt", false)));
+        // directories only have to pass the exclusions
+        assert!(wanted(&options, &item("docs", true)));
+        assert!(!wanted(&options, &item("build", true)));
+        assert!(wanted(&SyncOptions::default(), &item("anything", false)));
+    }
+
+    #[test]
+    fn both_ways()
+    {
+        let (left, right) = trees();
+        let tasks = plan_of(&left, &right, &SyncOptions::default());
+        assert_eq!(summary(&tasks), vec![
+            ("empty dir".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("only left dir".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("only left dir/inner".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("only left dir/inner/b.log".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("only left dir/a.txt".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("sub/deep".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("sub/deep/left only.txt".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("sub/newer right.txt".to_string(), Difference::RightNewer, Action::CopyLeft),
+            ("conflict".to_string(), Difference::Different, Action::Skip),
+            ("newer left.txt".to_string(), Difference::LeftNewer, Action::CopyRight),
+            ("size.txt".to_string(), Difference::Different, Action::Skip),
+            ("only right dir".to_string(), Difference::RightOnly, Action::CopyLeft),
+            ("only right dir/c.txt".to_string(), Difference::RightOnly, Action::CopyLeft),
+            ("right only.txt".to_string(), Difference::RightOnly, Action::CopyLeft),
+        ]);
+
+        let (done, progress) = apply_to(&left, &right, &tasks);
+        assert!(done.errors.is_empty(), "{:?}", done.errors);
+        assert_eq!((done.copied_right, done.copied_left, done.deleted, done.skipped),
+                   (8, 4, 0, 2));
+        assert_eq!(progress.files_done, progress.files_total);
+        assert_eq!(progress.bytes_done, progress.bytes_total);
+        assert_eq!(done.bytes, progress.bytes_done);
+        // the newer right file replaced the left one, keeping its time
+        assert_eq!(left.read(Path::new("/d/sub/newer right.txt")).unwrap(), b"newer");
+        assert_eq!(left.stat(Path::new("/d/sub/newer right.txt")).unwrap().modified,
+                   Some(UNIX_EPOCH + Duration::from_secs(2000)));
+        assert_eq!(right.read(Path::new("/d/newer left.txt")).unwrap(), b"new");
+        assert!(right.stat(Path::new("/d/empty dir")).unwrap().is_dir);
+        // only what was skipped still differs
+        assert_eq!(summary(&plan_of(&left, &right, &SyncOptions::default())), vec![
+            ("conflict".to_string(), Difference::Different, Action::Skip),
+            ("size.txt".to_string(), Difference::Different, Action::Skip),
+        ]);
+    }
+
+    #[test]
+    fn left_to_right_never_deletes()
+    {
+        let (left, right) = trees();
+        let options = SyncOptions { mode: SyncMode::LeftToRight, names: "*.txt".to_string(),
+                                    exclude_names: "deep; conflict".to_string(), .. SyncOptions::default() };
+        let tasks = plan_of(&left, &right, &options);
+        assert_eq!(summary(&tasks), vec![
+            ("empty dir".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("only left dir".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("only left dir/inner".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("only left dir/a.txt".to_string(), Difference::LeftOnly, Action::CopyRight),
+            ("sub/newer right.txt".to_string(), Difference::RightNewer, Action::Skip),
+            ("newer left.txt".to_string(), Difference::LeftNewer, Action::CopyRight),
+            ("size.txt".to_string(), Difference::Different, Action::Skip),
+            ("only right dir".to_string(), Difference::RightOnly, Action::Skip),
+            ("only right dir/c.txt".to_string(), Difference::RightOnly, Action::Skip),
+            ("right only.txt".to_string(), Difference::RightOnly, Action::Skip),
+        ]);
+        let before = contents(&right);
+        let (done, _) = apply_to(&left, &right, &tasks);
+        assert!(done.errors.is_empty(), "{:?}", done.errors);
+        assert_eq!(done.deleted, 0);
+        let after = contents(&right);
+        assert!(before.iter().filter(|&&(ref path, _)| path != "newer left.txt").all(|entry| after.contains(entry)));
+        assert_eq!(right.read(Path::new("/d/newer left.txt")).unwrap(), b"new");
+
+        // a file copied on its own makes the directories it goes into
+        let (left, right) = trees();
+        let only = [task(&tasks, "only left dir/a.txt").clone()];
+        let (done, _) = apply_to(&left, &right, &only);
+        assert_eq!((done.copied_right, done.errors.len()), (1, 0));
+        assert_eq!(right.read(Path::new("/d/only left dir/a.txt")).unwrap(), b"a");
+    }
+
+    #[test]
+    fn mirror_deletes_what_is_on_the_right_only()
+    {
+        let (left, right) = trees();
+        right.remove(Path::new("/d/conflict")).unwrap();
+        let options = SyncOptions { mode: SyncMode::Mirror, .. SyncOptions::default() };
+        let tasks = plan_of(&left, &right, &options);
+        assert_eq!(task(&tasks, "sub/newer right.txt").action, Action::CopyRight);
+        assert_eq!(task(&tasks, "size.txt").action, Action::CopyRight);
+        assert_eq!(task(&tasks, "only right dir").action, Action::Delete);
+        assert_eq!(task(&tasks, "only right dir/c.txt").action, Action::Delete);
+        assert_eq!(task(&tasks, "right only.txt").action, Action::Delete);
+        let (done, _) = apply_to(&left, &right, &tasks);
+        assert!(done.errors.is_empty(), "{:?}", done.errors);
+        assert_eq!(done.deleted, 3);
+        assert_eq!(contents(&right), contents(&left));
+
+        // a directory with a file kept in it cannot go; that is reported and
+        // the rest goes on
+        write(&right, "gone/keep.txt", "k", 1);
+        write(&right, "gone/drop.txt", "d", 1);
+        let mut tasks = plan_of(&left, &right, &options);
+        tasks.iter_mut().find(|task| task.path() == Path::new("gone/keep.txt")).unwrap().action = Action::Skip;
+        let (done, _) = apply_to(&left, &right, &tasks);
+        assert_eq!((done.deleted, done.skipped), (1, 1));
+        assert_eq!(done.errors.len(), 1);
+        assert!(done.errors[0].contains("gone"), "{:?}", done.errors);
+        assert!(right.read(Path::new("/d/gone/keep.txt")).is_some() && right.read(Path::new("/d/gone/drop.txt")).is_none());
+    }
+
+    #[test]
+    fn contents_and_cancel()
+    {
+        let (left, right) = trees();
+        // the same size and time, other bytes
+        write(&left, "same.txt", "samf", 1000);
+        let options = SyncOptions {
+            compare: CompareOptions { content: ContentCheck::Bytes, time_tolerance: Duration::from_secs(0) },
+            .. SyncOptions::default()
+        };
+        let root = Path::new("/d");
+        let (tasks, progress) = plan(&left, root, &right, root, &options, &AtomicBool::new(false), &mut |_| {}).unwrap();
+        assert_eq!(task(&tasks, "same.txt").difference, Difference::Different);
+        assert_eq!(progress.files_done, progress.files_total);
+        assert_eq!(progress.bytes_done, progress.bytes_total);
+
+        let (tasks, progress) = plan(&left, root, &right, root, &options, &AtomicBool::new(true), &mut |_| {}).unwrap();
+        assert!(progress.cancelled && tasks.is_empty());
+        let tasks = plan_of(&left, &right, &SyncOptions::default());
+        let (done, _) = apply(&left, root, &right, root, &tasks, &AtomicBool::new(true), &mut |_| {});
+        assert!(done.cancelled && done.copied_right == 0 && done.copied_left == 0);
+
+        // a missing side is an error from the plan
+        assert!(plan(&left, Path::new("/nope"), &right, root, &options, &AtomicBool::new(false), &mut |_| {}).is_err());
+    }
+
+    // A file system that ignores case, as Windows does; the names are kept
+    // as they are given.
+    struct NoCase(MemoryFs);
+
+    impl Vfs for NoCase {
+        fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>> { self.0.list(dir) }
+        fn stat(&self, path: &Path) -> io::Result<Metadata> { self.0.stat(path) }
+        fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>> { self.0.open_read(path) }
+        fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>> { self.0.open_write(path) }
+        fn mkdir(&self, path: &Path) -> io::Result<()> { self.0.mkdir(path) }
+        fn remove(&self, path: &Path) -> io::Result<()> { self.0.remove(path) }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> { self.0.rename(from, to) }
+        fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> { self.0.set_modified(path, time) }
+        fn ignores_case(&self) -> bool { true }
+    }
+
+    #[test]
+    fn names_paired_across_case()
+    {
+        let (left, right) = (NoCase(MemoryFs::new()), MemoryFs::new());
+        write(&left.0, "Docs/Readme.txt", "new", 2000);
+        write(&right, "DOCS/README.TXT", "old", 1000);
+        write(&left.0, "Docs/notes.txt", "old", 1000);
+        write(&right, "DOCS/Notes.txt", "newer", 2000);
+        write(&right, "DOCS/Extra/more.txt", "more", 1000);
+        let root = Path::new("/d");
+        let (tasks, _) = plan(&left, root, &right, root, &SyncOptions::default(), &AtomicBool::new(false), &mut |_| {})
+            .unwrap();
+        let paths: Vec<(String, String, Action)> = tasks.iter()
+            .map(|task| (task.left_path.to_string_lossy().replace('\\', "/"),
+                         task.right_path.to_string_lossy().replace('\\', "/"), task.action))
+            .collect();
+        assert_eq!(paths, vec![
+            ("Docs/notes.txt".to_string(), "DOCS/Notes.txt".to_string(), Action::CopyLeft),
+            ("Docs/Readme.txt".to_string(), "DOCS/README.TXT".to_string(), Action::CopyRight),
+            // on one side only, under the other side's name for the directory
+            ("Docs/Extra".to_string(), "DOCS/Extra".to_string(), Action::CopyLeft),
+            ("Docs/Extra/more.txt".to_string(), "DOCS/Extra/more.txt".to_string(), Action::CopyLeft),
+        ]);
+
+        let (done, _) = apply(&left, root, &right, root, &tasks, &AtomicBool::new(false), &mut |_| {});
+        assert!(done.errors.is_empty(), "{:?}", done.errors);
+        // each side's files were updated under their own names
+        let entry = |path: &str, text: &str| (path.to_string(), text.to_string());
+        assert_eq!(contents(&left.0), vec![
+            entry("Docs/", ""), entry("Docs/Extra/", ""), entry("Docs/Extra/more.txt", "more"),
+            entry("Docs/Readme.txt", "new"), entry("Docs/notes.txt", "newer"),
+        ]);
+        assert_eq!(contents(&right), vec![
+            entry("DOCS/", ""), entry("DOCS/Extra/", ""), entry("DOCS/Extra/more.txt", "more"),
+            entry("DOCS/Notes.txt", "newer"), entry("DOCS/README.TXT", "new"),
+        ]);
+    }
+}
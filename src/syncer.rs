@@ -0,0 +1,445 @@
+// Synchronize Directories window (Ctrl+Shift+S): the directories of the two
+// panels, the options on top and below them what differs, one line for each
+// file or directory with what is to be done about it. Compare and
+// Synchronize both run with the progress window of the file operations;
+// that sends CM_OPERATION_DONE here, and after a sync it is passed on to
+// the main window so the panels are read again.
+
+extern crate winapi;
+extern crate std;
+
+use ::commands;
+use ::compare::{CompareOptions, ContentCheck, Difference};
+use ::operation::{self, Work};
+use ::size_format::{SizeFormat, format_size};
+use ::sync_dirs::{self, Action, Summary, SyncMode, SyncOptions, Task};
+use ::vfs::Vfs;
+use win_gdi::{WinCls, rcrc, show_error, create_control, add_dialog, remove_dialog};
+use winapi::*;
+use win_layer::*;
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const WIDTH: c_int = 720;
+const HEIGHT: c_int = 560;
+// top of the task list in the client area
+const TASKS_TOP: c_int = 158;
+const STATUS_HEIGHT: c_int = 22;
+// most failures listed in the report at the end
+const MAX_REPORTED_ERRORS: usize = 10;
+
+// above the WM_COMMAND numbers of `commands`
+const IDC_NAMES: c_int = 200;
+const IDC_EXCLUDE_NAMES: c_int = 201;
+const IDC_MODE: c_int = 202;
+const IDC_CONTENT: c_int = 203;
+const IDC_TOLERANCE: c_int = 204;
+const IDC_COPY_RIGHT: c_int = 210;
+const IDC_COPY_LEFT: c_int = 211;
+const IDC_DELETE: c_int = 212;
+const IDC_SKIP: c_int = 213;
+const IDC_SYNC: c_int = 214;
+const IDC_TASKS: c_int = 220;
+const IDC_STATUS: c_int = 221;
+
+const MODES: [(&'static str, SyncMode); 3] = [
+    ("Both ways, newer files win", SyncMode::BothWays),
+    ("Left to right, newer files only", SyncMode::LeftToRight),
+    ("Mirror left to right, deleting the rest", SyncMode::Mirror),
+];
+
+// The operation running, and where it leaves what it comes to.
+enum Running {
+    Comparing(HWND, Arc<Mutex<Option<Vec<Task>>>>),
+    Synchronizing(HWND, Arc<Mutex<Option<Summary>>>),
+}
+
+pub struct SyncerCls {
+    // the main window, told when a sync has changed the panels' directories
+    main: HWND,
+    left: (Arc<Vfs>, PathBuf),
+    right: (Arc<Vfs>, PathBuf),
+    controls: RefCell<HashMap<c_int, HWND>>,
+    // in the order of the list
+    tasks: RefCell<Vec<Task>>,
+    running: RefCell<Option<Running>>,
+}
+
+fn action_text(action: Action) -> &'static str
+{
+    match action {
+        Action::CopyRight => "-->",
+        Action::CopyLeft => "<--",
+        Action::Delete => "Delete",
+        Action::Skip => "Skip",
+    }
+}
+
+fn difference_text(task: &Task) -> &'static str
+{
+    match task.difference {
+        _ if task.is_conflict() => "file and directory",
+        Difference::LeftOnly => "left only",
+        Difference::RightOnly => "right only",
+        Difference::LeftNewer => "left newer",
+        Difference::RightNewer => "right newer",
+        Difference::Different => "different",
+    }
+}
+
+fn task_line(task: &Task) -> String
+{
+    let side = |item: Option<&::compare::Item>| match item {
+        Some(item) if item.is_dir => "<DIR>".to_string(),
+        Some(item) => format_size(item.size, SizeFormat::Binary),
+        None => String::new(),
+    };
+    format!("{}\t{}\t{}\t{}\t{}", action_text(task.action), task.path().display(), difference_text(task),
+            side(task.left.as_ref()), side(task.right.as_ref()))
+}
+
+impl SyncerCls {
+    pub fn create(instance: HINSTANCE, main: HWND, left: (Arc<Vfs>, PathBuf), right: (Arc<Vfs>, PathBuf))
+        -> Result<HWND, u32>
+    {
+        let (left_text, right_text) = (format!("Left: {}", left.1.display()), format!("Right: {}", right.1.display()));
+        let inst = SyncerCls {
+            main: main,
+            left: left,
+            right: right,
+            controls: RefCell::new(HashMap::new()),
+            tasks: RefCell::new(Vec::new()),
+            running: RefCell::new(None),
+        };
+        let cls_id = try!(Self::get_cls_id());
+        let inst_rc = rcrc(inst);
+        let inst_ptr = Box::into_raw(Box::new(inst_rc.clone()) as Box<Any>);
+        let hwnd = try!(CreateWindowExW(
+            0,
+            cls_id,
+            Some(&wstr("Synchronize Directories")),
+            WS_OVERLAPPEDWINDOW,
+            CW_USEDEFAULT, CW_USEDEFAULT, WIDTH, HEIGHT,
+            None, None, instance, Some(inst_ptr as LPVOID)));
+
+        let edits = [
+            ("File names:", IDC_NAMES, "*", (10, 54, 100, 230)),
+            ("Except:", IDC_EXCLUDE_NAMES, "", (350, 54, 60, 230)),
+            ("Ignore time differences up to:", IDC_TOLERANCE, "2", (350, 82, 170, 40)),
+        ];
+        let buttons = [
+            ("Compare", IDOK, BS_DEFPUSHBUTTON, 10),
+            ("Copy -->", IDC_COPY_RIGHT, BS_PUSHBUTTON, 110),
+            ("<-- Copy", IDC_COPY_LEFT, BS_PUSHBUTTON, 200),
+            ("Delete", IDC_DELETE, BS_PUSHBUTTON, 290),
+            ("Skip", IDC_SKIP, BS_PUSHBUTTON, 380),
+            ("Synchronize", IDC_SYNC, BS_PUSHBUTTON, 490),
+            ("Close", IDCANCEL, BS_PUSHBUTTON, 610),
+        ];
+        {
+            let inst = inst_rc.borrow();
+            let mut controls = inst.controls.borrow_mut();
+            try!(create_control(hwnd, "STATIC", &left_text, SS_NOPREFIX, (10, 10, WIDTH - 40, 16), -1));
+            try!(create_control(hwnd, "STATIC", &right_text, SS_NOPREFIX, (10, 30, WIDTH - 40, 16), -1));
+            for &(label, id, text, (x, y, label_width, width)) in edits.iter() {
+                try!(create_control(hwnd, "STATIC", label, 0, (x, y + 3, label_width, 16), -1));
+                let edit = try!(create_control(
+                    hwnd, "EDIT", text, WS_BORDER | WS_TABSTOP | ES_AUTOHSCROLL,
+                    (x + label_width, y, width, 22), id));
+                controls.insert(id, edit);
+            }
+            try!(create_control(hwnd, "STATIC", "seconds", 0, (565, 85, 60, 16), -1));
+            try!(create_control(hwnd, "STATIC", "Mode:", 0, (10, 85, 100, 16), -1));
+            let modes = try!(create_control(
+                hwnd, "COMBOBOX", "", WS_TABSTOP | WS_VSCROLL | CBS_DROPDOWNLIST, (110, 82, 230, 200), IDC_MODE));
+            for &(text, _) in MODES.iter() {
+                let text = wstr(text);
+                SendMessageW(modes, CB_ADDSTRING, 0, text.as_ptr() as LPARAM);
+            }
+            SendMessageW(modes, CB_SETCURSEL, 0, 0);
+            controls.insert(IDC_MODE, modes);
+            let content = try!(create_control(
+                hwnd, "BUTTON", "Compare contents", WS_TABSTOP | BS_AUTOCHECKBOX, (110, 108, 230, 20), IDC_CONTENT));
+            controls.insert(IDC_CONTENT, content);
+            for &(label, id, style, x) in buttons.iter() {
+                let button = try!(create_control(hwnd, "BUTTON", label, WS_TABSTOP | style, (x, 128, 86, 24), id));
+                controls.insert(id, button);
+            }
+            let tasks = try!(create_control(
+                hwnd, "LISTBOX", "",
+                WS_BORDER | WS_TABSTOP | WS_VSCROLL | LBS_NOTIFY | LBS_NOINTEGRALHEIGHT | LBS_EXTENDEDSEL | LBS_USETABSTOPS,
+                (10, TASKS_TOP, 100, 100), IDC_TASKS));
+            // in dialog units: the action, the path, what differs and the two sizes
+            let stops: [c_int; 4] = [36, 260, 330, 380];
+            SendMessageW(tasks, LB_SETTABSTOPS, stops.len() as WPARAM, stops.as_ptr() as LPARAM);
+            controls.insert(IDC_TASKS, tasks);
+            let status = try!(create_control(hwnd, "STATIC", "", 0, (10, 0, 100, 16), IDC_STATUS));
+            controls.insert(IDC_STATUS, status);
+            try!(inst.layout(hwnd));
+        }
+        add_dialog(hwnd);
+        let _ = ShowWindow(hwnd, SW_SHOW);
+        let _ = SetFocus(inst_rc.borrow().control(IDOK));
+        Ok(hwnd)
+    }
+
+    fn control(&self, id: c_int) -> HWND
+    { self.controls.borrow().get(&id).cloned().unwrap_or(0 as HWND) }
+
+    // The task list and the status line take what is left of the window.
+    fn layout(&self, hwnd: HWND) -> Result<(), u32>
+    {
+        let rect = try!(GetClientRect(hwnd));
+        let width = rect.right - 20;
+        let status_top = rect.bottom - STATUS_HEIGHT;
+        try!(MoveWindow(self.control(IDC_TASKS), 10, TASKS_TOP, width, status_top - TASKS_TOP - 4, true));
+        MoveWindow(self.control(IDC_STATUS), 10, status_top + 2, width, 16, true)
+    }
+
+    fn read_options(&self) -> io::Result<SyncOptions>
+    {
+        let text = |id| GetWindowTextW(self.control(id)).trim().to_string();
+        let tolerance = text(IDC_TOLERANCE);
+        let seconds = try!(tolerance.parse().map_err(|_|
+            io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not a number of seconds", tolerance))));
+        let mode = SendMessageW(self.control(IDC_MODE), CB_GETCURSEL, 0, 0);
+        let content = SendMessageW(self.control(IDC_CONTENT), BM_GETCHECK, 0, 0) as WPARAM == BST_CHECKED;
+        Ok(SyncOptions {
+            mode: MODES.get(mode as usize).map_or(SyncMode::BothWays, |&(_, mode)| mode),
+            compare: CompareOptions {
+                content: if content { ContentCheck::Bytes } else { ContentCheck::Off },
+                time_tolerance: Duration::from_secs(seconds),
+            },
+            // `*` is every file, as when empty
+            names: if text(IDC_NAMES) == "*" { String::new() } else { text(IDC_NAMES) },
+            exclude_names: text(IDC_EXCLUDE_NAMES),
+        })
+    }
+
+    fn set_status(&self, text: &str)
+    {
+        let _ = SetWindowTextW(self.control(IDC_STATUS), &wstr(text));
+    }
+
+    fn update_status(&self)
+    {
+        let tasks = self.tasks.borrow();
+        let count = |action| tasks.iter().filter(|task| task.action == action).count();
+        self.set_status(&format!(
+            "{} differences: {} to copy right, {} to copy left, {} to delete, {} skipped",
+            tasks.len(), count(Action::CopyRight), count(Action::CopyLeft), count(Action::Delete), count(Action::Skip)));
+    }
+
+    fn compare(&self, hwnd: HWND)
+    {
+        if self.running.borrow().is_some() {
+            return;
+        }
+        let options = match self.read_options() {
+            Ok(options) => options,
+            Err(e) => return show_error(hwnd, "Synchronize Directories", &e),
+        };
+        SendMessageW(self.control(IDC_TASKS), LB_RESETCONTENT, 0, 0);
+        self.tasks.borrow_mut().clear();
+        let ((left_fs, left_dir), (right_fs, right_dir)) = (self.left.clone(), self.right.clone());
+        let found = Arc::new(Mutex::new(None));
+        let slot = found.clone();
+        let work: Work = Box::new(move |cancel, report| {
+            let (tasks, progress) = try!(sync_dirs::plan(
+                &*left_fs, &left_dir, &*right_fs, &right_dir, &options, cancel, report));
+            if !progress.cancelled {
+                *slot.lock().unwrap() = Some(tasks);
+            }
+            Ok(progress)
+        });
+        match operation::start(hwnd, "Compare", work) {
+            Ok(window) => *self.running.borrow_mut() = Some(Running::Comparing(window, found)),
+            Err(e) => show_error(hwnd, "Compare", &io::Error::from_raw_os_error(e as i32)),
+        }
+        self.set_status("Comparing...");
+    }
+
+    fn show_tasks(&self, tasks: Vec<Task>)
+    {
+        let list = self.control(IDC_TASKS);
+        for task in tasks.iter() {
+            let text = wstr(&task_line(task));
+            SendMessageW(list, LB_ADDSTRING, 0, text.as_ptr() as LPARAM);
+        }
+        *self.tasks.borrow_mut() = tasks;
+        self.update_status();
+    }
+
+    // Sets `action` on the selected lines whose tasks allow it.
+    fn set_action(&self, action: Action)
+    {
+        let list = self.control(IDC_TASKS);
+        let count = SendMessageW(list, LB_GETSELCOUNT, 0, 0);
+        if count <= 0 {
+            return;
+        }
+        let mut selected: Vec<c_int> = vec![0; count as usize];
+        SendMessageW(list, LB_GETSELITEMS, count as WPARAM, selected.as_mut_ptr() as LPARAM);
+        let top = SendMessageW(list, LB_GETTOPINDEX, 0, 0);
+        let mut tasks = self.tasks.borrow_mut();
+        for &idx in selected.iter() {
+            let task = match tasks.get_mut(idx as usize) {
+                Some(task) if task.allows(action) => task,
+                _ => continue,
+            };
+            task.action = action;
+            // lines cannot be changed, only replaced
+            let text = wstr(&task_line(task));
+            SendMessageW(list, LB_DELETESTRING, idx as WPARAM, 0);
+            SendMessageW(list, LB_INSERTSTRING, idx as WPARAM, text.as_ptr() as LPARAM);
+            SendMessageW(list, LB_SETSEL, 1, idx as LPARAM);
+        }
+        SendMessageW(list, LB_SETTOPINDEX, top as WPARAM, 0);
+        drop(tasks);
+        self.update_status();
+    }
+
+    fn synchronize(&self, hwnd: HWND)
+    {
+        if self.running.borrow().is_some() {
+            return;
+        }
+        let tasks = self.tasks.borrow().clone();
+        let deletions = tasks.iter().filter(|task| task.action == Action::Delete).count();
+        if tasks.iter().all(|task| task.action == Action::Skip) {
+            return;
+        }
+        if deletions > 0 {
+            let question = format!("{} files and directories will be deleted. Synchronize?", deletions);
+            match MessageBoxW(Some(hwnd), &wstr(&question), &wstr("Synchronize Directories"), MB_YESNO | MB_ICONWARNING) {
+                Ok(answer) if answer as c_int == IDYES => {},
+                _ => return,
+            }
+        }
+        let ((left_fs, left_dir), (right_fs, right_dir)) = (self.left.clone(), self.right.clone());
+        let done = Arc::new(Mutex::new(None));
+        let slot = done.clone();
+        let work: Work = Box::new(move |cancel, report| {
+            let (summary, progress) = sync_dirs::apply(&*left_fs, &left_dir, &*right_fs, &right_dir, &tasks, cancel, report);
+            *slot.lock().unwrap() = Some(summary);
+            Ok(progress)
+        });
+        match operation::start(hwnd, "Synchronize", work) {
+            Ok(window) => *self.running.borrow_mut() = Some(Running::Synchronizing(window, done)),
+            Err(e) => show_error(hwnd, "Synchronize", &io::Error::from_raw_os_error(e as i32)),
+        }
+    }
+
+    fn report(&self, hwnd: HWND, summary: &Summary)
+    {
+        let mut text = format!(
+            "Copied {} to the right and {} to the left, {} in all.\nDeleted {}, skipped {}.",
+            summary.copied_right, summary.copied_left, format_size(summary.bytes, SizeFormat::Binary),
+            summary.deleted, summary.skipped);
+        if summary.cancelled {
+            text.push_str("\n\nStopped before the end.");
+        }
+        if !summary.errors.is_empty() {
+            text.push_str(&format!("\n\n{} failed:", summary.errors.len()));
+            for error in summary.errors.iter().take(MAX_REPORTED_ERRORS) {
+                text.push_str(&format!("\n{}", error));
+            }
+            if summary.errors.len() > MAX_REPORTED_ERRORS {
+                text.push_str("\n...");
+            }
+        }
+        let icon = if summary.errors.is_empty() { MB_ICONINFORMATION } else { MB_ICONWARNING };
+        let _ = MessageBoxW(Some(hwnd), &wstr(&text), &wstr("Synchronize Directories"), MB_OK | icon);
+    }
+
+    fn on_operation_done(&self, hwnd: HWND, window: HWND)
+    {
+        let ours = match *self.running.borrow() {
+            Some(Running::Comparing(started, _)) | Some(Running::Synchronizing(started, _)) => started == window,
+            None => false,
+        };
+        if !ours {
+            return;
+        }
+        match self.running.borrow_mut().take() {
+            Some(Running::Comparing(_, found)) => match found.lock().unwrap().take() {
+                Some(tasks) => self.show_tasks(tasks),
+                None => self.set_status("The comparison was stopped"),
+            },
+            Some(Running::Synchronizing(_, done)) => {
+                SendMessageW(self.main, WM_COMMAND, commands::CM_OPERATION_DONE as WPARAM, window as LPARAM);
+                if let Some(summary) = done.lock().unwrap().take() {
+                    self.report(hwnd, &summary);
+                }
+                // what was done no longer differs; Compare shows what still does
+                SendMessageW(self.control(IDC_TASKS), LB_RESETCONTENT, 0, 0);
+                self.tasks.borrow_mut().clear();
+                self.set_status("Synchronized; compare again to see what still differs");
+            },
+            None => {},
+        }
+    }
+}
+
+impl WinCls for SyncerCls {
+    fn wnd_proc(
+        &self,
+        hwnd: HWND, msg: UINT,
+        param: WPARAM, para: LPARAM)
+        -> Option<LRESULT>
+    {
+        match msg {
+            WM_COMMAND if LOWORD(param as DWORD) == commands::CM_OPERATION_DONE => {
+                self.on_operation_done(hwnd, para as HWND);
+                Some(0)
+            },
+            WM_COMMAND => {
+                match LOWORD(param as DWORD) as c_int {
+                    IDOK => self.compare(hwnd),
+                    IDCANCEL => { let _ = DestroyWindow(hwnd); },
+                    IDC_COPY_RIGHT => self.set_action(Action::CopyRight),
+                    IDC_COPY_LEFT => self.set_action(Action::CopyLeft),
+                    IDC_DELETE => self.set_action(Action::Delete),
+                    IDC_SKIP => self.set_action(Action::Skip),
+                    IDC_SYNC => self.synchronize(hwnd),
+                    _ => return None,
+                }
+                Some(0)
+            },
+            WM_SIZE => {
+                let _ = self.layout(hwnd);
+                Some(0)
+            },
+            WM_DESTROY => {
+                remove_dialog(hwnd);
+                Some(0)
+            },
+            _ => None,
+        }
+    }
+
+    fn register () -> Result<ATOM, u32>
+    {
+        let wnd_cls = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as UINT,
+            style: CS_HREDRAW | CS_VREDRAW,
+            lpfnWndProc: Some(Self::wnd_proc_raw),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: 0 as HINSTANCE,
+            hIcon: try!(LoadIconW(0 as HINSTANCE, RC_IDI_APPLICATION)),
+            hCursor: try!(LoadCursorW(0 as HINSTANCE, RC_IDC_ARROW)),
+            hbrBackground: (COLOR_BTNFACE + 1) as HBRUSH,
+            lpszMenuName: 0 as *const u16,
+            lpszClassName: wstr("SyncerCls").as_ptr(),
+            hIconSm: try!(LoadIconW(0 as HINSTANCE, RC_IDI_APPLICATION)),
+        };
+
+        RegisterClassExW(&wnd_cls)
+    }
+}
@@ -0,0 +1,821 @@
+// TAR archives as a read-only file system, plain or compressed with gzip or
+// xz. Ustar headers, GNU long names and links, PAX extended headers (path,
+// linkpath, size, mtime) and base-256 numbers are understood.
+//
+// A tar has no index, so the whole archive is read once when it is opened,
+// skipping over the data, and each entry's place in the uncompressed stream
+// is kept. Reading an entry decompresses the stream up to it. The stream of
+// the last read is kept, so reading entries in the order they are stored, as
+// copying a tree out of the archive does, goes through the archive once.
+//
+// Symbolic links are listed as links to what they point to inside the
+// archive; hard links read the data of the entry they link to.
+
+extern crate flate2;
+extern crate lzma_rust2;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use self::flate2::read::MultiGzDecoder;
+use self::lzma_rust2::XzReader;
+
+use ::vfs::{DirEntry, Metadata, Vfs};
+use ::zip::safe_components;
+
+const BLOCK: usize = 512;
+// links followed while resolving a symbolic link
+const MAX_LINKS: usize = 16;
+
+const GZIP_MAGIC: &'static [u8] = &[0x1f, 0x8b];
+const XZ_MAGIC: &'static [u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Clone, Debug)]
+pub struct TarEntry {
+    pub name: String,
+    pub kind: Kind,
+    // where a symbolic link points
+    pub link: String,
+    pub mode: u32,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    // where the data starts in the uncompressed archive
+    pub offset: u64,
+}
+
+fn invalid(message: &str) -> io::Error
+{ io::Error::new(io::ErrorKind::InvalidData, message.to_string()) }
+
+// The text of a header field, up to the first NUL.
+fn text_field(field: &[u8]) -> &[u8]
+{
+    match field.iter().position(|&b| b == 0) {
+        Some(end) => &field[..end],
+        None => field,
+    }
+}
+
+// An octal number, or a base-256 one if the high bit of the first byte is set.
+fn number_field(field: &[u8]) -> io::Result<u64>
+{
+    if field.first().map_or(false, |&b| b & 0x80 != 0) {
+        if field[0] & 0x40 != 0 {
+            return Err(invalid("Negative number in a TAR header"));
+        }
+        let mut value = (field[0] & 0x3f) as u64;
+        for &b in &field[1..] {
+            if value >> 56 != 0 {
+                return Err(invalid("Number too large in a TAR header"));
+            }
+            value = value << 8 | b as u64;
+        }
+        return Ok(value);
+    }
+    let text = String::from_utf8_lossy(text_field(field));
+    let text = text.trim_matches(|c| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid("Damaged number in a TAR header"))
+}
+
+// Whether the checksum matches, summing the bytes as unsigned or, as some
+// old tars did, as signed.
+fn checksum_ok(header: &[u8]) -> io::Result<bool>
+{
+    let expected = try!(number_field(&header[148..156]));
+    let (mut unsigned, mut signed) = (0u64, 0i64);
+    for (idx, &b) in header.iter().enumerate() {
+        let b = if idx >= 148 && idx < 156 { b' ' } else { b };
+        unsigned += b as u64;
+        signed += b as i8 as i64;
+    }
+    Ok(unsigned == expected || signed as u64 == expected)
+}
+
+fn time_from_secs(secs: u64) -> SystemTime
+{ UNIX_EPOCH + Duration::from_secs(secs) }
+
+// A PAX time, seconds with an optional fraction.
+fn parse_pax_time(text: &str) -> Option<SystemTime>
+{
+    let mut parts = text.splitn(2, '.');
+    let secs: i64 = match parts.next().and_then(|s| s.parse().ok()) {
+        Some(secs) => secs,
+        None => return None,
+    };
+    let nanos = parts.next().map_or(0, |f| {
+        let digits: String = f.chars().take(9).collect();
+        format!("{:0<9}", digits).parse().unwrap_or(0)
+    });
+    if secs < 0 {
+        return UNIX_EPOCH.checked_sub(Duration::from_secs(-secs as u64));
+    }
+    Some(UNIX_EPOCH + Duration::new(secs as u64, nanos))
+}
+
+// The `length key=value\n` records of a PAX extended header.
+fn parse_pax(data: &[u8]) -> io::Result<HashMap<String, String>>
+{
+    let mut records = HashMap::new();
+    let mut rest = data;
+    while !rest.is_empty() && rest[0] != 0 {
+        let space = try!(rest.iter().position(|&b| b == b' ').ok_or_else(|| invalid("Damaged PAX header")));
+        let len: usize = try!(String::from_utf8_lossy(&rest[..space]).parse().map_err(|_| invalid("Damaged PAX header")));
+        if len <= space + 1 || len > rest.len() || rest[len - 1] != b'\n' {
+            return Err(invalid("Damaged PAX header"));
+        }
+        let record = String::from_utf8_lossy(&rest[space + 1..len - 1]).into_owned();
+        if let Some(eq) = record.find('=') {
+            records.insert(record[..eq].to_string(), record[eq + 1..].to_string());
+        }
+        rest = &rest[len..];
+    }
+    Ok(records)
+}
+
+// `size` rounded up to whole blocks.
+fn padded(size: u64) -> io::Result<u64>
+{
+    size.checked_add(BLOCK as u64 - 1)
+        .map(|end| end / BLOCK as u64 * BLOCK as u64)
+        .ok_or_else(|| invalid("Entry too large in a TAR header"))
+}
+
+// The offset `len` bytes past `offset`.
+fn advance(offset: u64, len: u64) -> io::Result<u64>
+{ offset.checked_add(len).ok_or_else(|| invalid("Entry too large in a TAR header")) }
+
+// Reads `len` bytes, failing if the archive ends before.
+fn read_data(input: &mut Read, len: u64) -> io::Result<Vec<u8>>
+{
+    let mut data = Vec::new();
+    try!(input.take(len).read_to_end(&mut data));
+    if (data.len() as u64) < len {
+        return Err(invalid("Unexpected end of the TAR archive"));
+    }
+    Ok(data)
+}
+
+fn skip(input: &mut Read, len: u64) -> io::Result<()>
+{
+    let skipped = try!(io::copy(&mut input.take(len), &mut io::sink()));
+    if skipped < len {
+        return Err(invalid("Unexpected end of the TAR archive"));
+    }
+    Ok(())
+}
+
+// Whether a block could be read; false at the end of the input.
+fn read_block(input: &mut Read, block: &mut [u8; BLOCK]) -> io::Result<bool>
+{
+    let mut read = 0;
+    while read < BLOCK {
+        match input.read(&mut block[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(invalid("Unexpected end of the TAR archive")),
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+// Reads the headers of the uncompressed archive `input`, skipping the data.
+pub fn read_entries(input: &mut Read) -> io::Result<Vec<TarEntry>>
+{
+    let mut entries: Vec<TarEntry> = Vec::new();
+    // file entries by name, for hard links
+    let mut files: HashMap<String, usize> = HashMap::new();
+    let mut global: HashMap<String, String> = HashMap::new();
+    let mut extended: HashMap<String, String> = HashMap::new();
+    let (mut long_name, mut long_link): (Option<String>, Option<String>) = (None, None);
+    let mut offset = 0u64;
+    let mut block = [0; BLOCK];
+    loop {
+        if !try!(read_block(input, &mut block)) {
+            break;
+        }
+        offset += BLOCK as u64;
+        // the archive ends with zero blocks
+        if block.iter().all(|&b| b == 0) {
+            break;
+        }
+        if !checksum_ok(&block).unwrap_or(false) {
+            return Err(invalid(if entries.is_empty() { "Not a TAR archive" } else { "Damaged TAR header" }));
+        }
+        let typeflag = block[156];
+        let mut size = try!(number_field(&block[124..136]));
+        match typeflag {
+            b'x' | b'g' | b'L' | b'K' => {
+                let len = try!(padded(size));
+                let data = try!(read_data(input, size));
+                try!(skip(input, len - size));
+                offset = try!(advance(offset, len));
+                let text = String::from_utf8_lossy(text_field(&data)).into_owned();
+                match typeflag {
+                    b'x' => extended = try!(parse_pax(&data)),
+                    b'g' => global.extend(try!(parse_pax(&data))),
+                    b'L' => long_name = Some(text),
+                    _ => long_link = Some(text),
+                }
+                continue;
+            },
+            _ => {},
+        }
+        if let Some(pax_size) = extended.get("size").and_then(|s| s.parse().ok()) {
+            size = pax_size;
+        }
+        let name = match extended.get("path").cloned().or(long_name.take()) {
+            Some(name) => name,
+            None => {
+                let name = String::from_utf8_lossy(text_field(&block[0..100])).into_owned();
+                // ustar splits long names into a prefix and a name
+                let prefix = String::from_utf8_lossy(text_field(&block[345..500])).into_owned();
+                if &block[257..263] == b"ustar\0" && !prefix.is_empty() { format!("{}/{}", prefix, name) } else { name }
+            },
+        };
+        let link = extended.get("linkpath").cloned().or(long_link.take())
+            .unwrap_or_else(|| String::from_utf8_lossy(text_field(&block[157..257])).into_owned());
+        // a global header only sets times; names and sizes are per entry
+        let modified = match extended.get("mtime").or_else(|| global.get("mtime")) {
+            Some(mtime) => parse_pax_time(mtime),
+            None => Some(time_from_secs(try!(number_field(&block[136..148])))),
+        };
+        let mode = try!(number_field(&block[100..108])) as u32;
+        extended.clear();
+        let mut entry = TarEntry {
+            name: name, kind: Kind::File, link: link, mode: mode, size: size, modified: modified, offset: offset,
+        };
+        let len = try!(padded(size));
+        offset = try!(advance(offset, len));
+        try!(skip(input, len));
+        match typeflag {
+            b'0' | b'\0' | b'7' => {},
+            b'5' => entry.kind = Kind::Dir,
+            b'2' => entry.kind = Kind::Symlink,
+            // a hard link reads the data of the file it links to
+            b'1' => match files.get(entry.link.trim_start_matches("./")) {
+                Some(&idx) => {
+                    entry.offset = entries[idx].offset;
+                    entry.size = entries[idx].size;
+                },
+                None => continue,
+            },
+            // devices, FIFOs and sparse files are left out
+            _ => continue,
+        }
+        if entry.name.ends_with('/') && entry.kind == Kind::File {
+            entry.kind = Kind::Dir;
+        }
+        if entry.kind != Kind::File {
+            entry.size = 0;
+        } else {
+            files.insert(entry.name.trim_start_matches("./").to_string(), entries.len());
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// Tells the compression from the first bytes of the file.
+pub fn detect(file: &Path) -> io::Result<Compression>
+{
+    let mut magic = [0; 6];
+    let mut file = try!(File::open(file));
+    let read = try!(file.read(&mut magic));
+    Ok(if magic[..read].starts_with(GZIP_MAGIC) {
+        Compression::Gzip
+    } else if magic[..read].starts_with(XZ_MAGIC) {
+        Compression::Xz
+    } else {
+        Compression::None
+    })
+}
+
+// The uncompressed archive, `position` bytes into it.
+struct Stream {
+    input: Box<Read + Send>,
+    position: u64,
+}
+
+// Opens `file` and decompresses it up to `offset`.
+fn open_stream(file: &Path, compression: Compression, offset: u64) -> io::Result<Stream>
+{
+    let mut file = try!(File::open(file));
+    let mut input: Box<Read + Send> = match compression {
+        Compression::None => {
+            try!(file.seek(SeekFrom::Start(offset)));
+            return Ok(Stream { input: Box::new(file), position: offset });
+        },
+        Compression::Gzip => Box::new(MultiGzDecoder::new(BufReader::new(file))),
+        Compression::Xz => Box::new(XzReader::new(BufReader::new(file), true)),
+    };
+    try!(skip(&mut input, offset));
+    Ok(Stream { input: input, position: offset })
+}
+
+// The data of one entry; the stream is handed back for the next read when
+// this is dropped.
+struct EntryReader {
+    stream: Option<Stream>,
+    remaining: u64,
+    spare: Arc<Mutex<Option<Stream>>>,
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        let stream = match self.stream {
+            Some(ref mut stream) => stream,
+            None => return Ok(0),
+        };
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let len = if (buf.len() as u64) < self.remaining { buf.len() } else { self.remaining as usize };
+        let read = try!(stream.input.read(&mut buf[..len]));
+        if read == 0 {
+            return Err(invalid("Unexpected end of the TAR archive"));
+        }
+        stream.position += read as u64;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+impl Drop for EntryReader {
+    fn drop(&mut self)
+    {
+        if let Some(stream) = self.stream.take() {
+            *self.spare.lock().unwrap() = Some(stream);
+        }
+    }
+}
+
+struct Node {
+    meta: Metadata,
+    // index into `TarFs::entries`; None for directories only implied by names
+    entry: Option<usize>,
+    // the first entry at or below this path, to list entries in archive order
+    order: usize,
+}
+
+pub struct TarFs {
+    // the archive on the disk
+    file: PathBuf,
+    // the path the archive is shown at; entries are below it
+    root: PathBuf,
+    compression: Compression,
+    entries: Vec<TarEntry>,
+    tree: BTreeMap<PathBuf, Node>,
+    // the stream left by the last read
+    spare: Arc<Mutex<Option<Stream>>>,
+}
+
+fn dir_metadata(modified: Option<SystemTime>) -> Metadata
+{ Metadata { is_dir: true, size: 0, modified: modified, hidden: false, read_only: false, is_link: false, mode: None } }
+
+fn entry_metadata(entry: &TarEntry) -> Metadata
+{
+    Metadata {
+        is_dir: entry.kind == Kind::Dir,
+        size: entry.size,
+        modified: entry.modified,
+        hidden: false,
+        read_only: entry.mode & 0o222 == 0,
+        is_link: entry.kind == Kind::Symlink,
+        mode: Some(entry.mode & 0o7777),
+    }
+}
+
+fn index(root: &Path, entries: &[TarEntry]) -> BTreeMap<PathBuf, Node>
+{
+    let mut tree: BTreeMap<PathBuf, Node> = BTreeMap::new();
+    for (idx, entry) in entries.iter().enumerate() {
+        let components = match safe_components(&entry.name) {
+            Some(ref components) if !components.is_empty() => components.clone(),
+            _ => continue,
+        };
+        let mut path = root.to_path_buf();
+        for (depth, component) in components.iter().enumerate() {
+            path.push(component);
+            if depth + 1 < components.len() {
+                tree.entry(path.clone()).or_insert(Node { meta: dir_metadata(None), entry: None, order: idx });
+            }
+        }
+        // a later entry of the same name replaces the earlier one, as when extracting
+        let order = tree.get(&path).map_or(idx, |node| node.order);
+        tree.insert(path, Node { meta: entry_metadata(entry), entry: Some(idx), order: order });
+    }
+    tree
+}
+
+impl TarFs {
+    // Opens the archive `file`, to be shown at `root`.
+    pub fn open(file: &Path, root: &Path) -> io::Result<TarFs>
+    {
+        let compression = try!(detect(file));
+        let mut stream = try!(open_stream(file, compression, 0));
+        let entries = try!(read_entries(&mut stream.input));
+        let mut tar = TarFs {
+            file: file.to_path_buf(),
+            root: root.to_path_buf(),
+            compression: compression,
+            tree: index(root, &entries),
+            entries: entries,
+            spare: Arc::new(Mutex::new(None)),
+        };
+        // links show what they point to
+        let links: Vec<PathBuf> = tar.tree.iter().filter(|&(_, node)| node.meta.is_link).map(|(path, _)| path.clone()).collect();
+        for path in links {
+            if let Some(target) = tar.resolve(&path).and_then(|target| tar.tree.get(&target)) {
+                let meta = Metadata { is_link: true, hidden: false, .. target.meta.clone() };
+                tar.tree.get_mut(&path).unwrap().meta = meta;
+            }
+        }
+        Ok(tar)
+    }
+
+    // What `path` ends up at once the symbolic links along it are followed;
+    // None if a link points outside the archive or nowhere.
+    fn resolve(&self, path: &Path) -> Option<PathBuf>
+    {
+        let rest = match path.strip_prefix(&self.root) {
+            Ok(rest) => rest,
+            Err(_) => return None,
+        };
+        let mut pending: Vec<String> = rest.components().rev()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        let mut current = self.root.clone();
+        let mut links = 0;
+        while let Some(component) = pending.pop() {
+            if component == ".." {
+                if current == self.root {
+                    return None;
+                }
+                current.pop();
+                continue;
+            }
+            current.push(&component);
+            let link = match self.tree.get(&current) {
+                Some(&Node { entry: Some(idx), .. }) if self.entries[idx].kind == Kind::Symlink => &self.entries[idx].link,
+                Some(_) => continue,
+                None => return None,
+            };
+            links += 1;
+            if links > MAX_LINKS || link.starts_with('/') {
+                return None;
+            }
+            current.pop();
+            pending.extend(link.split('/').rev().filter(|c| !c.is_empty() && *c != ".").map(|c| c.to_string()));
+        }
+        Some(current)
+    }
+
+    // The data of the file at `offset`, from the spare stream if it has not
+    // gone past it yet.
+    fn open_data(&self, offset: u64, size: u64) -> io::Result<EntryReader>
+    {
+        let spare = self.spare.lock().unwrap().take();
+        let mut stream = match spare {
+            Some(stream) => if stream.position <= offset { stream } else { try!(open_stream(&self.file, self.compression, offset)) },
+            None => try!(open_stream(&self.file, self.compression, offset)),
+        };
+        if stream.position < offset {
+            try!(skip(&mut stream.input, offset - stream.position));
+            stream.position = offset;
+        }
+        Ok(EntryReader { stream: Some(stream), remaining: size, spare: self.spare.clone() })
+    }
+}
+
+fn not_in_archive(path: &Path) -> io::Error
+{ io::Error::new(io::ErrorKind::NotFound, format!("\"{}\" is not in the archive", path.display())) }
+
+fn read_only(path: &Path) -> io::Error
+{ io::Error::new(io::ErrorKind::PermissionDenied, format!("Cannot change \"{}\": TAR archives are read-only", path.display())) }
+
+impl Vfs for TarFs {
+    // Entries come in the order they are stored.
+    fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>>
+    {
+        let dir = if dir == self.root { dir.to_path_buf() } else { try!(self.resolve(dir).ok_or_else(|| not_in_archive(dir))) };
+        if dir != self.root && !try!(self.tree.get(&dir).ok_or_else(|| not_in_archive(&dir))).meta.is_dir {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("\"{}\" is not a directory", dir.display())));
+        }
+        let mut children: Vec<(usize, DirEntry)> = self.tree.range(dir.clone()..)
+            .take_while(|&(path, _)| path.starts_with(&dir))
+            .filter(|&(path, _)| path.parent() == Some(&dir))
+            .map(|(path, node)| (node.order, DirEntry {
+                name: path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned()),
+                meta: node.meta.clone(),
+            }))
+            .collect();
+        children.sort_by_key(|&(order, _)| order);
+        Ok(children.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Metadata>
+    {
+        if path == self.root {
+            return Ok(dir_metadata(None));
+        }
+        // paths below links to directories are found through the links
+        self.tree.get(path)
+            .or_else(|| self.resolve(path).and_then(|target| self.tree.get(&target)))
+            .map(|node| node.meta.clone())
+            .ok_or_else(|| not_in_archive(path))
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>>
+    {
+        let target = try!(self.resolve(path).ok_or_else(||
+            io::Error::new(io::ErrorKind::NotFound, format!("\"{}\" points outside the archive", path.display()))));
+        let entry = match self.tree.get(&target) {
+            Some(&Node { entry: Some(idx), .. }) if self.entries[idx].kind == Kind::File => &self.entries[idx],
+            Some(_) => return Err(io::Error::new(io::ErrorKind::Other, format!("\"{}\" is a directory", path.display()))),
+            None => return Err(not_in_archive(path)),
+        };
+        Ok(Box::new(try!(self.open_data(entry.offset, entry.size))))
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>
+    { Err(read_only(path)) }
+
+    fn mkdir(&self, path: &Path) -> io::Result<()>
+    { Err(read_only(path)) }
+
+    fn remove(&self, path: &Path) -> io::Result<()>
+    { Err(read_only(path)) }
+
+    fn rename(&self, from: &Path, _to: &Path) -> io::Result<()>
+    { Err(read_only(from)) }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>
+    {
+        match self.tree.get(path) {
+            Some(&Node { entry: Some(idx), .. }) if self.entries[idx].kind == Kind::Symlink =>
+                Ok(PathBuf::from(&self.entries[idx].link)),
+            Some(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not a link", path.display()))),
+            None => Err(not_in_archive(path)),
+        }
+    }
+
+    fn mount_point(&self) -> Option<PathBuf>
+    { Some(self.root.clone()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::flate2::Compression as Level;
+    use super::flate2::write::GzEncoder;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    const MTIME: u64 = 1_500_000_000;
+
+    fn octal(field: &mut [u8], value: u64)
+    {
+        let text = format!("{:0width$o}\0", value, width = field.len() - 1);
+        field.copy_from_slice(text.as_bytes());
+    }
+
+    // Fills in the checksum after the other fields have changed.
+    fn seal(block: &mut [u8; BLOCK])
+    {
+        for b in &mut block[148..156] {
+            *b = b' ';
+        }
+        let sum: u64 = block.iter().map(|&b| b as u64).sum();
+        block[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    }
+
+    fn header(name: &str, typeflag: u8, size: u64, link: &str) -> [u8; BLOCK]
+    {
+        let mut block = [0; BLOCK];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut block[100..108], 0o644);
+        octal(&mut block[108..116], 0);
+        octal(&mut block[116..124], 0);
+        octal(&mut block[124..136], size);
+        octal(&mut block[136..148], MTIME);
+        block[156] = typeflag;
+        block[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        block[257..265].copy_from_slice(b"ustar\x0000");
+        seal(&mut block);
+        block
+    }
+
+    fn pad(tar: &mut Vec<u8>)
+    {
+        let len = tar.len();
+        tar.resize((len + BLOCK - 1) / BLOCK * BLOCK, 0);
+    }
+
+    fn push(tar: &mut Vec<u8>, block: [u8; BLOCK], data: &[u8])
+    {
+        tar.extend_from_slice(&block);
+        tar.extend_from_slice(data);
+        pad(tar);
+    }
+
+    fn push_file(tar: &mut Vec<u8>, name: &str, data: &[u8])
+    { push(tar, header(name, b'0', data.len() as u64, ""), data) }
+
+    fn pax_record(key: &str, value: &str) -> String
+    {
+        // the length counts its own digits
+        let rest = format!(" {}={}\n", key, value);
+        let mut len = rest.len() + 1;
+        while format!("{}{}", len, rest).len() != len {
+            len += 1;
+        }
+        format!("{}{}", len, rest)
+    }
+
+    fn finish(mut tar: Vec<u8>) -> Vec<u8>
+    {
+        tar.extend_from_slice(&[0; 2 * BLOCK]);
+        tar
+    }
+
+    fn names(entries: &[TarEntry]) -> Vec<(&str, Kind, u64)>
+    { entries.iter().map(|e| (&e.name[..], e.kind, e.size)).collect() }
+
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf
+    {
+        let path = env::temp_dir().join(format!("tar_test_{}_{}", process::id(), name));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn read_all(tar: &TarFs, path: &Path) -> Vec<u8>
+    {
+        let mut data = Vec::new();
+        tar.open_read(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn ustar_entries()
+    {
+        let mut tar = Vec::new();
+        push(&mut tar, header("dir/", b'5', 0, ""), b"");
+        push_file(&mut tar, "dir/a.txt", b"alpha");
+        // a name split into a prefix and the rest
+        let mut block = header("b.txt", b'0', 4, "");
+        block[345..345 + 8].copy_from_slice(b"dir/deep");
+        seal(&mut block);
+        push(&mut tar, block, b"beta");
+        push(&mut tar, header("dir/link", b'2', 0, "a.txt"), b"");
+        let tar = finish(tar);
+
+        let entries = read_entries(&mut &tar[..]).unwrap();
+        assert_eq!(names(&entries), vec![("dir/", Kind::Dir, 0), ("dir/a.txt", Kind::File, 5),
+                                         ("dir/deep/b.txt", Kind::File, 4), ("dir/link", Kind::Symlink, 0)]);
+        assert_eq!(entries[1].offset, 2 * BLOCK as u64);
+        assert_eq!(entries[1].modified, Some(time_from_secs(MTIME)));
+        assert_eq!(entries[1].mode, 0o644);
+        assert_eq!(entries[3].link, "a.txt");
+
+        let mut damaged = tar.clone();
+        damaged[BLOCK + 1] ^= 1;
+        assert_eq!(read_entries(&mut &damaged[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(read_entries(&mut &tar[..BLOCK * 3 + 10]).is_err());
+    }
+
+    #[test]
+    fn gnu_long_names_and_links()
+    {
+        let long_name = format!("{}/file.txt", "d".repeat(120));
+        let long_link = format!("{}/target", "t".repeat(110));
+        let mut tar = Vec::new();
+        let stored = format!("{}\0", long_name);
+        push(&mut tar, header("././@LongLink", b'L', stored.len() as u64, ""), stored.as_bytes());
+        push_file(&mut tar, "truncated", b"data");
+        push(&mut tar, header("././@LongLink", b'K', long_link.len() as u64, ""), long_link.as_bytes());
+        push(&mut tar, header("link", b'2', 0, "short"), b"");
+        // the long names apply to one entry only
+        push_file(&mut tar, "plain", b"");
+        let entries = read_entries(&mut &finish(tar)[..]).unwrap();
+        assert_eq!(names(&entries), vec![(&long_name[..], Kind::File, 4), ("link", Kind::Symlink, 0),
+                                         ("plain", Kind::File, 0)]);
+        assert_eq!(entries[1].link, long_link);
+        assert_eq!(entries[2].link, "");
+    }
+
+    #[test]
+    fn pax_path_size_and_time()
+    {
+        let records = format!("{}{}{}", pax_record("path", "a long \u{e9} name.txt"), pax_record("size", "6"),
+                              pax_record("mtime", "1500000000.25"));
+        let mut tar = Vec::new();
+        push(&mut tar, header("PaxHeaders/x", b'x', records.len() as u64, ""), records.as_bytes());
+        // the size in the header is what a tar without PAX would skip
+        tar.extend_from_slice(&header("short", b'0', 0, ""));
+        tar.extend_from_slice(b"sixsix");
+        pad(&mut tar);
+        push_file(&mut tar, "next", b"n");
+        let entries = read_entries(&mut &finish(tar)[..]).unwrap();
+        assert_eq!(names(&entries), vec![("a long \u{e9} name.txt", Kind::File, 6), ("next", Kind::File, 1)]);
+        assert_eq!(entries[0].modified, Some(time_from_secs(MTIME) + Duration::from_millis(250)));
+        assert_eq!(entries[1].modified, Some(time_from_secs(MTIME)));
+
+        let mut tar = Vec::new();
+        push(&mut tar, header("PaxHeaders/x", b'x', 5, ""), b"9 x=y\n");
+        assert_eq!(read_entries(&mut &finish(tar)[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn hard_links_read_their_target()
+    {
+        let mut tar = Vec::new();
+        push_file(&mut tar, "./a.txt", b"shared");
+        push(&mut tar, header("b.txt", b'1', 0, "a.txt"), b"");
+        push(&mut tar, header("dangling", b'1', 0, "none"), b"");
+        let file = temp_file("hard.tar", &finish(tar));
+        let entries = read_entries(&mut File::open(&file).unwrap()).unwrap();
+        assert_eq!(names(&entries), vec![("./a.txt", Kind::File, 6), ("b.txt", Kind::File, 6)]);
+        assert_eq!(entries[0].offset, entries[1].offset);
+
+        let tar = TarFs::open(&file, &file).unwrap();
+        assert_eq!(read_all(&tar, &file.join("b.txt")), b"shared");
+        assert_eq!(read_all(&tar, &file.join("a.txt")), b"shared");
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn gzip_wrapped()
+    {
+        let mut tar = Vec::new();
+        push(&mut tar, header("dir/", b'5', 0, ""), b"");
+        push_file(&mut tar, "dir/one", b"first");
+        push_file(&mut tar, "dir/two", &vec![b'2'; 3000]);
+        let mut gz = GzEncoder::new(Vec::new(), Level::default());
+        gz.write_all(&finish(tar)).unwrap();
+        let file = temp_file("wrapped.tar.gz", &gz.finish().unwrap());
+        assert_eq!(detect(&file).unwrap(), Compression::Gzip);
+
+        let tar = TarFs::open(&file, &file).unwrap();
+        let listed: Vec<String> = tar.list(&file.join("dir")).unwrap().into_iter().map(|e| e.name).collect();
+        assert_eq!(listed, vec!["one", "two"]);
+        // out of order, so the stream is opened again
+        assert_eq!(read_all(&tar, &file.join("dir").join("two")), vec![b'2'; 3000]);
+        assert_eq!(read_all(&tar, &file.join("dir").join("one")), b"first");
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn sizes_that_overflow_are_invalid()
+    {
+        assert_eq!(padded(0).unwrap(), 0);
+        assert_eq!(padded(1).unwrap(), BLOCK as u64);
+        assert_eq!(padded(BLOCK as u64).unwrap(), BLOCK as u64);
+        assert_eq!(padded(u64::max_value() - BLOCK as u64 + 1).unwrap(), u64::max_value() - BLOCK as u64 + 1);
+        assert_eq!(padded(u64::max_value()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(advance(u64::max_value(), 1).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        for &typeflag in &[b'0', b'L'] {
+            let mut block = header("huge", typeflag, 0, "");
+            // base-256, all ones
+            block[124] = 0x80;
+            for b in &mut block[125..128] {
+                *b = 0;
+            }
+            for b in &mut block[128..136] {
+                *b = 0xff;
+            }
+            seal(&mut block);
+            let err = read_entries(&mut &finish(block.to_vec())[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        // the largest size that pads without overflowing ends past what an offset holds
+        let mut block = header("huge", b'0', 0, "");
+        block[124] = 0x80;
+        block[125..136].copy_from_slice(&[0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0]);
+        seal(&mut block);
+        assert_eq!(read_entries(&mut &finish(block.to_vec())[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
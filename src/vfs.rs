@@ -0,0 +1,431 @@
+// File systems a panel can show. The listing and the file operations go
+// through `Vfs` instead of `std::fs`, so archives and remote sites only need
+// an implementation of their own. `LocalFs` is the disk; `MemoryFs` keeps a
+// tree in memory, which is handy for trying out code that works on files.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    pub is_dir: bool,
+    // 0 for directories
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    // hidden or system attribute on Windows, dot-file elsewhere
+    pub hidden: bool,
+    pub read_only: bool,
+    // a symbolic link; the other fields describe its target where possible
+    pub is_link: bool,
+    // UNIX permission bits, where the file system keeps them
+    pub mode: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub meta: Metadata,
+}
+
+pub trait Vfs: Send + Sync {
+    // Entries of the directory `dir`, in no particular order.
+    fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>>;
+    fn stat(&self, path: &Path) -> io::Result<Metadata>;
+    fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>>;
+    // Creates the file, or empties it if it exists.
+    fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>;
+    fn mkdir(&self, path: &Path) -> io::Result<()>;
+    // Removes a file, a link or an empty directory.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    // Where the symbolic link `path` points.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>
+    { Err(unsupported(path, "links")) }
+    // Makes `path` a symbolic link to `target`, which is a directory if `is_dir`.
+    fn symlink(&self, _target: &Path, path: &Path, _is_dir: bool) -> io::Result<()>
+    { Err(unsupported(path, "links")) }
+    // File systems that cannot keep times ignore this.
+    fn set_modified(&self, _path: &Path, _time: SystemTime) -> io::Result<()>
+    { Ok(()) }
+    // Where `path` is on the disk, for file systems that are the disk.
+    fn local_path(&self, _path: &Path) -> Option<PathBuf>
+    { None }
+    // For a file system shown inside another one, like an archive, the path
+    // it is shown at; leaving it goes back to the one below.
+    fn mount_point(&self) -> Option<PathBuf>
+    { None }
+    // Whether names that differ only in case are the same entry.
+    fn ignores_case(&self) -> bool
+    { false }
+}
+
+// `rwxr-xr-x` for the permission bits of `mode`.
+pub fn mode_text(mode: u32) -> String
+{
+    let mut text = String::new();
+    for &(shift, special, set) in &[(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = mode >> shift;
+        text.push(if bits & 4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 2 != 0 { 'w' } else { '-' });
+        text.push(match (bits & 1 != 0, mode & special != 0) {
+            (true, true) => set,
+            (false, true) => set.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    text
+}
+
+fn unsupported(path: &Path, what: &str) -> io::Error
+{ io::Error::new(io::ErrorKind::Other, format!("Cannot handle \"{}\": {} are not supported here", path.display(), what)) }
+
+// Whether `a` and `b` are the same file system, so that paths in one mean
+// the same files as paths in the other.
+pub fn same_fs(a: &Vfs, b: &Vfs) -> bool
+{
+    let root = Path::new("/");
+    (a.local_path(root).is_some() && b.local_path(root).is_some())
+        || a as *const Vfs as *const u8 == b as *const Vfs as *const u8
+}
+
+static LOCAL_COPIES: AtomicUsize = AtomicUsize::new(0);
+
+// `path` as a file on the disk, for the viewer and other programs: files of
+// other file systems are copied to a temporary directory first.
+pub fn local_copy(vfs: &Vfs, path: &Path) -> io::Result<PathBuf>
+{
+    if let Some(local) = vfs.local_path(path) {
+        return Ok(local);
+    }
+    let name = try!(path.file_name().ok_or_else(||
+        io::Error::new(io::ErrorKind::InvalidInput, format!("Cannot copy \"{}\"", path.display()))));
+    // a directory per copy, so files of the same name do not clash
+    let dir = env::temp_dir()
+        .join(format!("trusty-commander-{}", process::id()))
+        .join(LOCAL_COPIES.fetch_add(1, Ordering::SeqCst).to_string());
+    try!(fs::create_dir_all(&dir));
+    let target = dir.join(name);
+    let mut input = try!(vfs.open_read(path));
+    try!(File::create(&target).and_then(|mut output| io::copy(&mut input, &mut output)));
+    if let Some(modified) = vfs.stat(path).ok().and_then(|meta| meta.modified) {
+        let _ = LocalFs.set_modified(&target, modified);
+    }
+    Ok(target)
+}
+
+#[cfg(windows)]
+pub fn is_hidden(_name: &str, meta: &fs::Metadata) -> bool
+{
+    use std::os::windows::fs::MetadataExt;
+    use winapi::{FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_SYSTEM};
+    meta.file_attributes() & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM) != 0
+}
+
+#[cfg(not(windows))]
+pub fn is_hidden(name: &str, _meta: &fs::Metadata) -> bool
+{ name.starts_with('.') }
+
+// Opens a file or a directory to set its times.
+#[cfg(windows)]
+fn open_for_times(path: &Path) -> io::Result<File>
+{
+    use std::os::windows::fs::OpenOptionsExt;
+    use winapi::{FILE_FLAG_BACKUP_SEMANTICS, FILE_WRITE_ATTRIBUTES};
+    fs::OpenOptions::new().access_mode(FILE_WRITE_ATTRIBUTES).custom_flags(FILE_FLAG_BACKUP_SEMANTICS).open(path)
+}
+
+#[cfg(not(windows))]
+fn open_for_times(path: &Path) -> io::Result<File>
+{ File::open(path) }
+
+#[cfg(windows)]
+fn permission_bits(_meta: &fs::Metadata) -> Option<u32>
+{ None }
+
+#[cfg(not(windows))]
+fn permission_bits(meta: &fs::Metadata) -> Option<u32>
+{
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode() & 0o7777)
+}
+
+#[cfg(windows)]
+fn make_symlink(target: &Path, path: &Path, is_dir: bool) -> io::Result<()>
+{
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+    // links out of archives use `/`
+    let target = PathBuf::from(target.to_string_lossy().replace('/', "\\"));
+    if is_dir { symlink_dir(target, path) } else { symlink_file(target, path) }
+}
+
+#[cfg(not(windows))]
+fn make_symlink(target: &Path, path: &Path, _is_dir: bool) -> io::Result<()>
+{ ::std::os::unix::fs::symlink(target, path) }
+
+pub struct LocalFs;
+
+impl LocalFs {
+    // Follows links so links to directories can be entered, but still
+    // describes dangling links.
+    fn metadata(path: &Path, name: &str) -> io::Result<Metadata>
+    {
+        let link_meta = try!(fs::symlink_metadata(path));
+        let meta = fs::metadata(path).unwrap_or_else(|_| link_meta.clone());
+        Ok(Metadata {
+            is_dir: meta.is_dir(),
+            size: if meta.is_dir() { 0 } else { meta.len() },
+            modified: meta.modified().ok(),
+            hidden: is_hidden(name, &link_meta),
+            read_only: meta.permissions().readonly(),
+            is_link: link_meta.file_type().is_symlink(),
+            mode: permission_bits(&meta),
+        })
+    }
+}
+
+impl Vfs for LocalFs {
+    fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>>
+    {
+        let mut rv = Vec::new();
+        for entry in try!(fs::read_dir(dir)) {
+            let entry = try!(entry);
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let meta = try!(LocalFs::metadata(&entry.path(), &name));
+            rv.push(DirEntry { name: name, meta: meta });
+        }
+        Ok(rv)
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Metadata>
+    {
+        let name = path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        LocalFs::metadata(path, &name)
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>>
+    { File::open(path).map(|f| Box::new(f) as Box<Read + Send>) }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>
+    { File::create(path).map(|f| Box::new(f) as Box<Write + Send>) }
+
+    fn mkdir(&self, path: &Path) -> io::Result<()>
+    { fs::create_dir(path) }
+
+    fn remove(&self, path: &Path) -> io::Result<()>
+    {
+        let meta = try!(fs::symlink_metadata(path));
+        if meta.is_dir() {
+            fs::remove_dir(path)
+        } else {
+            // links to directories are directories to Windows
+            fs::remove_file(path).or_else(|e| fs::remove_dir(path).map_err(|_| e))
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>
+    { fs::rename(from, to) }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>
+    { fs::read_link(path) }
+
+    fn symlink(&self, target: &Path, path: &Path, is_dir: bool) -> io::Result<()>
+    { make_symlink(target, path, is_dir) }
+
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()>
+    { open_for_times(path).and_then(|f| f.set_modified(time)) }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf>
+    { Some(path.to_path_buf()) }
+
+    fn ignores_case(&self) -> bool
+    { cfg!(windows) }
+}
+
+#[derive(Clone)]
+struct Node {
+    // None for directories
+    data: Option<Vec<u8>>,
+    modified: SystemTime,
+}
+
+type Tree = Arc<Mutex<BTreeMap<PathBuf, Node>>>;
+
+// A tree of files held in memory; `/` is its root. Cloning gives another
+// handle to the same tree.
+#[derive(Clone)]
+pub struct MemoryFs {
+    tree: Tree,
+}
+
+fn not_found(path: &Path) -> io::Error
+{ io::Error::new(io::ErrorKind::NotFound, format!("\"{}\" does not exist", path.display())) }
+
+fn is_root(path: &Path) -> bool
+{ path.parent().is_none() }
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs
+    { MemoryFs { tree: Arc::new(Mutex::new(BTreeMap::new())) } }
+
+    fn check_parent(tree: &BTreeMap<PathBuf, Node>, path: &Path) -> io::Result<()>
+    {
+        let parent = try!(path.parent().ok_or_else(|| not_found(path)));
+        match tree.get(parent) {
+            _ if is_root(parent) => Ok(()),
+            Some(&Node { data: None, .. }) => Ok(()),
+            _ => Err(not_found(parent)),
+        }
+    }
+
+    // Adds a file with `data`, creating missing directories.
+    pub fn add_file(&self, path: &Path, data: &[u8])
+    {
+        let mut tree = self.tree.lock().unwrap();
+        for dir in path.ancestors().skip(1).filter(|d| !is_root(d)) {
+            tree.entry(dir.to_path_buf()).or_insert(Node { data: None, modified: SystemTime::now() });
+        }
+        tree.insert(path.to_path_buf(), Node { data: Some(data.to_vec()), modified: SystemTime::now() });
+    }
+
+    pub fn read(&self, path: &Path) -> Option<Vec<u8>>
+    { self.tree.lock().unwrap().get(path).and_then(|n| n.data.clone()) }
+}
+
+fn node_metadata(path: &Path, node: &Node) -> Metadata
+{
+    Metadata {
+        is_dir: node.data.is_none(),
+        size: node.data.as_ref().map_or(0, |d| d.len() as u64),
+        modified: Some(node.modified),
+        hidden: path.file_name().map_or(false, |n| n.to_string_lossy().starts_with('.')),
+        read_only: false,
+        is_link: false,
+        mode: None,
+    }
+}
+
+// Collects what is written and stores it as the file's contents when dropped.
+struct MemoryWriter {
+    tree: Tree,
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    { self.data.write(buf) }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        let mut tree = self.tree.lock().unwrap();
+        tree.insert(self.path.clone(), Node { data: Some(self.data.clone()), modified: SystemTime::now() });
+        Ok(())
+    }
+}
+
+impl Drop for MemoryWriter {
+    fn drop(&mut self)
+    { let _ = self.flush(); }
+}
+
+impl Vfs for MemoryFs {
+    fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>>
+    {
+        let tree = self.tree.lock().unwrap();
+        if !is_root(dir) && tree.get(dir).map_or(true, |n| n.data.is_some()) {
+            return Err(not_found(dir));
+        }
+        Ok(tree.iter()
+           .filter(|&(path, _)| path.parent() == Some(dir))
+           .map(|(path, node)| DirEntry {
+               name: path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned()),
+               meta: node_metadata(path, node),
+           })
+           .collect())
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Metadata>
+    {
+        if is_root(path) {
+            return Ok(node_metadata(path, &Node { data: None, modified: SystemTime::now() }));
+        }
+        self.tree.lock().unwrap().get(path).map(|n| node_metadata(path, n)).ok_or_else(|| not_found(path))
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>>
+    {
+        match self.read(path) {
+            Some(data) => Ok(Box::new(io::Cursor::new(data))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>
+    {
+        {
+            let mut tree = self.tree.lock().unwrap();
+            try!(MemoryFs::check_parent(&tree, path));
+            if tree.get(path).map_or(false, |n| n.data.is_none()) {
+                return Err(io::Error::new(io::ErrorKind::Other, format!("\"{}\" is a directory", path.display())));
+            }
+            tree.insert(path.to_path_buf(), Node { data: Some(Vec::new()), modified: SystemTime::now() });
+        }
+        Ok(Box::new(MemoryWriter { tree: self.tree.clone(), path: path.to_path_buf(), data: Vec::new() }))
+    }
+
+    fn mkdir(&self, path: &Path) -> io::Result<()>
+    {
+        let mut tree = self.tree.lock().unwrap();
+        try!(MemoryFs::check_parent(&tree, path));
+        if is_root(path) || tree.contains_key(path) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("\"{}\" already exists", path.display())));
+        }
+        tree.insert(path.to_path_buf(), Node { data: None, modified: SystemTime::now() });
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()>
+    {
+        let mut tree = self.tree.lock().unwrap();
+        if tree.keys().any(|p| p.parent() == Some(path)) {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("\"{}\" is not empty", path.display())));
+        }
+        tree.remove(path).map(|_| ()).ok_or_else(|| not_found(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>
+    {
+        let mut tree = self.tree.lock().unwrap();
+        if !tree.contains_key(from) {
+            return Err(not_found(from));
+        }
+        if to.starts_with(from) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("Cannot move \"{}\" into itself", from.display())));
+        }
+        try!(MemoryFs::check_parent(&tree, to));
+        let moved: Vec<PathBuf> = tree.keys().filter(|p| p.starts_with(from)).cloned().collect();
+        for path in moved {
+            let node = tree.remove(&path).unwrap();
+            let rest = path.strip_prefix(from).unwrap().to_path_buf();
+            tree.insert(if rest.as_os_str().is_empty() { to.to_path_buf() } else { to.join(rest) }, node);
+        }
+        Ok(())
+    }
+
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()>
+    {
+        match self.tree.lock().unwrap().get_mut(path) {
+            Some(node) => { node.modified = time; Ok(()) },
+            None => Err(not_found(path)),
+        }
+    }
+}
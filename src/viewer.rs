@@ -0,0 +1,893 @@
+// F3 viewer ("lister"): a top-level window showing a file through `pager::Pager`
+// in text mode, as rows of `hexdump` in hex mode, or, for BMP, PNG and JPEG
+// files, as a picture decoded by `image`. Searches run on a worker thread that
+// reports back with WM_SEARCH_PROGRESS and WM_SEARCH_DONE.
+
+extern crate winapi;
+extern crate std;
+
+use ::pager::{self, GotoTarget, Pager};
+use ::hexdump::{self, HexView};
+use ::encoding::Encoding;
+use ::search::{Outcome, PatternKind, SearchOptions, Searcher};
+use ::image::{self, ImageView};
+use ::settings::Settings;
+use win_gdi::{WinCls, rcrc, lparam_point, text_out, invalidate, show_error, prompt, prompt_with_options,
+              copy_to_clipboard, edit_files};
+use winapi::*;
+use win_layer::*;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+const STATUS_BAR_HEIGHT: c_int = 20;
+const WHEEL_ROWS: i64 = 3;
+const HORIZONTAL_STEP: i64 = 8;
+// line numbers are shown while counting them means scanning at most this much
+const LINE_NUMBER_SCAN: u64 = 32 * 1024 * 1024;
+// a copied selection becomes three times as long as hex text
+const MAX_COPY_BYTES: u64 = 1024 * 1024;
+const KEY_C: c_int = b'C' as c_int;
+const KEY_E: c_int = b'E' as c_int;
+const KEY_F: c_int = b'F' as c_int;
+const KEY_G: c_int = b'G' as c_int;
+const KEY_H: c_int = b'H' as c_int;
+const KEY_I: c_int = b'I' as c_int;
+const KEY_0: c_int = b'0' as c_int;
+const KEY_1: c_int = b'1' as c_int;
+const KEY_W: c_int = b'W' as c_int;
+// larger files are not decoded as images
+const MAX_IMAGE_BYTES: u64 = 256 * 1024 * 1024;
+const ZOOM_STEP: f64 = 1.25;
+// pixels moved by an arrow key in image mode
+const PAN_STEP: c_int = 32;
+const WM_SEARCH_PROGRESS: UINT = WM_APP + 1;
+const WM_SEARCH_DONE: UINT = WM_APP + 2;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Text,
+    Hex,
+    Image,
+}
+
+// A decoded image as the 32-bit pixels `StretchDIBits` takes, and how it is zoomed.
+pub struct Picture {
+    pub format: image::Format,
+    pub bgrx: Vec<u8>,
+    pub view: Cell<ImageView>,
+}
+
+impl Picture {
+    // Draws the picture as its view places it within `area`.
+    pub fn paint(&self, hdc: HDC, area: &RECT) -> Result<(), u32>
+    {
+        let view = self.view.get();
+        let viewport = (area.right - area.left, area.bottom - area.top);
+        let (left, top, width, height) = view.placement(viewport);
+        let dest = RECT { left: area.left + left, top: area.top + top,
+                          right: area.left + left + width, bottom: area.top + top + height };
+        // smooth when shrinking, sharp pixels when enlarging
+        SetStretchBltMode(hdc, if view.scale(viewport) < 1.0 { HALFTONE } else { COLORONCOLOR });
+        StretchDIBits(hdc, &dest, view.width as c_int, view.height as c_int, &self.bgrx)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ViewState {
+    top: u64,
+    wrap: bool,
+    mode: Mode,
+    encoding: Encoding,
+}
+
+// A search running on a worker thread.
+struct RunningSearch {
+    cancel: Arc<AtomicBool>,
+    // offset the search has reached
+    progress: Arc<AtomicU64>,
+    result: Arc<Mutex<Option<io::Result<Outcome>>>>,
+}
+
+thread_local! {
+    // where each file was left, so reopening it continues from there
+    static VIEW_STATES: RefCell<HashMap<PathBuf, ViewState>> = RefCell::new(HashMap::new());
+}
+
+pub struct ViewerCls {
+    path: PathBuf,
+    pager: RefCell<Pager<File>>,
+    hex: RefCell<HexView>,
+    mode: Cell<Mode>,
+    // the mouse is selecting bytes in hex mode, or moving the image
+    dragging: Cell<bool>,
+    // where the mouse was when the image was last moved
+    drag_point: Cell<(c_int, c_int)>,
+    picture: Option<Picture>,
+    // why the file could not be shown as an image
+    image_error: Option<String>,
+    font: HFONT,
+    // character cell of `font`, measured when painting
+    cell: Cell<(c_int, c_int)>,
+    // the last search, repeated by F3 and Shift+F3
+    search: RefCell<Option<SearchOptions>>,
+    running: RefCell<Option<RunningSearch>>,
+    // start and end offset of the last match
+    found: Cell<Option<(u64, u64)>>,
+}
+
+// Decodes the file as an image if it looks like one.
+pub fn load_picture(path: &Path, header: &[u8], len: u64) -> Option<io::Result<Picture>>
+{
+    let format = match image::sniff(header) {
+        Some(format) => format,
+        None => return None,
+    };
+    if len > MAX_IMAGE_BYTES {
+        return Some(Err(io::Error::new(io::ErrorKind::InvalidData, "The file is too large to show as an image")));
+    }
+    let picture = File::open(path).and_then(|mut file| {
+        let mut bytes = Vec::new();
+        try!(file.read_to_end(&mut bytes));
+        let decoded = try!(image::decode(&bytes));
+        // transparent parts show the window background
+        let background = GetSysColor(COLOR_WINDOW);
+        let background = [background as u8, (background >> 8) as u8, (background >> 16) as u8];
+        Ok(Picture {
+            format: format,
+            bgrx: decoded.to_bgrx(background),
+            view: Cell::new(ImageView::new(decoded.width, decoded.height)),
+        })
+    });
+    Some(picture)
+}
+
+pub fn fixed_font() -> Result<HFONT, u32>
+{
+    let mut font: LOGFONTW = unsafe { std::mem::zeroed() };
+    font.lfHeight = -14;
+    font.lfWeight = FW_NORMAL;
+    font.lfCharSet = DEFAULT_CHARSET as BYTE;
+    font.lfQuality = CLEARTYPE_QUALITY as BYTE;
+    font.lfPitchAndFamily = (FIXED_PITCH | FF_MODERN) as BYTE;
+    for (dst, src) in font.lfFaceName.iter_mut().zip(wstr("Consolas").iter()) {
+        *dst = *src;
+    }
+    CreateFontIndirectW(&font)
+}
+
+impl ViewerCls {
+    pub fn create(instance: HINSTANCE, path: &Path) -> io::Result<HWND>
+    {
+        let mut pager = try!(Pager::open(path));
+        let hex = HexView::new(pager.len());
+        let saved = VIEW_STATES.with(|t| t.borrow().get(path).cloned());
+        if let Some(state) = saved {
+            pager.set_wrap(state.wrap);
+            try!(pager.set_encoding(state.encoding));
+            try!(pager.goto_offset(state.top));
+        }
+        let header = try!(pager.read_range(0, 16));
+        let (picture, image_error) = match load_picture(path, &header, pager.len()) {
+            Some(Ok(picture)) => (Some(picture), None),
+            Some(Err(e)) => (None, Some(e.to_string())),
+            None => (None, None),
+        };
+        let font = try!(fixed_font().map_err(|e| io::Error::from_raw_os_error(e as i32)));
+        let inst = ViewerCls {
+            path: path.to_path_buf(),
+            pager: RefCell::new(pager),
+            hex: RefCell::new(hex),
+            mode: Cell::new(Mode::Text),
+            dragging: Cell::new(false),
+            drag_point: Cell::new((0, 0)),
+            picture: picture,
+            image_error: image_error,
+            font: font,
+            cell: Cell::new((8, 16)),
+            search: RefCell::new(None),
+            running: RefCell::new(None),
+            found: Cell::new(None),
+        };
+        match saved {
+            Some(ViewState { mode: Mode::Hex, .. }) => try!(inst.set_mode(Mode::Hex)),
+            Some(ViewState { mode: Mode::Text, .. }) => {},
+            _ => if inst.picture.is_some() { try!(inst.set_mode(Mode::Image)); },
+        }
+        let title = format!("Lister - {}", path.display());
+        let hwnd = Self::get_cls_id().and_then(|cls_id| {
+            let inst_ptr = Box::into_raw(Box::new(rcrc(inst)) as Box<Any>);
+            CreateWindowExW(
+                0,
+                cls_id,
+                Some(&wstr(&title)),
+                WS_OVERLAPPEDWINDOW | WS_VISIBLE,
+                CW_USEDEFAULT, CW_USEDEFAULT, 900, 700,
+                None, None, instance, Some(inst_ptr as LPVOID))
+        });
+        hwnd.map_err(|e| io::Error::from_raw_os_error(e as i32))
+    }
+
+    // Rows and columns of text that fit into the window.
+    fn page_size(&self, hwnd: HWND) -> (usize, usize)
+    {
+        let (cell_width, cell_height) = self.cell.get();
+        let rect = GetClientRect(hwnd).unwrap_or(RECT { left: 0, top: 0, right: 0, bottom: 0 });
+        let rows = (rect.bottom - rect.top - STATUS_BAR_HEIGHT) / cell_height;
+        let columns = (rect.right - rect.left - 4) / cell_width;
+        (if rows > 0 { rows as usize } else { 1 }, if columns > 0 { columns as usize } else { 1 })
+    }
+
+    // Switches between the modes keeping the position in the file.
+    fn set_mode(&self, mode: Mode) -> io::Result<()>
+    {
+        if mode == self.mode.get() {
+            return Ok(());
+        }
+        let mut pager = self.pager.borrow_mut();
+        let mut hex = self.hex.borrow_mut();
+        match (self.mode.get(), mode) {
+            (_, Mode::Hex) => {
+                let top = pager.top();
+                hex.set_cursor(top, false);
+                hex.top = top / hexdump::ROW_BYTES as u64 * hexdump::ROW_BYTES as u64;
+            },
+            (Mode::Hex, _) => try!(pager.goto_offset(hex.top)),
+            _ => {},
+        }
+        self.mode.set(mode);
+        Ok(())
+    }
+
+    fn view_state(&self) -> ViewState
+    {
+        let pager = self.pager.borrow();
+        let top = match self.mode.get() {
+            Mode::Text | Mode::Image => pager.top(),
+            Mode::Hex => self.hex.borrow().top,
+        };
+        ViewState { top: top, wrap: pager.wrap(), mode: self.mode.get(), encoding: pager.encoding() }
+    }
+
+    fn paint_text(&self, hwnd: HWND, hdc: HDC, rect: &RECT) -> Result<(), u32>
+    {
+        try!(SelectObject(hdc, self.font as HGDIOBJ));
+        let metrics = try!(GetTextMetricsW(hdc));
+        self.cell.set((metrics.tmAveCharWidth, metrics.tmHeight));
+        if self.mode.get() == Mode::Hex {
+            return self.paint_hex(hwnd, hdc);
+        }
+        let (rows, columns) = self.page_size(hwnd);
+        let lines = self.pager.borrow_mut().visible(rows, columns);
+        let lines = match lines {
+            Ok(lines) => lines,
+            Err(e) => vec![(0, 0, format!("Read error: {}", e))],
+        };
+        let found = self.found_columns();
+        let left = if self.pager.borrow().wrap() { 0 } else { self.pager.borrow().left() };
+        SetTextColor(hdc, GetSysColor(COLOR_WINDOWTEXT));
+        for (idx, &(line, row, ref text)) in lines.iter().enumerate() {
+            let y = STATUS_BAR_HEIGHT + idx as c_int * metrics.tmHeight;
+            if y >= rect.bottom { break; }
+            if let Some((found_line, start, end)) = found {
+                // columns of the match that fall on this row
+                let first = left + row * columns;
+                let (start, end) = (cmp::max(start, first), cmp::min(end, first + columns));
+                if line == found_line && start < end {
+                    let x = 2 + (start - first) as c_int * metrics.tmAveCharWidth;
+                    let width = (end - start) as c_int * metrics.tmAveCharWidth;
+                    let mark = RECT { left: x, right: x + width, top: y, bottom: y + metrics.tmHeight };
+                    try!(FillRect(hdc, &mark, (COLOR_HIGHLIGHT + 1) as HBRUSH));
+                }
+            }
+            try!(text_out(hdc, 2, y, text));
+        }
+        Ok(())
+    }
+
+    // Line of the last match and the display columns it covers.
+    fn found_columns(&self) -> Option<(u64, usize, usize)>
+    {
+        let (start, end) = match self.found.get() {
+            Some(found) => found,
+            None => return None,
+        };
+        let mut pager = self.pager.borrow_mut();
+        let columns = pager.line_start_at(start).and_then(|line| {
+            let first = try!(pager.column_at(line, start));
+            let last = try!(pager.column_at(line, end));
+            Ok((line, first, cmp::max(last, first + 1)))
+        });
+        columns.ok()
+    }
+
+    fn paint_hex(&self, hwnd: HWND, hdc: HDC) -> Result<(), u32>
+    {
+        let (cell_width, cell_height) = self.cell.get();
+        let (rows, _) = self.page_size(hwnd);
+        let row_bytes = hexdump::ROW_BYTES as u64;
+        let hex = self.hex.borrow();
+        let digits = hexdump::offset_digits(hex.len);
+        let data = self.pager.borrow_mut().read_range(hex.top, hex.top + rows as u64 * row_bytes);
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => return text_out(hdc, 2, STATUS_BAR_HEIGHT, &format!("Read error: {}", e)),
+        };
+        SetTextColor(hdc, GetSysColor(COLOR_WINDOWTEXT));
+        for (row, bytes) in data.chunks(hexdump::ROW_BYTES).enumerate() {
+            let offset = hex.top + row as u64 * row_bytes;
+            let y = STATUS_BAR_HEIGHT + row as c_int * cell_height;
+            for idx in 0..bytes.len() {
+                let at = offset + idx as u64;
+                let brush = if hex.is_selected(at) { COLOR_HIGHLIGHT + 1 }
+                            else if at == hex.cursor { COLOR_BTNFACE + 1 }
+                            else { continue };
+                let cells = [(hexdump::hex_column(idx, digits), 2), (hexdump::ascii_column(idx, digits), 1)];
+                for &(column, width) in cells.iter() {
+                    let left = 2 + column as c_int * cell_width;
+                    let cell = RECT { left: left, right: left + width * cell_width, top: y, bottom: y + cell_height };
+                    try!(FillRect(hdc, &cell, brush as HBRUSH));
+                }
+            }
+            try!(text_out(hdc, 2, y, &hexdump::format_row(offset, bytes, digits)));
+        }
+        Ok(())
+    }
+
+    // Byte under the client point `(x, y)` in hex mode.
+    fn hex_hit(&self, x: c_int, y: c_int) -> Option<u64>
+    {
+        let (cell_width, cell_height) = self.cell.get();
+        if y < STATUS_BAR_HEIGHT || x < 2 {
+            return None;
+        }
+        let hex = self.hex.borrow();
+        let row = ((y - STATUS_BAR_HEIGHT) / cell_height) as u64;
+        let column = ((x - 2) / cell_width) as usize;
+        hexdump::byte_at_column(column, hexdump::offset_digits(hex.len))
+            .map(|idx| hex.top + row * hexdump::ROW_BYTES as u64 + idx as u64)
+            .filter(|offset| *offset < hex.len)
+    }
+
+    fn copy_selection(&self, hwnd: HWND) -> io::Result<()>
+    {
+        let (start, end) = match self.hex.borrow().selection() {
+            Some(range) => range,
+            None => return Ok(()),
+        };
+        if end - start > MAX_COPY_BYTES {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "The selection is too large to copy, the limit is 1 MiB"));
+        }
+        let bytes = try!(self.pager.borrow_mut().read_range(start, end));
+        copy_to_clipboard(hwnd, &hexdump::hex_string(&bytes))
+            .map_err(|e| io::Error::from_raw_os_error(e as i32))
+    }
+
+    // Size of the area below the status bar.
+    fn viewport(&self, hwnd: HWND) -> (c_int, c_int)
+    {
+        let rect = GetClientRect(hwnd).unwrap_or(RECT { left: 0, top: 0, right: 0, bottom: 0 });
+        (rect.right - rect.left, cmp::max(rect.bottom - rect.top - STATUS_BAR_HEIGHT, 0))
+    }
+
+    fn paint_image(&self, hdc: HDC, rect: &RECT) -> Result<(), u32>
+    {
+        match self.picture {
+            Some(ref picture) => picture.paint(hdc, &RECT { top: STATUS_BAR_HEIGHT, .. *rect }),
+            None => Ok(()),
+        }
+    }
+
+    fn on_image_key(&self, hwnd: HWND, vk: c_int) -> bool
+    {
+        let picture = match self.picture {
+            Some(ref picture) => picture,
+            None => return false,
+        };
+        let viewport = self.viewport(hwnd);
+        let mut view = picture.view.get();
+        match vk {
+            VK_ADD | VK_OEM_PLUS => view.zoom_by(ZOOM_STEP, viewport),
+            VK_SUBTRACT | VK_OEM_MINUS => view.zoom_by(1.0 / ZOOM_STEP, viewport),
+            KEY_0 => view.set_zoom(None, viewport),
+            KEY_1 => view.set_zoom(Some(1.0), viewport),
+            VK_LEFT => view.pan(PAN_STEP, 0, viewport),
+            VK_RIGHT => view.pan(-PAN_STEP, 0, viewport),
+            VK_UP => view.pan(0, PAN_STEP, viewport),
+            VK_DOWN => view.pan(0, -PAN_STEP, viewport),
+            VK_PRIOR => view.pan(0, viewport.1, viewport),
+            VK_NEXT | VK_SPACE => view.pan(0, -viewport.1, viewport),
+            _ => return false,
+        }
+        picture.view.set(view);
+        true
+    }
+
+    fn paint_status_bar(&self, hdc: HDC, rect: &RECT) -> Result<(), u32>
+    {
+        let bar = RECT { left: 0, right: rect.right, top: 0, bottom: STATUS_BAR_HEIGHT };
+        try!(FillRect(hdc, &bar, (COLOR_BTNFACE + 1) as HBRUSH));
+        try!(SelectObject(hdc, try!(GetStockObject(DEFAULT_GUI_FONT))));
+        SetTextColor(hdc, GetSysColor(COLOR_WINDOWTEXT));
+        let mut pager = self.pager.borrow_mut();
+        let (position, mode) = match self.mode.get() {
+            Mode::Text => {
+                let top = pager.top();
+                let position = match pager.line_number(top, LINE_NUMBER_SCAN) {
+                    Ok(Some(line)) => format!("Line {}  {}%", line, pager.percent()),
+                    _ => format!("{}%", pager.percent()),
+                };
+                let wrap = if pager.wrap() { "Wrap" } else { "No wrap" };
+                (position, format!("{}    {}", pager.encoding().name(), wrap))
+            },
+            Mode::Hex => {
+                let hex = self.hex.borrow();
+                let mut position = format!("Offset 0x{:X}", hex.cursor);
+                if let Some((start, end)) = hex.selection() {
+                    position.push_str(&format!("  {} bytes selected", end - start));
+                }
+                (position, "Hex".to_string())
+            },
+            Mode::Image => {
+                let picture = self.picture.as_ref().expect("image mode without an image");
+                let view = picture.view.get();
+                let viewport = (rect.right, cmp::max(rect.bottom - STATUS_BAR_HEIGHT, 0));
+                let zoom = (view.scale(viewport) * 100.0).round();
+                (format!("{} x {} pixels", view.width, view.height),
+                 format!("{}    Zoom {}%", picture.format.name(), zoom))
+            },
+        };
+        let mode = match self.image_error {
+            Some(ref e) if self.mode.get() == Mode::Text => format!("{}    Cannot show as image: {}", mode, e),
+            _ => mode,
+        };
+        let mode = match *self.running.borrow() {
+            Some(ref running) if pager.len() > 0 => {
+                let percent = running.progress.load(Ordering::Relaxed) * 100 / pager.len();
+                format!("{}    Searching... {}% (Esc cancels)", mode, percent)
+            },
+            _ => mode,
+        };
+        let name = self.path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        let text = format!("{}    {} bytes    {}    {}", name, pager.len(), position, mode);
+        text_out(hdc, 4, 3, &text)
+    }
+
+    // Lets the user override the detected encoding.
+    fn show_encoding_menu(&self, hwnd: HWND, x: c_int, y: c_int) -> Result<(), u32>
+    {
+        let current = self.pager.borrow().encoding();
+        let encodings = Encoding::all();
+        let menu = try!(CreatePopupMenu());
+        for (idx, encoding) in encodings.iter().enumerate() {
+            let flags = if *encoding == current { MF_STRING | MF_CHECKED } else { MF_STRING };
+            try!(AppendMenuW(menu, flags, idx + 1, Some(&wstr(&encoding.name()))));
+        }
+        let origin = try!(ClientToScreen(hwnd, POINT { x: x, y: y }));
+        let chosen = TrackPopupMenu(
+            menu, TPM_LEFTALIGN | TPM_TOPALIGN | TPM_NONOTIFY, origin.x, origin.y, hwnd);
+        try!(DestroyMenu(menu));
+        let chosen = try!(chosen) as usize;
+        if let Some(encoding) = encodings.get(chosen.wrapping_sub(1)) {
+            let rv = self.pager.borrow_mut().set_encoding(*encoding);
+            if let Err(e) = rv {
+                show_error(hwnd, "Lister", &e);
+            }
+            let _ = invalidate(hwnd);
+        }
+        Ok(())
+    }
+
+    fn find_dialog(&self, hwnd: HWND)
+    {
+        let last = self.search.borrow().clone().unwrap_or(SearchOptions {
+            pattern: String::new(),
+            kind: PatternKind::Text,
+            case_sensitive: false,
+            whole_word: false,
+        });
+        let options = [
+            ("Case sensitive", last.case_sensitive),
+            ("Whole words", last.whole_word),
+            ("Regular expression", last.kind == PatternKind::Regex),
+            ("Hex bytes", last.kind == PatternKind::Hex),
+        ];
+        let label = "Search for (with \"Hex bytes\", bytes like 4D 5A 90):";
+        let (pattern, checked) = match prompt_with_options(hwnd, "Find", label, &last.pattern, &options) {
+            Ok(Some(result)) => result,
+            _ => return,
+        };
+        let kind = match (checked[2], checked[3]) {
+            (false, false) => PatternKind::Text,
+            (true, false) => PatternKind::Regex,
+            (false, true) => PatternKind::Hex,
+            (true, true) => {
+                let e = io::Error::new(io::ErrorKind::InvalidInput,
+                                       "Choose either a regular expression or hex bytes, not both");
+                return show_error(hwnd, "Find", &e);
+            },
+        };
+        *self.search.borrow_mut() = Some(SearchOptions {
+            pattern: pattern,
+            kind: kind,
+            case_sensitive: checked[0],
+            whole_word: checked[1],
+        });
+        self.found.set(None);
+        self.start_search(hwnd, false);
+    }
+
+    // Looks for the next match after the last one, or after the top of the
+    // view (the cursor in hex mode) if there is none yet.
+    fn start_search(&self, hwnd: HWND, backwards: bool)
+    {
+        if self.running.borrow().is_some() {
+            return;
+        }
+        let options = match self.search.borrow().clone() {
+            Some(options) => options,
+            None => return self.find_dialog(hwnd),
+        };
+        let (encoding, len, top) = {
+            let pager = self.pager.borrow();
+            (pager.encoding(), pager.len(), pager.top())
+        };
+        let searcher = match Searcher::new(&options, encoding) {
+            Ok(searcher) => searcher,
+            Err(e) => return show_error(hwnd, "Find", &e),
+        };
+        let from = match (self.found.get(), backwards) {
+            (Some((start, _)), false) => start + 1,
+            (Some((start, _)), true) => start,
+            (None, _) if self.mode.get() == Mode::Hex => self.hex.borrow().cursor,
+            (None, _) => top,
+        };
+        let running = RunningSearch {
+            cancel: Arc::new(AtomicBool::new(false)),
+            progress: Arc::new(AtomicU64::new(from)),
+            result: Arc::new(Mutex::new(None)),
+        };
+        let (cancel, progress, result) = (running.cancel.clone(), running.progress.clone(), running.result.clone());
+        let path = self.path.clone();
+        // HWND is not Send
+        let target = hwnd as usize;
+        thread::spawn(move || {
+            let outcome = File::open(&path).and_then(|mut file| {
+                searcher.find(&mut file, len, from, backwards, &cancel, &mut |pos| {
+                    progress.store(pos, Ordering::Relaxed);
+                    let _ = PostMessageW(target as HWND, WM_SEARCH_PROGRESS, 0, 0);
+                })
+            });
+            *result.lock().unwrap() = Some(outcome);
+            let _ = PostMessageW(target as HWND, WM_SEARCH_DONE, 0, 0);
+        });
+        *self.running.borrow_mut() = Some(running);
+        let _ = invalidate(hwnd);
+    }
+
+    fn on_search_done(&self, hwnd: HWND)
+    {
+        let outcome = self.running.borrow_mut().take()
+            .and_then(|running| running.result.lock().unwrap().take());
+        match outcome {
+            Some(Ok(Outcome::Found(start, end))) => {
+                self.found.set(Some((start, end)));
+                let (rows, columns) = self.page_size(hwnd);
+                if self.mode.get() == Mode::Image {
+                    let _ = self.set_mode(Mode::Text);
+                }
+                match self.mode.get() {
+                    Mode::Text | Mode::Image => {
+                        let rv = self.pager.borrow_mut().show_offset(start, columns);
+                        if let Err(e) = rv {
+                            show_error(hwnd, "Find", &e);
+                        }
+                    },
+                    Mode::Hex => {
+                        let mut hex = self.hex.borrow_mut();
+                        hex.set_cursor(start, false);
+                        hex.set_cursor(end - 1, true);
+                        hex.scroll_into_view(rows);
+                    },
+                }
+            },
+            Some(Ok(Outcome::NotFound)) => {
+                let pattern = self.search.borrow().as_ref().map_or(String::new(), |o| o.pattern.clone());
+                let _ = MessageBoxW(Some(hwnd), &wstr(&format!("\"{}\" was not found.", pattern)),
+                                    &wstr("Find"), MB_OK | MB_ICONINFORMATION);
+            },
+            Some(Err(e)) => show_error(hwnd, "Find", &e),
+            Some(Ok(Outcome::Cancelled)) | None => {},
+        }
+        let _ = invalidate(hwnd);
+    }
+
+    // F4: opens the file in the external editor at the top line of the view.
+    fn edit(&self, hwnd: HWND)
+    {
+        let line = match self.mode.get() {
+            Mode::Text => {
+                let mut pager = self.pager.borrow_mut();
+                let top = pager.top();
+                pager.line_number(top, LINE_NUMBER_SCAN).ok().and_then(|line| line)
+            },
+            _ => None,
+        };
+        edit_files(hwnd, &Settings::load().editor, &[self.path.clone()], line);
+    }
+
+    fn goto(&self, hwnd: HWND)
+    {
+        let label = match self.mode.get() {
+            Mode::Text | Mode::Image => "Line number, or position in percent (e.g. 50%):",
+            Mode::Hex => "Offset (0x1F, $1F, 1Fh or decimal), or position in percent (e.g. 50%):",
+        };
+        let input = match prompt(hwnd, "Go to", label, "") {
+            Ok(Some(input)) => input,
+            _ => return,
+        };
+        let rv = match (self.mode.get(), pager::parse_goto(&input)) {
+            (Mode::Hex, Some(GotoTarget::Percent(percent))) => {
+                let len = self.hex.borrow().len;
+                self.goto_offset((len as f64 * percent / 100.0) as u64);
+                Ok(())
+            },
+            (Mode::Hex, _) => match hexdump::parse_offset(&input) {
+                Some(offset) => { self.goto_offset(offset); Ok(()) },
+                None => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                           format!("\"{}\" is not an offset or a percentage", input.trim()))),
+            },
+            (_, Some(target)) => self.pager.borrow_mut().goto(target),
+            (_, None) => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                       format!("\"{}\" is not a line number or a percentage", input.trim()))),
+        };
+        if let Err(e) = rv {
+            show_error(hwnd, "Go to", &e);
+        }
+    }
+
+    // Hex mode: puts the cursor on `offset` and its row at the top.
+    fn goto_offset(&self, offset: u64)
+    {
+        let mut hex = self.hex.borrow_mut();
+        hex.set_cursor(offset, false);
+        let cursor = hex.cursor;
+        hex.top = cursor / hexdump::ROW_BYTES as u64 * hexdump::ROW_BYTES as u64;
+    }
+
+    fn on_hex_key(&self, vk: c_int, rows: usize) -> bool
+    {
+        let row_bytes = hexdump::ROW_BYTES as i64;
+        let extend = GetKeyState(VK_SHIFT);
+        let mut hex = self.hex.borrow_mut();
+        match vk {
+            VK_UP => hex.move_cursor(-row_bytes, extend),
+            VK_DOWN => hex.move_cursor(row_bytes, extend),
+            VK_LEFT => hex.move_cursor(-1, extend),
+            VK_RIGHT => hex.move_cursor(1, extend),
+            VK_PRIOR => hex.move_cursor(-(rows as i64) * row_bytes, extend),
+            VK_NEXT => hex.move_cursor(rows as i64 * row_bytes, extend),
+            VK_HOME => hex.set_cursor(0, extend),
+            VK_END => hex.set_cursor(u64::max_value(), extend),
+            _ => return false,
+        }
+        hex.scroll_into_view(rows);
+        true
+    }
+
+    fn on_key(&self, hwnd: HWND, vk: c_int) -> bool
+    {
+        match vk {
+            VK_F7 => { self.find_dialog(hwnd); return true; },
+            KEY_F if GetKeyState(VK_CONTROL) => { self.find_dialog(hwnd); return true; },
+            VK_F3 => { self.start_search(hwnd, GetKeyState(VK_SHIFT)); return true; },
+            VK_F4 => { self.edit(hwnd); return true; },
+            KEY_I if self.picture.is_some() => {
+                let mode = if self.mode.get() == Mode::Image { Mode::Text } else { Mode::Image };
+                if let Err(e) = self.set_mode(mode) {
+                    show_error(hwnd, "Lister", &e);
+                }
+                let _ = invalidate(hwnd);
+                return true;
+            },
+            VK_ESCAPE => {
+                match *self.running.borrow() {
+                    Some(ref running) => running.cancel.store(true, Ordering::Relaxed),
+                    None => { let _ = PostMessageW(hwnd, WM_CLOSE, 0, 0); },
+                }
+                return true;
+            },
+            _ => {},
+        }
+        let (rows, columns) = self.page_size(hwnd);
+        if self.mode.get() == Mode::Hex && self.on_hex_key(vk, rows) {
+            let _ = invalidate(hwnd);
+            return true;
+        }
+        if self.mode.get() == Mode::Image {
+            if self.on_image_key(hwnd, vk) {
+                let _ = invalidate(hwnd);
+                return true;
+            }
+            // of the text keys only the switch to hex mode applies to images
+            if vk != KEY_H {
+                return false;
+            }
+        }
+        let rv = {
+            let mut pager = self.pager.borrow_mut();
+            match vk {
+                VK_UP => pager.scroll(-1, columns),
+                VK_DOWN => pager.scroll(1, columns),
+                VK_PRIOR => pager.scroll(-(rows as i64), columns),
+                VK_NEXT | VK_SPACE => pager.scroll(rows as i64, columns),
+                VK_HOME => { pager.home(); Ok(()) },
+                VK_END => pager.end(rows, columns),
+                VK_LEFT => { pager.scroll_horizontal(-HORIZONTAL_STEP); Ok(()) },
+                VK_RIGHT => { pager.scroll_horizontal(HORIZONTAL_STEP); Ok(()) },
+                KEY_W => { let wrap = !pager.wrap(); pager.set_wrap(wrap); Ok(()) },
+                KEY_G | KEY_H | KEY_E => Ok(()),
+                KEY_C if GetKeyState(VK_CONTROL) => Ok(()),
+                _ => return false,
+            }
+        };
+        let rv = rv.and_then(|_| match vk {
+            KEY_G => { self.goto(hwnd); Ok(()) },
+            KEY_H => {
+                let mode = if self.mode.get() == Mode::Hex { Mode::Text } else { Mode::Hex };
+                self.set_mode(mode)
+            },
+            KEY_C => self.copy_selection(hwnd),
+            KEY_E => {
+                let _ = self.show_encoding_menu(hwnd, 4, STATUS_BAR_HEIGHT);
+                Ok(())
+            },
+            _ => Ok(()),
+        });
+        if let Err(e) = rv {
+            show_error(hwnd, "Lister", &e);
+        }
+        let _ = invalidate(hwnd);
+        true
+    }
+}
+
+impl WinCls for ViewerCls {
+    fn wnd_proc(
+        &self,
+        hwnd: HWND, msg: UINT,
+        param: WPARAM, para: LPARAM)
+        -> Option<LRESULT>
+    {
+        match msg {
+            WM_PAINT => {
+                let rv = BeginPaint(hwnd).and_then(|(ps, hdc)| {
+                    let rect = try!(GetClientRect(hwnd));
+                    try!(FillRect(hdc, &rect, (COLOR_WINDOW + 1) as HBRUSH));
+                    try!(SetBkMode(hdc, TRANSPARENT));
+                    if self.mode.get() == Mode::Image {
+                        // the status bar covers what of the image is above the viewport
+                        try!(self.paint_image(hdc, &rect));
+                        try!(self.paint_status_bar(hdc, &rect));
+                    } else {
+                        try!(self.paint_status_bar(hdc, &rect));
+                        try!(self.paint_text(hwnd, hdc, &rect));
+                    }
+                    EndPaint(hwnd, &ps)
+                });
+                match rv {
+                    Ok(_) => Some(1),
+                    Err(x) => Some(x as LRESULT),
+                }
+            },
+            WM_KEYDOWN => {
+                if self.on_key(hwnd, param as c_int) { Some(0) } else { None }
+            },
+            WM_MOUSEWHEEL => {
+                let notches = GET_WHEEL_DELTA_WPARAM(param) as i64 / 120;
+                let (_, columns) = self.page_size(hwnd);
+                match (self.mode.get(), self.picture.as_ref()) {
+                    (Mode::Image, Some(picture)) => {
+                        let mut view = picture.view.get();
+                        view.zoom_by(ZOOM_STEP.powi(notches as i32), self.viewport(hwnd));
+                        picture.view.set(view);
+                    },
+                    (Mode::Hex, _) => self.hex.borrow_mut().scroll(-WHEEL_ROWS * notches),
+                    _ => { let _ = self.pager.borrow_mut().scroll(-WHEEL_ROWS * notches, columns); },
+                }
+                let _ = invalidate(hwnd);
+                Some(0)
+            },
+            WM_LBUTTONDOWN | WM_MOUSEMOVE if self.mode.get() == Mode::Image => {
+                let point = lparam_point(para);
+                if msg == WM_LBUTTONDOWN {
+                    self.dragging.set(true);
+                    SetCapture(hwnd);
+                } else if let (true, Some(picture)) = (self.dragging.get(), self.picture.as_ref()) {
+                    let last = self.drag_point.get();
+                    let mut view = picture.view.get();
+                    view.pan(point.0 - last.0, point.1 - last.1, self.viewport(hwnd));
+                    picture.view.set(view);
+                    let _ = invalidate(hwnd);
+                }
+                self.drag_point.set(point);
+                Some(0)
+            },
+            WM_LBUTTONDOWN | WM_MOUSEMOVE => {
+                let dragging = msg == WM_MOUSEMOVE;
+                if self.mode.get() != Mode::Hex || (dragging && !self.dragging.get()) {
+                    return None;
+                }
+                let (x, y) = lparam_point(para);
+                if let Some(offset) = self.hex_hit(x, y) {
+                    let extend = dragging || param & MK_SHIFT != 0;
+                    self.hex.borrow_mut().set_cursor(offset, extend);
+                    let _ = invalidate(hwnd);
+                }
+                if !dragging {
+                    self.dragging.set(true);
+                    SetCapture(hwnd);
+                }
+                Some(0)
+            },
+            WM_RBUTTONUP => {
+                let (x, y) = lparam_point(para);
+                let _ = self.show_encoding_menu(hwnd, x, y);
+                Some(0)
+            },
+            WM_LBUTTONUP => {
+                if self.dragging.get() {
+                    self.dragging.set(false);
+                    ReleaseCapture();
+                }
+                None
+            },
+            WM_SEARCH_PROGRESS => {
+                let _ = invalidate(hwnd);
+                Some(0)
+            },
+            WM_SEARCH_DONE => {
+                self.on_search_done(hwnd);
+                Some(0)
+            },
+            WM_DESTROY => {
+                if let Some(ref running) = *self.running.borrow() {
+                    running.cancel.store(true, Ordering::Relaxed);
+                }
+                let state = self.view_state();
+                VIEW_STATES.with(|t| t.borrow_mut().insert(self.path.clone(), state));
+                let _ = DeleteObject(self.font as HGDIOBJ);
+                Some(0)
+            },
+            _ => None,
+        }
+    }
+
+    fn register () -> Result<ATOM, u32>
+    {
+        let wnd_cls = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as UINT,
+            style: CS_HREDRAW | CS_VREDRAW,
+            lpfnWndProc: Some(Self::wnd_proc_raw),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: 0 as HINSTANCE,
+            hIcon: try!(LoadIconW(0 as HINSTANCE, RC_IDI_APPLICATION)),
+            hCursor: try!(LoadCursorW(0 as HINSTANCE, RC_IDC_ARROW)),
+            hbrBackground: (winapi::COLOR_WINDOW + 1) as HBRUSH,
+            lpszMenuName: 0 as *const u16,
+            lpszClassName: wstr("ViewerCls").as_ptr(),
+            hIconSm: try!(LoadIconW(0 as HINSTANCE, RC_IDI_APPLICATION)),
+        };
+
+        RegisterClassExW(&wnd_cls)
+    }
+}
@@ -0,0 +1,1298 @@
+// WebDAV shares shown in a panel, at `\\webdav\<site>\`, over HTTP or HTTPS.
+// Directories are listed with PROPFIND, files downloaded with GET and
+// uploaded with PUT; MKCOL, MOVE and DELETE do the rest. Logins are Basic or
+// Digest, as the server asks: its last challenge is kept and answered in
+// every request after it.
+//
+// Uploads are sent in chunks, as their size is not known up front, after
+// `Expect: 100-continue` lets the server ask for a login before the data.
+// Connections are kept for reuse like FTP ones, a transfer having one to
+// itself.
+
+extern crate base64;
+extern crate md5;
+extern crate native_tls;
+
+use std::cmp;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use self::base64::Engine;
+use self::base64::engine::general_purpose::STANDARD;
+use self::native_tls::{HandshakeError, TlsConnector};
+
+use ::remote::{self, Pool, TIMEOUT, month_number, time_of};
+use ::sites::{Protocol, Site};
+use ::vfs::{DirEntry, Metadata, Vfs};
+
+// how long an upload waits for `100 Continue` before sending anyway
+const CONTINUE_WAIT: Duration = Duration::from_secs(2);
+const PROPFIND_BODY: &'static str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+    <propfind xmlns=\"DAV:\"><prop><resourcetype/><getcontentlength/><getlastmodified/></prop></propfind>\n";
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+fn bad_reply(what: &str) -> io::Error
+{ io::Error::new(io::ErrorKind::InvalidData, format!("The server sent {}", what)) }
+
+// `path` as it goes in a request: bytes other than letters, digits, `/` and
+// `-._~` are escaped.
+pub fn encode_path(path: &str) -> String
+{
+    let mut rv = String::new();
+    for &byte in path.as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            rv.push(byte as char);
+        } else {
+            rv.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    rv
+}
+
+pub fn decode_path(text: &str) -> String
+{
+    let bytes = text.as_bytes();
+    let mut rv = Vec::new();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let escaped = bytes.get(idx + 1..idx + 3)
+            .filter(|hex| hex.iter().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(&String::from_utf8_lossy(hex), 16).ok());
+        match (bytes[idx], escaped) {
+            (b'%', Some(byte)) => { rv.push(byte); idx += 3; },
+            (byte, _) => { rv.push(byte); idx += 1; },
+        }
+    }
+    String::from_utf8_lossy(&rv).into_owned()
+}
+
+// The path an `href` names; servers give either the path or the full URL.
+fn href_path(href: &str) -> String
+{
+    let path = match href.find("://") {
+        Some(scheme) => href[scheme + 3..].find('/').map_or("/", |slash| &href[scheme + 3 + slash..]),
+        None => href,
+    };
+    decode_path(path)
+}
+
+// An HTTP date: `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn parse_http_date(text: &str) -> Option<SystemTime>
+{
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.len() != 6 {
+        return None;
+    }
+    let day: i64 = try_opt!(words[1].parse().ok());
+    let month = try_opt!(month_number(words[2]));
+    let year: i64 = try_opt!(words[3].parse().ok());
+    let clock: Vec<u64> = words[4].split(':').filter_map(|field| field.parse().ok()).collect();
+    if clock.len() != 3 {
+        return None;
+    }
+    time_of(year, month, day, clock[0], clock[1], clock[2])
+}
+
+fn unescape_xml(text: &str) -> String
+{
+    let mut rv = String::new();
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        rv.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(::std::char::from_u32),
+            _ => None,
+        };
+        match c {
+            Some(c) => { rv.push(c); rest = &rest[end + 1..]; },
+            None => { rv.push('&'); rest = &rest[1..]; },
+        }
+    }
+    rv.push_str(rest);
+    rv
+}
+
+// What a PROPFIND reply tells of a file or collection.
+#[derive(Debug, PartialEq)]
+pub struct Resource {
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+// The `response` elements of a `multistatus` reply. Elements are known by
+// their names without the namespace prefix, which servers choose freely.
+pub fn parse_multistatus(xml: &str) -> Vec<Resource>
+{
+    let mut rv = Vec::new();
+    let mut open: Vec<String> = Vec::new();
+    let mut current: Option<Resource> = None;
+    let mut text = String::new();
+    let mut rest = xml;
+    while let Some(lt) = rest.find('<') {
+        text.push_str(&unescape_xml(&rest[..lt]));
+        rest = &rest[lt..];
+        if rest.starts_with("<![CDATA[") {
+            let end = rest.find("]]>").unwrap_or(rest.len());
+            text.push_str(&rest[9..end]);
+            rest = &rest[cmp::min(end + 3, rest.len())..];
+            continue;
+        }
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let gt = match rest.find('>') {
+            Some(gt) => gt,
+            None => break,
+        };
+        let tag = &rest[1..gt];
+        rest = &rest[gt + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if tag.starts_with('/') {
+            let name = open.pop().unwrap_or_default();
+            let parent = open.last().map_or("", |parent| parent.as_str());
+            let value = text.trim();
+            match (name.as_str(), parent, current.as_mut()) {
+                ("href", "response", Some(resource)) => resource.path = href_path(value),
+                ("getcontentlength", "prop", Some(resource)) => resource.size = value.parse().unwrap_or(0),
+                ("getlastmodified", "prop", Some(resource)) => resource.modified = parse_http_date(value),
+                ("response", _, _) => rv.extend(current.take()),
+                _ => {},
+            }
+            text.clear();
+            continue;
+        }
+        let qualified = tag.trim_end_matches('/').split_whitespace().next().unwrap_or("");
+        let name = qualified.rsplit(':').next().unwrap_or("").to_string();
+        match (name.as_str(), open.last().map_or("", |parent| parent.as_str())) {
+            ("response", _) => current = Some(Resource { path: String::new(), is_dir: false, size: 0, modified: None }),
+            ("collection", "resourcetype") => {
+                if let Some(ref mut resource) = current {
+                    resource.is_dir = true;
+                }
+            },
+            _ => {},
+        }
+        if !tag.ends_with('/') {
+            open.push(name);
+        }
+        text.clear();
+    }
+    rv
+}
+
+// What the server asked for to log in.
+#[derive(Clone, Debug)]
+enum Challenge {
+    Basic,
+    Digest {
+        realm: String,
+        nonce: String,
+        opaque: Option<String>,
+        // `qop=auth` offered
+        qop: bool,
+        // `algorithm=MD5-sess`
+        session: bool,
+        // requests made with `nonce`
+        count: u32,
+    },
+}
+
+// The `key=value` and `key="value"` pairs after the scheme of a challenge.
+fn auth_params(text: &str) -> Vec<(String, String)>
+{
+    let mut rv = Vec::new();
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        let eq = match rest.find('=') {
+            Some(eq) => eq,
+            None => break,
+        };
+        let key = rest[..eq].trim().to_lowercase();
+        rest = rest[eq + 1..].trim_start();
+        let mut value = String::new();
+        if rest.starts_with('"') {
+            let mut end = rest.len();
+            let mut escaped = false;
+            for (idx, c) in rest.char_indices().skip(1) {
+                match (escaped, c) {
+                    (true, _) => { value.push(c); escaped = false; },
+                    (false, '\\') => escaped = true,
+                    (false, '"') => { end = idx + 1; break; },
+                    _ => value.push(c),
+                }
+            }
+            rest = &rest[end..];
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            value.push_str(rest[..end].trim());
+            rest = &rest[end..];
+        }
+        rv.push((key, value));
+    }
+    rv
+}
+
+// The challenge to answer out of the `WWW-Authenticate` headers of a
+// reply: Digest rather than Basic, which sends the password as it is.
+fn parse_challenge(headers: &[&str]) -> Option<Challenge>
+{
+    let mut basic = false;
+    for header in headers {
+        let (scheme, rest) = match header.find(' ') {
+            Some(space) => (&header[..space], &header[space + 1..]),
+            None => (*header, ""),
+        };
+        if scheme.eq_ignore_ascii_case("basic") {
+            basic = true;
+        }
+        if !scheme.eq_ignore_ascii_case("digest") {
+            continue;
+        }
+        let params = auth_params(rest);
+        let param = |key: &str| params.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v.clone());
+        let algorithm = param("algorithm").unwrap_or_else(|| "MD5".to_string()).to_uppercase();
+        if algorithm != "MD5" && algorithm != "MD5-SESS" {
+            continue;
+        }
+        return Some(Challenge::Digest {
+            realm: param("realm").unwrap_or_default(),
+            nonce: try_opt!(param("nonce")),
+            opaque: param("opaque"),
+            qop: param("qop").map_or(false, |qop| qop.split(',').any(|q| q.trim() == "auth")),
+            session: algorithm == "MD5-SESS",
+            count: 0,
+        });
+    }
+    if basic { Some(Challenge::Basic) } else { None }
+}
+
+fn md5_hex(text: &str) -> String
+{ format!("{:x}", md5::compute(text.as_bytes())) }
+
+impl Challenge {
+    // The `Authorization` header for a request of `method` on `target`,
+    // with `cnonce` as the client's nonce of a Digest answer.
+    fn answer(&self, user: &str, password: &str, method: &str, target: &str, cnonce: &str) -> String
+    {
+        match *self {
+            Challenge::Basic => format!("Basic {}", STANDARD.encode(format!("{}:{}", user, password))),
+            Challenge::Digest { ref realm, ref nonce, ref opaque, qop, session, count } => {
+                let mut ha1 = md5_hex(&format!("{}:{}:{}", user, realm, password));
+                if session {
+                    ha1 = md5_hex(&format!("{}:{}:{}", ha1, nonce, cnonce));
+                }
+                let ha2 = md5_hex(&format!("{}:{}", method, target));
+                let response = if qop {
+                    md5_hex(&format!("{}:{}:{:08x}:{}:auth:{}", ha1, nonce, count, cnonce, ha2))
+                } else {
+                    md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2))
+                };
+                let mut header = format!("Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{}\", algorithm={}",
+                                         user, realm, nonce, target, response, if session { "MD5-sess" } else { "MD5" });
+                if qop {
+                    header.push_str(&format!(", qop=auth, nc={:08x}, cnonce=\"{}\"", count, cnonce));
+                }
+                if let Some(ref opaque) = *opaque {
+                    header.push_str(&format!(", opaque=\"{}\"", opaque));
+                }
+                header
+            },
+        }
+    }
+}
+
+// The head of a reply.
+struct Head {
+    status: u32,
+    reason: String,
+    // names in lower case
+    headers: Vec<(String, String)>,
+    keep_alive: bool,
+}
+
+// How the body of a reply ends.
+enum Body {
+    Length(u64),
+    Chunked { left: u64, done: bool },
+    AtClose,
+}
+
+impl Head {
+    fn header(&self, name: &str) -> Option<&str>
+    { self.headers.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| v.as_str()) }
+
+    fn headers(&self, name: &str) -> Vec<&str>
+    { self.headers.iter().filter(|&&(ref n, _)| n == name).map(|&(_, ref v)| v.as_str()).collect() }
+
+    fn is_ok(&self) -> bool
+    { self.status / 100 == 2 }
+
+    fn body(&self) -> Body
+    {
+        if self.status / 100 == 1 || self.status == 204 || self.status == 304 {
+            return Body::Length(0);
+        }
+        if self.header("transfer-encoding").map_or(false, |te| te.to_lowercase().contains("chunked")) {
+            return Body::Chunked { left: 0, done: false };
+        }
+        match self.header("content-length").and_then(|len| len.parse().ok()) {
+            Some(len) => Body::Length(len),
+            None => Body::AtClose,
+        }
+    }
+
+    // Whether the connection is good for another request after the body.
+    fn reusable(&self) -> bool
+    {
+        match self.body() {
+            Body::AtClose => false,
+            _ => self.keep_alive,
+        }
+    }
+
+    fn error(&self, path: &str) -> io::Error
+    {
+        let kind = match self.status {
+            401 | 403 => io::ErrorKind::PermissionDenied,
+            // 409 is a missing parent collection
+            404 | 409 | 410 => io::ErrorKind::NotFound,
+            412 => io::ErrorKind::AlreadyExists,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, format!("\"{}\": {} {}", path, self.status, self.reason))
+    }
+}
+
+struct Connection {
+    stream: BufReader<Box<Stream>>,
+    // the socket under `stream`, for its timeouts
+    socket: TcpStream,
+}
+
+impl Connection {
+    fn open(site: &Site) -> io::Result<Connection>
+    {
+        let stream = try!(remote::connect(site));
+        let socket = try!(stream.try_clone());
+        let stream: Box<Stream> = if site.protocol == Protocol::WebDavs {
+            let connector = try!(TlsConnector::new().map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+            match connector.connect(&site.host, stream) {
+                Ok(tls) => Box::new(tls),
+                Err(HandshakeError::Failure(e)) => return Err(io::Error::new(
+                    io::ErrorKind::Other, format!("Cannot make a secure connection to {}: {}", site.host, e))),
+                Err(HandshakeError::WouldBlock(_)) => return Err(io::Error::new(
+                    io::ErrorKind::TimedOut, format!("{} did not finish the secure handshake", site.host))),
+            }
+        } else {
+            Box::new(stream)
+        };
+        Ok(Connection { stream: BufReader::new(stream), socket: socket })
+    }
+
+    fn read_line(&mut self) -> io::Result<String>
+    {
+        let mut line = Vec::new();
+        if try!(self.stream.read_until(b'\n', &mut line)) == 0 {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "The server closed the connection"));
+        }
+        let line = String::from_utf8_lossy(&line);
+        Ok(line.trim_end_matches(|c| c == '\r' || c == '\n').to_string())
+    }
+
+    fn read_head(&mut self) -> io::Result<Head>
+    {
+        let status_line = try!(self.read_line());
+        let mut words = status_line.splitn(3, ' ');
+        let version = words.next().unwrap_or("").to_string();
+        let status = match words.next().and_then(|code| code.parse().ok()) {
+            Some(status) if version.starts_with("HTTP/") => status,
+            _ => return Err(bad_reply(&format!("\"{}\" for an HTTP reply", status_line))),
+        };
+        let reason = words.next().unwrap_or("").to_string();
+        let mut headers = Vec::new();
+        loop {
+            let line = try!(self.read_line());
+            if line.is_empty() {
+                break;
+            }
+            if let Some(colon) = line.find(':') {
+                headers.push((line[..colon].trim().to_lowercase(), line[colon + 1..].trim().to_string()));
+            }
+        }
+        let mut head = Head { status: status, reason: reason, headers: headers, keep_alive: false };
+        let connection = head.header("connection").map(|c| c.to_lowercase()).unwrap_or_default();
+        head.keep_alive = if version == "HTTP/1.0" { connection == "keep-alive" } else { connection != "close" };
+        Ok(head)
+    }
+
+    // The head of the final reply, past `100 Continue` and the like.
+    fn read_final_head(&mut self) -> io::Result<Head>
+    {
+        let mut head = try!(self.read_head());
+        while head.status / 100 == 1 {
+            head = try!(self.read_head());
+        }
+        Ok(head)
+    }
+
+    fn read_body(&mut self, body: &mut Body, buf: &mut [u8]) -> io::Result<usize>
+    {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let want = match *body {
+            Body::Length(left) => cmp::min(left, buf.len() as u64) as usize,
+            Body::Chunked { done: true, .. } => 0,
+            Body::Chunked { left: 0, .. } => {
+                let line = try!(self.read_line());
+                let size = try!(u64::from_str_radix(line.split(';').next().unwrap_or("").trim(), 16).map_err(|_|
+                    bad_reply("a chunk of a bad size")));
+                if size == 0 {
+                    // trailers
+                    while !try!(self.read_line()).is_empty() {}
+                    *body = Body::Chunked { left: 0, done: true };
+                    return Ok(0);
+                }
+                *body = Body::Chunked { left: size, done: false };
+                cmp::min(size, buf.len() as u64) as usize
+            },
+            Body::Chunked { left, .. } => cmp::min(left, buf.len() as u64) as usize,
+            Body::AtClose => return self.stream.read(buf),
+        };
+        if want == 0 {
+            return Ok(0);
+        }
+        let read = try!(self.stream.read(&mut buf[..want]));
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "The server closed the connection"));
+        }
+        match *body {
+            Body::Length(ref mut left) => *left -= read as u64,
+            Body::Chunked { ref mut left, .. } => {
+                *left -= read as u64;
+                if *left == 0 {
+                    // the line break after the chunk
+                    try!(self.read_line());
+                }
+            },
+            Body::AtClose => {},
+        }
+        Ok(read)
+    }
+
+    fn read_body_to_end(&mut self, head: &Head) -> io::Result<Vec<u8>>
+    {
+        let mut body = head.body();
+        let mut data = Vec::new();
+        let mut buf = [0; 16384];
+        loop {
+            match try!(self.read_body(&mut body, &mut buf)) {
+                0 => return Ok(data),
+                read => data.extend_from_slice(&buf[..read]),
+            }
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> io::Result<()>
+    {
+        let stream = self.stream.get_mut();
+        try!(stream.write_all(data));
+        stream.flush()
+    }
+
+    // Sends a request and reads the reply's head. Without a `body` the
+    // request is an upload: the server has a moment to refuse it, and
+    // `None` comes back if it did not.
+    fn exchange(&mut self, head: &str, body: Option<&[u8]>) -> io::Result<Option<Head>>
+    {
+        let mut request = head.as_bytes().to_vec();
+        request.extend_from_slice(body.unwrap_or(&[]));
+        try!(self.send(&request));
+        if body.is_some() {
+            return self.read_final_head().map(Some);
+        }
+        // servers that do not know `Expect: 100-continue` wait for the data
+        try!(self.socket.set_read_timeout(Some(CONTINUE_WAIT)));
+        let rv = self.read_head();
+        try!(self.socket.set_read_timeout(Some(TIMEOUT)));
+        match rv {
+            Ok(ref head) if head.status == 100 => Ok(None),
+            Ok(head) => Ok(Some(head)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// What the file system and its transfers share.
+struct Shared {
+    site: Site,
+    password: String,
+    // the `Host` header and the start of URLs
+    host: String,
+    // the last challenge, answered in every request
+    challenge: Mutex<Option<Challenge>>,
+    pool: Pool<Connection>,
+}
+
+impl Shared {
+    fn authorization(&self, method: &str, target: &str) -> Option<String>
+    {
+        let mut challenge = self.challenge.lock().unwrap();
+        let cnonce = match *challenge {
+            Some(Challenge::Digest { ref mut count, .. }) => {
+                *count += 1;
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+                md5_hex(&format!("{}:{}:{}", now.as_secs(), now.subsec_nanos(), count))[..16].to_string()
+            },
+            _ => String::new(),
+        };
+        challenge.as_ref().map(|challenge| challenge.answer(&self.site.user, &self.password, method, target, &cnonce))
+    }
+
+    fn request_head(&self, method: &str, target: &str, headers: &[(&str, String)], body: Option<&[u8]>) -> String
+    {
+        let mut text = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, target, self.host);
+        if let Some(authorization) = self.authorization(method, target) {
+            text.push_str(&format!("Authorization: {}\r\n", authorization));
+        }
+        for &(name, ref value) in headers {
+            text.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        match body {
+            Some(body) if !body.is_empty() => text.push_str(&format!("Content-Length: {}\r\n", body.len())),
+            _ => {},
+        }
+        text.push_str("\r\n");
+        text
+    }
+
+    // Sends a request for the remote `path` on a connection of its own, an
+    // idle one if there is any, and returns the connection with the head of
+    // the reply; as `Connection::exchange` for uploads. A challenge is
+    // answered once, and an idle connection the server closed replaced.
+    fn send(&self, method: &str, path: &str, headers: &[(&str, String)], body: Option<&[u8]>)
+        -> io::Result<(Connection, Option<Head>)>
+    {
+        let target = encode_path(path);
+        let mut answered = false;
+        loop {
+            let (mut conn, rv) = try!(self.pool.run(|| Connection::open(&self.site),
+                |conn| conn.exchange(&self.request_head(method, &target, headers, body), body)));
+            let head = try!(rv);
+            let challenge = match head {
+                Some(ref head) if head.status == 401 && !answered => parse_challenge(&head.headers("www-authenticate")),
+                _ => None,
+            };
+            match (challenge, head) {
+                (Some(challenge), Some(head)) => {
+                    // an upload refused is not sent, which leaves its
+                    // connection unusable
+                    if body.is_some() && head.reusable() {
+                        try!(conn.read_body_to_end(&head));
+                        self.pool.give_back(conn);
+                    }
+                    *self.challenge.lock().unwrap() = Some(challenge);
+                    answered = true;
+                },
+                (_, head) => return Ok((conn, head)),
+            }
+        }
+    }
+
+    // `send` with the whole reply read.
+    fn request(&self, method: &str, path: &str, headers: &[(&str, String)], body: &[u8]) -> io::Result<(Head, Vec<u8>)>
+    {
+        let (mut conn, head) = try!(self.send(method, path, headers, Some(body)));
+        let head = try!(head.ok_or_else(|| bad_reply("no reply")));
+        let data = try!(conn.read_body_to_end(&head));
+        if head.reusable() {
+            self.pool.give_back(conn);
+        }
+        Ok((head, data))
+    }
+}
+
+pub struct DavFs {
+    shared: Arc<Shared>,
+    // the path the share is shown at
+    root: PathBuf,
+    // the share's path on the server, without the trailing slash
+    base: String,
+}
+
+impl DavFs {
+    // Logs in to `site`, to be shown at `root`. The share is looked at
+    // right away, for a wrong address or password to show.
+    pub fn connect(site: &Site, password: &str, root: &Path) -> io::Result<DavFs>
+    {
+        let host = if site.host.contains(':') { format!("[{}]", site.host) } else { site.host.clone() };
+        let shared = Shared {
+            site: site.clone(),
+            password: password.to_string(),
+            host: if site.port == site.protocol.default_port() { host } else { format!("{}:{}", host, site.port) },
+            challenge: Mutex::new(None),
+            pool: Pool::new(Vec::new()),
+        };
+        let fs = DavFs {
+            shared: Arc::new(shared),
+            root: root.to_path_buf(),
+            base: site.directory.trim_end_matches('/').to_string(),
+        };
+        if !try!(fs.stat(root)).is_dir {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a WebDAV share", site.address())));
+        }
+        Ok(fs)
+    }
+
+    // `path` on the server.
+    fn remote(&self, path: &Path) -> io::Result<String>
+    {
+        let rest = try!(path.strip_prefix(&self.root).map_err(|_|
+            io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not in \"{}\"", path.display(), self.root.display()))));
+        let names: Vec<String> = rest.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        Ok(format!("{}/{}", self.base, names.join("/")))
+    }
+
+    // The remote path of a collection ends with a slash, as some servers
+    // insist on.
+    fn remote_dir(&self, path: &Path) -> io::Result<String>
+    {
+        let remote = try!(self.remote(path));
+        Ok(if remote.ends_with('/') { remote } else { remote + "/" })
+    }
+
+    fn propfind(&self, remote: &str, depth: &str) -> io::Result<Vec<Resource>>
+    {
+        let headers = [("Depth", depth.to_string()), ("Content-Type", "application/xml; charset=utf-8".to_string())];
+        let (head, data) = try!(self.shared.request("PROPFIND", remote, &headers, PROPFIND_BODY.as_bytes()));
+        if head.status != 207 {
+            return Err(head.error(remote));
+        }
+        Ok(parse_multistatus(&String::from_utf8_lossy(&data)))
+    }
+
+    // A request that must succeed, with no reply to read.
+    fn simple(&self, method: &str, remote: &str, headers: &[(&str, String)]) -> io::Result<()>
+    {
+        let (head, _) = try!(self.shared.request(method, remote, headers, &[]));
+        match head.status {
+            // some of a collection's contents failed
+            207 => Err(io::Error::new(io::ErrorKind::Other, format!("\"{}\": {} failed in part", remote, method))),
+            _ if head.is_ok() => Ok(()),
+            _ => Err(head.error(remote)),
+        }
+    }
+}
+
+fn metadata(resource: &Resource, name: &str) -> Metadata
+{
+    Metadata {
+        is_dir: resource.is_dir,
+        size: if resource.is_dir { 0 } else { resource.size },
+        modified: resource.modified,
+        hidden: name.starts_with('.'),
+        read_only: false,
+        is_link: false,
+        mode: None,
+    }
+}
+
+// A file being downloaded, complete once read to the end.
+struct Download {
+    shared: Arc<Shared>,
+    conn: Option<Connection>,
+    head: Head,
+    body: Body,
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        let read = match self.conn {
+            Some(ref mut conn) => try!(conn.read_body(&mut self.body, buf)),
+            None => return Ok(0),
+        };
+        if read == 0 && !buf.is_empty() {
+            if let Some(conn) = self.conn.take() {
+                if self.head.reusable() {
+                    self.shared.pool.give_back(conn);
+                }
+            }
+        }
+        Ok(read)
+    }
+}
+
+// A file being uploaded in chunks, complete once flushed or dropped.
+struct Upload {
+    shared: Arc<Shared>,
+    conn: Option<Connection>,
+    path: String,
+}
+
+impl Upload {
+    fn finish(&mut self) -> io::Result<()>
+    {
+        let mut conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => return Ok(()),
+        };
+        try!(conn.send(b"0\r\n\r\n"));
+        let head = try!(conn.read_final_head());
+        try!(conn.read_body_to_end(&head));
+        if head.reusable() {
+            self.shared.pool.give_back(conn);
+        }
+        if !head.is_ok() {
+            return Err(head.error(&self.path));
+        }
+        Ok(())
+    }
+}
+
+impl Write for Upload {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        let conn = try!(self.conn.as_mut().ok_or_else(||
+            io::Error::new(io::ErrorKind::Other, "The file is already complete")));
+        if !buf.is_empty() {
+            let mut chunk = format!("{:x}\r\n", buf.len()).into_bytes();
+            chunk.extend_from_slice(buf);
+            chunk.extend_from_slice(b"\r\n");
+            try!(conn.send(&chunk));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    { self.finish() }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self)
+    { let _ = self.finish(); }
+}
+
+impl Vfs for DavFs {
+    fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>>
+    {
+        let remote = try!(self.remote_dir(dir));
+        let mut entries = Vec::new();
+        for resource in try!(self.propfind(&remote, "1")) {
+            // the collection itself is among the resources
+            let path = resource.path.trim_end_matches('/');
+            if path == remote.trim_end_matches('/') {
+                continue;
+            }
+            let name = path.rsplit('/').next().unwrap_or("");
+            if !name.is_empty() {
+                entries.push(DirEntry { name: name.to_string(), meta: metadata(&resource, name) });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Metadata>
+    {
+        let remote = try!(self.remote(path));
+        let resources = try!(self.propfind(&remote, "0"));
+        let resource = try!(resources.first().ok_or_else(||
+            io::Error::new(io::ErrorKind::NotFound, format!("\"{}\" not found", path.display()))));
+        let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        Ok(metadata(resource, &name))
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>>
+    {
+        let remote = try!(self.remote(path));
+        let (mut conn, head) = try!(self.shared.send("GET", &remote, &[], Some(&[])));
+        let head = try!(head.ok_or_else(|| bad_reply("no reply")));
+        if head.status != 200 {
+            let _ = conn.read_body_to_end(&head);
+            return Err(head.error(&remote));
+        }
+        let body = head.body();
+        Ok(Box::new(Download { shared: self.shared.clone(), conn: Some(conn), head: head, body: body }))
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>
+    {
+        let remote = try!(self.remote(path));
+        let headers = [("Expect", "100-continue".to_string()), ("Transfer-Encoding", "chunked".to_string())];
+        let (conn, refused) = try!(self.shared.send("PUT", &remote, &headers, None));
+        if let Some(head) = refused {
+            return Err(head.error(&remote));
+        }
+        Ok(Box::new(Upload { shared: self.shared.clone(), conn: Some(conn), path: remote }))
+    }
+
+    fn mkdir(&self, path: &Path) -> io::Result<()>
+    {
+        let remote = try!(self.remote_dir(path));
+        let (head, _) = try!(self.shared.request("MKCOL", &remote, &[], &[]));
+        match head.status {
+            _ if head.is_ok() => Ok(()),
+            // MKCOL is not allowed on what exists
+            405 => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("\"{}\" exists already", remote))),
+            _ => Err(head.error(&remote)),
+        }
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()>
+    {
+        let remote = if try!(self.stat(path)).is_dir { try!(self.remote_dir(path)) } else { try!(self.remote(path)) };
+        self.simple("DELETE", &remote, &[])
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>
+    {
+        let (from, to) = if try!(self.stat(from)).is_dir {
+            (try!(self.remote_dir(from)), try!(self.remote_dir(to)))
+        } else {
+            (try!(self.remote(from)), try!(self.remote(to)))
+        };
+        let scheme = if self.shared.site.protocol == Protocol::WebDavs { "https" } else { "http" };
+        let destination = format!("{}://{}{}", scheme, self.shared.host, encode_path(&to));
+        self.simple("MOVE", &from, &[("Destination", destination), ("Overwrite", "F".to_string())])
+    }
+
+    fn mount_point(&self) -> Option<PathBuf>
+    { Some(self.root.clone()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // 2020-09-13 12:26:40 UTC, the time of everything on the stand-in
+    const TIME: u64 = 1_600_000_000;
+    const SHARE: &'static str = "/dav share";
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Auth { Basic, Digest }
+
+    type Files = Arc<Mutex<BTreeMap<String, Option<Vec<u8>>>>>;
+
+    // A WebDAV server holding its files in memory: paths under `SHARE` map
+    // to their data, or to None for collections. Me logs in with `secret`.
+    struct Server {
+        port: u16,
+        files: Files,
+        // the request lines, with ` expect` and ` chunked` added for those
+        // headers
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct Request {
+        method: String,
+        target: String,
+        headers: Vec<(String, String)>,
+    }
+
+    impl Request {
+        fn header(&self, name: &str) -> Option<&str>
+        { self.headers.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| v.as_str()) }
+    }
+
+    fn parent(path: &str) -> &str
+    { path.rfind('/').map_or("", |slash| &path[..slash]) }
+
+    // Whether `request` logs in as me, the Digest response checked as the
+    // server does.
+    fn logged_in(auth: Auth, request: &Request) -> bool
+    {
+        let header = request.header("authorization").unwrap_or("");
+        match auth {
+            Auth::Basic => header == format!("Basic {}", STANDARD.encode("me:secret")),
+            Auth::Digest if header.starts_with("Digest ") => {
+                let params = auth_params(&header[7..]);
+                let param = |key: &str| params.iter().find(|&&(ref k, _)| k == key).map_or("", |&(_, ref v)| v.as_str());
+                assert_eq!(param("uri"), request.target);
+                assert_eq!(param("opaque"), "opq");
+                let ha1 = md5_hex(&format!("{}:stand-in:secret", param("username")));
+                let ha2 = md5_hex(&format!("{}:{}", request.method, param("uri")));
+                param("response") == md5_hex(&format!("{}:n1:{}:{}:auth:{}", ha1, param("nc"), param("cnonce"), ha2))
+            },
+            Auth::Digest => false,
+        }
+    }
+
+    fn response_xml(path: &str, data: &Option<Vec<u8>>) -> String
+    {
+        let href = encode_path(path).replace("&", "&amp;");
+        let props = match *data {
+            None => format!("<D:href>{}/</D:href><D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype>", href),
+            Some(ref data) => format!("<D:href>http://localhost{}</D:href><D:propstat><D:prop><D:resourcetype/>\
+                                       <D:getcontentlength>{}</D:getcontentlength>", href, data.len()),
+        };
+        format!("<D:response>{}<D:getlastmodified>Sun, 13 Sep 2020 12:26:40 GMT</D:getlastmodified>\
+                 </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n", props)
+    }
+
+    fn read_request_body(input: &mut BufReader<TcpStream>, request: &Request) -> Vec<u8>
+    {
+        let mut body = Vec::new();
+        if request.header("transfer-encoding") == Some("chunked") {
+            loop {
+                let mut line = String::new();
+                input.read_line(&mut line).unwrap();
+                let size = usize::from_str_radix(line.trim(), 16).unwrap();
+                let mut chunk = vec![0; size + 2];
+                input.read_exact(&mut chunk).unwrap();
+                if size == 0 {
+                    return body;
+                }
+                body.extend_from_slice(&chunk[..size]);
+            }
+        }
+        if let Some(len) = request.header("content-length") {
+            body.resize(len.parse().unwrap(), 0);
+            input.read_exact(&mut body).unwrap();
+        }
+        body
+    }
+
+    fn serve(stream: TcpStream, auth: Auth, files: Files, requests: Arc<Mutex<Vec<String>>>)
+    {
+        stream.set_nodelay(true).unwrap();
+        let mut out = stream.try_clone().unwrap();
+        let mut input = BufReader::new(stream);
+        let mut served = 0;
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let mut words = line.split_whitespace();
+            let (method, target) = (words.next().unwrap().to_string(), words.next().unwrap().to_string());
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                input.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let colon = line.find(':').unwrap();
+                headers.push((line[..colon].to_lowercase(), line[colon + 1..].trim().to_string()));
+            }
+            let request = Request { method: method, target: target, headers: headers };
+            let expect = request.header("expect") == Some("100-continue");
+            let chunked = request.header("transfer-encoding") == Some("chunked");
+            requests.lock().unwrap().push(format!("{} {}{}{}", request.method, request.target,
+                                                  if expect { " expect" } else { "" }, if chunked { " chunked" } else { "" }));
+            served += 1;
+            if !logged_in(auth, &request) {
+                let challenge = match auth {
+                    Auth::Basic => "Basic realm=\"stand-in\"",
+                    Auth::Digest => "Digest realm=\"stand-in\", qop=\"auth,auth-int\", nonce=\"n1\", opaque=\"opq\"",
+                };
+                let reply = format!("HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"other\"\r\n\
+                                     WWW-Authenticate: {}\r\nContent-Length: 6\r\n\r\nno way", challenge);
+                out.write_all(reply.as_bytes()).unwrap();
+                // an upload refused is not sent
+                if expect {
+                    return;
+                }
+                read_request_body(&mut input, &request);
+                continue;
+            }
+            if expect {
+                let path = decode_path(&request.target);
+                if files.lock().unwrap().get(parent(&path)) != Some(&None) {
+                    out.write_all(b"HTTP/1.1 409 Conflict\r\nContent-Length: 0\r\n\r\n").unwrap();
+                    return;
+                }
+                out.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+            }
+            let body = read_request_body(&mut input, &request);
+            let path = decode_path(&request.target);
+            let path = path.trim_end_matches('/');
+            let mut files = files.lock().unwrap();
+            let (status, reply) = match request.method.as_str() {
+                "PROPFIND" => match files.get(path).cloned() {
+                    Some(data) => {
+                        let mut xml = "<?xml version=\"1.0\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n".to_string();
+                        xml.push_str(&response_xml(path, &data));
+                        if data.is_none() && request.header("depth") == Some("1") {
+                            for (child, data) in files.iter().filter(|&(child, _)| parent(child) == path) {
+                                xml.push_str(&response_xml(child, data));
+                            }
+                        }
+                        xml.push_str("</D:multistatus>\n");
+                        ("207 Multi-Status", xml.into_bytes())
+                    },
+                    None => ("404 Not Found", Vec::new()),
+                },
+                "GET" => match files.get(path) {
+                    Some(&Some(ref data)) => ("200 OK", data.clone()),
+                    _ => ("404 Not Found", Vec::new()),
+                },
+                "PUT" if files.get(parent(path)) != Some(&None) => ("409 Conflict", Vec::new()),
+                "PUT" => {
+                    files.insert(path.to_string(), Some(body));
+                    ("201 Created", Vec::new())
+                },
+                "MKCOL" if files.contains_key(path) => ("405 Method Not Allowed", Vec::new()),
+                "MKCOL" if files.get(parent(path)) != Some(&None) => ("409 Conflict", Vec::new()),
+                "MKCOL" => {
+                    files.insert(path.to_string(), None);
+                    ("201 Created", Vec::new())
+                },
+                "DELETE" | "MOVE" if !files.contains_key(path) => ("404 Not Found", Vec::new()),
+                "DELETE" => {
+                    let prefix = format!("{}/", path);
+                    files.retain(|other, _| other != path && !other.starts_with(&prefix));
+                    ("204 No Content", Vec::new())
+                },
+                "MOVE" => {
+                    let destination = request.header("destination").unwrap();
+                    let host = format!("http://127.0.0.1:{}", out.local_addr().unwrap().port());
+                    assert!(destination.starts_with(&host), "{}", destination);
+                    let to = decode_path(&destination[host.len()..]).trim_end_matches('/').to_string();
+                    if files.contains_key(&to) && request.header("overwrite") == Some("F") {
+                        ("412 Precondition Failed", Vec::new())
+                    } else {
+                        let prefix = format!("{}/", path);
+                        let moved: Vec<String> = files.keys()
+                            .filter(|other| *other == path || other.starts_with(&prefix))
+                            .cloned().collect();
+                        for old in moved {
+                            let data = files.remove(&old).unwrap();
+                            files.insert(format!("{}{}", to, &old[path.len()..]), data);
+                        }
+                        ("201 Created", Vec::new())
+                    }
+                },
+                _ => ("405 Method Not Allowed", Vec::new()),
+            };
+            // big files come in chunks, every other small one up to the close
+            let mut head = format!("HTTP/1.1 {}\r\n", status);
+            if request.method == "GET" && reply.len() > 50000 {
+                head.push_str("Transfer-Encoding: chunked\r\n\r\n");
+                let mut text = head.into_bytes();
+                for part in reply.chunks(7000) {
+                    text.extend_from_slice(format!("{:X};ext=1\r\n", part.len()).as_bytes());
+                    text.extend_from_slice(part);
+                    text.extend_from_slice(b"\r\n");
+                }
+                text.extend_from_slice(b"0\r\nX-Trailer: 1\r\n\r\n");
+                out.write_all(&text).unwrap();
+            } else if request.method == "GET" && status.starts_with("200") && served % 2 == 0 {
+                head.push_str("Connection: close\r\n\r\n");
+                out.write_all(&[head.into_bytes(), reply].concat()).unwrap();
+                return;
+            } else {
+                head.push_str(&format!("Content-Length: {}\r\n\r\n", reply.len()));
+                out.write_all(&[head.into_bytes(), reply].concat()).unwrap();
+            }
+        }
+    }
+
+    fn start(auth: Auth) -> Server
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut files = BTreeMap::new();
+        files.insert(SHARE.to_string(), None);
+        files.insert(format!("{}/sub dir", SHARE), None);
+        files.insert(format!("{}/a & b.txt", SHARE), Some(b"hello dav".to_vec()));
+        files.insert(format!("{}/.hidden", SHARE), Some(Vec::new()));
+        files.insert(format!("{}/sub dir/big ü.bin", SHARE), Some((0..200000u32).map(|i| (i % 251) as u8).collect()));
+        let server = Server {
+            port: listener.local_addr().unwrap().port(),
+            files: Arc::new(Mutex::new(files)),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        };
+        let (files, requests) = (server.files.clone(), server.requests.clone());
+        thread::spawn(move || for stream in listener.incoming() {
+            let (stream, files, requests) = (stream.unwrap(), files.clone(), requests.clone());
+            thread::spawn(move || serve(stream, auth, files, requests));
+        });
+        server
+    }
+
+    fn names(fs: &DavFs, dir: &Path) -> Vec<String>
+    {
+        let mut names: Vec<String> = fs.list(dir).unwrap().into_iter().map(|entry| entry.name).collect();
+        names.sort();
+        names
+    }
+
+    fn read_all(fs: &DavFs, path: &Path) -> Vec<u8>
+    {
+        let mut data = Vec::new();
+        fs.open_read(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    fn round_trip(auth: Auth)
+    {
+        let server = start(auth);
+        let mut site = Site::new("test");
+        site.set_address(&format!("webdav://me@127.0.0.1:{}{}/", server.port, SHARE)).unwrap();
+        let root = PathBuf::from("/webdav/test");
+        assert_eq!(DavFs::connect(&site, "wrong", &root).err().unwrap().kind(), io::ErrorKind::PermissionDenied);
+        let fs = DavFs::connect(&site, "secret", &root).unwrap();
+
+        // list, with hrefs as paths and as URLs
+        assert_eq!(names(&fs, &root), vec![".hidden", "a & b.txt", "sub dir"]);
+        let meta = fs.stat(&root.join("a & b.txt")).unwrap();
+        assert_eq!((meta.size, meta.is_dir, meta.hidden), (9, false, false));
+        assert_eq!(meta.modified, Some(UNIX_EPOCH + Duration::from_secs(TIME)));
+        assert!(fs.stat(&root.join(".hidden")).unwrap().hidden);
+        assert!(fs.stat(&root.join("sub dir")).unwrap().is_dir);
+        assert_eq!(fs.stat(&root.join("missing")).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        // downloads by length, up to the close and in chunks
+        for _ in 0..2 {
+            assert_eq!(read_all(&fs, &root.join("a & b.txt")), b"hello dav");
+        }
+        let big = read_all(&fs, &root.join("sub dir").join("big ü.bin"));
+        assert!(big.len() == 200000 && big[1000] == (1000 % 251) as u8);
+        assert_eq!(fs.open_read(&root.join("missing")).err().unwrap().kind(), io::ErrorKind::NotFound);
+
+        // uploads go in chunks, once the server said to go on
+        {
+            let mut output = fs.open_write(&root.join("sub dir").join("up.bin")).unwrap();
+            for _ in 0..10 {
+                output.write_all(&[4; 10000]).unwrap();
+            }
+            output.flush().unwrap();
+        }
+        drop(fs.open_write(&root.join("empty")).unwrap());
+        assert_eq!(fs.open_write(&root.join("no").join("such")).err().unwrap().kind(), io::ErrorKind::NotFound);
+        {
+            let files = server.files.lock().unwrap();
+            assert_eq!(files.get("/dav share/sub dir/up.bin"), Some(&Some(vec![4; 100000])));
+            assert_eq!(files.get("/dav share/empty"), Some(&Some(Vec::new())));
+        }
+
+        // MKCOL, MOVE and DELETE
+        fs.mkdir(&root.join("new")).unwrap();
+        assert_eq!(fs.mkdir(&root.join("new")).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs.mkdir(&root.join("x").join("y")).unwrap_err().kind(), io::ErrorKind::NotFound);
+        fs.rename(&root.join("sub dir").join("up.bin"), &root.join("new").join("moved #1.bin")).unwrap();
+        assert_eq!(fs.rename(&root.join("empty"), &root.join("a & b.txt")).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        fs.rename(&root.join("new"), &root.join("renamed")).unwrap();
+        assert_eq!(names(&fs, &root.join("renamed")), vec!["moved #1.bin"]);
+        fs.remove(&root.join("renamed")).unwrap();
+        fs.remove(&root.join("empty")).unwrap();
+        assert!(!server.files.lock().unwrap().keys().any(|path| path.contains("renamed") || path.ends_with("empty")));
+
+        let requests = server.requests.lock().unwrap();
+        let puts: Vec<&String> = requests.iter().filter(|request| request.starts_with("PUT ")).collect();
+        assert!(puts.iter().all(|put| put.ends_with(" expect chunked")), "{:?}", puts);
+        assert!(requests.contains(&"MKCOL /dav%20share/new/".to_string()));
+        assert!(requests.contains(&"MOVE /dav%20share/sub%20dir/up.bin".to_string()));
+        assert!(requests.contains(&"MOVE /dav%20share/new/".to_string()));
+        assert!(requests.contains(&"DELETE /dav%20share/renamed/".to_string()));
+        assert!(requests.contains(&"DELETE /dav%20share/empty".to_string()));
+    }
+
+    #[test]
+    fn round_trip_with_basic_login()
+    { round_trip(Auth::Basic); }
+
+    #[test]
+    fn round_trip_with_digest_login()
+    { round_trip(Auth::Digest); }
+
+    #[test]
+    fn multistatus_replies()
+    {
+        let xml = "<?xml version='1.0'?><!-- c --><multistatus xmlns='DAV:'><response><href>/d/</href><propstat><prop>\
+                   <resourcetype><collection/></resourcetype></prop></propstat></response>\
+                   <response><href><![CDATA[/d/x&y]]></href><propstat><prop><getcontentlength>42</getcontentlength>\
+                   <lockdiscovery><activelock><locktoken><href>opaquelocktoken:1</href></locktoken></activelock></lockdiscovery>\
+                   </prop></propstat></response>\
+                   <ns0:response xmlns:ns0=\"DAV:\"><ns0:href>https://h/d/a%26amp;b&amp;c</ns0:href><ns0:propstat><ns0:prop>\
+                   <ns0:getlastmodified>Sun, 06 Nov 1994 08:49:37 GMT</ns0:getlastmodified></ns0:prop></ns0:propstat>\
+                   </ns0:response></multistatus>";
+        let resources = parse_multistatus(xml);
+        assert_eq!(resources, vec![
+            Resource { path: "/d/".to_string(), is_dir: true, size: 0, modified: None },
+            // the lock token's href is not the resource's
+            Resource { path: "/d/x&y".to_string(), is_dir: false, size: 42, modified: None },
+            Resource { path: "/d/a&amp;b&c".to_string(), is_dir: false, size: 0,
+                       modified: Some(UNIX_EPOCH + Duration::from_secs(784111777)) },
+        ]);
+        assert!(parse_multistatus("<multistatus><response><href>/cut").is_empty());
+    }
+
+    #[test]
+    fn http_dates()
+    {
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(UNIX_EPOCH + Duration::from_secs(784111777)));
+        assert_eq!(parse_http_date("Sun, 13 SEP 2020 12:26:40 GMT"), Some(UNIX_EPOCH + Duration::from_secs(TIME)));
+        assert_eq!(parse_http_date("yesterday"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nix 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn paths_are_escaped()
+    {
+        assert_eq!(encode_path("/a b/ü%#?"), "/a%20b/%C3%BC%25%23%3F");
+        assert_eq!(encode_path("/Az09-._~"), "/Az09-._~");
+        assert_eq!(decode_path("/a%20b/%C3%BC%25%23%3F"), "/a b/ü%#?");
+        // escapes that are not are kept
+        assert_eq!(decode_path("/100%/%zz/%4"), "/100%/%zz/%4");
+        assert_eq!(href_path("http://h:8080/a%20b/"), "/a b/");
+        assert_eq!(href_path("https://h"), "/");
+    }
+
+    #[test]
+    fn digest_answers_as_in_rfc_7616()
+    {
+        // the MD5 example of section 3.9.1
+        let header = "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=MD5, \
+                      nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+                      opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+        let mut challenge = parse_challenge(&["Basic realm=\"x\"", header]).unwrap();
+        if let Challenge::Digest { ref mut count, .. } = challenge {
+            *count = 1;
+        }
+        let answer = challenge.answer("Mufasa", "Circle of Life", "GET", "/dir/index.html",
+                                      "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ");
+        assert_eq!(answer, "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+                            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", \
+                            response=\"8ca523f5e9506fed4657c9700eebdbec\", algorithm=MD5, qop=auth, nc=00000001, \
+                            cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", \
+                            opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"");
+        // algorithms other than MD5 are passed over, for Basic here
+        match parse_challenge(&["Digest realm=\"r\", nonce=\"n\", algorithm=SHA-256", "Basic realm=\"r\""]) {
+            Some(Challenge::Basic) => {},
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(Challenge::Basic.answer("Aladdin", "open sesame", "GET", "/", ""), "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+    }
+}
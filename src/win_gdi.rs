@@ -3,12 +3,45 @@ extern crate std;
 extern crate user32;
 
 use ::messages;
+use ::commands;
+use ::listing::Listing;
+use ::breadcrumb;
+use ::settings::Settings;
+use ::size_format::{SizeFormat, format_size};
+use ::file_ops::{self, PackOptions};
+use ::vfs::{self, Vfs};
+use ::plugins::{self, PluginFs};
+use ::sites::{self, Protocol, Site};
+use ::remote;
+use ::ftp::FtpFs;
+use ::sftp::{self, HostKey, Login, SftpFs};
+use ::webdav::DavFs;
+use ::operation::{self, Work};
+use ::compare::{self, CompareOptions, Comparison, ContentCheck};
+use ::command_template::{self, EditorSettings};
+use ::viewer::{self, Picture, ViewerCls};
+use ::finder::FinderCls;
+use ::syncer::SyncerCls;
+use ::find_files::Found;
+use ::pager::Pager;
+use ::hexdump::{self, HexView};
+use ::encoding;
+use ::quick_view::{self, DirSummary, Kind};
+use ::roots::{self, Root, RootSource, SystemRoots};
 use winapi::*;
 use win_layer::*;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::any::{Any, TypeId};
 use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[allow(dead_code)]
 struct DebugBlock {
@@ -36,13 +69,30 @@ macro_rules! dbg_block {
 
 type RcRc<T> = Rc<RefCell<T>>;
 #[inline]
-fn rcrc<T>(x: T) -> RcRc<T>
+pub fn rcrc<T>(x: T) -> RcRc<T>
 { Rc::new(RefCell::new(x)) }
 
 type HwndMap = HashMap<HWND, Box<Any>>;
 thread_local! {
     static HWND_TABLE: RefCell<HwndMap> = RefCell::new(HashMap::new());
     static ATOM_TABLE: RefCell<HashMap<TypeId, ATOM>> = RefCell::new(HashMap::new());
+    // modeless windows with dialog controls, see `dialog_message`
+    static DIALOGS: RefCell<Vec<HWND>> = RefCell::new(Vec::new());
+}
+
+pub fn add_dialog(hwnd: HWND)
+{ DIALOGS.with(|d| d.borrow_mut().push(hwnd)); }
+
+pub fn remove_dialog(hwnd: HWND)
+{ DIALOGS.with(|d| d.borrow_mut().retain(|h| *h != hwnd)); }
+
+// Tab, Enter and Esc for modeless dialogs. Returns whether one of them took
+// `msg`, in which case it must not be dispatched again.
+pub fn dialog_message(msg: &mut MSG) -> bool
+{
+    let dialogs = DIALOGS.with(|d| d.borrow().clone());
+    // the wrapper returns true for messages the dialog did not consume
+    dialogs.into_iter().any(|hwnd| !IsDialogMessage(hwnd, msg))
 }
 
 fn lookup_hwnd<T>(hwnd: HWND)
@@ -151,12 +201,25 @@ pub trait WinCls: Sized + 'static {
 pub struct MainCls {
     panel1: HWND,
     panel2: HWND,
+    settings: RcRc<Settings>,
+    // panel that had the focus when the window was last deactivated
+    active_panel: Cell<HWND>,
+    // the progress window of a Compare directories reading files, and
+    // where it leaves what it found
+    comparing: RefCell<Option<(HWND, Arc<Mutex<Option<Comparison>>>)>>,
 }
 
 impl MainCls {
     pub fn create(instance: HINSTANCE) -> Result<HWND, u32>
     {
-        let inst = MainCls { panel1: 0 as HWND, panel2: 0 as HWND };
+        let inst = MainCls {
+            panel1: 0 as HWND,
+            panel2: 0 as HWND,
+            settings: rcrc(Settings::load()),
+            active_panel: Cell::new(0 as HWND),
+            comparing: RefCell::new(None),
+        };
+        let settings = inst.settings.clone();
         let cls_id = try!(Self::get_cls_id());
         let inst_rc = rcrc(inst);
         let inst_ptr = Box::into_raw(Box::new(inst_rc.clone()) as Box<Any>);
@@ -168,14 +231,197 @@ impl MainCls {
             CW_USEDEFAULT, CW_USEDEFAULT, 1024, 768,
             None, None, instance, Some(inst_ptr as LPVOID)));
 
-        let panel1 = try!(FilePanelCls::create(instance, hwnd));
-        let panel2 = try!(FilePanelCls::create(instance, hwnd));
+        let start_dir = std::env::current_dir().unwrap_or_default();
+        let panel1 = try!(FilePanelCls::create(instance, hwnd, &start_dir, settings.clone()));
+        let panel2 = try!(FilePanelCls::create(instance, hwnd, &start_dir, settings));
 
         let mut inst = inst_rc.borrow_mut();
         inst.panel1 = panel1;
         inst.panel2 = panel2;
+        inst.active_panel.set(panel1);
         Ok(hwnd)
     }
+
+    fn on_command(&self, command: WORD, source: HWND) -> Result<(), u32>
+    {
+        match command {
+            commands::CM_LEFT_DRIVE_MENU => with_panel(self.panel1, |p| p.show_drive_menu(self.panel1)),
+            commands::CM_RIGHT_DRIVE_MENU => with_panel(self.panel2, |p| p.show_drive_menu(self.panel2)),
+            commands::CM_SETTINGS_CHANGED => {
+                try!(invalidate(self.panel1));
+                invalidate(self.panel2)
+            },
+            commands::CM_MKDIR => {
+                let created_in = try!(with_panel(source, |p| Ok(p.make_dir(source))));
+                let other = if source == self.panel1 { self.panel2 } else { self.panel1 };
+                if let Some(dir) = created_in {
+                    try!(with_panel(other, |p| { p.refresh_if_showing(other, &dir); Ok(()) }));
+                }
+                Ok(())
+            },
+            commands::CM_VIEW => with_panel(source, |p| { p.view(source); Ok(()) }),
+            commands::CM_EDIT => with_panel(source, |p| { p.edit(source); Ok(()) }),
+            commands::CM_OPEN_WITH => with_panel(source, |p| p.show_open_with_menu(source, None)),
+            commands::CM_FIND_FILES => with_panel(source, |p| { p.find_files(source); Ok(()) }),
+            commands::CM_COPY => {
+                let other = if source == self.panel1 { self.panel2 } else { self.panel1 };
+                match try!(with_panel(other, |p| Ok(p.location()))) {
+                    Some((target_fs, target)) => with_panel(source, |p| { p.copy(source, target_fs, &target); Ok(()) }),
+                    None => Ok(()),
+                }
+            },
+            commands::CM_PACK => {
+                let other = if source == self.panel1 { self.panel2 } else { self.panel1 };
+                match try!(with_panel(other, |p| Ok(p.location()))) {
+                    Some((target_fs, target)) => with_panel(source, |p| { p.pack(source, target_fs, &target); Ok(()) }),
+                    None => Ok(()),
+                }
+            },
+            commands::CM_DELETE => with_panel(source, |p| { p.delete(source); Ok(()) }),
+            commands::CM_CONNECT => with_panel(source, |p| p.show_sites_menu(source)),
+            commands::CM_GO_TO_DIRECTORY => with_panel(source, |p| { p.go_to_directory(source); Ok(()) }),
+            commands::CM_OPERATION_DONE => {
+                try!(with_panel(self.panel1, |p| { p.refresh(self.panel1); Ok(()) }));
+                try!(with_panel(self.panel2, |p| { p.refresh(self.panel2); Ok(()) }));
+                let compared = match *self.comparing.borrow() {
+                    Some((window, ref found)) if window == source => found.lock().unwrap().take(),
+                    _ => return Ok(()),
+                };
+                *self.comparing.borrow_mut() = None;
+                match compared {
+                    Some(comparison) => self.show_comparison(GetParent(source).unwrap_or(source), &comparison),
+                    None => Ok(()),
+                }
+            },
+            commands::CM_COMPARE => self.compare_panels(source),
+            commands::CM_SYNC => {
+                let left = try!(with_panel(self.panel1, |p| Ok(p.location())));
+                let right = try!(with_panel(self.panel2, |p| Ok(p.location())));
+                let owner = GetParent(source).unwrap_or(source);
+                if let (Some(left), Some(right)) = (left, right) {
+                    let instance = GetModuleHandleW(None).unwrap_or(0 as HINSTANCE);
+                    if let Err(e) = SyncerCls::create(instance, owner, left, right) {
+                        show_error(owner, "Synchronize Directories", &io::Error::from_raw_os_error(e as i32));
+                    }
+                }
+                Ok(())
+            },
+            commands::CM_QUICK_VIEW => {
+                // Ctrl+Q in the pane itself turns it back into a listing
+                if try!(with_panel(source, |p| Ok(p.is_quick_view()))) {
+                    return with_panel(source, |p| { p.toggle_quick_view(source); Ok(()) });
+                }
+                let other = if source == self.panel1 { self.panel2 } else { self.panel1 };
+                if try!(with_panel(other, |p| Ok(p.toggle_quick_view(other)))) {
+                    let path = try!(with_panel(source, |p| Ok(p.focused_path())));
+                    try!(with_panel(other, |p| { p.preview(other, path); Ok(()) }));
+                }
+                Ok(())
+            },
+            commands::CM_FOCUS_CHANGED => {
+                let other = if source == self.panel1 { self.panel2 } else { self.panel1 };
+                let path = try!(with_panel(source, |p| Ok(p.focused_path())));
+                with_panel(other, |p| { p.preview(other, path); Ok(()) })
+            },
+            commands::CM_TOGGLE_HIDDEN => {
+                let show = !self.settings.borrow().show_hidden;
+                self.settings.borrow_mut().show_hidden = show;
+                let saved = self.settings.borrow().save();
+                try!(with_panel(self.panel1, |p| { p.apply_settings(self.panel1); Ok(()) }));
+                try!(with_panel(self.panel2, |p| { p.apply_settings(self.panel2); Ok(()) }));
+                if let Err(e) = saved {
+                    show_error(self.panel1, "Save settings", &e);
+                }
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // Shift+F2: marks what differs between the two panels, after asking how
+    // to compare. A content check reads the files with a progress window and
+    // the marks are set when it is done.
+    fn compare_panels(&self, source: HWND) -> Result<(), u32>
+    {
+        const CONTENT: [(&'static str, ContentCheck); 3] = [
+            ("No, sizes and times only", ContentCheck::Off),
+            ("Byte by byte", ContentCheck::Bytes),
+            ("By hash, one file at a time", ContentCheck::Hash)];
+        let owner = GetParent(source).unwrap_or(source);
+        let left = try!(with_panel(self.panel1, |p| Ok(p.compare_side())));
+        let right = try!(with_panel(self.panel2, |p| Ok(p.compare_side())));
+        let ((left_fs, left_dir, left), (right_fs, right_dir, right)) = match (left, right) {
+            (Some(left), Some(right)) => (left, right),
+            _ => {
+                let _ = MessageBoxW(Some(owner), &wstr("Search results cannot be compared."),
+                                    &wstr("Compare directories"), MB_OK | MB_ICONINFORMATION);
+                return Ok(());
+            },
+        };
+        let choices: Vec<&str> = CONTENT.iter().map(|&(name, _)| name).collect();
+        let (text, chosen) = match try!(prompt_with_choice(
+            owner, "Compare directories", "Ignore time differences up to (seconds):", "2", &[],
+            Some(("Compare contents:", &choices, 0)))) {
+            Some((text, _, chosen)) => (text, chosen),
+            None => return Ok(()),
+        };
+        let seconds = match text.trim().parse() {
+            Ok(seconds) => seconds,
+            Err(_) => {
+                let e = io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not a number of seconds", text.trim()));
+                show_error(owner, "Compare directories", &e);
+                return Ok(());
+            },
+        };
+        let options = CompareOptions {
+            content: CONTENT.get(chosen).map_or(ContentCheck::Off, |&(_, content)| content),
+            time_tolerance: Duration::from_secs(seconds),
+        };
+        if options.content == ContentCheck::Off {
+            // nothing is read, so there is nothing to wait for
+            let compared = compare::compare(&*left_fs, &left_dir, &left, &*right_fs, &right_dir, &right,
+                                            &options, &AtomicBool::new(false), &mut |_| {});
+            return match compared {
+                Ok((comparison, _)) => self.show_comparison(owner, &comparison),
+                Err(e) => { show_error(owner, "Compare directories", &e); Ok(()) },
+            };
+        }
+        let found = Arc::new(Mutex::new(None));
+        let slot = found.clone();
+        let work: Work = Box::new(move |cancel, report| {
+            let (comparison, progress) = try!(compare::compare(
+                &*left_fs, &left_dir, &left, &*right_fs, &right_dir, &right, &options, cancel, report));
+            if !progress.cancelled {
+                *slot.lock().unwrap() = Some(comparison);
+            }
+            Ok(progress)
+        });
+        match operation::start(owner, "Compare directories", work) {
+            Ok(window) => *self.comparing.borrow_mut() = Some((window, found)),
+            Err(e) => show_error(owner, "Compare directories", &io::Error::from_raw_os_error(e as i32)),
+        }
+        Ok(())
+    }
+
+    fn show_comparison(&self, owner: HWND, comparison: &Comparison) -> Result<(), u32>
+    {
+        try!(with_panel(self.panel1, |p| { p.mark(self.panel1, &comparison.marked_left()); Ok(()) }));
+        try!(with_panel(self.panel2, |p| { p.mark(self.panel2, &comparison.marked_right()); Ok(()) }));
+        if comparison.differences.is_empty() {
+            let _ = MessageBoxW(Some(owner), &wstr("The directories have the same files."),
+                                &wstr("Compare directories"), MB_OK | MB_ICONINFORMATION);
+        }
+        Ok(())
+    }
+}
+
+pub fn with_panel<F, T>(hwnd: HWND, f: F) -> Result<T, u32>
+    where F: FnOnce(&FilePanelCls) -> Result<T, u32>, T: Default
+{
+    match lookup_hwnd::<FilePanelCls>(hwnd) {
+        Some(panel) => f(&panel.borrow()),
+        None => Ok(T::default()),
+    }
 }
 
 impl WinCls for MainCls {
@@ -218,10 +464,30 @@ impl WinCls for MainCls {
     fn wnd_proc(
         &self,
         hwnd: HWND, msg: UINT,
-        _param: WPARAM, para: LPARAM)
+        param: WPARAM, para: LPARAM)
         -> Option<LRESULT>
     {
         match msg {
+            WM_ACTIVATE => {
+                if LOWORD(param as DWORD) == WA_INACTIVE {
+                    match GetFocus() {
+                        Some(focus) if focus == self.panel1 || focus == self.panel2 =>
+                            self.active_panel.set(focus),
+                        _ => {},
+                    }
+                }
+                None
+            },
+            WM_SETFOCUS => {
+                let _ = SetFocus(self.active_panel.get());
+                Some(0)
+            },
+            WM_COMMAND => {
+                match self.on_command(LOWORD(param as DWORD), para as HWND) {
+                    Ok(_) => Some(0),
+                    Err(x) => Some(x as LRESULT),
+                }
+            },
             WM_SIZE => {
                 let rv = GetClientRect(hwnd).and_then(|rect| {
                     let width = rect.right - rect.left;
@@ -292,12 +558,440 @@ impl WinCls for ButtonCls {
 }
 */
 
+const ROW_HEIGHT: c_int = 18;
+const DRIVE_BAR_HEIGHT: c_int = 22;
+const PATH_BAR_HEIGHT: c_int = 20;
+const LIST_TOP: c_int = DRIVE_BAR_HEIGHT + PATH_BAR_HEIGHT;
+const STATUS_BAR_HEIGHT: c_int = 20;
+// room left for the size when permissions are shown before it
+const SIZE_COLUMN_WIDTH: c_int = 90;
+// RGB(220, 0, 0)
+const SELECTED_TEXT_COLOR: COLORREF = 0x0000_00dc;
+
+// Posted by the path editor: wParam is one of the PATH_EDIT_* values.
+const WM_PATH_EDIT_DONE: UINT = WM_APP + 1;
+// Sent by the path editor on Tab.
+const WM_PATH_EDIT_COMPLETE: UINT = WM_APP + 2;
+const PATH_EDIT_CANCEL: WPARAM = 0;
+const PATH_EDIT_COMMIT: WPARAM = 1;
+const PATH_EDIT_FOCUS_LOST: WPARAM = 2;
+const PATH_SEPARATOR: &'static str = " \u{203a} ";
+// the worker loading a quick view has news
+const WM_QUICK_VIEW_UPDATE: UINT = WM_APP + 3;
+const QUICK_VIEW_TIMER: UINT_PTR = 1;
+const QUICK_VIEW_WHEEL_ROWS: i64 = 3;
+
+thread_local! {
+    // window procedure of the EDIT class, shared by every subclassed path editor
+    static EDIT_PROC: Cell<LONG_PTR> = Cell::new(0);
+}
+
+extern "system"
+fn path_edit_proc(hwnd: HWND, msg: UINT, param: WPARAM, para: LPARAM) -> LRESULT
+{
+    let panel = GetParent(hwnd).unwrap_or(0 as HWND);
+    match (msg, param as c_int) {
+        (WM_KEYDOWN, VK_RETURN) => { let _ = PostMessageW(panel, WM_PATH_EDIT_DONE, PATH_EDIT_COMMIT, 0); return 0; },
+        (WM_KEYDOWN, VK_ESCAPE) => { let _ = PostMessageW(panel, WM_PATH_EDIT_DONE, PATH_EDIT_CANCEL, 0); return 0; },
+        (WM_KEYDOWN, VK_TAB) => { SendMessageW(panel, WM_PATH_EDIT_COMPLETE, 0, 0); return 0; },
+        // swallow the characters of the keys above so the edit control does not beep
+        (WM_CHAR, 0x0d) | (WM_CHAR, 0x09) | (WM_CHAR, 0x1b) => return 0,
+        (WM_KILLFOCUS, _) => { let _ = PostMessageW(panel, WM_PATH_EDIT_DONE, PATH_EDIT_FOCUS_LOST, 0); },
+        _ => {},
+    }
+    let prev_proc = EDIT_PROC.with(|p| p.get());
+    CallWindowProcW(prev_proc, hwnd, msg, param, para)
+}
+
+#[derive(Clone)]
+enum PathHit {
+    Segment(PathBuf),
+    Separator(PathBuf),
+}
+
+#[inline]
+pub fn lparam_point(para: LPARAM) -> (c_int, c_int)
+{ ((para & 0xffff) as i16 as c_int, ((para >> 16) & 0xffff) as i16 as c_int) }
+
+pub fn text_out(hdc: HDC, x: c_int, y: c_int, text: &str) -> Result<(), u32>
+{
+    let wide: Vec<u16> = text.encode_utf16().collect();
+    TextOutW(hdc, x, y, &wide)
+}
+
+fn text_width(hdc: HDC, text: &str) -> Result<c_int, u32>
+{
+    let wide: Vec<u16> = text.encode_utf16().collect();
+    GetTextExtentPoint32W(hdc, &wide).map(|size| size.cx)
+}
+
+pub fn invalidate(hwnd: HWND) -> Result<(), u32>
+{
+    let rect = try!(GetClientRect(hwnd));
+    InvalidateRect(hwnd, &rect, true)
+}
+
+pub fn show_error(hwnd: HWND, title: &str, err: &io::Error)
+{
+    let _ = MessageBoxW(Some(hwnd), &wstr(&err.to_string()), &wstr(title), MB_OK | MB_ICONERROR);
+}
+
+// Puts `text` on the clipboard as CF_UNICODETEXT.
+pub fn copy_to_clipboard(hwnd: HWND, text: &str) -> Result<(), u32>
+{
+    let wide = wstr(text);
+    let size = wide.len() * std::mem::size_of::<u16>();
+    let mem = try!(GlobalAlloc(GMEM_MOVEABLE, size));
+    let filled = GlobalLock(mem).map(|ptr| {
+        unsafe { std::ptr::copy_nonoverlapping(wide.as_ptr(), ptr as *mut u16, wide.len()); }
+        GlobalUnlock(mem);
+    });
+    let rv = filled.and_then(|_| {
+        try!(OpenClipboard(hwnd));
+        let set = EmptyClipboard().and_then(|_| SetClipboardData(CF_UNICODETEXT, mem as HANDLE));
+        let _ = CloseClipboard();
+        set
+    });
+    if rv.is_err() {
+        GlobalFree(mem);
+    }
+    rv.map(|_| ())
+}
+
+// Opens each of `files` in the editor configured for it.
+pub fn edit_files(hwnd: HWND, editor: &EditorSettings, files: &[PathBuf], line: Option<u64>)
+{
+    let rv = editor.commands(files, line).and_then(|commands| {
+        for (argv, file) in commands.iter().zip(files) {
+            try!(file_ops::launch(argv, file.parent().unwrap_or(Path::new("."))));
+        }
+        Ok(())
+    });
+    if let Err(e) = rv {
+        show_error(hwnd, "Edit", &e);
+    }
+}
+
+// Opens `file` with a command template, or with the system's default program.
+pub fn open_file(hwnd: HWND, template: Option<&str>, file: &Path)
+{
+    let rv = match template {
+        Some(template) => command_template::expand(template, file, None).and_then(|argv|
+            file_ops::launch(&argv, file.parent().unwrap_or(Path::new(".")))),
+        None => file_ops::open_with_default(file),
+    };
+    if let Err(e) = rv {
+        show_error(hwnd, "Open", &e);
+    }
+}
+
+fn current_modifiers() -> commands::Modifiers
+{
+    commands::Modifiers {
+        alt: GetKeyState(VK_MENU),
+        ctrl: GetKeyState(VK_CONTROL),
+        shift: GetKeyState(VK_SHIFT),
+    }
+}
+
+// Panels report commands to the main window, passing themselves as the source.
+fn send_command(hwnd: HWND, command: WORD)
+{
+    if let Some(parent) = GetParent(hwnd) {
+        SendMessageW(parent, WM_COMMAND, command as WPARAM, hwnd as LPARAM);
+    }
+}
+
+fn root_menu_text(root: &Root, format: SizeFormat) -> String
+{
+    let mut text = root.path.to_string_lossy().into_owned();
+    if !root.label.is_empty() {
+        text.push_str("  ");
+        text.push_str(&root.label);
+    }
+    if let Some(free) = root.free_bytes {
+        text.push_str(&format!("\t{} free", format_size(free, format)));
+    }
+    text
+}
+
+// Asks for the address and the name of `site`. Returns `None` if cancelled
+// or not valid; `sites` are the saved ones, whose names are taken.
+fn edit_site(owner: HWND, site: &Site, sites: &[Site]) -> Option<Site>
+{
+    let title = if site.name.is_empty() { "New site" } else { "Edit site" };
+    let label = "Address, as [ftp://, sftp://, webdav:// or webdavs://][user@]host[:port][/directory]:";
+    let (address, checked) = match prompt_with_options(
+        owner, title, label, &site.address(), &[("Passive mode (FTP)", site.passive)]) {
+        Ok(Some((ref text, ref checked))) if !text.trim().is_empty() => (text.clone(), checked.clone()),
+        _ => return None,
+    };
+    let mut edited = site.clone();
+    edited.passive = checked[0];
+    if let Err(message) = edited.set_address(&address) {
+        show_error(owner, title, &io::Error::new(io::ErrorKind::InvalidInput, message));
+        return None;
+    }
+    if edited.protocol == Protocol::Sftp {
+        let label = "Private key file (empty to log in with a password):";
+        edited.key_file = match prompt(owner, title, label, &site.key_file) {
+            Ok(Some(text)) => text.trim().to_string(),
+            _ => return None,
+        };
+    }
+    let initial = if site.name.is_empty() { edited.host.clone() } else { site.name.clone() };
+    let name = match prompt(owner, title, "Site name:", &initial) {
+        Ok(Some(ref text)) if !text.trim().is_empty() => text.trim().to_string(),
+        _ => return None,
+    };
+    // the name is an ini section and a path component
+    let error = if name.contains(|c| "[]\\/".contains(c)) {
+        Some(io::Error::new(io::ErrorKind::InvalidInput, "Site names cannot contain [ ] \\ or /"))
+    } else if name != site.name && sites.iter().any(|other| other.name == name) {
+        Some(io::Error::new(io::ErrorKind::AlreadyExists, format!("There is a site called \"{}\" already", name)))
+    } else {
+        None
+    };
+    if let Some(e) = error {
+        show_error(owner, title, &e);
+        return None;
+    }
+    edited.name = name;
+    Some(edited)
+}
+
+// Logs in to an FTP site, asking for the password unless the login is
+// anonymous. Returns the file system and where to start in it, or `None` if
+// cancelled.
+fn connect_ftp(owner: HWND, site: &Site, root: &Path) -> Option<io::Result<(Arc<Vfs>, PathBuf)>>
+{
+    let password = if site.user.is_empty() || site.user == "anonymous" {
+        String::new()
+    } else {
+        let label = format!("Password for {} at {}:", site.user, site.host);
+        match prompt_password(owner, "Connect", &label) {
+            Ok(Some(password)) => password,
+            _ => return None,
+        }
+    };
+    Some(FtpFs::connect(site, &password, root).map(|fs| {
+        let start = fs.start_path();
+        (Arc::new(fs) as Arc<Vfs>, start)
+    }))
+}
+
+// `connect_ftp` for an SFTP site, logging in with its key, whose passphrase
+// is asked for, or else a password. A host key not known yet is shown for
+// the user to accept.
+fn connect_sftp(owner: HWND, site: &Site, root: &Path) -> Option<io::Result<(Arc<Vfs>, PathBuf)>>
+{
+    let login = if site.key_file.is_empty() {
+        let label = format!("Password for {} at {}:", site.user, site.host);
+        match prompt_password(owner, "Connect", &label) {
+            Ok(Some(password)) => Login::Password(password),
+            _ => return None,
+        }
+    } else {
+        let label = format!("Passphrase for {} (empty if none):", site.key_file);
+        match prompt_password(owner, "Connect", &label) {
+            Ok(Some(passphrase)) => Login::Key(PathBuf::from(&site.key_file), passphrase),
+            _ => return None,
+        }
+    };
+    let mut trust = |key: &HostKey| {
+        let question = format!(
+            "{} is not among your known hosts. Its {} key has the fingerprint\n\n{}\n\n\
+             Connect and remember the key?", key.host, key.kind, key.fingerprint);
+        match MessageBoxW(Some(owner), &wstr(&question), &wstr("Connect"), MB_YESNO | MB_ICONWARNING) {
+            Ok(answer) => answer as c_int == IDYES,
+            Err(_) => false,
+        }
+    };
+    let known_hosts = sftp::known_hosts_path();
+    let rv = sftp::ssh_connector(site, login, known_hosts.as_ref().map(|path| path.as_path()), &mut trust)
+        .and_then(|connector| SftpFs::open(connector, &site.directory, root))
+        .map(|fs| {
+            let start = fs.start_path();
+            (Arc::new(fs) as Arc<Vfs>, start)
+        });
+    Some(rv)
+}
+
+// `connect_ftp` for a WebDAV share, which is shown from its top. Without a
+// user no password is asked for.
+fn connect_webdav(owner: HWND, site: &Site, root: &Path) -> Option<io::Result<(Arc<Vfs>, PathBuf)>>
+{
+    let password = if site.user.is_empty() {
+        String::new()
+    } else {
+        let label = format!("Password for {} at {}:", site.user, site.host);
+        match prompt_password(owner, "Connect", &label) {
+            Ok(Some(password)) => password,
+            _ => return None,
+        }
+    };
+    Some(DavFs::connect(site, &password, root).map(|fs| (Arc::new(fs) as Arc<Vfs>, root.to_path_buf())))
+}
+
 pub struct FilePanelCls {
+    listing: RefCell<Listing>,
+    roots: Box<RootSource>,
+    root_cache: RefCell<Vec<Root>>,
+    drive_buttons: RefCell<Vec<RECT>>,
+    path_hits: RefCell<Vec<(RECT, PathHit)>>,
+    path_edit: Cell<HWND>,
+    completion: RefCell<breadcrumb::TabCompletion>,
+    // set while an invalid path is being reported, so the focus loss does not close the editor
+    reporting_path_error: Cell<bool>,
+    settings: RcRc<Settings>,
+    // free and total bytes of the volume, computed once per directory
+    space_cache: RefCell<Option<(PathBuf, Option<(u64, u64)>)>>,
+    // set while the panel previews the other panel's file instead of listing
+    quick_view: RefCell<Option<QuickView>>,
+    // the focused file last reported with CM_FOCUS_CHANGED
+    reported_focus: RefCell<Option<PathBuf>>,
+}
+
+// What a quick view pane shows.
+enum Preview {
+    Nothing,
+    Loading,
+    Text(Pager<File>),
+    Hex(Pager<File>, HexView),
+    Image(Picture),
+    Directory(DirSummary),
+    Error(String),
+}
+
+// What the worker thread of a quick view pane produces.
+enum Loaded {
+    Image(Picture),
+    Directory(DirSummary),
+}
+
+struct Worker {
+    cancel: Arc<AtomicBool>,
+    // the latest result; directory counts are replaced as they grow
+    result: Arc<Mutex<Option<io::Result<Loaded>>>>,
+}
+
+#[derive(Clone, Copy)]
+enum QuickScroll {
+    Rows(i64),
+    Home,
+    End,
+}
+
+struct QuickView {
+    font: HFONT,
+    // the file shown and the one waiting for the debounce timer
+    target: quick_view::Target,
+    preview: Preview,
+    worker: Option<Worker>,
+    // rows and columns of text that fit, measured when painting
+    page: (usize, usize),
+}
+
+impl QuickView {
+    fn cancel_worker(&mut self)
+    {
+        if let Some(worker) = self.worker.take() {
+            worker.cancel.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+// Runs `work` on a thread that posts WM_QUICK_VIEW_UPDATE to `hwnd` with
+// each partial result it publishes and with the final one.
+fn spawn_worker<F>(hwnd: HWND, work: F) -> Worker
+    where F: FnOnce(&AtomicBool, &mut FnMut(Loaded)) -> io::Result<Loaded> + Send + 'static
+{
+    let worker = Worker { cancel: Arc::new(AtomicBool::new(false)), result: Arc::new(Mutex::new(None)) };
+    let (cancel, result) = (worker.cancel.clone(), worker.result.clone());
+    // HWND is not Send
+    let target = hwnd as usize;
+    thread::spawn(move || {
+        let rv = work(&cancel, &mut |partial| {
+            *result.lock().unwrap() = Some(Ok(partial));
+            let _ = PostMessageW(target as HWND, WM_QUICK_VIEW_UPDATE, 0, 0);
+        });
+        if !cancel.load(Ordering::Relaxed) {
+            *result.lock().unwrap() = Some(rv);
+            let _ = PostMessageW(target as HWND, WM_QUICK_VIEW_UPDATE, 0, 0);
+        }
+    });
+    worker
+}
+
+// Opens `path` for the quick view; directories and images continue on a worker.
+fn start_preview(hwnd: HWND, path: &Path) -> (Preview, Option<Worker>)
+{
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(e) => return (Preview::Error(e.to_string()), None),
+    };
+    let path = path.to_path_buf();
+    if meta.is_dir() {
+        let worker = spawn_worker(hwnd, move |cancel, publish| {
+            let summary = try!(quick_view::summarize_dir(&path, cancel, &mut |partial| {
+                publish(Loaded::Directory(*partial))
+            }));
+            Ok(Loaded::Directory(summary))
+        });
+        return (Preview::Directory(DirSummary::default()), Some(worker));
+    }
+    let opened = Pager::open(&path).and_then(|mut pager| {
+        let len = pager.len();
+        let sample = try!(pager.read_range(0, encoding::SAMPLE_SIZE as u64));
+        Ok((pager, sample, len))
+    });
+    let (mut pager, sample, len) = match opened {
+        Ok(opened) => opened,
+        Err(e) => return (Preview::Error(e.to_string()), None),
+    };
+    match quick_view::kind_of(false, &sample) {
+        Kind::Image => {
+            let worker = spawn_worker(hwnd, move |_, _| {
+                match viewer::load_picture(&path, &sample, len) {
+                    Some(picture) => picture.map(Loaded::Image),
+                    None => Err(io::Error::new(io::ErrorKind::InvalidData, "Not an image")),
+                }
+            });
+            (Preview::Loading, Some(worker))
+        },
+        Kind::Hex => (Preview::Hex(pager, HexView::new(len)), None),
+        Kind::Text | Kind::Directory => {
+            pager.set_wrap(true);
+            (Preview::Text(pager), None)
+        },
+    }
 }
 impl FilePanelCls {
-    pub fn create(instance: HINSTANCE, parent: HWND) -> Result<HWND, u32>
+    pub fn create(
+        instance: HINSTANCE,
+        parent: HWND,
+        path: &Path,
+        settings: RcRc<Settings>)
+        -> Result<HWND, u32>
     {
-        let inst = FilePanelCls { };
+        let mut listing = Listing::new();
+        listing.show_hidden = settings.borrow().show_hidden;
+        let _ = listing.navigate(path);
+        let inst = FilePanelCls {
+            listing: RefCell::new(listing),
+            roots: Box::new(SystemRoots),
+            root_cache: RefCell::new(Vec::new()),
+            drive_buttons: RefCell::new(Vec::new()),
+            path_hits: RefCell::new(Vec::new()),
+            path_edit: Cell::new(0 as HWND),
+            completion: RefCell::new(breadcrumb::TabCompletion::new()),
+            reporting_path_error: Cell::new(false),
+            settings: settings,
+            space_cache: RefCell::new(None),
+            quick_view: RefCell::new(None),
+            reported_focus: RefCell::new(None),
+        };
+        inst.refresh_roots();
         let cls_id = try!(Self::get_cls_id());
         let inst_rc = rcrc(inst);
         let inst_ptr = Box::into_raw(Box::new(inst_rc) as Box<Any>);
@@ -311,50 +1005,1461 @@ impl FilePanelCls {
 
         Ok(hwnd)
     }
-}
-impl WinCls for FilePanelCls {
-    fn wnd_proc(
-        &self,
-        hwnd: HWND, msg: UINT,
-        _param: WPARAM, _para: LPARAM)
-        -> Option<LRESULT>
+
+    fn refresh_roots(&self)
     {
-        match msg {
-            WM_PAINT => {
-                let rv = BeginPaint(hwnd).and_then(|(ps, hdc)| {
-                    let rect = try!(GetClientRect(hwnd));
-                    try!(FillRect(hdc, &rect, (COLOR_WINDOW + 0) as HBRUSH));
-                    try!(SetBkMode(hdc, TRANSPARENT));
-                    try!(TextOutW(hdc, 0, 0, &wstr("test")));
-                    let rect = RECT { left: 0, right: 200, top: 0, bottom: 20 };
-                    try!(DrawFocusRect(hdc, &rect));
-                    EndPaint(hwnd, &ps)
-                });
-                match rv {
-                    Ok(_) => Some(1),
-                    Err(x) => Some(x as LRESULT),
-                }
-            },
-            _ => None,
+        if let Ok(roots) = self.roots.roots() {
+            *self.root_cache.borrow_mut() = roots;
         }
     }
-    fn register () -> Result<ATOM, u32>
+
+    pub fn navigate(&self, hwnd: HWND, path: &Path)
     {
-        let wnd_cls = WNDCLASSEXW {
-            cbSize: std::mem::size_of::<WNDCLASSEXW>() as UINT,
-            style: CS_HREDRAW | CS_VREDRAW,
-            lpfnWndProc: Some(Self::wnd_proc_raw),
-            cbClsExtra: 0,
-            cbWndExtra: 0,
-            hInstance: 0 as HINSTANCE,
-            hIcon: try!(LoadIconW(0 as HINSTANCE, RC_IDI_APPLICATION)),
-            hCursor: try!(LoadCursorW(0 as HINSTANCE, RC_IDC_ARROW)),
-            hbrBackground: (winapi::COLOR_WINDOW + 1) as HBRUSH,
-            lpszMenuName: 0 as *const u16,
-            lpszClassName: wstr("FilePanelCls").as_ptr(),
-            hIconSm: try!(LoadIconW(0 as HINSTANCE, RC_IDI_APPLICATION)),
+        let rv = self.listing.borrow_mut().navigate(path);
+        if let Err(e) = rv {
+            show_error(hwnd, "Change directory", &e);
+        }
+        let _ = invalidate(hwnd);
+    }
+
+    // Re-reads the directory after its contents changed, e.g. after a file operation.
+    pub fn refresh(&self, hwnd: HWND)
+    {
+        let rv = self.listing.borrow_mut().refresh();
+        if let Err(e) = rv {
+            show_error(hwnd, "Refresh", &e);
+        }
+        *self.space_cache.borrow_mut() = None;
+        let _ = invalidate(hwnd);
+    }
+
+    pub fn current_dir(&self) -> PathBuf
+    { self.listing.borrow().path.clone() }
+
+    // The file system shown and the directory in it; an Option for `with_panel`.
+    pub fn location(&self) -> Option<(Arc<Vfs>, PathBuf)>
+    {
+        let listing = self.listing.borrow();
+        Some((listing.vfs.clone(), listing.path.clone()))
+    }
+
+    // What Compare directories works on: the directory and its entries as
+    // listed, which search results are not.
+    pub fn compare_side(&self) -> Option<(Arc<Vfs>, PathBuf, Vec<compare::Item>)>
+    {
+        let listing = self.listing.borrow();
+        if listing.search_results {
+            return None;
+        }
+        let items = listing.entries.iter()
+            .filter(|e| !e.is_parent())
+            .map(|e| compare::Item { name: e.name.clone(), is_dir: e.is_dir, size: e.size, modified: e.modified })
+            .collect();
+        Some((listing.vfs.clone(), listing.path.clone(), items))
+    }
+
+    // Selects the entries named, and only those.
+    pub fn mark(&self, hwnd: HWND, names: &[String])
+    {
+        self.listing.borrow_mut().select_names(names);
+        let _ = invalidate(hwnd);
+    }
+
+    // F5: copies the selected entries, or the focused one, to `target` in
+    // `target_fs` or wherever the user changes that to.
+    pub fn copy(&self, hwnd: HWND, target_fs: Arc<Vfs>, target: &Path)
+    {
+        let (source_fs, sources) = {
+            let listing = self.listing.borrow();
+            (listing.vfs.clone(), listing.chosen_paths())
+        };
+        let label = match sources.len() {
+            0 => return,
+            1 => format!("Copy \"{}\" to:", sources[0].display()),
+            n => format!("Copy {} items to:", n),
+        };
+        let owner = GetParent(hwnd).unwrap_or(hwnd);
+        let target = match prompt(owner, "Copy", &label, &target.to_string_lossy()) {
+            Ok(Some(ref text)) if !text.trim().is_empty() => self.current_dir().join(text.trim()),
+            _ => return,
+        };
+        let work: Work = Box::new(move |cancel, report|
+            file_ops::copy(&*source_fs, &sources, &*target_fs, &target, cancel, report));
+        if let Err(e) = operation::start(owner, "Copy", work) {
+            show_error(owner, "Copy", &io::Error::from_raw_os_error(e as i32));
+        }
+    }
+
+    // Alt+F5: packs the selected entries, or the focused one, into a ZIP
+    // archive in `target_dir` of `target_fs`, or wherever the user says.
+    pub fn pack(&self, hwnd: HWND, target_fs: Arc<Vfs>, target_dir: &Path)
+    {
+        const LEVELS: [(&'static str, u32); 4] = [("Store", 0), ("Fastest", 1), ("Normal", 6), ("Maximum", 9)];
+        let (source_fs, sources, dir_name) = {
+            let listing = self.listing.borrow();
+            let dir_name = listing.path.file_name().map(|n| n.to_string_lossy().into_owned());
+            (listing.vfs.clone(), listing.chosen_paths(), dir_name)
         };
+        let (label, stem) = match sources.len() {
+            0 => return,
+            1 => (format!("Pack \"{}\" to archive:", sources[0].display()),
+                  sources[0].file_stem().map(|n| n.to_string_lossy().into_owned())),
+            n => (format!("Pack {} items to archive:", n), dir_name),
+        };
+        let initial = target_dir.join(format!("{}.zip", stem.unwrap_or_else(|| "archive".to_string())));
+        let owner = GetParent(hwnd).unwrap_or(hwnd);
+        let levels: Vec<&str> = LEVELS.iter().map(|&(name, _)| name).collect();
+        let options = [("Store paths relative to the current directory", true), ("Move files to the archive", false)];
+        let (archive, checked, level) = match prompt_with_choice(
+            owner, "Pack", &label, &initial.to_string_lossy(), &options, Some(("Compression:", &levels, 2))) {
+            Ok(Some((ref text, ref checked, level))) if !text.trim().is_empty() =>
+                (target_dir.join(text.trim()), checked.clone(), level),
+            _ => return,
+        };
+        let options = PackOptions {
+            level: LEVELS.get(level).map_or(6, |&(_, level)| level),
+            relative_paths: checked[0],
+            move_files: checked[1],
+        };
+        let work: Work = Box::new(move |cancel, report|
+            file_ops::pack(&*source_fs, &sources, &*target_fs, &archive, &options, cancel, report));
+        if let Err(e) = operation::start(owner, "Pack", work) {
+            show_error(owner, "Pack", &io::Error::from_raw_os_error(e as i32));
+        }
+    }
 
-        RegisterClassExW(&wnd_cls)
+    // F8 or Delete: deletes the selected entries, or the focused one, after asking.
+    pub fn delete(&self, hwnd: HWND)
+    {
+        let (vfs, paths) = {
+            let listing = self.listing.borrow();
+            (listing.vfs.clone(), listing.chosen_paths())
+        };
+        let question = match paths.len() {
+            0 => return,
+            1 => format!("Delete \"{}\"?", paths[0].display()),
+            n => format!("Delete {} items?", n),
+        };
+        let owner = GetParent(hwnd).unwrap_or(hwnd);
+        match MessageBoxW(Some(owner), &wstr(&question), &wstr("Delete"), MB_YESNO | MB_ICONWARNING) {
+            Ok(answer) if answer as c_int == IDYES => {},
+            _ => return,
+        }
+        let work: Work = Box::new(move |cancel, report| file_ops::delete(&*vfs, &paths, cancel, report));
+        if let Err(e) = operation::start(owner, "Delete", work) {
+            show_error(owner, "Delete", &io::Error::from_raw_os_error(e as i32));
+        }
+    }
+
+    // Ctrl+PgUp: in search results the directory of the focused entry,
+    // elsewhere the parent directory.
+    pub fn go_to_directory(&self, hwnd: HWND)
+    {
+        let result = {
+            let listing = self.listing.borrow();
+            match listing.focused() {
+                Some(entry) if listing.search_results && !entry.is_parent() => Some(listing.entry_path(entry)),
+                _ => None,
+            }
+        };
+        match result {
+            Some(path) => self.show_file(hwnd, &path),
+            None => self.go_up(hwnd),
+        }
+    }
+
+    // Alt+F7: opens Find Files for the directory shown.
+    pub fn find_files(&self, hwnd: HWND)
+    {
+        let root = self.listing.borrow().path.clone();
+        let instance = GetModuleHandleW(None).unwrap_or(0 as HINSTANCE);
+        if let Err(e) = FinderCls::create(instance, hwnd, &root) {
+            show_error(hwnd, "Find Files", &io::Error::from_raw_os_error(e as i32));
+        }
+    }
+
+    // Shows the directory of `path` with the cursor on it.
+    pub fn show_file(&self, hwnd: HWND, path: &Path)
+    {
+        if let (Some(dir), Some(name)) = (path.parent(), path.file_name()) {
+            self.navigate(hwnd, dir);
+            self.listing.borrow_mut().select_name(&name.to_string_lossy());
+            self.report_focus(hwnd);
+        }
+    }
+
+    // "Feed to panel" of Find Files.
+    pub fn show_found(&self, hwnd: HWND, root: &Path, found: &[Found])
+    {
+        if self.is_quick_view() {
+            self.toggle_quick_view(hwnd);
+        }
+        self.listing.borrow_mut().show_found(root, found);
+        self.report_focus(hwnd);
+        let _ = invalidate(hwnd);
+    }
+
+    pub fn refresh_if_showing(&self, hwnd: HWND, path: &Path)
+    {
+        if self.listing.borrow().path == path {
+            self.refresh(hwnd);
+        }
+    }
+
+    // F7: asks for a name, creates the directory and puts the cursor on it.
+    // Returns the directory the new entry appeared in.
+    pub fn make_dir(&self, hwnd: HWND) -> Option<PathBuf>
+    {
+        let owner = GetParent(hwnd).unwrap_or(hwnd);
+        let name = match prompt(owner, "Make directory", "New directory (a\\b\\c creates nested directories):", "") {
+            Ok(Some(name)) => name,
+            _ => return None,
+        };
+        let (vfs, base) = {
+            let listing = self.listing.borrow();
+            (listing.vfs.clone(), listing.path.clone())
+        };
+        match file_ops::make_dir(&*vfs, &base, &name) {
+            Ok(first) => {
+                self.refresh(hwnd);
+                if let Some(first) = first {
+                    self.listing.borrow_mut().select_name(&first);
+                }
+                let _ = invalidate(hwnd);
+                Some(base)
+            },
+            Err(e) => {
+                show_error(owner, "Make directory", &e);
+                None
+            },
+        }
+    }
+
+    // A file of the panel's file system as a file on the disk, copied out of
+    // an archive if need be; errors are shown.
+    fn local_file(&self, hwnd: HWND, path: &Path) -> Option<PathBuf>
+    {
+        let vfs = self.listing.borrow().vfs.clone();
+        match vfs::local_copy(&*vfs, path) {
+            Ok(local) => Some(local),
+            Err(e) => {
+                show_error(hwnd, "Copy", &e);
+                None
+            },
+        }
+    }
+
+    // F3: opens the focused file in the lister.
+    pub fn view(&self, hwnd: HWND)
+    {
+        let path = match self.focused_file().and_then(|path| self.local_file(hwnd, &path)) {
+            Some(path) => path,
+            None => return,
+        };
+        let instance = GetModuleHandleW(None).unwrap_or(0 as HINSTANCE);
+        if let Err(e) = ViewerCls::create(instance, &path) {
+            show_error(hwnd, "Lister", &e);
+        }
+    }
+
+    // F4: opens the selected files, or the focused one, in the external editor.
+    pub fn edit(&self, hwnd: HWND)
+    {
+        let files: Vec<PathBuf> = {
+            let listing = self.listing.borrow();
+            let selected: Vec<PathBuf> = listing.entries.iter()
+                .filter(|e| e.selected && !e.is_dir)
+                .map(|e| listing.entry_path(e))
+                .collect();
+            match listing.focused() {
+                _ if !selected.is_empty() => selected,
+                Some(entry) if !entry.is_dir => vec![listing.entry_path(entry)],
+                _ => return,
+            }
+        };
+        let files: Vec<PathBuf> = files.iter().filter_map(|file| self.local_file(hwnd, file)).collect();
+        let owner = GetParent(hwnd).unwrap_or(hwnd);
+        edit_files(owner, &self.settings.borrow().editor, &files, None);
+    }
+
+    pub fn is_quick_view(&self) -> bool
+    { self.quick_view.borrow().is_some() }
+
+    // Ctrl+Q: switches between the listing and the quick view pane.
+    // Returns whether the pane is on.
+    pub fn toggle_quick_view(&self, hwnd: HWND) -> bool
+    {
+        let closing = self.quick_view.borrow_mut().take();
+        match closing {
+            Some(mut quick_view) => {
+                quick_view.cancel_worker();
+                let _ = KillTimer(hwnd, QUICK_VIEW_TIMER);
+                let _ = DeleteObject(quick_view.font as HGDIOBJ);
+            },
+            None => match viewer::fixed_font() {
+                Ok(font) => *self.quick_view.borrow_mut() = Some(QuickView {
+                    font: font,
+                    target: quick_view::Target::default(),
+                    preview: Preview::Nothing,
+                    worker: None,
+                    page: (1, 1),
+                }),
+                Err(e) => show_error(hwnd, "Quick view", &io::Error::from_raw_os_error(e as i32)),
+            },
+        }
+        let _ = invalidate(hwnd);
+        self.is_quick_view()
+    }
+
+    // The focused entry, for the quick view in the other panel; None on "..".
+    pub fn focused_path(&self) -> Option<PathBuf>
+    {
+        let listing = self.listing.borrow();
+        match listing.focused() {
+            Some(entry) if !entry.is_parent() => Some(listing.entry_path(entry)),
+            _ => None,
+        }
+    }
+
+    // Tells the main window when the cursor has moved to another entry.
+    fn report_focus(&self, hwnd: HWND)
+    {
+        if self.is_quick_view() {
+            return;
+        }
+        let focused = self.focused_path();
+        if *self.reported_focus.borrow() != focused {
+            *self.reported_focus.borrow_mut() = focused;
+            send_command(hwnd, commands::CM_FOCUS_CHANGED);
+        }
+    }
+
+    // Shows `path` in the quick view pane once the cursor has rested on it
+    // for DEBOUNCE_MS, so scrolling through a directory does not read every file.
+    pub fn preview(&self, hwnd: HWND, path: Option<PathBuf>)
+    {
+        if let Some(ref mut quick_view) = *self.quick_view.borrow_mut() {
+            if quick_view.target.focus(path, Instant::now()) {
+                let _ = SetTimer(hwnd, QUICK_VIEW_TIMER, quick_view::DEBOUNCE_MS);
+            } else {
+                let _ = KillTimer(hwnd, QUICK_VIEW_TIMER);
+            }
+        }
+    }
+
+    fn load_quick_view(&self, hwnd: HWND)
+    {
+        if let Some(ref mut quick_view) = *self.quick_view.borrow_mut() {
+            let due = quick_view.target.due(Instant::now());
+            if quick_view.target.is_pending() {
+                // the timer fired early and keeps running
+                return;
+            }
+            let _ = KillTimer(hwnd, QUICK_VIEW_TIMER);
+            if !due {
+                return;
+            }
+            quick_view.cancel_worker();
+            let (preview, worker) = match quick_view.target.shown() {
+                Some(path) => start_preview(hwnd, path),
+                None => (Preview::Nothing, None),
+            };
+            quick_view.preview = preview;
+            quick_view.worker = worker;
+        }
+        let _ = invalidate(hwnd);
+    }
+
+    fn on_quick_view_update(&self, hwnd: HWND)
+    {
+        if let Some(ref mut quick_view) = *self.quick_view.borrow_mut() {
+            let result = quick_view.worker.as_ref().and_then(|w| w.result.lock().unwrap().take());
+            quick_view.preview = match result {
+                Some(Ok(Loaded::Image(picture))) => Preview::Image(picture),
+                Some(Ok(Loaded::Directory(summary))) => Preview::Directory(summary),
+                Some(Err(e)) => Preview::Error(e.to_string()),
+                None => return,
+            };
+        }
+        let _ = invalidate(hwnd);
+    }
+
+    fn paint_quick_view(&self, hdc: HDC, rect: &RECT) -> Result<(), u32>
+    {
+        let mut quick_view = self.quick_view.borrow_mut();
+        let quick_view = match *quick_view {
+            Some(ref mut quick_view) => quick_view,
+            None => return Ok(()),
+        };
+        let format = self.settings.borrow().size_format;
+        let header = RECT { bottom: LIST_TOP, .. *rect };
+        try!(FillRect(hdc, &header, (COLOR_BTNFACE + 1) as HBRUSH));
+        SetTextColor(hdc, GetSysColor(COLOR_WINDOWTEXT));
+        let name = quick_view.target.shown()
+            .and_then(|p| p.file_name())
+            .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        try!(text_out(hdc, 4, 4, &format!("Quick view: {}", name)));
+        let description = match quick_view.preview {
+            Preview::Text(ref pager) => format!("Text, {}, {}", pager.encoding().name(), format_size(pager.len(), format)),
+            Preview::Hex(ref pager, _) => format!("Binary, {}", format_size(pager.len(), format)),
+            Preview::Image(ref picture) => {
+                let view = picture.view.get();
+                format!("{} image, {} x {} pixels", picture.format.name(), view.width, view.height)
+            },
+            Preview::Directory(_) => "Folder".to_string(),
+            Preview::Loading | Preview::Nothing | Preview::Error(_) => String::new(),
+        };
+        try!(text_out(hdc, 4, DRIVE_BAR_HEIGHT + 2, &description));
+
+        let area = RECT { top: LIST_TOP, .. *rect };
+        let lines = match quick_view.preview {
+            Preview::Nothing => vec!["Nothing to show".to_string()],
+            Preview::Loading => vec!["Loading...".to_string()],
+            Preview::Error(ref e) => vec![e.clone()],
+            Preview::Directory(ref summary) => {
+                let mut lines = vec![
+                    format!("Files: {}", summary.files),
+                    format!("Folders: {}", summary.dirs),
+                    format!("Size: {} ({} bytes)", format_size(summary.bytes, format), summary.bytes),
+                ];
+                if summary.unreadable > 0 {
+                    lines.push(format!("Could not read: {}", summary.unreadable));
+                }
+                if !summary.complete {
+                    lines.push("Counting...".to_string());
+                }
+                lines
+            },
+            Preview::Image(ref picture) => return picture.paint(hdc, &area),
+            Preview::Text(_) | Preview::Hex(_, _) => {
+                try!(SelectObject(hdc, quick_view.font as HGDIOBJ));
+                let metrics = try!(GetTextMetricsW(hdc));
+                let rows = cmp::max((area.bottom - area.top) / metrics.tmHeight, 1) as usize;
+                let columns = cmp::max((area.right - area.left - 8) / metrics.tmAveCharWidth, 1) as usize;
+                quick_view.page = (rows, columns);
+                match quick_view.preview {
+                    Preview::Text(ref mut pager) => match pager.visible(rows, columns) {
+                        Ok(rows) => rows.into_iter().map(|(_, _, text)| text).collect(),
+                        Err(e) => vec![e.to_string()],
+                    },
+                    Preview::Hex(ref mut pager, ref hex) => {
+                        let digits = hexdump::offset_digits(hex.len);
+                        let row_bytes = hexdump::ROW_BYTES as u64;
+                        let mut lines = Vec::with_capacity(rows);
+                        let mut offset = hex.top;
+                        while lines.len() < rows && offset < hex.len {
+                            let end = cmp::min(offset + row_bytes, hex.len);
+                            match pager.read_range(offset, end) {
+                                Ok(bytes) => lines.push(hexdump::format_row(offset, &bytes, digits)),
+                                Err(e) => { lines.push(e.to_string()); break; },
+                            }
+                            offset = end;
+                        }
+                        lines
+                    },
+                    _ => Vec::new(),
+                }
+            },
+        };
+        let metrics = try!(GetTextMetricsW(hdc));
+        for (idx, line) in lines.iter().enumerate() {
+            let y = area.top + 2 + idx as c_int * metrics.tmHeight;
+            if y >= area.bottom { break; }
+            try!(text_out(hdc, 4, y, line));
+        }
+        Ok(())
+    }
+
+    fn scroll_quick_view(&self, scroll: QuickScroll)
+    {
+        if let Some(ref mut quick_view) = *self.quick_view.borrow_mut() {
+            let (page_rows, columns) = quick_view.page;
+            match (&mut quick_view.preview, scroll) {
+                (&mut Preview::Text(ref mut pager), QuickScroll::Rows(rows)) => { let _ = pager.scroll(rows, columns); },
+                (&mut Preview::Text(ref mut pager), QuickScroll::Home) => pager.home(),
+                (&mut Preview::Text(ref mut pager), QuickScroll::End) => { let _ = pager.end(page_rows, columns); },
+                (&mut Preview::Hex(_, ref mut hex), QuickScroll::Rows(rows)) => hex.scroll(rows),
+                (&mut Preview::Hex(_, ref mut hex), QuickScroll::Home) => hex.top = 0,
+                (&mut Preview::Hex(_, ref mut hex), QuickScroll::End) => {
+                    hex.set_cursor(u64::max_value(), false);
+                    hex.scroll_into_view(page_rows);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn on_quick_view_key(&self, hwnd: HWND, vk: c_int) -> bool
+    {
+        let page = self.quick_view.borrow().as_ref().map_or(1, |q| q.page.0) as i64;
+        match vk {
+            VK_UP => self.scroll_quick_view(QuickScroll::Rows(-1)),
+            VK_DOWN => self.scroll_quick_view(QuickScroll::Rows(1)),
+            VK_PRIOR => self.scroll_quick_view(QuickScroll::Rows(-page)),
+            VK_NEXT | VK_SPACE => self.scroll_quick_view(QuickScroll::Rows(page)),
+            VK_HOME => self.scroll_quick_view(QuickScroll::Home),
+            VK_END => self.scroll_quick_view(QuickScroll::End),
+            _ => return false,
+        }
+        let _ = invalidate(hwnd);
+        true
+    }
+
+    // Picks up settings that affect what the listing contains.
+    pub fn apply_settings(&self, hwnd: HWND)
+    {
+        let show_hidden = self.settings.borrow().show_hidden;
+        let rv = self.listing.borrow_mut().set_show_hidden(show_hidden);
+        if let Err(e) = rv {
+            show_error(hwnd, "Refresh", &e);
+        }
+        let _ = invalidate(hwnd);
+    }
+
+    fn enter(&self, hwnd: HWND)
+    {
+        let rv = self.listing.borrow_mut().enter();
+        match rv {
+            Ok(true) => {},
+            Ok(false) => self.open(hwnd),
+            Err(e) => show_error(hwnd, "Change directory", &e),
+        }
+        let _ = invalidate(hwnd);
+    }
+
+    fn focused_file(&self) -> Option<PathBuf>
+    {
+        let listing = self.listing.borrow();
+        match listing.focused() {
+            Some(entry) if !entry.is_dir => Some(listing.entry_path(entry)),
+            _ => None,
+        }
+    }
+
+    // Enter on a file: runs the first association matching it, else the
+    // system's default program.
+    fn open(&self, hwnd: HWND)
+    {
+        let path = match self.focused_file().and_then(|path| self.local_file(hwnd, &path)) {
+            Some(path) => path,
+            None => return,
+        };
+        let command = self.settings.borrow().associations.command_for(&path).map(|c| c.to_string());
+        let owner = GetParent(hwnd).unwrap_or(hwnd);
+        open_file(owner, command.as_ref().map(|c| c.as_str()), &path);
+    }
+
+    // Shift+Enter or a right click on a file: every matching association,
+    // the default program and a command typed in by the user. The menu opens
+    // at `at`, or under the cursor row.
+    pub fn show_open_with_menu(&self, hwnd: HWND, at: Option<(c_int, c_int)>) -> Result<(), u32>
+    {
+        if self.is_quick_view() {
+            return Ok(());
+        }
+        let path = match self.focused_file() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let choices: Vec<(String, String)> = self.settings.borrow().associations.matching(&path).iter()
+            .map(|a| (a.label(), a.command.clone()))
+            .collect();
+        let default_id = choices.len() + 1;
+        let other_id = choices.len() + 2;
+        let menu = try!(CreatePopupMenu());
+        for (idx, &(ref label, _)) in choices.iter().enumerate() {
+            try!(AppendMenuW(menu, MF_STRING, idx + 1, Some(&wstr(label))));
+        }
+        if !choices.is_empty() {
+            try!(AppendMenuW(menu, MF_SEPARATOR, 0, None));
+        }
+        try!(AppendMenuW(menu, MF_STRING, default_id, Some(&wstr("Default program"))));
+        try!(AppendMenuW(menu, MF_STRING, other_id, Some(&wstr("Other command..."))));
+        let (x, y) = at.unwrap_or_else(|| {
+            let listing = self.listing.borrow();
+            let row = listing.cursor.saturating_sub(listing.top) as c_int + 1;
+            (ROW_HEIGHT, LIST_TOP + row * ROW_HEIGHT)
+        });
+        let origin = try!(ClientToScreen(hwnd, POINT { x: x, y: y }));
+        let chosen = TrackPopupMenu(
+            menu, TPM_LEFTALIGN | TPM_TOPALIGN | TPM_NONOTIFY, origin.x, origin.y, hwnd);
+        try!(DestroyMenu(menu));
+        let chosen = try!(chosen) as usize;
+        let path = match chosen {
+            0 => return Ok(()),
+            _ => match self.local_file(hwnd, &path) {
+                Some(path) => path,
+                None => return Ok(()),
+            },
+        };
+        let owner = GetParent(hwnd).unwrap_or(hwnd);
+        if let Some(&(_, ref command)) = choices.get(chosen.wrapping_sub(1)) {
+            open_file(owner, Some(command), &path);
+        } else if chosen == default_id {
+            open_file(owner, None, &path);
+        } else if chosen == other_id {
+            try!(self.open_with_command(owner, &path));
+        }
+        Ok(())
+    }
+
+    // "Other command...": asks for a command template and can keep it as the
+    // association for the file's extension.
+    fn open_with_command(&self, owner: HWND, path: &Path) -> Result<(), u32>
+    {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        let always = extension.as_ref().map(|e| format!("Always use for *.{} files", e));
+        let options: Vec<(&str, bool)> = always.iter().map(|label| (label.as_str(), false)).collect();
+        let answer = try!(prompt_with_options(
+            owner, "Open with", "Command (%P is the file's path, %N its name):", "", &options));
+        let (command, checked) = match answer {
+            Some((ref command, _)) if command.trim().is_empty() => return Ok(()),
+            Some(answer) => answer,
+            None => return Ok(()),
+        };
+        if let (Some(extension), Some(&true)) = (extension, checked.first()) {
+            self.settings.borrow_mut().associations.add_first(&format!("*.{}", extension), &command);
+            let saved = self.settings.borrow().save();
+            if let Err(e) = saved {
+                show_error(owner, "Save settings", &e);
+            }
+        }
+        open_file(owner, Some(&command), path);
+        Ok(())
+    }
+
+    fn go_up(&self, hwnd: HWND)
+    {
+        let rv = self.listing.borrow_mut().go_up();
+        if let Err(e) = rv {
+            show_error(hwnd, "Change directory", &e);
+        }
+        let _ = invalidate(hwnd);
+    }
+
+    pub fn show_drive_menu(&self, hwnd: HWND) -> Result<(), u32>
+    {
+        self.refresh_roots();
+        let menu = try!(CreatePopupMenu());
+        {
+            let roots = self.root_cache.borrow();
+            let current = roots::root_of(&roots, &self.listing.borrow().path);
+            let format = self.settings.borrow().size_format;
+            for (idx, root) in roots.iter().enumerate() {
+                let flags = if Some(idx) == current { MF_STRING | MF_CHECKED } else { MF_STRING };
+                try!(AppendMenuW(menu, flags, idx + 1, Some(&wstr(&root_menu_text(root, format)))));
+            }
+        }
+        // plugin file systems follow the roots
+        let file_systems = plugins::file_systems();
+        let first_plugin = self.root_cache.borrow().len() + 1;
+        if !file_systems.is_empty() {
+            try!(AppendMenuW(menu, MF_SEPARATOR, 0, None));
+        }
+        for (idx, &(ref plugin, _)) in file_systems.iter().enumerate() {
+            try!(AppendMenuW(menu, MF_STRING, first_plugin + idx, Some(&wstr(plugin.name()))));
+        }
+        let origin = try!(ClientToScreen(hwnd, POINT { x: 0, y: DRIVE_BAR_HEIGHT }));
+        let chosen = TrackPopupMenu(
+            menu, TPM_LEFTALIGN | TPM_TOPALIGN | TPM_NONOTIFY, origin.x, origin.y, hwnd);
+        try!(DestroyMenu(menu));
+        let chosen = try!(chosen) as usize;
+        if let Some(&(ref plugin, ref root)) = file_systems.get(chosen.wrapping_sub(first_plugin)) {
+            let _ = SetFocus(hwnd);
+            let rv = PluginFs::open(plugin, None, root, true)
+                .and_then(|fs| self.listing.borrow_mut().set_vfs(Arc::new(fs), root));
+            if let Err(e) = rv {
+                show_error(hwnd, "Change directory", &e);
+            }
+            let _ = invalidate(hwnd);
+            return Ok(());
+        }
+        let target = self.root_cache.borrow().get(chosen.wrapping_sub(1)).map(|r| r.path.clone());
+        if let Some(path) = target {
+            let _ = SetFocus(hwnd);
+            self.navigate(hwnd, &path);
+        }
+        Ok(())
+    }
+
+    // Ctrl+F: the saved FTP, SFTP and WebDAV sites, to connect to one, and entries to add,
+    // change and delete sites.
+    pub fn show_sites_menu(&self, hwnd: HWND) -> Result<(), u32>
+    {
+        const NEW_SITE: usize = 1;
+        const CONNECT: usize = 1000;
+        const EDIT: usize = 2000;
+        const DELETE: usize = 3000;
+        let mut sites = sites::load();
+        let menu = try!(CreatePopupMenu());
+        let edit_menu = try!(CreatePopupMenu());
+        let delete_menu = try!(CreatePopupMenu());
+        for (idx, site) in sites.iter().enumerate() {
+            let text = format!("{}\t{}", site.name, site.address());
+            try!(AppendMenuW(menu, MF_STRING, CONNECT + idx, Some(&wstr(&text))));
+            try!(AppendMenuW(edit_menu, MF_STRING, EDIT + idx, Some(&wstr(&site.name))));
+            try!(AppendMenuW(delete_menu, MF_STRING, DELETE + idx, Some(&wstr(&site.name))));
+        }
+        if !sites.is_empty() {
+            try!(AppendMenuW(menu, MF_SEPARATOR, 0, None));
+        }
+        // the submenus are destroyed with the menu
+        let more = if sites.is_empty() { MF_POPUP | MF_GRAYED } else { MF_POPUP };
+        try!(AppendMenuW(menu, MF_STRING, NEW_SITE, Some(&wstr("New site..."))));
+        try!(AppendMenuW(menu, more, edit_menu as usize, Some(&wstr("Edit site"))));
+        try!(AppendMenuW(menu, more, delete_menu as usize, Some(&wstr("Delete site"))));
+        let origin = try!(ClientToScreen(hwnd, POINT { x: 0, y: DRIVE_BAR_HEIGHT }));
+        let chosen = TrackPopupMenu(
+            menu, TPM_LEFTALIGN | TPM_TOPALIGN | TPM_NONOTIFY, origin.x, origin.y, hwnd);
+        try!(DestroyMenu(menu));
+        let chosen = try!(chosen) as usize;
+        let owner = GetParent(hwnd).unwrap_or(hwnd);
+        let changed = match chosen {
+            NEW_SITE => match edit_site(owner, &Site::new(""), &sites) {
+                Some(site) => { sites.push(site); true },
+                None => false,
+            },
+            n if n >= CONNECT && n < CONNECT + sites.len() => {
+                self.connect(hwnd, &sites[n - CONNECT]);
+                false
+            },
+            n if n >= EDIT && n < EDIT + sites.len() => match edit_site(owner, &sites[n - EDIT], &sites) {
+                Some(site) => { sites[n - EDIT] = site; true },
+                None => false,
+            },
+            n if n >= DELETE && n < DELETE + sites.len() => {
+                let question = format!("Delete the site \"{}\"?", sites[n - DELETE].name);
+                match MessageBoxW(Some(owner), &wstr(&question), &wstr("Delete site"), MB_YESNO | MB_ICONWARNING) {
+                    Ok(answer) if answer as c_int == IDYES => { sites.remove(n - DELETE); true },
+                    _ => false,
+                }
+            },
+            _ => false,
+        };
+        if changed {
+            if let Err(e) = sites::save(&sites) {
+                show_error(owner, "Save sites", &e);
+            }
+        }
+        Ok(())
+    }
+
+    // Logs in to `site` and shows its directory.
+    fn connect(&self, hwnd: HWND, site: &Site)
+    {
+        let owner = GetParent(hwnd).unwrap_or(hwnd);
+        let root = remote::root_of(site);
+        let opened = match site.protocol {
+            Protocol::Ftp => connect_ftp(owner, site, &root),
+            Protocol::Sftp => connect_sftp(owner, site, &root),
+            Protocol::WebDav | Protocol::WebDavs => connect_webdav(owner, site, &root),
+        };
+        let rv = match opened {
+            Some(rv) => rv,
+            None => return,
+        };
+        let rv = rv.and_then(|(fs, start)| {
+            let mut listing = self.listing.borrow_mut();
+            // a directory that is gone leaves the site's top one
+            listing.set_vfs(fs.clone(), &start).or_else(|_| listing.set_vfs(fs, &root))
+        });
+        if let Err(e) = rv {
+            show_error(hwnd, "Connect", &e);
+        }
+        let _ = invalidate(hwnd);
+    }
+
+    fn show_sibling_menu(&self, hwnd: HWND, dir: &Path, x: c_int) -> Result<(), u32>
+    {
+        let vfs = self.listing.borrow().vfs_for(dir);
+        let names = match breadcrumb::subdirectories(&*vfs, dir) {
+            Ok(names) => names,
+            Err(e) => { show_error(hwnd, "Change directory", &e); return Ok(()); },
+        };
+        if names.is_empty() {
+            return Ok(());
+        }
+        let current = self.listing.borrow().path.clone();
+        let menu = try!(CreatePopupMenu());
+        for (idx, name) in names.iter().enumerate() {
+            let on_path = current.starts_with(dir.join(name));
+            let flags = if on_path { MF_STRING | MF_CHECKED } else { MF_STRING };
+            try!(AppendMenuW(menu, flags, idx + 1, Some(&wstr(name))));
+        }
+        let origin = try!(ClientToScreen(hwnd, POINT { x: x, y: LIST_TOP }));
+        let chosen = TrackPopupMenu(
+            menu, TPM_LEFTALIGN | TPM_TOPALIGN | TPM_NONOTIFY, origin.x, origin.y, hwnd);
+        try!(DestroyMenu(menu));
+        let chosen = try!(chosen) as usize;
+        if let Some(name) = names.get(chosen.wrapping_sub(1)) {
+            self.navigate(hwnd, &dir.join(name));
+        }
+        Ok(())
+    }
+
+    fn begin_path_edit(&self, hwnd: HWND) -> Result<(), u32>
+    {
+        if self.path_edit.get() as usize != 0 {
+            return Ok(());
+        }
+        let rect = try!(GetClientRect(hwnd));
+        let path = self.listing.borrow().path.to_string_lossy().into_owned();
+        let instance = try!(GetModuleHandleW(None));
+        let edit = try!(CreateWindowExW(
+            0,
+            WinClsIdW::ClsName(wstr("EDIT")),
+            Some(&wstr(&path)),
+            WS_VISIBLE | WS_CHILD | WS_BORDER | ES_AUTOHSCROLL,
+            0, DRIVE_BAR_HEIGHT, rect.right, PATH_BAR_HEIGHT,
+            Some(hwnd), None, instance, None));
+        SendMessageW(edit, WM_SETFONT, try!(GetStockObject(DEFAULT_GUI_FONT)) as WPARAM, 0);
+        let prev_proc = try!(SetWindowLongPtrW(edit, GWLP_WNDPROC, path_edit_proc as LONG_PTR));
+        EDIT_PROC.with(|p| p.set(prev_proc));
+        SendMessageW(edit, EM_SETSEL as UINT, 0, -1);
+        self.path_edit.set(edit);
+        *self.completion.borrow_mut() = breadcrumb::TabCompletion::new();
+        let _ = SetFocus(edit);
+        Ok(())
+    }
+
+    fn end_path_edit(&self, hwnd: HWND, how: WPARAM)
+    {
+        let edit = self.path_edit.get();
+        if edit as usize == 0 || self.reporting_path_error.get() {
+            return;
+        }
+        if how == PATH_EDIT_COMMIT {
+            let base = self.listing.borrow().path.clone();
+            let rv = breadcrumb::validate(&GetWindowTextW(edit), &base)
+                .and_then(|path| self.listing.borrow_mut().navigate(&path));
+            if let Err(e) = rv {
+                self.reporting_path_error.set(true);
+                show_error(hwnd, "Change directory", &e);
+                self.reporting_path_error.set(false);
+                let _ = SetFocus(edit);
+                return;
+            }
+        }
+        self.path_edit.set(0 as HWND);
+        let _ = DestroyWindow(edit);
+        if how != PATH_EDIT_FOCUS_LOST {
+            let _ = SetFocus(hwnd);
+        }
+        let _ = invalidate(hwnd);
+    }
+
+    fn complete_path_edit(&self)
+    {
+        let edit = self.path_edit.get();
+        let base = self.listing.borrow().path.clone();
+        let completed = self.completion.borrow_mut().advance(&GetWindowTextW(edit), &base);
+        if let Some(text) = completed {
+            let _ = SetWindowTextW(edit, &wstr(&text));
+            let end = text.encode_utf16().count();
+            SendMessageW(edit, EM_SETSEL as UINT, end as WPARAM, end as LPARAM);
+        }
+    }
+
+    fn paint_path_bar(&self, hdc: HDC, rect: &RECT) -> Result<(), u32>
+    {
+        let bar = RECT { left: 0, right: rect.right, top: DRIVE_BAR_HEIGHT, bottom: LIST_TOP };
+        try!(FillRect(hdc, &bar, (COLOR_BTNFACE + 1) as HBRUSH));
+        SetTextColor(hdc, GetSysColor(COLOR_WINDOWTEXT));
+        let mut hits = Vec::new();
+        let mut x = 4;
+        for segment in breadcrumb::segments(&self.listing.borrow().path) {
+            for &(ref text, is_separator) in [(segment.label.as_str(), false), (PATH_SEPARATOR, true)].iter() {
+                let width = try!(text_width(hdc, text));
+                try!(text_out(hdc, x, DRIVE_BAR_HEIGHT + 2, text));
+                let hit_rect = RECT { left: x, right: x + width, top: DRIVE_BAR_HEIGHT, bottom: LIST_TOP };
+                let hit = if is_separator { PathHit::Separator(segment.path.clone()) }
+                          else { PathHit::Segment(segment.path.clone()) };
+                hits.push((hit_rect, hit));
+                x += width;
+            }
+        }
+        if self.listing.borrow().search_results {
+            try!(text_out(hdc, x, DRIVE_BAR_HEIGHT + 2, "(search results)"));
+        }
+        *self.path_hits.borrow_mut() = hits;
+        Ok(())
+    }
+
+    fn on_path_bar_click(&self, hwnd: HWND, x: c_int, double: bool) -> Result<(), u32>
+    {
+        if double {
+            return self.begin_path_edit(hwnd);
+        }
+        let hit = self.path_hits.borrow().iter()
+            .find(|&&(ref r, _)| x >= r.left && x < r.right)
+            .map(|&(ref r, ref hit)| (r.left, hit.clone()));
+        match hit {
+            Some((_, PathHit::Segment(path))) => self.navigate(hwnd, &path),
+            Some((left, PathHit::Separator(dir))) => try!(self.show_sibling_menu(hwnd, &dir, left)),
+            None => {},
+        }
+        Ok(())
+    }
+
+    fn visible_rows(rect: &RECT) -> usize
+    {
+        let rows = (rect.bottom - rect.top - LIST_TOP - STATUS_BAR_HEIGHT) / ROW_HEIGHT;
+        if rows > 0 { rows as usize } else { 1 }
+    }
+
+    fn paint_drive_bar(&self, hdc: HDC) -> Result<(), u32>
+    {
+        let roots = self.root_cache.borrow();
+        let current = roots::root_of(&roots, &self.listing.borrow().path);
+        let mut buttons = Vec::with_capacity(roots.len());
+        let mut x = 2;
+        for (idx, root) in roots.iter().enumerate() {
+            let name = format!("[{}]", root.short_name());
+            let button = RECT {
+                left: x, top: 2,
+                right: x + try!(text_width(hdc, &name)) + 6, bottom: DRIVE_BAR_HEIGHT - 2,
+            };
+            if Some(idx) == current {
+                try!(FillRect(hdc, &button, (COLOR_BTNFACE + 1) as HBRUSH));
+            }
+            try!(text_out(hdc, x + 3, 4, &name));
+            buttons.push(button);
+            x = button.right + 2;
+        }
+        *self.drive_buttons.borrow_mut() = buttons;
+        Ok(())
+    }
+
+    fn paint_entries(&self, hwnd: HWND, hdc: HDC, rect: &RECT) -> Result<(), u32>
+    {
+        let rows = Self::visible_rows(rect);
+        let mut listing = self.listing.borrow_mut();
+        listing.scroll_into_view(rows);
+        let listing: &Listing = &*listing;
+        let focused = GetFocus() == Some(hwnd);
+        let format = self.settings.borrow().size_format;
+        for (idx, entry) in listing.entries.iter().enumerate().skip(listing.top).take(rows) {
+            let y = LIST_TOP + (idx - listing.top) as c_int * ROW_HEIGHT;
+            let row = RECT { left: 0, right: rect.right, top: y, bottom: y + ROW_HEIGHT };
+            let is_cursor = idx == listing.cursor;
+            if is_cursor && focused {
+                try!(FillRect(hdc, &row, (COLOR_HIGHLIGHT + 1) as HBRUSH));
+            }
+            let color = if entry.selected { SELECTED_TEXT_COLOR }
+                        else if is_cursor && focused { GetSysColor(COLOR_HIGHLIGHTTEXT) }
+                        else if entry.hidden { GetSysColor(COLOR_GRAYTEXT) }
+                        else { GetSysColor(COLOR_WINDOWTEXT) };
+            SetTextColor(hdc, color);
+            let name = if entry.is_dir { format!("[{}]", entry.name) } else { entry.name.clone() };
+            try!(text_out(hdc, 4, y + 2, &name));
+            let size = if entry.is_dir { "<DIR>".to_string() } else { format_size(entry.size, format) };
+            let size_x = rect.right - 4 - try!(text_width(hdc, &size));
+            try!(text_out(hdc, size_x, y + 2, &size));
+            if let Some(mode) = entry.mode {
+                let mode = vfs::mode_text(mode);
+                let mode_x = rect.right - 4 - SIZE_COLUMN_WIDTH - try!(text_width(hdc, &mode));
+                try!(text_out(hdc, mode_x, y + 2, &mode));
+            }
+            if is_cursor && !focused {
+                try!(DrawFocusRect(hdc, &row));
+            }
+        }
+        Ok(())
+    }
+
+    fn volume_space(&self) -> Option<(u64, u64)>
+    {
+        let path = self.listing.borrow().path.clone();
+        let mut cache = self.space_cache.borrow_mut();
+        let stale = cache.as_ref().map_or(true, |&(ref cached, _)| *cached != path);
+        if stale {
+            let space = self.roots.space(&path).ok();
+            *cache = Some((path, space));
+        }
+        cache.as_ref().and_then(|&(_, space)| space)
+    }
+
+    fn paint_status_bar(&self, hdc: HDC, rect: &RECT) -> Result<(), u32>
+    {
+        let bar = RECT { left: 0, right: rect.right, top: rect.bottom - STATUS_BAR_HEIGHT, bottom: rect.bottom };
+        try!(FillRect(hdc, &bar, (COLOR_BTNFACE + 1) as HBRUSH));
+        SetTextColor(hdc, GetSysColor(COLOR_WINDOWTEXT));
+        let format = self.settings.borrow().size_format;
+        let summary = self.listing.borrow().selection_summary();
+        let selection = format!(
+            "{} of {} files, {} of {} selected",
+            summary.selected_files, summary.total_files,
+            format_size(summary.selected_bytes, format), format_size(summary.total_bytes, format));
+        try!(text_out(hdc, 4, bar.top + 3, &selection));
+        if let Some((free, total)) = self.volume_space() {
+            let space = format!("{} free of {}", format_size(free, format), format_size(total, format));
+            let x = rect.right - 4 - try!(text_width(hdc, &space));
+            try!(text_out(hdc, x, bar.top + 3, &space));
+        }
+        Ok(())
+    }
+
+    fn show_size_format_menu(&self, hwnd: HWND, x: c_int, y: c_int) -> Result<(), u32>
+    {
+        let current = self.settings.borrow().size_format;
+        let menu = try!(CreatePopupMenu());
+        for (idx, format) in SizeFormat::all().iter().enumerate() {
+            let flags = if *format == current { MF_STRING | MF_CHECKED } else { MF_STRING };
+            try!(AppendMenuW(menu, flags, idx + 1, Some(&wstr(format.description()))));
+        }
+        let origin = try!(ClientToScreen(hwnd, POINT { x: x, y: y }));
+        let chosen = TrackPopupMenu(
+            menu, TPM_LEFTALIGN | TPM_TOPALIGN | TPM_NONOTIFY, origin.x, origin.y, hwnd);
+        try!(DestroyMenu(menu));
+        let chosen = try!(chosen) as usize;
+        if let Some(format) = SizeFormat::all().get(chosen.wrapping_sub(1)) {
+            self.settings.borrow_mut().size_format = *format;
+            let saved = self.settings.borrow().save();
+            if let Err(e) = saved {
+                show_error(hwnd, "Save settings", &e);
+            }
+            send_command(hwnd, commands::CM_SETTINGS_CHANGED);
+        }
+        Ok(())
+    }
+
+    fn on_click(&self, hwnd: HWND, x: c_int, y: c_int, double: bool)
+    {
+        let _ = SetFocus(hwnd);
+        if y < DRIVE_BAR_HEIGHT {
+            let hit = self.drive_buttons.borrow().iter()
+                .position(|b| x >= b.left && x < b.right);
+            let target = hit.and_then(|idx| self.root_cache.borrow().get(idx).map(|r| r.path.clone()));
+            if let Some(path) = target {
+                self.navigate(hwnd, &path);
+            }
+            return;
+        }
+        if y < LIST_TOP {
+            if let Err(e) = self.on_path_bar_click(hwnd, x, double) {
+                show_error(hwnd, "Change directory", &io::Error::from_raw_os_error(e as i32));
+            }
+            return;
+        }
+        let in_status_bar = GetClientRect(hwnd).map_or(true, |r| y >= r.bottom - STATUS_BAR_HEIGHT);
+        if in_status_bar {
+            return;
+        }
+        let row = ((y - LIST_TOP) / ROW_HEIGHT) as usize;
+        let hit = {
+            let mut listing = self.listing.borrow_mut();
+            let idx = listing.top + row;
+            if idx < listing.entries.len() { listing.set_cursor(idx); true } else { false }
+        };
+        if hit && double {
+            self.enter(hwnd);
+        }
+        let _ = invalidate(hwnd);
+    }
+
+    fn on_key(&self, hwnd: HWND, vk: c_int) -> bool
+    {
+        let rows = GetClientRect(hwnd).map(|r| Self::visible_rows(&r)).unwrap_or(1) as isize;
+        match vk {
+            VK_UP => self.listing.borrow_mut().move_cursor(-1),
+            VK_DOWN => self.listing.borrow_mut().move_cursor(1),
+            VK_PRIOR => self.listing.borrow_mut().move_cursor(-rows),
+            VK_NEXT => self.listing.borrow_mut().move_cursor(rows),
+            VK_HOME => self.listing.borrow_mut().set_cursor(0),
+            VK_END => self.listing.borrow_mut().set_cursor(usize::max_value()),
+            VK_INSERT => {
+                let mut listing = self.listing.borrow_mut();
+                listing.toggle_selection();
+                listing.move_cursor(1);
+            },
+            VK_SPACE => self.listing.borrow_mut().toggle_selection(),
+            VK_RETURN => self.enter(hwnd),
+            VK_BACK => self.go_up(hwnd),
+            _ => return false,
+        }
+        let _ = invalidate(hwnd);
+        true
+    }
+}
+impl WinCls for FilePanelCls {
+    fn wnd_proc(
+        &self,
+        hwnd: HWND, msg: UINT,
+        param: WPARAM, para: LPARAM)
+        -> Option<LRESULT>
+    {
+        match msg {
+            WM_PAINT => {
+                let rv = BeginPaint(hwnd).and_then(|(ps, hdc)| {
+                    let rect = try!(GetClientRect(hwnd));
+                    try!(FillRect(hdc, &rect, (COLOR_WINDOW + 0) as HBRUSH));
+                    try!(SelectObject(hdc, try!(GetStockObject(DEFAULT_GUI_FONT))));
+                    try!(SetBkMode(hdc, TRANSPARENT));
+                    if self.is_quick_view() {
+                        try!(self.paint_quick_view(hdc, &rect));
+                        return EndPaint(hwnd, &ps);
+                    }
+                    try!(self.paint_drive_bar(hdc));
+                    try!(self.paint_path_bar(hdc, &rect));
+                    try!(self.paint_status_bar(hdc, &rect));
+                    try!(self.paint_entries(hwnd, hdc, &rect));
+                    EndPaint(hwnd, &ps)
+                });
+                match rv {
+                    Ok(_) => Some(1),
+                    Err(x) => Some(x as LRESULT),
+                }
+            },
+            WM_KEYDOWN | WM_SYSKEYDOWN => {
+                let handled = if let Some(command) = commands::key_command(param as c_int, current_modifiers()) {
+                    send_command(hwnd, command);
+                    true
+                } else if msg != WM_KEYDOWN {
+                    false
+                } else if self.is_quick_view() {
+                    self.on_quick_view_key(hwnd, param as c_int)
+                } else {
+                    self.on_key(hwnd, param as c_int)
+                };
+                self.report_focus(hwnd);
+                if handled { Some(0) } else { None }
+            },
+            WM_LBUTTONDOWN | WM_LBUTTONDBLCLK if self.is_quick_view() => {
+                let _ = SetFocus(hwnd);
+                Some(0)
+            },
+            WM_LBUTTONDOWN | WM_LBUTTONDBLCLK => {
+                let (x, y) = lparam_point(para);
+                self.on_click(hwnd, x, y, msg == WM_LBUTTONDBLCLK);
+                self.report_focus(hwnd);
+                Some(0)
+            },
+            WM_RBUTTONUP if self.is_quick_view() => None,
+            WM_RBUTTONUP => {
+                let (x, y) = lparam_point(para);
+                let in_status_bar = GetClientRect(hwnd).map_or(false, |r| y >= r.bottom - STATUS_BAR_HEIGHT);
+                if in_status_bar {
+                    let _ = self.show_size_format_menu(hwnd, x, y);
+                    return Some(0);
+                }
+                if y >= LIST_TOP {
+                    let hit = {
+                        let mut listing = self.listing.borrow_mut();
+                        let idx = listing.top + ((y - LIST_TOP) / ROW_HEIGHT) as usize;
+                        if idx < listing.entries.len() { listing.set_cursor(idx); true } else { false }
+                    };
+                    if hit {
+                        let _ = SetFocus(hwnd);
+                        let _ = invalidate(hwnd);
+                        self.report_focus(hwnd);
+                        let _ = self.show_open_with_menu(hwnd, Some((x, y)));
+                        return Some(0);
+                    }
+                }
+                None
+            },
+            WM_MOUSEWHEEL => {
+                let notches = GET_WHEEL_DELTA_WPARAM(param) as isize / 120;
+                if self.is_quick_view() {
+                    self.scroll_quick_view(QuickScroll::Rows(-QUICK_VIEW_WHEEL_ROWS * notches as i64));
+                } else {
+                    self.listing.borrow_mut().move_cursor(-3 * notches);
+                    self.report_focus(hwnd);
+                }
+                let _ = invalidate(hwnd);
+                Some(0)
+            },
+            WM_SETFOCUS | WM_KILLFOCUS => {
+                let _ = invalidate(hwnd);
+                Some(0)
+            },
+            WM_TIMER if param == QUICK_VIEW_TIMER => {
+                self.load_quick_view(hwnd);
+                Some(0)
+            },
+            WM_QUICK_VIEW_UPDATE => {
+                self.on_quick_view_update(hwnd);
+                Some(0)
+            },
+            WM_SIZE => {
+                let edit = self.path_edit.get();
+                if edit as usize != 0 {
+                    let width = (para & 0xffff) as c_int;
+                    let _ = MoveWindow(edit, 0, DRIVE_BAR_HEIGHT, width, PATH_BAR_HEIGHT, true);
+                }
+                None
+            },
+            WM_PATH_EDIT_DONE => {
+                self.end_path_edit(hwnd, param);
+                self.report_focus(hwnd);
+                Some(0)
+            },
+            WM_PATH_EDIT_COMPLETE => {
+                self.complete_path_edit();
+                Some(0)
+            },
+            _ => None,
+        }
+    }
+    fn register () -> Result<ATOM, u32>
+    {
+        let wnd_cls = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as UINT,
+            style: CS_HREDRAW | CS_VREDRAW | CS_DBLCLKS,
+            lpfnWndProc: Some(Self::wnd_proc_raw),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: 0 as HINSTANCE,
+            hIcon: try!(LoadIconW(0 as HINSTANCE, RC_IDI_APPLICATION)),
+            hCursor: try!(LoadCursorW(0 as HINSTANCE, RC_IDC_ARROW)),
+            hbrBackground: (winapi::COLOR_WINDOW + 1) as HBRUSH,
+            lpszMenuName: 0 as *const u16,
+            lpszClassName: wstr("FilePanelCls").as_ptr(),
+            hIconSm: try!(LoadIconW(0 as HINSTANCE, RC_IDI_APPLICATION)),
+        };
+
+        RegisterClassExW(&wnd_cls)
+    }
+}
+
+const IDC_PROMPT_EDIT: c_int = 100;
+const IDC_PROMPT_OPTION: c_int = 200;
+const IDC_PROMPT_CHOICE: c_int = 300;
+
+// Modal single-line input box; see `prompt`.
+pub struct InputDlgCls {
+    edit: Cell<HWND>,
+    options: RefCell<Vec<HWND>>,
+    // the drop-down list of `prompt_with_choice`, or null
+    choice: Cell<HWND>,
+    result: RefCell<Option<(String, Vec<bool>, usize)>>,
+    done: Cell<bool>,
+}
+
+impl InputDlgCls {
+    // The window is destroyed by `prompt` once the owner is enabled again,
+    // otherwise Windows would activate some other application.
+    fn finish(&self, accepted: bool)
+    {
+        if accepted {
+            let checked = self.options.borrow().iter()
+                .map(|option| SendMessageW(*option, BM_GETCHECK, 0, 0) == BST_CHECKED as LRESULT)
+                .collect();
+            let chosen = match self.choice.get() {
+                choice if choice.is_null() => 0,
+                choice => cmp::max(SendMessageW(choice, CB_GETCURSEL, 0, 0), 0) as usize,
+            };
+            *self.result.borrow_mut() = Some((GetWindowTextW(self.edit.get()), checked, chosen));
+        }
+        self.done.set(true);
+    }
+}
+
+impl WinCls for InputDlgCls {
+    fn wnd_proc(
+        &self,
+        _hwnd: HWND, msg: UINT,
+        param: WPARAM, _para: LPARAM)
+        -> Option<LRESULT>
+    {
+        match msg {
+            WM_COMMAND => {
+                match LOWORD(param as DWORD) as c_int {
+                    IDOK => { self.finish(true); Some(0) },
+                    IDCANCEL => { self.finish(false); Some(0) },
+                    _ => None,
+                }
+            },
+            WM_CLOSE => { self.finish(false); Some(0) },
+            _ => None,
+        }
+    }
+
+    fn register () -> Result<ATOM, u32>
+    {
+        let wnd_cls = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as UINT,
+            style: CS_HREDRAW | CS_VREDRAW,
+            lpfnWndProc: Some(Self::wnd_proc_raw),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: 0 as HINSTANCE,
+            hIcon: 0 as HICON,
+            hCursor: try!(LoadCursorW(0 as HINSTANCE, RC_IDC_ARROW)),
+            hbrBackground: (COLOR_BTNFACE + 1) as HBRUSH,
+            lpszMenuName: 0 as *const u16,
+            lpszClassName: wstr("InputDlgCls").as_ptr(),
+            hIconSm: 0 as HICON,
+        };
+
+        RegisterClassExW(&wnd_cls)
+    }
+}
+
+pub fn create_control(
+    parent: HWND,
+    class: &str,
+    text: &str,
+    style: DWORD,
+    rect: (c_int, c_int, c_int, c_int),
+    id: c_int)
+    -> Result<HWND, u32>
+{
+    let instance = try!(GetModuleHandleW(None));
+    let (x, y, width, height) = rect;
+    let hwnd = try!(CreateWindowExW(
+        0,
+        WinClsIdW::ClsName(wstr(class)),
+        Some(&wstr(text)),
+        WS_VISIBLE | WS_CHILD | style,
+        x, y, width, height,
+        Some(parent), Some(id as usize as HMENU), instance, None));
+    SendMessageW(hwnd, WM_SETFONT, try!(GetStockObject(DEFAULT_GUI_FONT)) as WPARAM, 0);
+    Ok(hwnd)
+}
+
+// Asks the user for a line of text. Returns `None` if the box was cancelled.
+// `owner` is disabled while the box is shown.
+pub fn prompt(owner: HWND, title: &str, label: &str, initial: &str) -> Result<Option<String>, u32>
+{
+    prompt_with_options(owner, title, label, initial, &[])
+        .map(|result| result.map(|(text, _)| text))
+}
+
+// `prompt` with a checkbox under the text for each of `options`, given as
+// (label, initially checked). Returns the text and the state of the checkboxes.
+pub fn prompt_with_options(
+    owner: HWND,
+    title: &str,
+    label: &str,
+    initial: &str,
+    options: &[(&str, bool)])
+    -> Result<Option<(String, Vec<bool>)>, u32>
+{
+    prompt_with_choice(owner, title, label, initial, options, None)
+        .map(|result| result.map(|(text, checked, _)| (text, checked)))
+}
+
+// `prompt` for a password, which is not shown as it is typed.
+pub fn prompt_password(owner: HWND, title: &str, label: &str) -> Result<Option<String>, u32>
+{
+    input_box(owner, title, label, "", &[], None, ES_PASSWORD)
+        .map(|result| result.map(|(text, _, _)| text))
+}
+
+// `prompt_with_options` with a drop-down list between the text and the
+// checkboxes, given as (label, items, initially chosen). Also returns the
+// index of the chosen item.
+pub fn prompt_with_choice(
+    owner: HWND,
+    title: &str,
+    label: &str,
+    initial: &str,
+    options: &[(&str, bool)],
+    choice: Option<(&str, &[&str], usize)>)
+    -> Result<Option<(String, Vec<bool>, usize)>, u32>
+{
+    input_box(owner, title, label, initial, options, choice, 0)
+}
+
+fn input_box(
+    owner: HWND,
+    title: &str,
+    label: &str,
+    initial: &str,
+    options: &[(&str, bool)],
+    choice: Option<(&str, &[&str], usize)>,
+    edit_style: DWORD)
+    -> Result<Option<(String, Vec<bool>, usize)>, u32>
+{
+    const WIDTH: c_int = 420;
+    const OPTION_HEIGHT: c_int = 22;
+    const CHOICE_HEIGHT: c_int = 30;
+    let choice_height = if choice.is_some() { CHOICE_HEIGHT } else { 0 };
+    let height = 140 + choice_height + options.len() as c_int * OPTION_HEIGHT;
+    let inst = InputDlgCls {
+        edit: Cell::new(0 as HWND),
+        options: RefCell::new(Vec::new()),
+        choice: Cell::new(0 as HWND),
+        result: RefCell::new(None),
+        done: Cell::new(false),
+    };
+    let cls_id = try!(InputDlgCls::get_cls_id());
+    let inst_rc = rcrc(inst);
+    let inst_ptr = Box::into_raw(Box::new(inst_rc.clone()) as Box<Any>);
+    let owner_rect = try!(GetWindowRect(owner));
+    let x = (owner_rect.left + owner_rect.right - WIDTH) / 2;
+    let y = (owner_rect.top + owner_rect.bottom - height) / 2;
+    let instance = try!(GetModuleHandleW(None));
+    let hwnd = try!(CreateWindowExW(
+        WS_EX_DLGMODALFRAME,
+        cls_id,
+        Some(&wstr(title)),
+        WS_POPUP | WS_CAPTION | WS_SYSMENU,
+        x, y, WIDTH, height,
+        Some(owner), None, instance, Some(inst_ptr as LPVOID)));
+
+    let client = try!(GetClientRect(hwnd));
+    let inner = client.right - 20;
+    try!(create_control(hwnd, "STATIC", label, 0, (10, 10, inner, 16), -1));
+    let edit = try!(create_control(
+        hwnd, "EDIT", initial, WS_BORDER | WS_TABSTOP | ES_AUTOHSCROLL | edit_style,
+        (10, 30, inner, 22), IDC_PROMPT_EDIT));
+    if let Some((text, items, chosen)) = choice {
+        try!(create_control(hwnd, "STATIC", text, 0, (10, 64, 140, 16), -1));
+        let list = try!(create_control(
+            hwnd, "COMBOBOX", "", WS_TABSTOP | WS_VSCROLL | CBS_DROPDOWNLIST,
+            (150, 60, inner - 140, 200), IDC_PROMPT_CHOICE));
+        for item in items {
+            let text = wstr(item);
+            SendMessageW(list, CB_ADDSTRING, 0, text.as_ptr() as LPARAM);
+        }
+        SendMessageW(list, CB_SETCURSEL, chosen as WPARAM, 0);
+        inst_rc.borrow().choice.set(list);
+    }
+    for (idx, &(text, checked)) in options.iter().enumerate() {
+        let option = try!(create_control(
+            hwnd, "BUTTON", text, WS_TABSTOP | BS_AUTOCHECKBOX,
+            (10, 60 + choice_height + idx as c_int * OPTION_HEIGHT, inner, 20), IDC_PROMPT_OPTION + idx as c_int));
+        if checked {
+            SendMessageW(option, BM_SETCHECK, BST_CHECKED, 0);
+        }
+        inst_rc.borrow().options.borrow_mut().push(option);
+    }
+    try!(create_control(
+        hwnd, "BUTTON", "OK", WS_TABSTOP | BS_DEFPUSHBUTTON,
+        (client.right - 180, client.bottom - 34, 80, 24), IDOK));
+    try!(create_control(
+        hwnd, "BUTTON", "Cancel", WS_TABSTOP | BS_PUSHBUTTON,
+        (client.right - 90, client.bottom - 34, 80, 24), IDCANCEL));
+    inst_rc.borrow().edit.set(edit);
+
+    let _ = EnableWindow(owner, false);
+    let _ = ShowWindow(hwnd, SW_SHOW);
+    SendMessageW(edit, EM_SETSEL as UINT, 0, -1);
+    let _ = SetFocus(edit);
+
+    let mut quit = false;
+    while !inst_rc.borrow().done.get() {
+        match blocking_msg_loop(None).next() {
+            Some(Ok(mut msg)) => {
+                // the wrapper returns true for messages the dialog did not consume
+                if IsDialogMessage(hwnd, &mut msg) {
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            },
+            Some(Err(_)) => break,
+            None => { quit = true; break; },
+        }
+    }
+    let _ = EnableWindow(owner, true);
+    let _ = DestroyWindow(hwnd);
+    let _ = SetFocus(owner);
+    if quit {
+        // hand WM_QUIT back to the main loop
+        PostQuitMessage(0);
     }
+    let result = inst_rc.borrow().result.borrow_mut().take();
+    Ok(result)
 }
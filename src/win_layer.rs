@@ -4,6 +4,7 @@ extern crate winapi;
 extern crate kernel32;
 extern crate user32;
 extern crate gdi32;
+extern crate shell32;
 extern crate std;
 
 use winapi::*;
@@ -12,8 +13,64 @@ extern "system"
 {
     #[link_name = "BeginDeferWindowPos"]
     fn user32_BeginDeferWindowPos(nNumWindows: c_int) -> HDWP;
+    #[link_name = "TrackPopupMenu"]
+    fn user32_TrackPopupMenu(
+        hMenu: HMENU, uFlags: UINT, x: c_int, y: c_int,
+        nReserved: c_int, hWnd: HWND, prcRect: *const RECT) -> BOOL;
 }
 
+pub const TPM_LEFTALIGN: UINT = 0x0000;
+pub const TPM_TOPALIGN: UINT = 0x0000;
+pub const TPM_NONOTIFY: UINT = 0x0080;
+pub const TPM_RETURNCMD: UINT = 0x0100;
+pub const WA_INACTIVE: WORD = 0;
+pub const IDOK: c_int = 1;
+pub const IDCANCEL: c_int = 2;
+pub const IDYES: c_int = 6;
+pub const GMEM_MOVEABLE: UINT = 0x0002;
+pub const BM_GETCHECK: UINT = 0x00f0;
+pub const BM_SETCHECK: UINT = 0x00f1;
+pub const BST_CHECKED: WPARAM = 1;
+pub const COLORONCOLOR: c_int = 3;
+pub const HALFTONE: c_int = 4;
+pub const GDI_ERROR: c_int = -1;
+pub const BST_INDETERMINATE: WPARAM = 2;
+pub const LBS_NOTIFY: DWORD = 0x0001;
+pub const LBS_NOINTEGRALHEIGHT: DWORD = 0x0100;
+pub const LBN_DBLCLK: WORD = 2;
+pub const LB_ADDSTRING: UINT = 0x0180;
+pub const LB_RESETCONTENT: UINT = 0x0184;
+pub const LB_SETCURSEL: UINT = 0x0186;
+pub const LB_GETCURSEL: UINT = 0x0188;
+pub const LBS_USETABSTOPS: DWORD = 0x0080;
+pub const LBS_EXTENDEDSEL: DWORD = 0x0800;
+pub const LB_INSERTSTRING: UINT = 0x0181;
+pub const LB_DELETESTRING: UINT = 0x0182;
+pub const LB_SETSEL: UINT = 0x0185;
+pub const LB_GETTOPINDEX: UINT = 0x018e;
+pub const LB_GETSELCOUNT: UINT = 0x0190;
+pub const LB_GETSELITEMS: UINT = 0x0191;
+pub const LB_SETTABSTOPS: UINT = 0x0192;
+pub const LB_SETTOPINDEX: UINT = 0x0197;
+pub const LB_ERR: LRESULT = -1;
+pub const CBS_DROPDOWNLIST: DWORD = 0x0003;
+pub const SS_NOPREFIX: DWORD = 0x0080;
+
+// what ShellExecuteW returns on failure besides system error codes
+const SE_ERR_OOM: usize = 8;
+const SE_ERR_SHARE: usize = 26;
+const SE_ERR_ASSOCINCOMPLETE: usize = 27;
+const SE_ERR_DDETIMEOUT: usize = 28;
+const SE_ERR_DDEFAIL: usize = 29;
+const SE_ERR_DDEBUSY: usize = 30;
+const SE_ERR_NOASSOC: usize = 31;
+const SE_ERR_DLLNOTFOUND: usize = 32;
+const ERROR_NOT_ENOUGH_MEMORY: u32 = 8;
+const ERROR_SHARING_VIOLATION: u32 = 32;
+const ERROR_NO_ASSOCIATION: u32 = 1155;
+const ERROR_DDE_FAIL: u32 = 1156;
+const ERROR_DLL_NOT_FOUND: u32 = 1157;
+
 #[allow(dead_code)]
 #[inline]
 pub fn ExitProcess(code: c_uint) -> !
@@ -23,6 +80,10 @@ pub fn ExitProcess(code: c_uint) -> !
 pub fn GetLastError() -> u32
 { unsafe { kernel32::GetLastError() } }
 
+#[inline]
+pub fn SetLastError(code: u32)
+{ unsafe { kernel32::SetLastError(code) } }
+
 #[inline]
 pub fn GetModuleHandleW(name: Option<&[u16]>) -> Result<HMODULE, u32>
 {
@@ -101,6 +162,40 @@ pub fn from_wstr(w: &[u16]) -> String
     OsString::from_wide(w).to_string_lossy().to_string()
 }
 
+// Like `from_wstr`, for text in a Windows code page.
+#[inline]
+pub fn from_code_page(code_page: UINT, bytes: &[u8]) -> String
+{
+    match MultiByteToWideChar(code_page, bytes) {
+        Ok(wide) => from_wstr(&wide),
+        Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+#[inline]
+pub fn MultiByteToWideChar(code_page: UINT, bytes: &[u8]) -> Result<Vec<u16>, u32>
+{
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+    let src = bytes.as_ptr() as LPCSTR;
+    let len = unsafe { kernel32::MultiByteToWideChar(
+            code_page, 0, src, bytes.len() as c_int, 0 as LPWSTR, 0) };
+    if len == 0 {
+        return Err(GetLastError());
+    }
+    let mut wide = vec![0u16; len as usize];
+    let result = unsafe { kernel32::MultiByteToWideChar(
+            code_page, 0, src, bytes.len() as c_int, wide.as_mut_ptr(), len) };
+
+    if result != 0 { wide.truncate(result as usize); Ok(wide) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn IsDBCSLeadByteEx(code_page: UINT, byte: u8) -> bool
+{ unsafe { kernel32::IsDBCSLeadByteEx(code_page, byte) != 0 } }
+
 #[inline]
 pub fn ShowWindow(hWnd: HWND, nCmdShow: c_int) -> bool
 {
@@ -549,3 +644,450 @@ pub fn FillRect(hdc: HDC, rect: &RECT, brush: HBRUSH)
     if result != 0 { Ok(()) }
     else { Err(GetLastError()) }
 }
+
+#[inline]
+pub fn GetLogicalDrives() -> Result<DWORD, u32>
+{
+    let result = unsafe { kernel32::GetLogicalDrives() };
+
+    if result != 0 { Ok(result) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn GetDriveTypeW(root: &[u16]) -> UINT
+{ unsafe { kernel32::GetDriveTypeW(root.as_ptr()) } }
+
+/// Returns the volume label and the file system name.
+#[inline]
+pub fn GetVolumeInformationW(root: &[u16]) -> Result<(String, String), u32>
+{
+    let mut label = [0u16; MAX_PATH + 1];
+    let mut fs_name = [0u16; MAX_PATH + 1];
+    let result = unsafe { kernel32::GetVolumeInformationW(
+            root.as_ptr(),
+            label.as_mut_ptr(), label.len() as DWORD,
+            0 as LPDWORD, 0 as LPDWORD, 0 as LPDWORD,
+            fs_name.as_mut_ptr(), fs_name.len() as DWORD) };
+
+    let until_nul = |w: &[u16]| w.iter().position(|c| *c == 0).unwrap_or(w.len());
+    if result != 0 {
+        Ok((from_wstr(&label[..until_nul(&label)]),
+            from_wstr(&fs_name[..until_nul(&fs_name)])))
+    }
+    else { Err(GetLastError()) }
+}
+
+/// Returns the bytes available to the caller and the total size of the volume.
+#[inline]
+pub fn GetDiskFreeSpaceExW(directory: &[u16]) -> Result<(u64, u64), u32>
+{
+    let mut available: ULARGE_INTEGER = 0;
+    let mut total: ULARGE_INTEGER = 0;
+    let result = unsafe { kernel32::GetDiskFreeSpaceExW(
+            directory.as_ptr(),
+            &mut available as PULARGE_INTEGER,
+            &mut total as PULARGE_INTEGER,
+            0 as PULARGE_INTEGER) };
+
+    if result != 0 { Ok((available, total)) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn CreatePopupMenu() -> Result<HMENU, u32>
+{
+    let result = unsafe { user32::CreatePopupMenu() };
+
+    if result as usize != 0 { Ok(result) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn AppendMenuW(
+    menu: HMENU,
+    flags: UINT,
+    id: usize,
+    text: Option<&[u16]>)
+    -> Result<(), u32>
+{
+    let result = unsafe { user32::AppendMenuW(
+            menu, flags, id as UINT_PTR,
+            text.map_or(0 as LPCWSTR, |x|x.as_ptr() as LPCWSTR)) };
+
+    if result != 0 { Ok(()) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn DestroyMenu(menu: HMENU) -> Result<(), u32>
+{
+    let result = unsafe { user32::DestroyMenu(menu) };
+
+    if result != 0 { Ok(()) }
+    else { Err(GetLastError()) }
+}
+
+/// Shows the menu and returns the chosen command id, zero if the menu was dismissed.
+/// Always passes `TPM_RETURNCMD`.
+#[inline]
+pub fn TrackPopupMenu(
+    menu: HMENU,
+    flags: UINT,
+    x: c_int, y: c_int,
+    hwnd: HWND)
+    -> Result<UINT, u32>
+{
+    let result = unsafe { user32_TrackPopupMenu(
+            menu, flags | TPM_RETURNCMD, x, y, 0, hwnd, 0 as *const RECT) };
+
+    if result != 0 { Ok(result as UINT) }
+    else {
+        let err = GetLastError();
+        if err == 0 { Ok(0) } else { Err(err) }
+    }
+}
+
+/// Opens `file` the way Explorer would, with the verb's default program.
+#[inline]
+pub fn ShellExecuteW(
+    hwnd: Option<HWND>,
+    verb: &[u16],
+    file: &[u16],
+    directory: Option<&[u16]>)
+    -> Result<(), u32>
+{
+    let result = unsafe { shell32::ShellExecuteW(
+            hwnd.unwrap_or(0 as HWND),
+            verb.as_ptr(),
+            file.as_ptr(),
+            0 as LPCWSTR,
+            directory.map_or(0 as LPCWSTR, |d| d.as_ptr()),
+            SW_SHOWNORMAL) };
+
+    // values up to 32 are errors of its own, given as the nearest system error
+    match result as usize {
+        0 | SE_ERR_OOM => Err(ERROR_NOT_ENOUGH_MEMORY),
+        SE_ERR_SHARE => Err(ERROR_SHARING_VIOLATION),
+        SE_ERR_ASSOCINCOMPLETE | SE_ERR_NOASSOC => Err(ERROR_NO_ASSOCIATION),
+        SE_ERR_DDETIMEOUT | SE_ERR_DDEFAIL | SE_ERR_DDEBUSY => Err(ERROR_DDE_FAIL),
+        SE_ERR_DLLNOTFOUND => Err(ERROR_DLL_NOT_FOUND),
+        // the rest are the system errors they stand for, like ERROR_FILE_NOT_FOUND
+        code if code <= 32 => Err(code as u32),
+        _ => Ok(()),
+    }
+}
+
+#[inline]
+pub fn ClientToScreen(hwnd: HWND, point: POINT) -> Result<POINT, u32>
+{
+    let mut point = point;
+    let result = unsafe { user32::ClientToScreen(hwnd, &mut point as *mut POINT) };
+
+    if result != 0 { Ok(point) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn SetFocus(hwnd: HWND) -> Option<HWND>
+{
+    let prev = unsafe { user32::SetFocus(hwnd) };
+
+    if prev as usize != 0 { Some(prev) }
+    else { None }
+}
+
+#[inline]
+pub fn GetFocus() -> Option<HWND>
+{
+    let hwnd = unsafe { user32::GetFocus() };
+
+    if hwnd as usize != 0 { Some(hwnd) }
+    else { None }
+}
+
+#[inline]
+pub fn GetParent(hwnd: HWND) -> Option<HWND>
+{
+    let parent = unsafe { user32::GetParent(hwnd) };
+
+    if parent as usize != 0 { Some(parent) }
+    else { None }
+}
+
+#[inline]
+pub fn GetKeyState(virt_key: c_int) -> bool
+{ unsafe { (user32::GetKeyState(virt_key) as u16 & 0x8000) != 0 } }
+
+#[inline]
+pub fn GetStockObject(object: c_int) -> Result<HGDIOBJ, u32>
+{
+    let result = unsafe { gdi32::GetStockObject(object) };
+
+    if result as usize != 0 { Ok(result) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn SelectObject(hdc: HDC, object: HGDIOBJ) -> Result<HGDIOBJ, u32>
+{
+    let result = unsafe { gdi32::SelectObject(hdc, object) };
+
+    if result as usize != 0 { Ok(result) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn SetTextColor(hdc: HDC, color: COLORREF) -> COLORREF
+{ unsafe { gdi32::SetTextColor(hdc, color) } }
+
+#[inline]
+pub fn GetSysColor(index: c_int) -> COLORREF
+{ unsafe { user32::GetSysColor(index) } }
+
+#[inline]
+pub fn GetTextExtentPoint32W(hdc: HDC, string: &[u16]) -> Result<SIZE, u32>
+{
+    let mut size = SIZE { cx: 0, cy: 0 };
+    let result = unsafe { gdi32::GetTextExtentPoint32W(
+            hdc, string.as_ptr(), string.len() as c_int, &mut size as LPSIZE) };
+
+    if result != 0 { Ok(size) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn PostMessageW(
+    hwnd: HWND,
+    msg: UINT,
+    param: WPARAM,
+    para: LPARAM)
+    -> Result<(), u32>
+{
+    let result = unsafe { user32::PostMessageW(hwnd, msg, param, para) };
+
+    if result != 0 { Ok(()) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn SetWindowLongPtrW(hwnd: HWND, index: c_int, value: LONG_PTR) -> Result<LONG_PTR, u32>
+{
+    // 0 is also what a value never set before reads as, so only the error
+    // code tells a failure apart
+    SetLastError(0);
+    let result = unsafe { user32::SetWindowLongPtrW(hwnd, index, value) };
+
+    if result != 0 { Ok(result) }
+    else {
+        let err = GetLastError();
+        if err == 0 { Ok(0) } else { Err(err) }
+    }
+}
+
+#[inline]
+pub fn CallWindowProcW(
+    prev_proc: LONG_PTR,
+    hwnd: HWND,
+    msg: UINT,
+    param: WPARAM,
+    para: LPARAM)
+    -> LRESULT
+{
+    unsafe {
+        let prev_proc: WNDPROC = std::mem::transmute(prev_proc);
+        user32::CallWindowProcW(prev_proc, hwnd, msg, param, para)
+    }
+}
+
+#[inline]
+pub fn GetWindowTextW(hwnd: HWND) -> String
+{
+    let len = unsafe { user32::GetWindowTextLengthW(hwnd) };
+    let mut buffer = vec![0u16; len as usize + 1];
+    let copied = unsafe { user32::GetWindowTextW(hwnd, buffer.as_mut_ptr(), buffer.len() as c_int) };
+    from_wstr(&buffer[..copied as usize])
+}
+
+#[inline]
+pub fn SetWindowTextW(hwnd: HWND, text: &[u16]) -> Result<(), u32>
+{
+    let result = unsafe { user32::SetWindowTextW(hwnd, text.as_ptr()) };
+
+    if result != 0 { Ok(()) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn EnableWindow(hwnd: HWND, enable: bool) -> bool
+{
+    let enable = if enable { 1 } else { 0 };
+    unsafe { user32::EnableWindow(hwnd, enable) != 0 }
+}
+
+#[inline]
+pub fn GetWindowRect(hwnd: HWND) -> Result<RECT, u32>
+{
+    let mut rect;
+    let result = unsafe {
+        rect = std::mem::zeroed::<RECT>();
+        user32::GetWindowRect(hwnd, &mut rect as *mut RECT)
+    };
+
+    if result != 0 { Ok(rect) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn CreateFontIndirectW(font: &LOGFONTW) -> Result<HFONT, u32>
+{
+    let result = unsafe { gdi32::CreateFontIndirectW(font as *const LOGFONTW) };
+
+    if result as usize != 0 { Ok(result) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn DeleteObject(object: HGDIOBJ) -> Result<(), u32>
+{
+    let result = unsafe { gdi32::DeleteObject(object) };
+
+    if result != 0 { Ok(()) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn GetTextMetricsW(hdc: HDC) -> Result<TEXTMETRICW, u32>
+{
+    let mut metrics;
+    let result = unsafe {
+        metrics = std::mem::zeroed::<TEXTMETRICW>();
+        gdi32::GetTextMetricsW(hdc, &mut metrics as *mut TEXTMETRICW)
+    };
+
+    if result != 0 { Ok(metrics) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn SetBkColor(hdc: HDC, color: COLORREF) -> COLORREF
+{ unsafe { gdi32::SetBkColor(hdc, color) } }
+
+#[inline]
+pub fn OpenClipboard(hwnd: HWND) -> Result<(), u32>
+{
+    let result = unsafe { user32::OpenClipboard(hwnd) };
+
+    if result != 0 { Ok(()) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn EmptyClipboard() -> Result<(), u32>
+{
+    let result = unsafe { user32::EmptyClipboard() };
+
+    if result != 0 { Ok(()) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn CloseClipboard() -> Result<(), u32>
+{
+    let result = unsafe { user32::CloseClipboard() };
+
+    if result != 0 { Ok(()) }
+    else { Err(GetLastError()) }
+}
+
+/// On success the clipboard owns `data`.
+#[inline]
+pub fn SetClipboardData(format: UINT, data: HANDLE) -> Result<HANDLE, u32>
+{
+    let result = unsafe { user32::SetClipboardData(format, data) };
+
+    if result as usize != 0 { Ok(result) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn GlobalAlloc(flags: UINT, size: usize) -> Result<HGLOBAL, u32>
+{
+    let result = unsafe { kernel32::GlobalAlloc(flags, size as SIZE_T) };
+
+    if result as usize != 0 { Ok(result) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn GlobalLock(mem: HGLOBAL) -> Result<LPVOID, u32>
+{
+    let result = unsafe { kernel32::GlobalLock(mem) };
+
+    if result as usize != 0 { Ok(result) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn GlobalUnlock(mem: HGLOBAL)
+{ unsafe { kernel32::GlobalUnlock(mem); } }
+
+#[inline]
+pub fn GlobalFree(mem: HGLOBAL)
+{ unsafe { kernel32::GlobalFree(mem); } }
+
+#[inline]
+pub fn SetTimer(hwnd: HWND, id: UINT_PTR, elapse_ms: UINT) -> Result<UINT_PTR, u32>
+{
+    let result = unsafe { user32::SetTimer(hwnd, id, elapse_ms, None) };
+
+    if result != 0 { Ok(result) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn KillTimer(hwnd: HWND, id: UINT_PTR) -> Result<(), u32>
+{
+    let result = unsafe { user32::KillTimer(hwnd, id) };
+
+    if result != 0 { Ok(()) }
+    else { Err(GetLastError()) }
+}
+
+#[inline]
+pub fn SetCapture(hwnd: HWND)
+{ unsafe { user32::SetCapture(hwnd); } }
+
+#[inline]
+pub fn ReleaseCapture()
+{ unsafe { user32::ReleaseCapture(); } }
+
+#[inline]
+pub fn SetStretchBltMode(hdc: HDC, mode: c_int) -> c_int
+{ unsafe { gdi32::SetStretchBltMode(hdc, mode) } }
+
+/// Draws `pixels`, a top-down 32-bit BGRX bitmap of `width` by `height`,
+/// scaled into `dest`. Parts of `dest` outside the clip region are skipped.
+#[inline]
+pub fn StretchDIBits(hdc: HDC, dest: &RECT, width: c_int, height: c_int, pixels: &[u8]) -> Result<(), u32>
+{
+    assert!(width >= 0 && height >= 0 && pixels.len() >= width as usize * height as usize * 4);
+    let mut info: BITMAPINFO = unsafe { std::mem::zeroed() };
+    info.bmiHeader.biSize = std::mem::size_of::<BITMAPINFOHEADER>() as DWORD;
+    info.bmiHeader.biWidth = width;
+    // a negative height makes the rows go from the top
+    info.bmiHeader.biHeight = -height;
+    info.bmiHeader.biPlanes = 1;
+    info.bmiHeader.biBitCount = 32;
+    info.bmiHeader.biCompression = BI_RGB;
+    let result = unsafe {
+        gdi32::StretchDIBits(
+            hdc, dest.left, dest.top, dest.right - dest.left, dest.bottom - dest.top,
+            0, 0, width, height,
+            pixels.as_ptr() as *const VOID, &info as *const BITMAPINFO,
+            DIB_RGB_COLORS, SRCCOPY)
+    };
+
+    if result != 0 && result != GDI_ERROR { Ok(()) }
+    else { Err(GetLastError()) }
+}
@@ -0,0 +1,1291 @@
+// ZIP archives as a file system. The central directory is read when the
+// archive is opened and again when the file changes; entries are read from the
+// archive file on demand, stored or deflated, and checked against their CRC.
+// ZIP64 sizes and offsets, the UTF-8 name flag and the Info-ZIP Unicode path
+// field are understood.
+//
+// `ZipWriter` adds entries to a new or an existing archive: new data goes
+// where the central directory was and the directory is written again after
+// it, also when adding fails part way. Removing an entry only drops it from
+// the directory.
+//
+// Paths inside the archive hang off the archive's own path, so `C:\a.zip\doc`
+// is the `doc` directory of `C:\a.zip`. Entries whose names would land outside
+// the archive (`../x`, `/etc/x`, `C:x`) are not listed and so cannot be
+// extracted.
+
+extern crate flate2;
+
+use std::cmp;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use self::flate2::{Compression, Crc};
+use self::flate2::read::DeflateDecoder;
+use self::flate2::write::DeflateEncoder;
+
+use ::encoding::{self, Encoding};
+use ::remote::{civil_from_days, days_from_civil};
+use ::vfs::{DirEntry, Metadata, Vfs};
+
+const LOCAL_HEADER_SIG: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIG: u32 = 0x02014b50;
+const END_SIG: u32 = 0x06054b50;
+const ZIP64_END_SIG: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIG: u32 = 0x07064b50;
+
+const END_LEN: usize = 22;
+const ZIP64_LOCATOR_LEN: usize = 20;
+const CENTRAL_HEADER_LEN: usize = 46;
+const LOCAL_HEADER_LEN: usize = 30;
+const MAX_COMMENT: usize = 0xffff;
+
+const ZIP64_END_LEN: usize = 56;
+const COPY_CHUNK: usize = 1024 * 1024;
+
+const FLAG_ENCRYPTED: u16 = 1;
+const FLAG_UTF8: u16 = 1 << 11;
+
+pub const STORED: u16 = 0;
+pub const DEFLATED: u16 = 8;
+
+const EXTRA_ZIP64: u16 = 0x0001;
+const EXTRA_NTFS: u16 = 0x000a;
+const EXTRA_TIMESTAMP: u16 = 0x5455;
+const EXTRA_UNICODE_PATH: u16 = 0x7075;
+
+const HOST_DOS: u8 = 0;
+const HOST_UNIX: u8 = 3;
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+// sizes from here on get ZIP64 fields
+const ZIP64_LIMIT: u64 = 0xffffffff;
+// files expected to be at least this large get ZIP64 fields in their local
+// header, in case compression makes them larger
+const ZIP64_LOCAL_LIMIT: u64 = 0xffff0000;
+const DOS_DIRECTORY: u32 = 0x10;
+const DOS_HIDDEN: u32 = 0x02;
+const DOS_READ_ONLY: u32 = 0x01;
+const UNIX_TYPE_MASK: u32 = 0o170000;
+const UNIX_DIRECTORY: u32 = 0o040000;
+const UNIX_SYMLINK: u32 = 0o120000;
+
+// 100ns ticks between 1601-01-01 and 1970-01-01
+const FILETIME_UNIX_EPOCH: u64 = 116444736000000000;
+
+fn invalid(message: &str) -> io::Error
+{ io::Error::new(io::ErrorKind::InvalidData, message.to_string()) }
+
+fn u16_at(bytes: &[u8], at: usize) -> u16
+{ bytes[at] as u16 | (bytes[at + 1] as u16) << 8 }
+
+fn u32_at(bytes: &[u8], at: usize) -> u32
+{ u16_at(bytes, at) as u32 | (u16_at(bytes, at + 2) as u32) << 16 }
+
+fn u64_at(bytes: &[u8], at: usize) -> u64
+{ u32_at(bytes, at) as u64 | (u32_at(bytes, at + 4) as u64) << 32 }
+
+fn put_u16(out: &mut Vec<u8>, value: u16)
+{ out.extend_from_slice(&[value as u8, (value >> 8) as u8]); }
+
+fn put_u32(out: &mut Vec<u8>, value: u32)
+{ put_u16(out, value as u16); put_u16(out, (value >> 16) as u16); }
+
+fn put_u64(out: &mut Vec<u8>, value: u64)
+{ put_u32(out, value as u32); put_u32(out, (value >> 32) as u32); }
+
+// MS-DOS date and time fields. They carry no time zone; they are taken as UTC
+// unless an extra field gives the real time.
+fn from_dos_time(date: u16, time: u16) -> Option<SystemTime>
+{
+    let (year, month, day) = (1980 + (date >> 9) as i64, (date >> 5 & 0xf) as i64, (date & 0x1f) as i64);
+    let (hour, minute, second) = ((time >> 11) as u64, (time >> 5 & 0x3f) as u64, 2 * (time & 0x1f) as u64);
+    if month < 1 || month > 12 || day < 1 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day) as u64;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second))
+}
+
+// The MS-DOS (date, time) of `time`, within the years DOS can count.
+fn to_dos_time(time: SystemTime) -> (u16, u16)
+{
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    if year < 1980 {
+        return ((1 << 5) | 1, 0);
+    }
+    if year > 2107 {
+        return ((127 << 9) | (12 << 5) | 31, (23 << 11) | (59 << 5) | 29);
+    }
+    let in_day = seconds % 86400;
+    let date = ((year - 1980) << 9 | month << 5 | day) as u16;
+    let time = (in_day / 3600 << 11 | in_day % 3600 / 60 << 5 | in_day % 60 / 2) as u16;
+    (date, time)
+}
+
+fn from_filetime(ticks: u64) -> Option<SystemTime>
+{
+    ticks.checked_sub(FILETIME_UNIX_EPOCH)
+        .map(|t| UNIX_EPOCH + Duration::new(t / 10_000_000, (t % 10_000_000) as u32 * 100))
+}
+
+#[derive(Clone, Debug)]
+pub struct ZipEntry {
+    // the name as stored, `/` separated
+    pub name: String,
+    // host system and version of the program that made the entry, and the
+    // attributes in that system's terms
+    pub made_by: u16,
+    pub attributes: u32,
+    pub is_dir: bool,
+    pub method: u16,
+    pub flags: u16,
+    pub crc: u32,
+    pub compressed_size: u64,
+    pub size: u64,
+    // of the local header
+    pub offset: u64,
+    pub modified: Option<SystemTime>,
+    pub hidden: bool,
+    pub read_only: bool,
+    pub is_link: bool,
+}
+
+// The fields of an extra block that matter here; `entry` has the values of
+// the fixed header, which the ZIP64 field replaces where they are all ones.
+fn apply_extra(entry: &mut ZipEntry, raw_name: &[u8], mut extra: &[u8])
+{
+    while extra.len() >= 4 {
+        let (id, len) = (u16_at(extra, 0), u16_at(extra, 2) as usize);
+        if extra.len() < 4 + len {
+            break;
+        }
+        let data = &extra[4..4 + len];
+        match id {
+            EXTRA_ZIP64 => {
+                let mut at = 0;
+                let mut next = |value: &mut u64| if *value == 0xffffffff && at + 8 <= data.len() {
+                    *value = u64_at(data, at);
+                    at += 8;
+                };
+                next(&mut entry.size);
+                next(&mut entry.compressed_size);
+                next(&mut entry.offset);
+            },
+            EXTRA_TIMESTAMP if len >= 5 && data[0] & 1 != 0 => {
+                let seconds = u32_at(data, 1) as i32;
+                if seconds >= 0 {
+                    entry.modified = Some(UNIX_EPOCH + Duration::from_secs(seconds as u64));
+                }
+            },
+            EXTRA_NTFS if len >= 32 && u16_at(data, 4) == 1 && u16_at(data, 6) >= 8 => {
+                if let Some(time) = from_filetime(u64_at(data, 8)) {
+                    entry.modified = Some(time);
+                }
+            },
+            // only valid while the name it was made for is unchanged
+            EXTRA_UNICODE_PATH if len >= 5 && data[0] == 1 => {
+                let mut crc = Crc::new();
+                crc.update(raw_name);
+                if crc.sum() == u32_at(data, 1) {
+                    if let Ok(name) = String::from_utf8(data[5..].to_vec()) {
+                        entry.name = name;
+                    }
+                }
+            },
+            _ => {},
+        }
+        extra = &extra[4 + len..];
+    }
+}
+
+// Parses one central directory header at the start of `bytes` and returns it
+// with its total length.
+fn parse_central_header(bytes: &[u8]) -> io::Result<(ZipEntry, usize)>
+{
+    if bytes.len() < CENTRAL_HEADER_LEN || u32_at(bytes, 0) != CENTRAL_HEADER_SIG {
+        return Err(invalid("Damaged ZIP central directory"));
+    }
+    let (name_len, extra_len, comment_len) =
+        (u16_at(bytes, 28) as usize, u16_at(bytes, 30) as usize, u16_at(bytes, 32) as usize);
+    let len = CENTRAL_HEADER_LEN + name_len + extra_len + comment_len;
+    if bytes.len() < len {
+        return Err(invalid("Damaged ZIP central directory"));
+    }
+    let raw_name = &bytes[CENTRAL_HEADER_LEN..CENTRAL_HEADER_LEN + name_len];
+    let flags = u16_at(bytes, 8);
+    let name = if flags & FLAG_UTF8 != 0 {
+        String::from_utf8_lossy(raw_name).into_owned()
+    } else {
+        encoding::decode(raw_name, Encoding::CodePage(437))
+    };
+    let made_by = u16_at(bytes, 4);
+    let host = (made_by >> 8) as u8;
+    let attributes = u32_at(bytes, 38);
+    let unix_mode = if host == HOST_UNIX { attributes >> 16 } else { 0 };
+    let mut entry = ZipEntry {
+        is_dir: name.ends_with('/') || name.ends_with('\\')
+            || attributes & DOS_DIRECTORY != 0 && host != HOST_UNIX
+            || unix_mode & UNIX_TYPE_MASK == UNIX_DIRECTORY,
+        name: name,
+        made_by: made_by,
+        attributes: attributes,
+        method: u16_at(bytes, 10),
+        flags: flags,
+        crc: u32_at(bytes, 16),
+        compressed_size: u32_at(bytes, 20) as u64,
+        size: u32_at(bytes, 24) as u64,
+        offset: u32_at(bytes, 42) as u64,
+        modified: from_dos_time(u16_at(bytes, 14), u16_at(bytes, 12)),
+        hidden: host != HOST_UNIX && attributes & DOS_HIDDEN != 0,
+        read_only: if host == HOST_UNIX { unix_mode != 0 && unix_mode & 0o222 == 0 }
+                   else { attributes & DOS_READ_ONLY != 0 },
+        is_link: unix_mode & UNIX_TYPE_MASK == UNIX_SYMLINK,
+    };
+    let extra_start = CENTRAL_HEADER_LEN + name_len;
+    apply_extra(&mut entry, raw_name, &bytes[extra_start..extra_start + extra_len]);
+    Ok((entry, len))
+}
+
+// Where the central directory is and how many entries it has, from the end
+// of central directory record and, for ZIP64 archives, its ZIP64 version.
+fn find_central_directory<R: Read + Seek>(file: &mut R) -> io::Result<(u64, u64, u64)>
+{
+    let file_len = try!(file.seek(SeekFrom::End(0)));
+    let tail_len = cmp::min(file_len, (END_LEN + MAX_COMMENT + ZIP64_LOCATOR_LEN) as u64) as usize;
+    let tail_start = file_len - tail_len as u64;
+    let mut tail = vec![0; tail_len];
+    try!(file.seek(SeekFrom::Start(tail_start)));
+    try!(file.read_exact(&mut tail));
+    let end = try!((0..(tail_len + 1).saturating_sub(END_LEN)).rev()
+        .find(|&at| u32_at(&tail, at) == END_SIG && at + END_LEN + u16_at(&tail, at + 20) as usize <= tail_len)
+        .ok_or_else(|| invalid("Not a ZIP archive")));
+    if u16_at(&tail, end + 4) != u16_at(&tail, end + 6) {
+        return Err(io::Error::new(io::ErrorKind::Other, "Archives split over several files are not supported"));
+    }
+    let mut count = u16_at(&tail, end + 10) as u64;
+    let mut size = u32_at(&tail, end + 12) as u64;
+    let mut offset = u32_at(&tail, end + 16) as u64;
+    if end >= ZIP64_LOCATOR_LEN && u32_at(&tail, end - ZIP64_LOCATOR_LEN) == ZIP64_LOCATOR_SIG {
+        let record_offset = u64_at(&tail, end - ZIP64_LOCATOR_LEN + 8);
+        let mut record = [0; ZIP64_END_LEN];
+        try!(file.seek(SeekFrom::Start(record_offset)));
+        try!(file.read_exact(&mut record));
+        if u32_at(&record, 0) != ZIP64_END_SIG {
+            return Err(invalid("Damaged ZIP64 end of central directory"));
+        }
+        count = u64_at(&record, 32);
+        size = u64_at(&record, 40);
+        offset = u64_at(&record, 48);
+    }
+    if offset.checked_add(size).map_or(true, |end| end > file_len) {
+        return Err(invalid("Damaged ZIP end of central directory"));
+    }
+    Ok((offset, size, count))
+}
+
+// All entries of the archive, in the order of its central directory.
+pub fn read_entries<R: Read + Seek>(file: &mut R) -> io::Result<Vec<ZipEntry>>
+{ read_directory(file).map(|(entries, _)| entries) }
+
+// The entries and where the central directory starts, which is where the
+// data of the entries ends.
+fn read_directory<R: Read + Seek>(file: &mut R) -> io::Result<(Vec<ZipEntry>, u64)>
+{
+    let (offset, size, count) = try!(find_central_directory(file));
+    let mut directory = vec![0; size as usize];
+    try!(file.seek(SeekFrom::Start(offset)));
+    try!(file.read_exact(&mut directory));
+    let mut entries = Vec::with_capacity(cmp::min(count, 1 << 16) as usize);
+    let mut at = 0;
+    while at < directory.len() {
+        let (entry, len) = try!(parse_central_header(&directory[at..]));
+        entries.push(entry);
+        at += len;
+    }
+    Ok((entries, offset))
+}
+
+// The components of an entry name, or None if it would leave the archive.
+pub fn safe_components(name: &str) -> Option<Vec<&str>>
+{
+    if name.starts_with('/') || name.starts_with('\\') || name.contains(':') {
+        return None;
+    }
+    let components: Vec<&str> = name.split(|c| c == '/' || c == '\\')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect();
+    if components.iter().any(|c| *c == "..") { None } else { Some(components) }
+}
+
+// Checks the CRC and the length of what an entry decompresses to.
+struct Checked<R> {
+    inner: R,
+    crc: Crc,
+    expected_crc: u32,
+    expected_size: u64,
+}
+
+impl<R: Read> Read for Checked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        let read = try!(self.inner.read(buf));
+        self.crc.update(&buf[..read]);
+        if read == 0 && !buf.is_empty()
+            && (self.crc.sum() != self.expected_crc || self.crc.amount() as u64 != self.expected_size) {
+            return Err(invalid("CRC error, the archive is damaged"));
+        }
+        Ok(read)
+    }
+}
+
+
+// The central directory header of `entry`, with ZIP64 fields where its
+// numbers do not fit and the modification time as an extended timestamp.
+fn central_header(entry: &ZipEntry) -> Vec<u8>
+{
+    let mut zip64 = Vec::new();
+    for &value in &[entry.size, entry.compressed_size, entry.offset] {
+        if value >= ZIP64_LIMIT {
+            put_u64(&mut zip64, value);
+        }
+    }
+    let mut extra = Vec::new();
+    if !zip64.is_empty() {
+        put_u16(&mut extra, EXTRA_ZIP64);
+        put_u16(&mut extra, zip64.len() as u16);
+        extra.extend_from_slice(&zip64);
+    }
+    timestamp_extra(&mut extra, entry.modified);
+    let (date, time) = to_dos_time(entry.modified.unwrap_or(UNIX_EPOCH));
+    let name = entry.name.as_bytes();
+    let mut out = Vec::with_capacity(CENTRAL_HEADER_LEN + name.len() + extra.len());
+    put_u32(&mut out, CENTRAL_HEADER_SIG);
+    put_u16(&mut out, entry.made_by);
+    put_u16(&mut out, if zip64.is_empty() { VERSION_DEFAULT } else { VERSION_ZIP64 });
+    put_u16(&mut out, name_flags(entry));
+    put_u16(&mut out, entry.method);
+    put_u16(&mut out, time);
+    put_u16(&mut out, date);
+    put_u32(&mut out, entry.crc);
+    put_u32(&mut out, cmp::min(entry.compressed_size, ZIP64_LIMIT) as u32);
+    put_u32(&mut out, cmp::min(entry.size, ZIP64_LIMIT) as u32);
+    put_u16(&mut out, name.len() as u16);
+    put_u16(&mut out, extra.len() as u16);
+    // comment length, disk, internal attributes
+    put_u16(&mut out, 0);
+    put_u16(&mut out, 0);
+    put_u16(&mut out, 0);
+    put_u32(&mut out, entry.attributes);
+    put_u32(&mut out, cmp::min(entry.offset, ZIP64_LIMIT) as u32);
+    out.extend_from_slice(name);
+    out.extend_from_slice(&extra);
+    out
+}
+
+fn timestamp_extra(extra: &mut Vec<u8>, modified: Option<SystemTime>)
+{
+    let seconds = modified.and_then(|m| m.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+    if seconds > 0 && seconds <= i32::max_value() as u64 {
+        put_u16(extra, EXTRA_TIMESTAMP);
+        put_u16(extra, 5);
+        extra.push(1);
+        put_u32(extra, seconds as u32);
+    }
+}
+
+// Names are written as UTF-8, flagged as such unless they are plain ASCII.
+fn name_flags(entry: &ZipEntry) -> u16
+{
+    let flags = entry.flags & !FLAG_UTF8;
+    if entry.name.is_ascii() { flags } else { flags | FLAG_UTF8 }
+}
+
+// Counts what the compressor hands on to the archive file.
+struct Counter {
+    file: File,
+    written: u64,
+}
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        let written = try!(self.file.write(buf));
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    { self.file.flush() }
+}
+
+enum Compressor {
+    Stored(Counter),
+    Deflated(DeflateEncoder<Counter>),
+}
+
+// Adds entries to an archive file. Nothing is readable as an archive until
+// `finish` has written the central directory; a writer dropped without it
+// writes the directory of the entries completed so far, so an archive that
+// fails to take a file is left with the files before it.
+pub struct ZipWriter {
+    file: File,
+    entries: Vec<ZipEntry>,
+    // names of `entries`, to find the one a new entry replaces
+    names: HashSet<String>,
+    // where the next entry goes
+    offset: u64,
+    // data has gone over the central directory since it was last written
+    dirty: bool,
+}
+
+// An entry whose data is being written; `finish` completes it and dropping
+// it abandons it.
+pub struct EntryWriter {
+    // before `zip`, so what it flushes when dropped goes under the directory
+    // `zip` writes when dropped
+    output: Compressor,
+    zip: ZipWriter,
+    entry: ZipEntry,
+    // the local header has ZIP64 sizes
+    large: bool,
+    crc: Crc,
+}
+
+impl ZipWriter {
+    pub fn create(path: &Path) -> io::Result<ZipWriter>
+    {
+        let file = try!(File::create(path));
+        Ok(ZipWriter { file: file, entries: Vec::new(), names: HashSet::new(), offset: 0, dirty: false })
+    }
+
+    // Continues an existing archive.
+    pub fn append(path: &Path) -> io::Result<ZipWriter>
+    {
+        let mut file = try!(OpenOptions::new().read(true).write(true).open(path));
+        let (entries, offset) = try!(read_directory(&mut file));
+        let names = entries.iter().map(|e| e.name.clone()).collect();
+        Ok(ZipWriter { file: file, entries: entries, names: names, offset: offset, dirty: false })
+    }
+
+    pub fn entries_mut(&mut self) -> &mut Vec<ZipEntry>
+    { &mut self.entries }
+
+    // Drops the entries called `name`, or below it for directories.
+    pub fn remove(&mut self, name: &str)
+    {
+        let dir = format!("{}/", name.trim_end_matches('/'));
+        self.entries.retain(|e| e.name != name && !e.name.starts_with(&dir));
+        self.names = self.entries.iter().map(|e| e.name.clone()).collect();
+    }
+
+    // Adds `entry` to the directory in place of one with the same name.
+    fn push(&mut self, entry: ZipEntry)
+    {
+        if !self.names.insert(entry.name.clone()) {
+            self.entries.retain(|e| e.name != entry.name);
+        }
+        self.entries.push(entry);
+    }
+
+    fn new_entry(name: &str, is_dir: bool, method: u16, modified: Option<SystemTime>) -> ZipEntry
+    {
+        ZipEntry {
+            name: name.to_string(),
+            made_by: (HOST_DOS as u16) << 8 | VERSION_ZIP64,
+            attributes: if is_dir { DOS_DIRECTORY } else { 0 },
+            is_dir: is_dir,
+            method: method,
+            flags: 0,
+            crc: 0,
+            compressed_size: 0,
+            size: 0,
+            offset: 0,
+            modified: modified,
+            hidden: false,
+            read_only: false,
+            is_link: false,
+        }
+    }
+
+    fn local_header(entry: &ZipEntry, large: bool) -> Vec<u8>
+    {
+        let mut extra = Vec::new();
+        if large {
+            put_u16(&mut extra, EXTRA_ZIP64);
+            put_u16(&mut extra, 16);
+            put_u64(&mut extra, entry.size);
+            put_u64(&mut extra, entry.compressed_size);
+        }
+        timestamp_extra(&mut extra, entry.modified);
+        let (date, time) = to_dos_time(entry.modified.unwrap_or(UNIX_EPOCH));
+        let name = entry.name.as_bytes();
+        let mut out = Vec::with_capacity(LOCAL_HEADER_LEN + name.len() + extra.len());
+        put_u32(&mut out, LOCAL_HEADER_SIG);
+        put_u16(&mut out, if large { VERSION_ZIP64 } else { VERSION_DEFAULT });
+        put_u16(&mut out, name_flags(entry));
+        put_u16(&mut out, entry.method);
+        put_u16(&mut out, time);
+        put_u16(&mut out, date);
+        put_u32(&mut out, entry.crc);
+        put_u32(&mut out, if large { ZIP64_LIMIT as u32 } else { entry.compressed_size as u32 });
+        put_u32(&mut out, if large { ZIP64_LIMIT as u32 } else { entry.size as u32 });
+        put_u16(&mut out, name.len() as u16);
+        put_u16(&mut out, extra.len() as u16);
+        out.extend_from_slice(name);
+        out.extend_from_slice(&extra);
+        out
+    }
+
+    // Adds the directory `name`; its entry ends in `/`.
+    pub fn add_dir(&mut self, name: &str, modified: Option<SystemTime>) -> io::Result<()>
+    {
+        let mut entry = ZipWriter::new_entry(&format!("{}/", name.trim_end_matches('/')), true, STORED, modified);
+        entry.offset = self.offset;
+        let header = ZipWriter::local_header(&entry, false);
+        self.dirty = true;
+        try!(self.file.seek(SeekFrom::Start(self.offset)));
+        try!(self.file.write_all(&header));
+        self.offset += header.len() as u64;
+        self.push(entry);
+        Ok(())
+    }
+
+    // Starts the file `name`, compressed at `level` (0 stores it). `size` is
+    // what the file is expected to take, if known.
+    pub fn begin_file(mut self, name: &str, modified: Option<SystemTime>, level: u32, size: Option<u64>)
+                      -> io::Result<EntryWriter>
+    {
+        let method = if level == 0 { STORED } else { DEFLATED };
+        let mut entry = ZipWriter::new_entry(name, false, method, modified);
+        entry.offset = self.offset;
+        let large = size.map_or(true, |size| size >= ZIP64_LOCAL_LIMIT);
+        let header = ZipWriter::local_header(&entry, large);
+        self.dirty = true;
+        try!(self.file.seek(SeekFrom::Start(self.offset)));
+        try!(self.file.write_all(&header));
+        let counter = Counter { file: try!(self.file.try_clone()), written: 0 };
+        let output = match method {
+            STORED => Compressor::Stored(counter),
+            _ => Compressor::Deflated(DeflateEncoder::new(counter, Compression::new(cmp::min(level, 9)))),
+        };
+        Ok(EntryWriter { output: output, zip: self, entry: entry, large: large, crc: Crc::new() })
+    }
+
+    // Adds a file with the contents of `input`, reporting the bytes read.
+    // Returns None, and leaves the archive as it was, if cancelled.
+    pub fn add_file(self, name: &str, modified: Option<SystemTime>, level: u32, size: Option<u64>,
+                    input: &mut Read, cancel: &AtomicBool, report: &mut FnMut(u64))
+                    -> io::Result<(ZipWriter, bool)>
+    {
+        let mut writer = try!(self.begin_file(name, modified, level, size));
+        let mut buffer = vec![0; COPY_CHUNK];
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                return Ok((writer.abandon(), false));
+            }
+            let read = try!(input.read(&mut buffer));
+            if read == 0 {
+                break;
+            }
+            try!(writer.write_all(&buffer[..read]));
+            report(read as u64);
+        }
+        writer.finish().map(|zip| (zip, true))
+    }
+
+    // Writes the central directory after the last entry and cuts off anything
+    // beyond it; the writer can go on adding entries afterwards.
+    pub fn finish(&mut self) -> io::Result<()>
+    {
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            directory.extend_from_slice(&central_header(entry));
+        }
+        let (offset, size, count) = (self.offset, directory.len() as u64, self.entries.len() as u64);
+        if count >= 0xffff || offset >= ZIP64_LIMIT || size >= ZIP64_LIMIT {
+            put_u32(&mut directory, ZIP64_END_SIG);
+            put_u64(&mut directory, ZIP64_END_LEN as u64 - 12);
+            put_u16(&mut directory, (HOST_DOS as u16) << 8 | VERSION_ZIP64);
+            put_u16(&mut directory, VERSION_ZIP64);
+            put_u32(&mut directory, 0);
+            put_u32(&mut directory, 0);
+            put_u64(&mut directory, count);
+            put_u64(&mut directory, count);
+            put_u64(&mut directory, size);
+            put_u64(&mut directory, offset);
+            put_u32(&mut directory, ZIP64_LOCATOR_SIG);
+            put_u32(&mut directory, 0);
+            put_u64(&mut directory, offset + size);
+            put_u32(&mut directory, 1);
+        }
+        put_u32(&mut directory, END_SIG);
+        put_u16(&mut directory, 0);
+        put_u16(&mut directory, 0);
+        put_u16(&mut directory, cmp::min(count, 0xffff) as u16);
+        put_u16(&mut directory, cmp::min(count, 0xffff) as u16);
+        put_u32(&mut directory, cmp::min(size, ZIP64_LIMIT) as u32);
+        put_u32(&mut directory, cmp::min(offset, ZIP64_LIMIT) as u32);
+        put_u16(&mut directory, 0);
+        try!(self.file.seek(SeekFrom::Start(offset)));
+        try!(self.file.write_all(&directory));
+        try!(self.file.set_len(offset + directory.len() as u64));
+        try!(self.file.flush());
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Drop for ZipWriter {
+    fn drop(&mut self)
+    {
+        if self.dirty {
+            let _ = self.finish();
+        }
+    }
+}
+
+impl EntryWriter {
+    // Completes the entry: fills in its header and adds it to the directory.
+    pub fn finish(self) -> io::Result<ZipWriter>
+    {
+        let EntryWriter { output, mut zip, mut entry, large, crc } = self;
+        let compressed = match output {
+            Compressor::Stored(counter) => counter.written,
+            Compressor::Deflated(encoder) => try!(encoder.finish()).written,
+        };
+        entry.crc = crc.sum();
+        entry.size = crc.amount() as u64;
+        entry.compressed_size = compressed;
+        if !large && (entry.size >= ZIP64_LIMIT || compressed >= ZIP64_LIMIT) {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      format!("\"{}\" grew too large for the archive", entry.name)));
+        }
+        let header = ZipWriter::local_header(&entry, large);
+        try!(zip.file.seek(SeekFrom::Start(entry.offset)));
+        try!(zip.file.write_all(&header));
+        zip.offset = entry.offset + header.len() as u64 + compressed;
+        zip.push(entry);
+        Ok(zip)
+    }
+
+    // Gives up the entry; its data is overwritten by whatever comes next.
+    pub fn abandon(self) -> ZipWriter
+    { self.zip }
+}
+
+impl Write for EntryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        let written = try!(match self.output {
+            Compressor::Stored(ref mut counter) => counter.write(buf),
+            Compressor::Deflated(ref mut encoder) => encoder.write(buf),
+        });
+        self.crc.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        match self.output {
+            Compressor::Stored(ref mut counter) => counter.flush(),
+            Compressor::Deflated(ref mut encoder) => encoder.flush(),
+        }
+    }
+}
+
+struct Node {
+    meta: Metadata,
+    // index into `State::entries`; None for directories only implied by names
+    entry: Option<usize>,
+}
+
+// What the archive held when it was last read, and how the file looked then.
+struct State {
+    entries: Vec<ZipEntry>,
+    tree: BTreeMap<PathBuf, Node>,
+    stamp: (u64, Option<SystemTime>),
+}
+
+pub struct ZipFs {
+    // the archive on the disk
+    file: PathBuf,
+    // the path the archive is shown at; entries are below it
+    root: PathBuf,
+    // false for a copy of an archive that is not on the disk
+    writable: bool,
+    state: Arc<Mutex<State>>,
+}
+
+fn stamp(file: &Path) -> io::Result<(u64, Option<SystemTime>)>
+{ fs::metadata(file).map(|meta| (meta.len(), meta.modified().ok())) }
+
+fn not_writable(path: &Path) -> io::Error
+{ io::Error::new(io::ErrorKind::PermissionDenied, format!("Cannot change \"{}\": the archive is read-only", path.display())) }
+
+fn dir_metadata(modified: Option<SystemTime>) -> Metadata
+{ Metadata { is_dir: true, size: 0, modified: modified, hidden: false, read_only: false, is_link: false, mode: None } }
+
+fn index(root: &Path, entries: &[ZipEntry]) -> BTreeMap<PathBuf, Node>
+{
+    let mut tree = BTreeMap::new();
+    for (idx, entry) in entries.iter().enumerate() {
+        let components = match safe_components(&entry.name) {
+            Some(ref components) if !components.is_empty() => components.clone(),
+            _ => continue,
+        };
+        let mut path = root.to_path_buf();
+        for (depth, component) in components.iter().enumerate() {
+            path.push(component);
+            if depth + 1 < components.len() {
+                tree.entry(path.clone()).or_insert(Node { meta: dir_metadata(None), entry: None });
+            }
+        }
+        tree.insert(path, Node {
+            meta: Metadata {
+                is_dir: entry.is_dir,
+                size: if entry.is_dir { 0 } else { entry.size },
+                modified: entry.modified,
+                hidden: entry.hidden,
+                read_only: entry.read_only,
+                is_link: entry.is_link,
+                mode: None,
+            },
+            entry: Some(idx),
+        });
+    }
+    tree
+}
+
+impl ZipFs {
+    // Opens the archive `file`, to be shown at `root`.
+    pub fn open(file: &Path, root: &Path, writable: bool) -> io::Result<ZipFs>
+    {
+        let state = try!(ZipFs::read_state(file, root));
+        Ok(ZipFs {
+            file: file.to_path_buf(),
+            root: root.to_path_buf(),
+            writable: writable,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    fn read_state(file: &Path, root: &Path) -> io::Result<State>
+    {
+        let stamp = try!(stamp(file));
+        let entries = try!(File::open(file).and_then(|mut f| read_entries(&mut f)));
+        let tree = index(root, &entries);
+        Ok(State { entries: entries, tree: tree, stamp: stamp })
+    }
+
+    // The state, read again if the file changed since, say by packing into it.
+    fn with_state<T, F: FnOnce(&State) -> io::Result<T>>(&self, f: F) -> io::Result<T>
+    {
+        let mut state = self.state.lock().unwrap();
+        if stamp(&self.file).ok() != Some(state.stamp) {
+            *state = try!(ZipFs::read_state(&self.file, &self.root));
+        }
+        f(&state)
+    }
+
+    // Runs `f` on a writer for the archive, then writes the central directory.
+    fn update<F: FnOnce(&mut ZipWriter) -> io::Result<()>>(&self, path: &Path, f: F) -> io::Result<()>
+    {
+        if !self.writable {
+            return Err(not_writable(path));
+        }
+        let mut state = self.state.lock().unwrap();
+        let mut zip = try!(ZipWriter::append(&self.file));
+        try!(f(&mut zip));
+        try!(zip.finish());
+        drop(zip);
+        *state = try!(ZipFs::read_state(&self.file, &self.root));
+        Ok(())
+    }
+
+    // The name of the entry at `path`.
+    fn entry_name(&self, path: &Path) -> io::Result<String>
+    {
+        let rest = try!(path.strip_prefix(&self.root).map_err(|_|
+            io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is not in the archive", path.display()))));
+        let names: Vec<String> = rest.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        if names.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" is the archive itself", path.display())));
+        }
+        Ok(names.join("/"))
+    }
+
+    // Reads the entry's data, without the CRC check.
+    fn open_entry(&self, entry: &ZipEntry) -> io::Result<Box<Read + Send>>
+    {
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("\"{}\" is encrypted", entry.name)));
+        }
+        let mut file = try!(File::open(&self.file));
+        let mut header = [0; LOCAL_HEADER_LEN];
+        try!(file.seek(SeekFrom::Start(entry.offset)));
+        try!(file.read_exact(&mut header));
+        if u32_at(&header, 0) != LOCAL_HEADER_SIG {
+            return Err(invalid("Damaged ZIP local header"));
+        }
+        let skip = u16_at(&header, 26) as i64 + u16_at(&header, 28) as i64;
+        try!(file.seek(SeekFrom::Current(skip)));
+        let data = file.take(entry.compressed_size);
+        match entry.method {
+            STORED => Ok(Box::new(data)),
+            DEFLATED => Ok(Box::new(DeflateDecoder::new(data))),
+            method => Err(io::Error::new(io::ErrorKind::Other,
+                                         format!("\"{}\" uses compression method {}, which is not supported",
+                                                 entry.name, method))),
+        }
+    }
+}
+
+fn not_in_archive(path: &Path) -> io::Error
+{ io::Error::new(io::ErrorKind::NotFound, format!("\"{}\" is not in the archive", path.display())) }
+
+// A file being written into an archive shown in a panel. It is added to the
+// archive when flushed, and the archive is read again after that; dropped
+// before, it is left out and the archive keeps the files it had.
+struct ZipFsWriter {
+    entry: Option<EntryWriter>,
+    state: Arc<Mutex<State>>,
+}
+
+impl ZipFsWriter {
+    fn complete(&mut self) -> io::Result<()>
+    {
+        let entry = match self.entry.take() {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        let rv = entry.finish().and_then(|mut zip| zip.finish());
+        self.state.lock().unwrap().stamp = (u64::max_value(), None);
+        rv
+    }
+}
+
+impl Write for ZipFsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        match self.entry {
+            Some(ref mut entry) => entry.write(buf),
+            None => Err(io::Error::new(io::ErrorKind::Other, "The file is already in the archive")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    { self.complete() }
+}
+
+impl Drop for ZipFsWriter {
+    fn drop(&mut self)
+    {
+        if let Some(entry) = self.entry.take() {
+            // the writer below puts the directory back
+            drop(entry);
+            self.state.lock().unwrap().stamp = (u64::max_value(), None);
+        }
+    }
+}
+
+impl Vfs for ZipFs {
+    fn list(&self, dir: &Path) -> io::Result<Vec<DirEntry>>
+    {
+        self.with_state(|state| {
+            if dir != self.root && !try!(state.tree.get(dir).ok_or_else(|| not_in_archive(dir))).meta.is_dir {
+                return Err(io::Error::new(io::ErrorKind::Other, format!("\"{}\" is not a directory", dir.display())));
+            }
+            Ok(state.tree.range(dir.to_path_buf()..)
+               .take_while(|&(path, _)| path.starts_with(dir))
+               .filter(|&(path, _)| path.parent() == Some(dir))
+               .map(|(path, node)| DirEntry {
+                   name: path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned()),
+                   meta: node.meta.clone(),
+               })
+               .collect())
+        })
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Metadata>
+    {
+        if path == self.root {
+            return Ok(dir_metadata(None));
+        }
+        self.with_state(|state| state.tree.get(path).map(|node| node.meta.clone()).ok_or_else(|| not_in_archive(path)))
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<Read + Send>>
+    {
+        let entry = try!(self.with_state(|state| match state.tree.get(path) {
+            Some(&Node { entry: Some(idx), .. }) if !state.entries[idx].is_dir => Ok(state.entries[idx].clone()),
+            Some(_) => Err(io::Error::new(io::ErrorKind::Other, format!("\"{}\" is a directory", path.display()))),
+            None => Err(not_in_archive(path)),
+        }));
+        let data = try!(self.open_entry(&entry));
+        Ok(Box::new(Checked { inner: data, crc: Crc::new(), expected_crc: entry.crc, expected_size: entry.size }))
+    }
+
+    // Entries are deflated at the default level.
+    fn open_write(&self, path: &Path) -> io::Result<Box<Write + Send>>
+    {
+        if !self.writable {
+            return Err(not_writable(path));
+        }
+        let name = try!(self.entry_name(path));
+        let _state = self.state.lock().unwrap();
+        let zip = try!(ZipWriter::append(&self.file));
+        let entry = try!(zip.begin_file(&name, Some(SystemTime::now()), 6, None));
+        Ok(Box::new(ZipFsWriter { entry: Some(entry), state: self.state.clone() }))
+    }
+
+    fn mkdir(&self, path: &Path) -> io::Result<()>
+    {
+        if self.stat(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("\"{}\" already exists", path.display())));
+        }
+        let name = try!(self.entry_name(path));
+        self.update(path, |zip| zip.add_dir(&name, Some(SystemTime::now())))
+    }
+
+    // The entry is dropped from the directory; its data stays in the file.
+    fn remove(&self, path: &Path) -> io::Result<()>
+    {
+        if self.list(path).map(|entries| !entries.is_empty()).unwrap_or(false) {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("\"{}\" is not empty", path.display())));
+        }
+        let name = try!(self.entry_name(path));
+        self.update(path, |zip| { zip.remove(&name); Ok(()) })
+    }
+
+    fn rename(&self, from: &Path, _to: &Path) -> io::Result<()>
+    {
+        Err(io::Error::new(io::ErrorKind::Other,
+                           format!("Cannot rename \"{}\": renaming inside archives is not supported", from.display())))
+    }
+
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()>
+    {
+        let name = try!(self.entry_name(path));
+        self.update(path, |zip| {
+            for entry in zip.entries_mut().iter_mut().filter(|e| e.name.trim_end_matches('/') == name) {
+                entry.modified = Some(time);
+            }
+            Ok(())
+        })
+    }
+
+    fn mount_point(&self) -> Option<PathBuf>
+    { Some(self.root.clone()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn temp_path(name: &str) -> PathBuf
+    { env::temp_dir().join(format!("zip_test_{}_{}", process::id(), name)) }
+
+    fn read_all(zip: &ZipFs, path: &Path) -> Vec<u8>
+    {
+        let mut data = Vec::new();
+        zip.open_read(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    fn names(zip: &ZipFs, dir: &Path) -> Vec<String>
+    {
+        let mut names: Vec<String> = zip.list(dir).unwrap().into_iter().map(|e| e.name).collect();
+        names.sort();
+        names
+    }
+
+    // An archive put together byte by byte, one stored entry for each raw
+    // name, flags, central extra field and data. With `zip64` the central
+    // directory has its sizes and offsets in ZIP64 fields only.
+    fn hand_built(entries: &[(&[u8], u16, &[u8], &[u8])], zip64: bool) -> Vec<u8>
+    {
+        let (mut out, mut central) = (Vec::new(), Vec::new());
+        for &(name, flags, extra, data) in entries {
+            let (offset, len) = (out.len() as u64, data.len() as u64);
+            let mut crc = Crc::new();
+            crc.update(data);
+            put_u32(&mut out, LOCAL_HEADER_SIG);
+            put_u16(&mut out, VERSION_DEFAULT);
+            put_u16(&mut out, flags);
+            put_u16(&mut out, STORED);
+            put_u16(&mut out, 0);
+            put_u16(&mut out, 0x21);
+            put_u32(&mut out, crc.sum());
+            put_u32(&mut out, len as u32);
+            put_u32(&mut out, len as u32);
+            put_u16(&mut out, name.len() as u16);
+            put_u16(&mut out, 0);
+            out.extend_from_slice(name);
+            out.extend_from_slice(data);
+
+            let mut extra = extra.to_vec();
+            if zip64 {
+                put_u16(&mut extra, EXTRA_ZIP64);
+                put_u16(&mut extra, 24);
+                put_u64(&mut extra, len);
+                put_u64(&mut extra, len);
+                put_u64(&mut extra, offset);
+            }
+            let fixed = |value: u64| if zip64 { ZIP64_LIMIT as u32 } else { value as u32 };
+            put_u32(&mut central, CENTRAL_HEADER_SIG);
+            put_u16(&mut central, (HOST_DOS as u16) << 8 | VERSION_DEFAULT);
+            put_u16(&mut central, VERSION_DEFAULT);
+            put_u16(&mut central, flags);
+            put_u16(&mut central, STORED);
+            put_u16(&mut central, 0);
+            put_u16(&mut central, 0x21);
+            put_u32(&mut central, crc.sum());
+            put_u32(&mut central, fixed(len));
+            put_u32(&mut central, fixed(len));
+            put_u16(&mut central, name.len() as u16);
+            put_u16(&mut central, extra.len() as u16);
+            put_u16(&mut central, 0);
+            put_u16(&mut central, 0);
+            put_u16(&mut central, 0);
+            put_u32(&mut central, 0);
+            put_u32(&mut central, fixed(offset));
+            central.extend_from_slice(name);
+            central.extend_from_slice(&extra);
+        }
+        let (offset, size, count) = (out.len() as u64, central.len() as u64, entries.len() as u64);
+        out.extend_from_slice(&central);
+        if zip64 {
+            put_u32(&mut out, ZIP64_END_SIG);
+            put_u64(&mut out, ZIP64_END_LEN as u64 - 12);
+            put_u16(&mut out, VERSION_ZIP64);
+            put_u16(&mut out, VERSION_ZIP64);
+            put_u32(&mut out, 0);
+            put_u32(&mut out, 0);
+            put_u64(&mut out, count);
+            put_u64(&mut out, count);
+            put_u64(&mut out, size);
+            put_u64(&mut out, offset);
+            put_u32(&mut out, ZIP64_LOCATOR_SIG);
+            put_u32(&mut out, 0);
+            put_u64(&mut out, offset + size);
+            put_u32(&mut out, 1);
+        }
+        put_u32(&mut out, END_SIG);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, if zip64 { 0xffff } else { count as u16 });
+        put_u16(&mut out, if zip64 { 0xffff } else { count as u16 });
+        put_u32(&mut out, if zip64 { ZIP64_LIMIT as u32 } else { size as u32 });
+        put_u32(&mut out, if zip64 { ZIP64_LIMIT as u32 } else { offset as u32 });
+        put_u16(&mut out, 0);
+        out
+    }
+
+    #[test]
+    fn stored_and_deflated_round_trip()
+    {
+        let file = temp_path("round_trip.zip");
+        let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        let text = b"stored as it is".to_vec();
+        let repeated: Vec<u8> = b"squeeze me ".iter().cycle().take(10000).cloned().collect();
+        let cancel = AtomicBool::new(false);
+        let mut zip = ZipWriter::create(&file).unwrap();
+        zip.add_dir("docs", Some(time)).unwrap();
+        let (zip, _) = zip.add_file("docs/a.txt", Some(time), 0, Some(text.len() as u64),
+                                    &mut &text[..], &cancel, &mut |_| {}).unwrap();
+        // without a size the local header gets ZIP64 fields
+        let (mut zip, added) = zip.add_file("b.bin", None, 9, None, &mut &repeated[..], &cancel, &mut |_| {}).unwrap();
+        assert!(added);
+        zip.finish().unwrap();
+        drop(zip);
+
+        let entries = read_entries(&mut File::open(&file).unwrap()).unwrap();
+        let found: Vec<(&str, u16, bool)> = entries.iter().map(|e| (&e.name[..], e.method, e.is_dir)).collect();
+        assert_eq!(found, vec![("docs/", STORED, true), ("docs/a.txt", STORED, false), ("b.bin", DEFLATED, false)]);
+        assert_eq!((entries[1].size, entries[1].compressed_size), (15, 15));
+        assert!(entries[2].size == 10000 && entries[2].compressed_size < 1000);
+        assert_eq!(entries[1].modified, Some(time));
+
+        let zip = ZipFs::open(&file, &file, true).unwrap();
+        assert_eq!(names(&zip, &file), vec!["b.bin", "docs"]);
+        assert_eq!(read_all(&zip, &file.join("docs").join("a.txt")), text);
+        assert_eq!(read_all(&zip, &file.join("b.bin")), repeated);
+        assert!(zip.stat(&file.join("docs")).unwrap().is_dir);
+        fs::remove_file(&file).unwrap();
+    }
+
+    // Gives a few bytes and then fails.
+    struct FailingReader(&'static [u8]);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+        {
+            if self.0.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "gone"));
+            }
+            let len = cmp::min(self.0.len(), buf.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    fn entry_names(file: &Path) -> Vec<String>
+    { read_entries(&mut File::open(file).unwrap()).unwrap().into_iter().map(|e| e.name).collect() }
+
+    #[test]
+    fn failed_file_leaves_the_entries_before_it()
+    {
+        let file = temp_path("failed.zip");
+        fs::write(&file, hand_built(&[(b"old.txt", 0, b"", b"old")], false)).unwrap();
+        let cancel = AtomicBool::new(false);
+        for &level in &[0, 6] {
+            let zip = ZipWriter::append(&file).unwrap();
+            let err = zip.add_file("broken.txt", None, level, None, &mut FailingReader(b"partial"),
+                                   &cancel, &mut |_| {}).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+            assert_eq!(entry_names(&file), vec!["old.txt"]);
+        }
+        // a writer dropped without `finish` still writes the entries it completed
+        let zip = ZipWriter::append(&file).unwrap();
+        let (zip, _) = zip.add_file("new.txt", None, 6, Some(3), &mut &b"new"[..], &cancel, &mut |_| {}).unwrap();
+        drop(zip);
+        assert_eq!(entry_names(&file), vec!["old.txt", "new.txt"]);
+        let zip = ZipFs::open(&file, &file, false).unwrap();
+        assert_eq!(read_all(&zip, &file.join("old.txt")), b"old");
+        assert_eq!(read_all(&zip, &file.join("new.txt")), b"new");
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn files_written_into_an_archive_count_once_flushed()
+    {
+        let file = temp_path("written.zip");
+        fs::write(&file, hand_built(&[(b"old.txt", 0, b"", b"old")], false)).unwrap();
+        let len = fs::metadata(&file).unwrap().len();
+        let zip = ZipFs::open(&file, &file, true).unwrap();
+
+        let mut output = zip.open_write(&file.join("dropped.txt")).unwrap();
+        output.write_all(&vec![b'x'; 100000]).unwrap();
+        drop(output);
+        assert_eq!(names(&zip, &file), vec!["old.txt"]);
+        // the data written is cut off again
+        assert!(fs::metadata(&file).unwrap().len() < len + 100);
+        assert_eq!(read_all(&zip, &file.join("old.txt")), b"old");
+
+        let mut output = zip.open_write(&file.join("kept.txt")).unwrap();
+        output.write_all(b"kept").unwrap();
+        output.flush().unwrap();
+        assert!(output.write_all(b"more").is_err());
+        drop(output);
+        assert_eq!(names(&zip, &file), vec!["kept.txt", "old.txt"]);
+        assert_eq!(read_all(&zip, &file.join("kept.txt")), b"kept");
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn damaged_data_fails_the_crc()
+    {
+        let file = temp_path("damaged.zip");
+        let mut bytes = hand_built(&[(b"a.txt", 0, b"", b"hello")], false);
+        bytes[LOCAL_HEADER_LEN + 5] = b'j';
+        fs::write(&file, &bytes).unwrap();
+        let zip = ZipFs::open(&file, &file, false).unwrap();
+        let mut data = Vec::new();
+        let err = zip.open_read(&file.join("a.txt")).unwrap().read_to_end(&mut data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn zip64_central_directory()
+    {
+        let bytes = hand_built(&[(b"first", 0, b"", b"one"), (b"second", 0, b"", b"two!")], true);
+        let entries = read_entries(&mut io::Cursor::new(&bytes)).unwrap();
+        let found: Vec<(&str, u64, u64, u64)> =
+            entries.iter().map(|e| (&e.name[..], e.size, e.compressed_size, e.offset)).collect();
+        assert_eq!(found, vec![("first", 3, 3, 0), ("second", 4, 4, LOCAL_HEADER_LEN as u64 + 5 + 3)]);
+
+        let file = temp_path("zip64.zip");
+        fs::write(&file, &bytes).unwrap();
+        let zip = ZipFs::open(&file, &file, false).unwrap();
+        assert_eq!(read_all(&zip, &file.join("second")), b"two!");
+        fs::remove_file(&file).unwrap();
+
+        // a locator pointing at something else is damage, not a guess
+        let mut broken = bytes.clone();
+        let locator = broken.len() - END_LEN - ZIP64_LOCATOR_LEN;
+        broken[locator + 8] += 1;
+        assert_eq!(read_entries(&mut io::Cursor::new(&broken)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn cp437_and_utf8_names()
+    {
+        // 0x81 is u with diaeresis in code page 437
+        let mut unicode_path = vec![1];
+        let mut crc = Crc::new();
+        crc.update(b"\x81ber.txt");
+        put_u32(&mut unicode_path, crc.sum());
+        unicode_path.extend_from_slice("\u{fc}ber-u.txt".as_bytes());
+        let mut extra = Vec::new();
+        put_u16(&mut extra, EXTRA_UNICODE_PATH);
+        put_u16(&mut extra, unicode_path.len() as u16);
+        extra.extend_from_slice(&unicode_path);
+        let bytes = hand_built(&[
+            (b"\x81ber.txt", 0, b"", b"1"),
+            ("na\u{ef}ve.txt".as_bytes(), FLAG_UTF8, b"", b"2"),
+            ("na\u{ef}ve.txt".as_bytes(), 0, b"", b"3"),
+            (b"\x81ber.txt", 0, &extra, b"4"),
+        ], false);
+        let entries = read_entries(&mut io::Cursor::new(&bytes)).unwrap();
+        // the system converts code page 437; elsewhere only its ASCII half is known
+        let (ueber, i_diaeresis) =
+            if cfg!(windows) { ("\u{fc}", "\u{251c}\u{bb}") } else { ("\u{fffd}", "\u{fffd}\u{fffd}") };
+        assert_eq!(entries[0].name, format!("{}ber.txt", ueber));
+        assert_eq!(entries[1].name, "na\u{ef}ve.txt");
+        // the same bytes without the flag are code page 437
+        assert_eq!(entries[2].name, format!("na{}ve.txt", i_diaeresis));
+        // a Unicode path field made for the raw name replaces it
+        assert_eq!(entries[3].name, "\u{fc}ber-u.txt");
+
+        // names written back are UTF-8, flagged unless they are ASCII
+        assert_eq!(name_flags(&ZipWriter::new_entry("plain.txt", false, STORED, None)), 0);
+        assert_eq!(name_flags(&ZipWriter::new_entry("na\u{ef}ve.txt", false, STORED, None)), FLAG_UTF8);
+    }
+
+    #[test]
+    fn names_leaving_the_archive_are_dropped()
+    {
+        assert_eq!(safe_components("a/./b//c"), Some(vec!["a", "b", "c"]));
+        assert_eq!(safe_components("a\\b"), Some(vec!["a", "b"]));
+        assert_eq!(safe_components("a/..b"), Some(vec!["a", "..b"]));
+        for name in &["../x", "/x", "\\x", "C:x", "C:/x", "a/../x", "a\\..\\..\\x"] {
+            assert_eq!(safe_components(name), None, "{}", name);
+        }
+
+        let file = temp_path("unsafe.zip");
+        fs::write(&file, hand_built(&[
+            (b"../x", 0, b"", b"1"),
+            (b"/x", 0, b"", b"2"),
+            (b"C:x", 0, b"", b"3"),
+            (b"a\\..\\..\\x", 0, b"", b"4"),
+            (b"a\\ok", 0, b"", b"5"),
+        ], false)).unwrap();
+        let zip = ZipFs::open(&file, &file, false).unwrap();
+        assert_eq!(names(&zip, &file), vec!["a"]);
+        assert_eq!(names(&zip, &file.join("a")), vec!["ok"]);
+        assert_eq!(zip.stat(&file.join("x")).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(read_all(&zip, &file.join("a").join("ok")), b"5");
+        fs::remove_file(&file).unwrap();
+    }
+}